@@ -0,0 +1,95 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use less_2_task::calc::{compile, evaluate, parse, process, tokerize, Limits, NoResolver};
+
+// Набор выражений: короткое, длинное и несколько патологических (глубокая вложенность
+// скобок и функций, длинная цепочка унарных операторов, большой список)
+fn inputs() -> Vec<(&'static str, String)> {
+    let mut long = String::from("1");
+    while long.len() < 10_000 {
+        long.push_str(" + 2.5 * (3 - 4)");
+    }
+    let list = (1..=5_000).map(|item| item.to_string()).collect::<Vec<String>>().join(", ");
+    vec![
+        ("short", "2 + 3 * (4 - 1) / sqrt(16)".to_string()),
+        ("long", long),
+        ("nested_parentheses", format!("{}1{}", "(".repeat(60), ")".repeat(60))),
+        ("nested_functions", format!("{}2{}", "sqrt(".repeat(60), ")".repeat(60))),
+        ("unary_chain", format!("{}1", "-".repeat(200))),
+        ("large_list", format!("sum([{}])", list)),
+    ]
+}
+
+fn limits() -> Limits {
+    Limits { max_tokens: usize::MAX, max_steps: usize::MAX, ..Limits::default() }
+}
+
+// Разбиение на токены: токенов в секунду
+fn lexer(c: &mut Criterion) {
+    let limits = limits();
+    let mut group = c.benchmark_group("lexer");
+    for (name, input) in inputs() {
+        let tokens = tokerize(&input, &limits).unwrap().len();
+        group.throughput(Throughput::Elements(tokens as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &input, |b, input| {
+            b.iter(|| tokerize(input, &limits).unwrap().len())
+        });
+    }
+    group.finish();
+}
+
+// Разбор целиком (токены, проверки, преобразование в ОПН): токенов в секунду
+fn parser(c: &mut Criterion) {
+    let limits = limits();
+    let mut group = c.benchmark_group("parser");
+    for (name, input) in inputs() {
+        let tokens = tokerize(&input, &limits).unwrap().len();
+        group.throughput(Throughput::Elements(tokens as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &input, |b, input| {
+            b.iter(|| parse(input, &limits).unwrap().len())
+        });
+    }
+    group.finish();
+}
+
+// Вычисление готовой записи ОПН: вычислений в секунду
+fn evaluator(c: &mut Criterion) {
+    let limits = limits();
+    let mut group = c.benchmark_group("evaluator");
+    group.throughput(Throughput::Elements(1));
+    for (name, input) in inputs() {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &input, |b, input| {
+            b.iter_batched(|| parse(input, &limits).unwrap(), |rpn| evaluate(rpn, &limits).unwrap(), BatchSize::SmallInput)
+        });
+    }
+    group.finish();
+}
+
+// Повторное вычисление скомпилированного выражения: вычислений в секунду
+fn compiled(c: &mut Criterion) {
+    let limits = limits();
+    let mut group = c.benchmark_group("compiled");
+    group.throughput(Throughput::Elements(1));
+    for (name, input) in inputs() {
+        let program = compile(&input, &limits).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(name), &program, |b, program| {
+            b.iter(|| program.run(&limits, &NoResolver).unwrap())
+        });
+    }
+    group.finish();
+}
+
+// Полная обработка выражения: вычислений в секунду
+fn end_to_end(c: &mut Criterion) {
+    let limits = limits();
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Elements(1));
+    for (name, input) in inputs() {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &input, |b, input| {
+            b.iter(|| process(input, &limits).unwrap().result)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lexer, parser, evaluator, compiled, end_to_end);
+criterion_main!(benches);
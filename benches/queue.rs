@@ -0,0 +1,28 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use less_2_task::Queue;
+
+// Полный цикл "заполнить и опустошить очередь": при O(1) извлечении время на один
+// элемент не должно расти вместе с размером очереди
+fn fill_and_drain(c: &mut Criterion) {
+    let mut group = c.benchmark_group("queue_fill_and_drain");
+    for size in [1_000, 10_000, 100_000].iter() {
+        group.throughput(Throughput::Elements(*size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
+            b.iter(|| {
+                let mut queue = Queue::new();
+                for item in 0..size {
+                    queue.enqueue(item);
+                }
+                let mut sum = 0u64;
+                while !queue.is_empty() {
+                    sum += queue.dequeue() as u64;
+                }
+                sum
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fill_and_drain);
+criterion_main!(benches);
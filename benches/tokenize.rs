@@ -0,0 +1,50 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use less_2_task::calc::{tokerize, IncrementalTokens, Limits};
+
+// Разбор выражений разной длины (вплоть до 1 МБ): при линейном разборе время на
+// один байт входа не должно расти вместе с длиной выражения
+fn tokenize_long_input(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tokenize_long_input");
+    group.sample_size(10);
+    for size in [10_000, 100_000, 1_000_000].iter() {
+        let mut input = String::from("1");
+        while input.len() < *size {
+            input.push_str(" + 2.5 * (3 - 4)");
+        }
+        let limits = Limits { max_tokens: usize::MAX, ..Limits::default() };
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &input, |b, input| {
+            b.iter(|| tokerize(input, &limits).unwrap().len())
+        });
+    }
+    group.finish();
+}
+
+// Правка одного числа в середине длинного выражения: лексемы выделяются заново только
+// рядом с правкой, а с длиной выражения растут лишь сравнение строк и сдвиг мест лексем,
+// поэтому правка должна быть намного быстрее разбора всей строки (tokenize_long_input)
+fn tokenize_edit(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tokenize_edit");
+    group.sample_size(10);
+    for size in [10_000, 100_000, 1_000_000].iter() {
+        let mut input = String::from("1");
+        while input.len() < *size {
+            input.push_str(" + 2.5 * (3 - 4)");
+        }
+        // Число 2 из повторяющегося фрагмента примерно в середине
+        let middle = 1 + 16 * (input.len() / 32) + 3;
+        let edited = format!("{}7{}", &input[..middle], &input[middle + 1..]);
+        let limits = Limits { max_tokens: usize::MAX, ..Limits::default() };
+        let mut tokens = IncrementalTokens::new(&input, &limits).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(size), &(input, edited), |b, (input, edited)| {
+            b.iter(|| {
+                tokens.update(edited, &limits).unwrap();
+                tokens.update(input, &limits).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tokenize_long_input, tokenize_edit);
+criterion_main!(benches);
@@ -0,0 +1,20 @@
+#![no_main]
+
+use less_2_task::calc::{tokerize, IncrementalTokens, Limits};
+use libfuzzer_sys::fuzz_target;
+
+// Токены после правки строки, выделенные заново только в изменённой части, должны
+// совпадать с токенами всей новой строки, а при ошибке содержимое не должно меняться
+fuzz_target!(|strings: (&str, &str)| {
+    let (old, new) = strings;
+    let limits = Limits::default();
+    let mut tokens = match IncrementalTokens::new(old, &limits) {
+        Ok(tokens) => tokens,
+        Err(_) => return,
+    };
+    match (tokens.update(new, &limits), tokerize(new, &limits)) {
+        (Ok(_), Ok(expected)) => assert_eq!(tokens.tokens(), expected),
+        (Err(_), Err(_)) => assert_eq!(tokens.input(), old),
+        (updated, expected) => panic!("update: {:?}, tokerize: {:?}", updated.is_ok(), expected.is_ok()),
+    }
+});
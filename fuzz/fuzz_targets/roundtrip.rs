@@ -0,0 +1,117 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use less_2_task::calc::{convert_to_rpn, process, rpn_to_infix, tokerize, Limits};
+use libfuzzer_sys::fuzz_target;
+
+// Генератор заведомо корректных выражений
+#[derive(Arbitrary, Debug)]
+enum Expr {
+    Int(u16),
+    Float(u16, u8),
+    Unary(bool, Box<Expr>),
+    Binary(Box<Expr>, BinaryOperator, Box<Expr>),
+    Function(Function, Vec<Expr>),
+    Parenthesized(Box<Expr>),
+    List(Vec<Expr>),
+}
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Shl,
+    Shr,
+}
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+enum Function {
+    Abs,
+    Sqrt,
+    Min,
+    Max,
+}
+
+impl Expr {
+    // Запись выражения в инфиксной форме; операнды унарных операторов и функций
+    // берутся в скобки, чтобы выражение оставалось корректным
+    fn write(&self, out: &mut String) {
+        match self {
+            Expr::Int(value) => out.push_str(&value.to_string()),
+            Expr::Float(int, frac) => out.push_str(&format!("{}.{}", int, frac)),
+            Expr::Unary(negative, arg) => {
+                out.push_str(if *negative { "-(" } else { "+(" });
+                arg.write(out);
+                out.push(')');
+            },
+            Expr::Binary(arg1, op, arg2) => {
+                arg1.write(out);
+                out.push_str(match op {
+                    BinaryOperator::Add => " + ",
+                    BinaryOperator::Sub => " - ",
+                    BinaryOperator::Mul => " * ",
+                    BinaryOperator::Div => " / ",
+                    BinaryOperator::Shl => " << ",
+                    BinaryOperator::Shr => " >> ",
+                });
+                arg2.write(out);
+            },
+            Expr::Function(function, args) => {
+                let (name, arity) = match function {
+                    Function::Abs => ("abs", 1),
+                    Function::Sqrt => ("sqrt", 1),
+                    Function::Min => ("min", 2),
+                    Function::Max => ("max", 2),
+                };
+                out.push_str(name);
+                out.push('(');
+                for index in 0..arity {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    match args.get(index) {
+                        Some(arg) => arg.write(out),
+                        None => out.push('1'),
+                    }
+                }
+                out.push(')');
+            },
+            Expr::Parenthesized(arg) => {
+                out.push('(');
+                arg.write(out);
+                out.push(')');
+            },
+            Expr::List(items) => {
+                out.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    item.write(out);
+                }
+                out.push(']');
+            },
+        }
+    }
+}
+
+// Вычисление выражения и его же, восстановленного из ОПН, даёт одинаковый результат
+fuzz_target!(|expr: Expr| {
+    let mut infix = String::new();
+    expr.write(&mut infix);
+    let limits = Limits::default();
+    let evaluation = match process(&infix, &limits) {
+        Ok(evaluation) => evaluation,
+        Err(_) => return,
+    };
+
+    let tokens = tokerize(&infix, &limits).expect("корректное выражение должно разбиваться на токены");
+    let rpn = convert_to_rpn(tokens, &infix, &limits).expect("корректное выражение должно преобразовываться в ОПН");
+    let printed = rpn_to_infix(&rpn).expect("ОПН корректного выражения должна восстанавливаться");
+    // Восстановленное выражение может содержать больше скобок, чем исходное
+    let relaxed = Limits { max_depth: usize::MAX, max_nesting: usize::MAX, ..Limits::default() };
+    let again = process(&printed, &relaxed).expect("восстановленное выражение должно вычисляться");
+    assert_eq!(evaluation.result, again.result, "{} -> {}", infix, printed);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use less_2_task::calc::{process, tokerize, Limits};
+use libfuzzer_sys::fuzz_target;
+
+// Разбор и вычисление произвольной строки не должны приводить к панике:
+// любая ошибка во входных данных должна возвращаться как CalcError
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        let _ = tokerize(input, &Limits::default());
+        let _ = process(input, &Limits::default());
+    }
+});
@@ -0,0 +1,59 @@
+/* Интерфейс калькулятора less_2_task для C/C++ (сборка: cargo rustc --lib --crate-type cdylib --release --features ffi) */
+#ifndef L2T_H
+#define L2T_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum l2t_status {
+    L2T_OK = 0,
+    L2T_NULL_POINTER = 1,
+    L2T_INVALID_UTF8 = 2,
+    L2T_BUFFER_TOO_SMALL = 3,
+    L2T_PANIC = 4,
+    /* Ошибки разбора и вычисления выражения: номер кода ошибки (E0001...) плюс 99 */
+    L2T_UNKNOWN_TOKEN = 100,
+    L2T_MISSING_PARENTHESIS = 101,
+    L2T_MISSING_ARGUMENT_SEPARATOR = 102,
+    L2T_INVALID_OUTPUT_QUEUE = 103,
+    L2T_NO_RESULT = 104,
+    L2T_TOO_DEEP = 105,
+    L2T_TOO_COMPLEX = 106,
+    L2T_BUDGET_EXCEEDED = 107,
+    L2T_TIMEOUT = 108,
+    L2T_MISSING_OPERATOR = 109,
+    L2T_UNKNOWN_FUNCTION = 110,
+    L2T_INVALID_FUNCTION_CALL = 111,
+    L2T_EMPTY_EXPRESSION = 112,
+    L2T_EMPTY_PARENTHESES = 113,
+    L2T_DANGLING_OPERATOR = 114,
+    L2T_INVALID_NUMBER = 115,
+    L2T_INVALID_SHIFT = 116,
+    L2T_SHAPE_MISMATCH = 117,
+    L2T_UNIT_MISMATCH = 118,
+    L2T_UNKNOWN_UNIT = 119,
+    L2T_INVALID_DATE = 120,
+    L2T_INVALID_INTERVAL = 121,
+    L2T_UNKNOWN_VARIABLE = 122,
+    L2T_CANCELLED = 123,
+    L2T_FORBIDDEN = 124,
+    L2T_OVERFLOW = 125,
+    L2T_DIVISION_BY_ZERO = 126,
+    L2T_INEXACT_DIVISION = 127,
+    L2T_NO_CONVERGENCE = 128,
+    L2T_OUT_OF_RANGE = 129
+} l2t_status;
+
+/* Вычисляет выражение expr и записывает в out (не больше cap байт, с завершающим
+   нулём) результат либо сообщение об ошибке. Результат, не поместившийся в буфер,
+   не записывается: возвращается L2T_BUFFER_TOO_SMALL. */
+l2t_status l2t_eval(const char *expr, char *out, size_t cap);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,147 @@
+// Кэш разбора для REPL: запись ОПН и скомпилированное выражение недавно введённых строк,
+// чтобы повторный ввод той же строки не разбирался заново
+use less_2_task::calc::{parse, CalcError, Evaluation, Limits, Program, Resolver};
+
+// Число хранимых выражений; при переполнении вытесняется давно не использованное
+const CAPACITY: usize = 64;
+
+// Запись кэша: нормализованная строка, строка, введённая впервые (к ней относятся места
+// в скомпилированном выражении), запись ОПН и скомпилированное выражение
+type Entry = (String, String, String, Program);
+
+#[derive(Default)]
+pub struct ParseCache {
+    // Записи от давно использованных к недавним
+    entries: Vec<Entry>,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+// Ключ кэша: строка с пробелами, сжатыми до одного между лексемами
+fn normalize(input: &str) -> String {
+    input.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// Место в строке `to`, соответствующее месту `position` в строке `from`. Строки с одним
+// ключом различаются только пробелами, поэтому место определяется числом непробельных
+// символов перед ним; конец диапазона (`end`) ставится сразу после последнего из них
+fn rebase(position: usize, from: &str, to: &str, end: bool) -> usize {
+    let count = from[..position].chars().filter(|c| !c.is_whitespace()).count();
+    let mut visible = to.char_indices().filter(|(_, c)| !c.is_whitespace());
+    match (end, count) {
+        (true, 0) => 0,
+        (true, count) => visible.nth(count - 1).map_or(to.len(), |(index, c)| index + c.len_utf8()),
+        (false, count) => visible.nth(count).map_or(to.len(), |(index, _)| index),
+    }
+}
+
+// Ошибка вычисления сохранённого выражения с местами в строке `to` вместо строки `from`
+fn rebase_error(mut why: CalcError, from: &str, to: &str) -> CalcError {
+    let span = |(start, end): (usize, usize)| {
+        let start = rebase(start, from, to, false);
+        (start, rebase(end, from, to, true).max(start))
+    };
+    why.span = why.span.map(span);
+    for note in &mut why.notes {
+        note.0 = note.0.map(span);
+    }
+    why
+}
+
+impl ParseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        CAPACITY
+    }
+
+    // То же, что process_with, но разбор повторно введённой строки берётся из кэша.
+    // Ошибки разбора не кэшируются
+    pub fn process(&mut self, input: &str, limits: &Limits, resolver: &dyn Resolver) -> Result<Evaluation, CalcError> {
+        let key = normalize(input);
+        match self.entries.iter().position(|entry| entry.0 == key) {
+            Some(index) => {
+                self.hits += 1;
+                let entry = self.entries.remove(index);
+                self.entries.push(entry);
+            },
+            None => {
+                self.misses += 1;
+                let output = parse(input, limits)?;
+                let rpn = output.iter().map(|tok| &*tok.1).collect::<Vec<&str>>().join(" ");
+                let program = Program::from_rpn(output)?;
+                if self.entries.len() >= CAPACITY {
+                    self.entries.remove(0);
+                }
+                self.entries.push((key, input.to_string(), rpn, program));
+            },
+        }
+
+        let (_, first, rpn, program) = self.entries.last().unwrap();
+        match program.run_traced(limits, resolver) {
+            Ok((value, trace)) => Ok(Evaluation { rpn: rpn.clone(), result: value.to_string(), value, trace }),
+            // Места в строке у сохранённого выражения относятся к строке, введённой впервые
+            Err(why) => Err(rebase_error(why, first, input)),
+        }
+    }
+
+    // Строка команды REPL :stats
+    pub fn stats(&self) -> String {
+        format!("Кэш разбора: попаданий {}, промахов {}, выражений {} из {}", self.hits, self.misses, self.len(), self.capacity())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use less_2_task::calc::{process, ErrorKind, NoResolver, Value};
+
+    #[test]
+    fn hits_misses_and_eviction() {
+        let limits = Limits::default();
+        let mut cache = ParseCache::new();
+        assert_eq!(cache.process("1 + 2", &limits, &NoResolver).unwrap().value, Value::Number(3.0));
+        // Та же строка с другими пробелами - попадание
+        assert_eq!(cache.process("  1 +   2 ", &limits, &NoResolver).unwrap().value, Value::Number(3.0));
+        assert_eq!(cache.process("1 + 2 + 3", &limits, &NoResolver).unwrap().rpn, "1 2 + 3 +");
+        assert_eq!((cache.hits, cache.misses, cache.len()), (1, 2, 2));
+        assert_eq!(cache.stats(), format!("Кэш разбора: попаданий 1, промахов 2, выражений 2 из {}", CAPACITY));
+        // Ошибки разбора не кэшируются
+        assert!(cache.process("1 +", &limits, &NoResolver).is_err());
+        assert_eq!((cache.misses, cache.len()), (3, 2));
+
+        // При переполнении вытесняется давно не использованное выражение
+        for number in 0..CAPACITY - 1 {
+            cache.process(&number.to_string(), &limits, &NoResolver).unwrap();
+        }
+        assert_eq!(cache.len(), CAPACITY);
+        assert!(cache.entries.iter().all(|entry| entry.0 != "1 + 2"));
+        assert!(cache.entries.iter().any(|entry| entry.0 == "1 + 2 + 3"));
+        cache.process("1 + 2 + 3", &limits, &NoResolver).unwrap();
+        cache.process("1 + 2", &limits, &NoResolver).unwrap();
+        assert_eq!((cache.hits, cache.misses, cache.len()), (2, 3 + CAPACITY, CAPACITY));
+    }
+
+    #[test]
+    fn errors_point_into_the_entered_line() {
+        let limits = Limits::default();
+        let mut cache = ParseCache::new();
+        let error = |cache: &mut ParseCache, input: &str| cache.process(input, &limits, &NoResolver).map(|evaluation| evaluation.value).expect_err("ошибка вычисления");
+        assert_eq!(error(&mut cache, "2 *   [1, 2] + [1, 2, 3]").span, Some((13, 14)));
+        // Выражение из кэша не вычисляется второй раз, а места сдвигаются к введённой строке
+        let why = error(&mut cache, "2 * [1, 2]    + [1,   2, 3]");
+        assert_eq!(cache.hits, 1);
+        let expected = process("2 * [1, 2]    + [1,   2, 3]", &limits).map(|evaluation| evaluation.value).expect_err("размеры не совпадают");
+        assert_eq!((why.kind, why.span, why.notes), (ErrorKind::ShapeMismatch, expected.span, expected.notes));
+
+        assert_eq!(error(&mut cache, "1 / (x - 1)").span, Some((5, 6)));
+        assert_eq!(error(&mut cache, "   1 /  (x - 1)").span, Some((9, 10)));
+        assert_eq!(cache.hits, 2);
+    }
+}
@@ -0,0 +1,5573 @@
+// Разбор и вычисление арифметических выражений: разбиение на токены, преобразование
+// в обратную польскую нотацию (ОПН) и вычисление результата
+
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::cell::Cell;
+use core::convert::TryFrom;
+use core::{fmt, mem};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+#[cfg(feature = "std")]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(all(feature = "regex", not(feature = "logos")))]
+use regex::Regex;
+use crate::{BoundedStack, Full, Stack, Queue};
+
+// Без стандартной библиотеки математические функции f32 берутся из libm. Тестам
+// нужна std, а её собственные методы f32 перекрывают методы трейтов, поэтому
+// в тестовой сборке трейты не объявляются
+#[cfg(all(not(feature = "std"), not(test)))]
+trait FloatMath {
+    fn sqrt(self) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn log10(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn tan(self) -> Self;
+    fn powf(self, n: Self) -> Self;
+    fn floor(self) -> Self;
+    fn ceil(self) -> Self;
+    fn round(self) -> Self;
+    fn fract(self) -> Self;
+}
+
+// Округление f64 при записи результата и корень дискриминанта в solve
+#[cfg(all(not(feature = "std"), not(test)))]
+trait RoundingMath {
+    fn round(self) -> Self;
+    fn trunc(self) -> Self;
+    fn round_ties_even(self) -> Self;
+    fn sqrt(self) -> Self;
+}
+
+#[cfg(all(not(feature = "std"), not(test)))]
+impl RoundingMath for f64 {
+    fn round(self) -> Self { libm::round(self) }
+    fn trunc(self) -> Self { libm::trunc(self) }
+    fn round_ties_even(self) -> Self { libm::rint(self) }
+    fn sqrt(self) -> Self { libm::sqrt(self) }
+}
+
+#[cfg(all(not(feature = "std"), not(test)))]
+impl FloatMath for f32 {
+    fn sqrt(self) -> Self { libm::sqrtf(self) }
+    fn exp(self) -> Self { libm::expf(self) }
+    fn ln(self) -> Self { libm::logf(self) }
+    fn log10(self) -> Self { libm::log10f(self) }
+    fn sin(self) -> Self { libm::sinf(self) }
+    fn cos(self) -> Self { libm::cosf(self) }
+    fn tan(self) -> Self { libm::tanf(self) }
+    fn powf(self, n: Self) -> Self { libm::powf(self, n) }
+    fn floor(self) -> Self { libm::floorf(self) }
+    fn ceil(self) -> Self { libm::ceilf(self) }
+    fn round(self) -> Self { libm::roundf(self) }
+    fn fract(self) -> Self { self - libm::truncf(self) }
+}
+// Типы доступных токенов (лексем)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TokenType {
+    NumberInt,
+    NumberFloat,
+    Date,
+    UnaryOperator,
+    BinaryOperator,
+    Function,
+    OpenedParenthesis,
+    ClosedParenthesis,
+    OpenedBracket,
+    ClosedBracket,
+    ArgumentSeparator,
+    Whitespaces,
+    // Единица измерения (km, h, ...): сама по себе - величина, равная одной единице,
+    // а сразу после числа - множитель этого числа
+    Unit,
+    // Составление списка из вычисленных элементов; встречается только в записи ОПН
+    List,
+    // Имя, не являющееся ни функцией, ни единицей измерения; значение переменной
+    // сообщает Resolver
+    Variable,
+    // Черта модуля |x|; встречается только при выделении лексем, затем заменяется
+    // на "abs(" или ")"
+    Bar,
+}
+
+// Байтовый диапазон [начало, конец) в исходной строке
+pub type Span = (usize, usize);
+
+// Определим кортеж для удобства работы - (Тип токена, "символьное представление", место в исходной строке).
+// Представления операторов, скобок, известных функций и единиц ссылаются на статические
+// таблицы (см. intern), строки выделяются только для чисел и неизвестных имён
+pub type Token = (TokenType, Cow<'static, str>, Span);
+
+// Ассоциативность оператора
+#[derive(Clone, Copy, PartialEq)]
+enum OperatorAssociation {
+    LeftAssociation,
+    RightAssociatoin,
+}
+
+// Псевдоним для наглядности
+type OperatorOrder = u8;
+
+// Определим тип для определения действий: имя, приоритет, ассоциативность и справка для :doc
+type Operator = (&'static str, OperatorOrder, OperatorAssociation, &'static str);
+
+// Список известных (поддерживаемых операторов)
+static KNOWNS_OPERATORS: &[Operator] = &[
+    ("POS", 1, OperatorAssociation::RightAssociatoin, "+x - унарный плюс, значение без изменений"),
+    ("NEG", 1, OperatorAssociation::RightAssociatoin, "-x - смена знака (также −x)"),
+    // Погрешность: 2.0±0.1 - интервал [1.9, 2.1]
+    ("±", 1, OperatorAssociation::LeftAssociation, "x ± y - интервал [x - y, x + y]"),
+    ("/", 2, OperatorAssociation::LeftAssociation, "x / y - деление (также ÷)"),
+    ("*", 2, OperatorAssociation::LeftAssociation, "x * y - умножение (также × и ·)"),
+    ("%", 2, OperatorAssociation::LeftAssociation, "x % y - остаток от деления"),
+    ("+", 3, OperatorAssociation::LeftAssociation, "x + y - сложение; дата + длительность"),
+    ("-", 3, OperatorAssociation::LeftAssociation, "x - y - вычитание (также −); разность дат в днях"),
+    ("<<", 4, OperatorAssociation::LeftAssociation, "x << y - сдвиг целого влево на y бит"),
+    (">>", 4, OperatorAssociation::LeftAssociation, "x >> y - сдвиг целого вправо на y бит"),
+    // Сравнения дают логическое значение - условие для if
+    ("<", 5, OperatorAssociation::LeftAssociation, "x < y - меньше: true или false"),
+    (">", 5, OperatorAssociation::LeftAssociation, "x > y - больше: true или false"),
+    ("<=", 5, OperatorAssociation::LeftAssociation, "x <= y - меньше или равно: true или false"),
+    (">=", 5, OperatorAssociation::LeftAssociation, "x >= y - больше или равно: true или false"),
+    ("==", 5, OperatorAssociation::LeftAssociation, "x == y - равно: true или false"),
+    ("!=", 5, OperatorAssociation::LeftAssociation, "x != y - не равно: true или false"),
+    // Цепочка сравнений 1 < x < 10 записывается в ОПН как 1 < x AND x < 10
+    ("AND", 6, OperatorAssociation::LeftAssociation, "x < y < z - цепочка сравнений: x < y и y < z (в записи ОПН - AND)"),
+    // Знак уравнения: только в первом аргументе solve
+    ("=", 7, OperatorAssociation::LeftAssociation, "a = b - уравнение в solve(a = b, x)"),
+];
+
+// Список известных функций: имя, число аргументов и справка для :doc
+pub static KNOWNS_FUNCTIONS: &[(&str, usize, &str)] = &[
+    ("abs", 1, "модуль числа, то же, что |x|"),
+    ("sqrt", 1, "квадратный корень"),
+    ("exp", 1, "экспонента e^x"),
+    ("ln", 1, "натуральный логарифм"),
+    ("log", 1, "десятичный логарифм"),
+    ("sin", 1, "синус угла в радианах (90° - в градусах)"),
+    ("cos", 1, "косинус угла в радианах"),
+    ("tan", 1, "тангенс угла в радианах"),
+    ("min", 2, "меньшее из двух чисел"),
+    ("max", 2, "большее из двух чисел"),
+    ("pow", 2, "x в степени y"),
+    ("now", 0, "текущая дата"),
+    ("base", 2, "запись числа x в системе счисления с основанием y от 2 до 36"),
+    ("transpose", 1, "транспонированная матрица"),
+    ("det", 1, "определитель квадратной матрицы"),
+    ("matmul", 2, "произведение матриц"),
+    ("sum", 1, "сумма всех чисел списка; sum(var, start, stop, expr) - сумма выражения при целых var от start до stop"),
+    ("mean", 1, "среднее арифметическое чисел списка"),
+    ("median", 1, "медиана чисел списка"),
+    ("variance", 1, "дисперсия чисел списка"),
+    ("stddev", 1, "стандартное отклонение чисел списка"),
+    ("count", 1, "количество чисел в списке"),
+    ("table", 5, "таблица [[var, expr], ...] значений выражения при var от start до stop с шагом step"),
+    ("integrate", 4, "интеграл выражения по var от a до b (адаптивный метод Симпсона с относительной погрешностью --tolerance)"),
+    ("root", 3, "корень выражения по var рядом с guess (метод Ньютона, при неудаче - деление пополам)"),
+    ("solve", 2, "решение линейного или квадратного уравнения по var: solve(2 * x + 3 = 11, x) - x = 4"),
+    // sum с четырьмя аргументами: в записи ОПН - SUM, чтобы число аргументов определялось по имени
+    ("SUM", 4, "сумма выражения при целых var от start до stop: sum(i, 1, 100, i * i)"),
+    ("prod", 4, "произведение выражения при целых var от start до stop: prod(k, 1, 10, k)"),
+    ("if", 3, "then, если условие cond (сравнение) верно, иначе else; вычисляется только выбранная ветвь"),
+];
+
+// Функции, первый аргумент которых - выражение от переменной, заданной вторым аргументом:
+// выражение не вычисляется перед вызовом, функция сама вычисляет его при разных значениях
+// переменной. Имя и аргументы для :doc
+static BOUND_FUNCTIONS: &[(&str, &str)] = &[
+    ("table", "expr, var, start, stop, step"),
+    ("integrate", "expr, var, a, b"),
+    ("root", "expr, var, guess"),
+    ("solve", "equation, var"),
+    ("SUM", "var, start, stop, expr"),
+    ("prod", "var, start, stop, expr"),
+];
+
+// Функции со связанной переменной в индексной форме: переменная - первый аргумент,
+// выражение - последний
+static INDEX_FUNCTIONS: &[&str] = &["SUM", "prod"];
+
+// Размерность величины - степени метра, килограмма, секунды и денежной единицы
+pub type Dimension = [i8; 4];
+
+// Безразмерная величина (обычное число)
+const DIMENSIONLESS: Dimension = [0, 0, 0, 0];
+
+// Размерность денежной суммы; валюты добавляются из файла курсов (set_extra_units)
+pub const CURRENCY: Dimension = [0, 0, 0, 1];
+
+// Обозначения основных единиц в порядке степеней размерности. Основная валюта - та, курс
+// которой равен 1; пока валюты не заданы, она обозначается знаком ¤
+static BASE_UNITS: [&str; 4] = ["m", "kg", "s", "¤"];
+
+// Суффиксы углов у числа (90°, 90deg, 100grad) и множители перевода в радианы, в которых
+// считают тригонометрические функции. Угол переводится при разборе числа
+pub static KNOWNS_ANGLE_SUFFIXES: &[(&str, f32)] = &[
+    ("°", core::f32::consts::PI / 180.0),
+    ("deg", core::f32::consts::PI / 180.0),
+    ("grad", core::f32::consts::PI / 200.0),
+];
+
+// Список известных единиц измерения: обозначение, значение в основных единицах и размерность
+pub static KNOWNS_UNITS: &[(&str, f32, Dimension)] = &[
+    ("m", 1.0, [1, 0, 0, 0]),
+    ("km", 1000.0, [1, 0, 0, 0]),
+    ("cm", 0.01, [1, 0, 0, 0]),
+    ("mm", 0.001, [1, 0, 0, 0]),
+    ("mi", 1609.344, [1, 0, 0, 0]),
+    ("ft", 0.3048, [1, 0, 0, 0]),
+    ("in", 0.0254, [1, 0, 0, 0]),
+    ("g", 0.001, [0, 1, 0, 0]),
+    ("kg", 1.0, [0, 1, 0, 0]),
+    ("t", 1000.0, [0, 1, 0, 0]),
+    ("lb", 0.453_592_37, [0, 1, 0, 0]),
+    ("s", 1.0, [0, 0, 1, 0]),
+    ("ms", 0.001, [0, 0, 1, 0]),
+    ("min", 60.0, [0, 0, 1, 0]),
+    ("h", 3600.0, [0, 0, 1, 0]),
+    ("d", 86400.0, [0, 0, 1, 0]),
+    ("w", 604_800.0, [0, 0, 1, 0]),
+    ("mph", 0.447_04, [1, 0, -1, 0]),
+];
+
+// Единицы измерения, добавленные программой к KNOWNS_UNITS (например, из файла настроек);
+// задаются один раз, до разбора выражений
+#[cfg(feature = "std")]
+static EXTRA_UNITS: OnceLock<Vec<(String, f32, Dimension)>> = OnceLock::new();
+
+// Добавление единиц измерения к известным; имена встроенных единиц не переопределяются.
+// Повторный вызов не меняет единицы и возвращает переданный список
+#[cfg(feature = "std")]
+pub fn set_extra_units(units: Vec<(String, f32, Dimension)>) -> Result<(), Vec<(String, f32, Dimension)>> {
+    EXTRA_UNITS.set(units)
+}
+
+#[cfg(feature = "std")]
+fn extra_units() -> &'static [(String, f32, Dimension)] {
+    EXTRA_UNITS.get().map_or(&[], Vec::as_slice)
+}
+
+#[cfg(not(feature = "std"))]
+fn extra_units() -> &'static [(String, f32, Dimension)] {
+    &[]
+}
+
+// Обозначения встроенных и добавленных единиц измерения
+pub fn unit_names() -> impl Iterator<Item = &'static str> {
+    KNOWNS_UNITS.iter().map(|unit| unit.0).chain(extra_units().iter().map(|unit| unit.0.as_str()))
+}
+
+// Список известных токенов и соответствующих им шаблонов поиска в исходной строке
+// (лексер на регулярных выражениях, feature "regex"; без неё используется
+// эквивалентный лексер без зависимостей - см. longest_match, а с feature "logos" -
+// конечный автомат, построенный при компиляции)
+#[cfg(all(feature = "regex", not(feature = "logos")))]
+static KNOWNS_TOKENS: &[(TokenType, &str)] = &[
+    (TokenType::OpenedParenthesis, r"^(\()"),
+    (TokenType::ClosedParenthesis, r"^(\))"),
+    (TokenType::OpenedBracket, r"^(\[)"),
+    (TokenType::ClosedBracket, r"^(\])"),
+    (TokenType::Function, r"^[a-zA-Z]+"),
+    // Унарные + и - распознаются этим же шаблоном и отличаются от бинарных по контексту.
+    // Типографские знаки (×, ÷, −, ·) приводятся к обычным операторам
+    (TokenType::BinaryOperator, r"^(<<|>>|<=|>=|==|!=|[\+\-/\*%×÷−·±=<>])"),
+    (TokenType::Date, r"^(\d{4}-\d{2}-\d{2})"),
+    (TokenType::NumberFloat, r"^(\d+\.\d+)"),
+    // Угол: 90°, 1.5deg, 100grad (суффиксы - KNOWNS_ANGLE_SUFFIXES)
+    (TokenType::NumberFloat, r"^(\d+(\.\d+)?(°|deg|grad))"),
+    (TokenType::NumberInt, r"^(\d+)"),
+    // Целое число в системе счисления с основанием от 2 до 36: 16#ff, 2#1010, 36#z
+    (TokenType::NumberInt, r"^(\d+#[0-9a-zA-Z]+)"),
+    (TokenType::ArgumentSeparator, r"^(,{1,1})"),
+    (TokenType::Bar, r"^(\|)"),
+    (TokenType::Whitespaces, r"^(\s+)"),
+];
+
+// Виды ошибок разбора и вычисления выражения
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    UnknownToken,
+    MissingParenthesis,
+    MissingArgumentSeparator,
+    InvalidOutputQueue,
+    NoResult,
+    TooDeep,
+    TooComplex,
+    BudgetExceeded,
+    Timeout,
+    MissingOperator,
+    UnknownFunction,
+    InvalidFunctionCall,
+    EmptyExpression,
+    EmptyParentheses,
+    DanglingOperator,
+    InvalidNumber,
+    InvalidShift,
+    ShapeMismatch,
+    UnitMismatch,
+    UnknownUnit,
+    InvalidDate,
+    InvalidInterval,
+    UnknownVariable,
+    Cancelled,
+    Forbidden,
+    Overflow,
+    DivisionByZero,
+    InexactDivision,
+    NoConvergence,
+    OutOfRange,
+}
+
+// Стабильные коды ошибок: код не зависит от текста сообщения, поэтому по нему программы
+// различают ошибки (формат JSON, код возврата) и подбирают перевод сообщения. Новый вид
+// ошибки получает следующий свободный код, коды не переиспользуются.
+// Вид ошибки, код, имя и общее описание
+pub static KNOWNS_ERRORS: &[(ErrorKind, &str, &str, &str)] = &[
+    (ErrorKind::UnknownToken, "E0001", "unknown_token", "символы, не образующие ни одной лексемы"),
+    (ErrorKind::MissingParenthesis, "E0002", "missing_parenthesis", "у скобки нет парной"),
+    (ErrorKind::MissingArgumentSeparator, "E0003", "missing_argument_separator", "между аргументами функции пропущена запятая"),
+    (ErrorKind::InvalidOutputQueue, "E0004", "invalid_output_queue", "операция не применима к значениям или запись ОПН составлена неправильно"),
+    (ErrorKind::NoResult, "E0005", "no_result", "вычисление не дало результата"),
+    (ErrorKind::TooDeep, "E0006", "too_deep", "превышена наибольшая глубина выражения"),
+    (ErrorKind::TooComplex, "E0007", "too_complex", "превышено наибольшее число токенов или вложенность скобок"),
+    (ErrorKind::BudgetExceeded, "E0008", "budget_exceeded", "превышено наибольшее число шагов вычисления"),
+    (ErrorKind::Timeout, "E0009", "timeout", "превышено время вычисления"),
+    (ErrorKind::MissingOperator, "E0010", "missing_operator", "между двумя значениями пропущен оператор"),
+    (ErrorKind::UnknownFunction, "E0011", "unknown_function", "вызов неизвестной функции"),
+    (ErrorKind::InvalidFunctionCall, "E0012", "invalid_function_call", "неверный вызов функции: число аргументов, разделители или скобки"),
+    (ErrorKind::EmptyExpression, "E0013", "empty_expression", "пустое выражение"),
+    (ErrorKind::EmptyParentheses, "E0014", "empty_parentheses", "пустые скобки не при вызове функции"),
+    (ErrorKind::DanglingOperator, "E0015", "dangling_operator", "оператору не хватает операнда"),
+    (ErrorKind::InvalidNumber, "E0016", "invalid_number", "некорректное число или основание системы счисления"),
+    (ErrorKind::InvalidShift, "E0017", "invalid_shift", "величина сдвига не целое число от 0 до 63"),
+    (ErrorKind::ShapeMismatch, "E0018", "shape_mismatch", "размеры списков или матриц не подходят для операции"),
+    (ErrorKind::UnitMismatch, "E0019", "unit_mismatch", "несовместимые единицы измерения"),
+    (ErrorKind::UnknownUnit, "E0020", "unknown_unit", "неизвестная единица измерения"),
+    (ErrorKind::InvalidDate, "E0021", "invalid_date", "некорректная дата или операция с датой"),
+    (ErrorKind::InvalidInterval, "E0022", "invalid_interval", "операция не применима к интервалу"),
+    (ErrorKind::UnknownVariable, "E0023", "unknown_variable", "переменная без значения"),
+    (ErrorKind::Cancelled, "E0024", "cancelled", "вычисление отменено"),
+    (ErrorKind::Forbidden, "E0025", "forbidden", "конструкция запрещена параметрами разбора или режимом вычисления"),
+    (ErrorKind::Overflow, "E0026", "overflow", "переполнение целого числа"),
+    (ErrorKind::DivisionByZero, "E0027", "division_by_zero", "деление на ноль"),
+    (ErrorKind::InexactDivision, "E0028", "inexact_division", "целочисленное деление с остатком при точном делении"),
+    (ErrorKind::NoConvergence, "E0029", "no_convergence", "численный метод не нашёл решение"),
+    (ErrorKind::OutOfRange, "E0030", "out_of_range", "номер результата вне допустимого диапазона"),
+];
+
+impl ErrorKind {
+    fn info(self) -> &'static (ErrorKind, &'static str, &'static str, &'static str) {
+        KNOWNS_ERRORS.iter().find(|error| error.0 == self).expect("у каждого вида ошибки есть код")
+    }
+
+    // Стабильный код ошибки: "E0001"
+    pub fn code(self) -> &'static str {
+        self.info().1
+    }
+
+    // Машиночитаемое имя вида ошибки: "unknown_token"
+    pub fn name(self) -> &'static str {
+        self.info().2
+    }
+
+    // Общее описание ошибок этого вида, без подробностей конкретного случая
+    pub fn description(self) -> &'static str {
+        self.info().3
+    }
+}
+
+// Ошибка обработки выражения: вид, текст и (если известно) место в исходной строке
+#[derive(Debug, Clone)]
+pub struct CalcError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<Span>,
+    pub lexeme: Option<String>,
+    // Пояснения: с местом в строке - подпись к связанному с ошибкой месту, без места -
+    // подсказка, как исправить ошибку
+    pub notes: Vec<(Option<Span>, String)>,
+}
+
+impl CalcError {
+    fn new(kind: ErrorKind, message: &str) -> Self {
+        CalcError { kind, message: message.to_string(), span: None, lexeme: None, notes: Vec::new() }
+    }
+
+    fn with_label(mut self, span: Span, label: &str) -> Self {
+        self.notes.push((Some(span), label.to_string()));
+        self
+    }
+
+    fn with_note(mut self, note: &str) -> Self {
+        self.notes.push((None, note.to_string()));
+        self
+    }
+
+    fn with_span(mut self, start: usize, lexeme: &str) -> Self {
+        self.span = Some((start, start + lexeme.len()));
+        self.lexeme = Some(lexeme.to_string());
+        self
+    }
+
+    // Место ошибки, заданное диапазоном исходной строки `input`
+    fn with_source_span(self, input: &str, span: Span) -> Self {
+        self.with_span(span.0, &input[span.0..span.1])
+    }
+
+    // Текстовое представление для терминала: сообщение диагностики для исходной строки `input`
+    pub fn to_text(&self, input: &str) -> String {
+        Diagnostic::from(self).render(input)
+    }
+
+    // Представление в формате JSON для редакторов и других программ: code - стабильный код
+    // ошибки, kind - имя её вида
+    pub fn to_json(&self) -> String {
+        let span = match self.span {
+            Some((start, end)) => format!("{{\"start\":{},\"end\":{}}}", start, end),
+            None => "null".to_string(),
+        };
+        let lexeme = match &self.lexeme {
+            Some(lexeme) => json_string(lexeme),
+            None => "null".to_string(),
+        };
+        format!("{{\"code\":{},\"kind\":{},\"message\":{},\"span\":{},\"lexeme\":{}}}",
+            json_string(self.kind.code()), json_string(self.kind.name()), json_string(&self.message), span, lexeme)
+    }
+}
+
+// Важность сообщения диагностики
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "ошибка",
+            Severity::Warning => "предупреждение",
+            Severity::Note => "примечание",
+        }
+    }
+}
+
+// Сообщение о проблеме во входной строке для вывода в терминал
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // Код ошибки из KNOWNS_ERRORS
+    pub code: Option<&'static str>,
+    pub message: String,
+    // Основное место, подчёркивается знаками ^
+    pub span: Option<Span>,
+    // Связанные места с подписями, подчёркиваются знаками -
+    pub labels: Vec<(Span, String)>,
+    pub notes: Vec<String>,
+}
+
+impl From<&CalcError> for Diagnostic {
+    fn from(error: &CalcError) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: Some(error.kind.code()),
+            message: error.message.clone(),
+            span: error.span,
+            labels: error.notes.iter().filter_map(|note| note.0.map(|span| (span, note.1.clone()))).collect(),
+            notes: error.notes.iter().filter(|note| note.0.is_none()).map(|note| note.1.clone()).collect(),
+        }
+    }
+}
+
+impl Diagnostic {
+    // Вывод в духе rustc: строка входа `input` с подчёркнутыми местами, подпись самого
+    // правого места - в строке подчёркивания, остальные - под своим местом, затем подсказки:
+    //
+    //   ошибка[E0010]: пропущен оператор между '2' и '3'
+    //     | 2 3 + 4
+    //     | - ^
+    //     | |
+    //     | значение без оператора после него
+    //     = подсказка: ...
+    pub fn render(&self, input: &str) -> String {
+        let mut text = match self.code {
+            Some(code) => format!("{}[{}]: {}", self.severity.name(), code, self.message),
+            None => format!("{}: {}", self.severity.name(), self.message),
+        };
+        // Выводится строка входа, в которой находится основное место
+        if let Some(anchor) = self.span.or_else(|| self.labels.first().map(|label| label.0)) {
+            let line_start = input[..anchor.0].rfind('\n').map_or(0, |index| index + 1);
+            let line_end = input[anchor.0..].find('\n').map_or(input.len(), |index| anchor.0 + index);
+            // Место в строке: столбец, ширина, знак подчёркивания и подпись
+            let mut markers = self.span.map(|span| (span, '^', "")).into_iter()
+                .chain(self.labels.iter().map(|(span, label)| (*span, '-', label.as_str())))
+                .filter(|(span, _, _)| (line_start..=line_end).contains(&span.0))
+                .map(|(span, sign, label)| {
+                    let column = input[line_start..span.0].chars().count();
+                    let width = input[span.0..span.1.clamp(span.0, line_end)].chars().count().max(1);
+                    (column, width, sign, label)
+                })
+                .collect::<Vec<(usize, usize, char, &str)>>();
+            markers.sort_by_key(|marker| marker.0);
+
+            let mut underline: Vec<char> = Vec::new();
+            for &(column, width, sign, _) in &markers {
+                if underline.len() < column + width {
+                    underline.resize(column + width, ' ');
+                }
+                underline[column..column + width].iter_mut().for_each(|cell| *cell = sign);
+            }
+            text.push_str(&format!("\n  | {}\n  | {}", &input[line_start..line_end], underline.iter().collect::<String>()));
+
+            let mut labeled = markers.iter()
+                .filter(|marker| !marker.3.is_empty())
+                .map(|marker| (marker.0, marker.3))
+                .collect::<Vec<(usize, &str)>>();
+            if markers.last().is_some_and(|last| !last.3.is_empty()) {
+                let (_, label) = labeled.pop().unwrap();
+                text.push(' ');
+                text.push_str(label);
+            }
+            // Линии от подписей, ещё не выведенных, к их местам
+            let connectors = |labeled: &[(usize, &str)], length: usize| {
+                let mut line = vec![' '; length];
+                for &(column, _) in labeled.iter().filter(|label| label.0 < length) {
+                    line[column] = '|';
+                }
+                line.into_iter().collect::<String>()
+            };
+            while let Some((column, label)) = labeled.pop() {
+                text.push_str(&format!("\n  | {}|", connectors(&labeled, column)));
+                text.push_str(&format!("\n  | {}{}", connectors(&labeled, column), label));
+            }
+        }
+        for note in &self.notes {
+            text.push_str(&format!("\n  = подсказка: {}", note));
+        }
+        text
+    }
+}
+
+// Экранирование строки для JSON
+pub fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// Получаем информацию об операторе из таблицы
+fn get_op_info(op: &str) -> Option<(OperatorOrder, OperatorAssociation)> {
+    for operator in KNOWNS_OPERATORS {
+        if op == operator.0 {
+            return Some((operator.1, operator.2));
+        }
+    }
+
+    None
+}
+
+// Обозначение оператора в выражении: унарные POS и NEG записываются как + и -
+fn operator_symbol(operator: &Operator) -> &'static str {
+    match operator.0 {
+        "POS" => "+",
+        "NEG" => "-",
+        name => name,
+    }
+}
+
+// Справка для команды :doc по имени оператора, функции, единицы измерения или по коду
+// ошибки; у + и -
+// справка и для унарного, и для бинарного оператора, у min - и для функции, и для единицы
+pub fn describe(name: &str) -> Option<String> {
+    let mut descriptions: Vec<String> = KNOWNS_OPERATORS.iter()
+        .filter(|operator| operator_symbol(operator) == name)
+        .map(|operator| operator.3.to_string())
+        .collect();
+    if let Some(description) = describe_function(name) {
+        descriptions.push(description);
+    }
+    if let Some((value, dimension)) = get_unit_info(name) {
+        descriptions.push(format!("{} - единица измерения, 1 {} = {} {}", name, name, value, dimension_name(dimension)));
+    }
+    if let Some(error) = KNOWNS_ERRORS.iter().find(|error| error.1 == name) {
+        descriptions.push(format!("{} ({}) - {}", error.1, error.2, error.3));
+    }
+    match descriptions.is_empty() {
+        true => None,
+        false => Some(descriptions.join("\n")),
+    }
+}
+
+// Имена для команды :doc по категориям
+pub fn documented_names() -> Vec<(&'static str, Vec<&'static str>)> {
+    let mut operators: Vec<&str> = Vec::new();
+    for symbol in KNOWNS_OPERATORS.iter().map(operator_symbol) {
+        if !operators.contains(&symbol) {
+            operators.push(symbol);
+        }
+    }
+    vec![
+        ("операторы", operators),
+        ("функции", KNOWNS_FUNCTIONS.iter().map(|function| function.0).collect()),
+        ("единицы измерения", unit_names().collect()),
+    ]
+}
+
+// Получаем число аргументов функции из таблицы
+fn get_function_arity(name: &str) -> Option<usize> {
+    KNOWNS_FUNCTIONS.iter().find(|function| function.0 == name).map(|function| function.1)
+}
+
+// Получаем значение и размерность единицы измерения из таблицы, затем из добавленных единиц
+fn get_unit_info(name: &str) -> Option<(f32, Dimension)> {
+    KNOWNS_UNITS.iter().find(|unit| unit.0 == name).map(|unit| (unit.1, unit.2))
+        .or_else(|| extra_units().iter().find(|unit| unit.0 == name).map(|unit| (unit.1, unit.2)))
+}
+
+// Ошибка для неизвестной функции с ближайшими по написанию известными функциями
+fn unknown_function_error(tok: &Token) -> CalcError {
+    let similar = similar_names(&tok.1, KNOWNS_FUNCTIONS.iter().map(|function| function.0));
+    let message = with_suggestions(format!("неизвестная функция '{}'", tok.1), &similar);
+    CalcError::new(ErrorKind::UnknownFunction, &message).with_span(tok.2.0, &tok.1)
+}
+
+// Ошибка для неизвестной переменной с ближайшими по написанию функциями и единицами измерения
+fn unknown_variable_error(tok: &Token) -> CalcError {
+    let names = KNOWNS_FUNCTIONS.iter().map(|function| function.0).chain(unit_names());
+    let message = with_suggestions(format!("неизвестная переменная '{}'", tok.1), &similar_names(&tok.1, names));
+    CalcError::new(ErrorKind::UnknownVariable, &message).with_span(tok.2.0, &tok.1)
+}
+
+// Сообщение об ошибке с подсказкой: "неизвестная функция 'sqr' (возможно, sqrt)"
+fn with_suggestions(message: String, similar: &[&str]) -> String {
+    match similar.is_empty() {
+        true => message,
+        false => format!("{} (возможно, {})", message, similar.join(", ")),
+    }
+}
+
+// Описание функции с аргументами: "min(x, y) - меньшее из двух чисел"
+fn describe_function(name: &str) -> Option<String> {
+    let function = KNOWNS_FUNCTIONS.iter().find(|function| function.0 == name)?;
+    let arguments = match BOUND_FUNCTIONS.iter().find(|bound| bound.0 == name) {
+        Some(bound) => bound.1.to_string(),
+        None if name == "if" => "cond, then, else".to_string(),
+        None => ["x", "y"].iter().take(function.1).copied().collect::<Vec<&str>>().join(", "),
+    };
+    Some(format!("{}({}) - {}", function.0, arguments, function.2))
+}
+
+// Известные имена, ближайшие к неизвестному по расстоянию Левенштейна. Допускается
+// не больше трети длины имени (но хотя бы одна правка) и меньше, чем символов в имени,
+// чтобы для однобуквенных имён не предлагались все однобуквенные единицы
+fn similar_names<'a>(name: &str, names: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let length = name.chars().count();
+    let limit = (length / 3).max(1).min(length.saturating_sub(1));
+    let mut best = usize::MAX;
+    let mut similar: Vec<&str> = Vec::new();
+    for candidate in names {
+        let distance = edit_distance(name, candidate);
+        if distance > limit || distance > best {
+            continue;
+        }
+        if distance < best {
+            best = distance;
+            similar.clear();
+        }
+        if !similar.contains(&candidate) {
+            similar.push(candidate);
+        }
+    }
+    similar
+}
+
+// Расстояние Левенштейна: наименьшее число вставок, удалений и замен символов
+fn edit_distance(from: &str, to: &str) -> usize {
+    let to: Vec<char> = to.chars().collect();
+    // Строка таблицы расстояний от префикса from до всех префиксов to
+    let mut row: Vec<usize> = (0..=to.len()).collect();
+    for (i, from_char) in from.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, to_char) in to.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = match from_char == *to_char {
+                true => diagonal,
+                false => 1 + diagonal.min(above).min(row[j]),
+            };
+            diagonal = above;
+        }
+    }
+    row[to.len()]
+}
+
+// Определяем, нужно ли выталкивать из стека имеющийся там (на вершине) оператор op2
+// перед тем, как положить в стек оператор op1
+fn need_op_pop_from_stack(op1: &Token, op2: &Token) -> bool {
+    // Выталкиваются только операторы - бинарные и унарные, но не скобки и функции
+    if op2.0 != TokenType::BinaryOperator && op2.0 != TokenType::UnaryOperator {
+        return false;
+    }
+    // Унарный оператор стоит перед своим операндом, поэтому ничего не выталкивает:
+    //   цепочки унарных операторов (--5, +-3) применяются справа налево.
+    if op1.0 == TokenType::UnaryOperator {
+        return false;
+    }
+
+    let (op1_prio, op1_associo) = match get_op_info(&op1.1) {
+        Some(info) => info,
+        None => return false,
+    };
+    let op2_prio = match get_op_info(&op2.1) {
+        Some((prio, _)) => prio,
+        None => return false,
+    };
+    // Если приоритет op2 выше приоритета op1, либо приоритеты равны и при этом op1
+    // является левоассоциативным (правоассоциативный op1 при равенстве приоритетов
+    // ложится в стек поверх op2)
+    op2_prio < op1_prio ||
+        (op2_prio == op1_prio && op1_associo == OperatorAssociation::LeftAssociation)
+}
+
+// Приведение типографских знаков операций, встречающихся в документах, к ASCII
+fn normalize_operator(op: Cow<'static, str>) -> Cow<'static, str> {
+    match &*op {
+        "×" | "·" => Cow::Borrowed("*"),
+        "÷" => Cow::Borrowed("/"),
+        "−" => Cow::Borrowed("-"),
+        _ => op,
+    }
+}
+
+// Имена, которые лексер может выделить, в порядке возрастания байтов: скобки, операторы
+// (с типографскими знаками), функции и единицы измерения. Тест interned_names_cover_tables
+// проверяет, что таблица упорядочена и включает имена из всех таблиц
+static INTERNED: &[&str] = &[
+    "!=", "%", "(", ")", "*", "+", ",", "-", "/", "<", "<<", "<=", "=", "==", ">", ">=", ">>", "AND", "NEG", "POS", "SUM", "[", "]", "abs", "base", "cm",
+    "cos", "count", "d", "det", "exp", "ft", "g", "h", "if", "in", "integrate", "kg", "km", "lb", "ln", "log", "m",
+    "matmul", "max", "mean", "median", "mi", "min", "mm", "mph", "ms", "now", "pow", "prod", "root", "s", "sin",
+    "solve", "sqrt", "stddev", "sum", "t", "table", "tan", "transpose", "variance", "w", "|", "±", "·", "×", "÷", "−",
+];
+
+// Представление лексемы: ссылка на статическую таблицу для известных операторов, скобок,
+// функций и единиц измерения (поиск делением пополам), иначе - новая строка
+fn intern(lexeme: &str) -> Cow<'static, str> {
+    match INTERNED.binary_search(&lexeme) {
+        Ok(index) => Cow::Borrowed(INTERNED[index]),
+        Err(_) => Cow::Owned(lexeme.to_string()),
+    }
+}
+
+// Завершает ли токен данного типа операнд: после него + и - являются бинарными операторами
+fn ends_operand(token_type: Option<TokenType>) -> bool {
+    match token_type {
+        Some(token_type) => [TokenType::NumberFloat, TokenType::NumberInt, TokenType::Date, TokenType::ClosedParenthesis,
+            TokenType::ClosedBracket, TokenType::Unit, TokenType::Variable].contains(&token_type),
+        None => false,
+    }
+}
+
+// Разбиваем входную строку на токены (лексемы) в два этапа:
+//  1. выделение лексем: в каждой позиции выбирается самое длинное совпадение среди шаблонов
+//     (при равной длине - шаблон, стоящий в таблице раньше);
+//  2. классификация: уточнение типа лексем с учётом контекста.
+pub fn tokerize(in_string: &str, limits: &Limits) -> Result<Vec<Token>, CalcError> {
+    let lexemes = scan_lexemes(in_string, limits, None)?;
+    Ok(classify_lexemes(expand_bars(lexemes)))
+}
+
+// Токены редактируемой строки для интерактивных интерфейсов (подсветка при вводе,
+// песочница в браузере): после правки лексемы выделяются заново только в изменённой
+// части строки, а лексемы до и после неё сохраняются со сдвинутыми местами
+pub struct IncrementalTokens {
+    input: String,
+    lexemes: Vec<Token>,
+}
+
+impl IncrementalTokens {
+    pub fn new(input: &str, limits: &Limits) -> Result<IncrementalTokens, CalcError> {
+        let lexemes = scan_lexemes(input, limits, None)?;
+        Ok(IncrementalTokens { input: input.to_string(), lexemes })
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    // Токены текущей строки, те же, что вернёт tokerize. Лексемы здесь только уточняются
+    // по соседям, поэтому тип токена рядом с правкой может измениться (унарный минус,
+    // имя перед скобкой), хотя сама лексема выделялась раньше
+    pub fn tokens(&self) -> Vec<Token> {
+        classify_lexemes(expand_bars(self.lexemes.clone()))
+    }
+
+    // Переход к новому содержимому строки. Изменённая часть - между общими началом и концом
+    // старой и новой строки. Лексемы не содержат пробелов, поэтому выделение начинается
+    // с последнего пробела перед правкой и заканчивается на первой лексеме после правки,
+    // с которой разбор старой строки совпадает. Возвращает диапазон новой строки, в котором
+    // лексемы выделены заново. При ошибке сохраняется прежнее содержимое
+    pub fn update(&mut self, input: &str, limits: &Limits) -> Result<Span, CalcError> {
+        let old = self.input.as_str();
+        let mut prefix = old.bytes().zip(input.bytes()).take_while(|(left, right)| left == right).count();
+        while !input.is_char_boundary(prefix) {
+            prefix -= 1;
+        }
+        let mut suffix = old[prefix..].bytes().rev().zip(input[prefix..].bytes().rev())
+            .take_while(|(left, right)| left == right).count();
+        while !input.is_char_boundary(input.len() - suffix) {
+            suffix -= 1;
+        }
+
+        let start = input[..prefix].trim_end_matches(|c: char| !c.is_whitespace()).len();
+        let kept = self.lexemes.partition_point(|lexeme| lexeme.2.0 < start);
+        // Позиция в старой строке, соответствующая позиции новой строки в неизменённом конце
+        let old_position = |position: usize| position + old.len() - input.len();
+        let synchronized = |position: usize| {
+            let position = old_position(position);
+            position == old.len() || old[..position].ends_with(char::is_whitespace)
+                || self.lexemes.binary_search_by_key(&position, |lexeme| lexeme.2.0).is_ok()
+        };
+        let mut lexemes = Vec::new();
+        let end = scan_from(input, start, &mut lexemes, limits, None,
+            |position| position >= input.len() - suffix && synchronized(position))?;
+        let reused = old_position(end);
+        let replaced = kept..self.lexemes.partition_point(|lexeme| lexeme.2.0 < reused);
+        if self.lexemes.len() - replaced.len() + lexemes.len() > limits.max_tokens {
+            return Err(too_many_tokens_error(limits));
+        }
+
+        // Лексемы после правки не выделяются заново, а только сдвигаются
+        let moved = kept + lexemes.len();
+        self.lexemes.splice(replaced, lexemes);
+        for lexeme in &mut self.lexemes[moved..] {
+            lexeme.2 = (lexeme.2.0 - reused + end, lexeme.2.1 - reused + end);
+        }
+        self.input.replace_range(prefix..self.input.len() - suffix, &input[prefix..input.len() - suffix]);
+        Ok((start, end))
+    }
+}
+
+// Шаблоны токенов компилируются один раз за время работы программы
+#[cfg(all(feature = "regex", not(feature = "logos")))]
+fn token_patterns() -> &'static [(TokenType, Regex)] {
+    static PATTERNS: OnceLock<Vec<(TokenType, Regex)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| KNOWNS_TOKENS.iter()
+        .map(|tok| (tok.0, Regex::new(tok.1).unwrap()))
+        .collect())
+}
+
+// Самое длинное совпадение среди шаблонов KNOWNS_TOKENS в начале строки
+// (при равной длине - шаблон, стоящий в таблице раньше)
+#[cfg(all(feature = "regex", not(feature = "logos")))]
+fn longest_match(target_string: &str) -> Option<(TokenType, usize)> {
+    let mut longest: Option<(TokenType, usize)> = None;
+    for (token_type, rgx) in token_patterns() {
+        if let Some(found) = rgx.find(target_string) {
+            if longest.is_none_or(|(_, len)| found.end() > len) {
+                longest = Some((*token_type, found.end()));
+            }
+        }
+    }
+    longest
+}
+
+// Лексемы для лексера logos: правила те же, что у шаблонов KNOWNS_TOKENS (цифры - только
+// ASCII, как во встроенном лексере), но все шаблоны собираются при компиляции в один
+// конечный автомат, и лексема находится за один проход по её байтам
+#[cfg(feature = "logos")]
+#[derive(logos::Logos, Debug, Clone, Copy, PartialEq)]
+enum Lexeme {
+    #[token("(")]
+    OpenedParenthesis,
+    #[token(")")]
+    ClosedParenthesis,
+    #[token("[")]
+    OpenedBracket,
+    #[token("]")]
+    ClosedBracket,
+    #[token(",")]
+    ArgumentSeparator,
+    #[token("|")]
+    Bar,
+    #[regex("[a-zA-Z]+")]
+    Name,
+    #[regex(r"[\+\-/\*%×÷−·±=<>]|<<|>>|<=|>=|==|!=")]
+    Operator,
+    #[regex("[0-9]{4}-[0-9]{2}-[0-9]{2}")]
+    Date,
+    #[regex(r"[0-9]+\.[0-9]+")]
+    #[regex("[0-9]+(\\.[0-9]+)?(°|deg|grad)")]
+    Float,
+    #[regex("[0-9]+|[0-9]+#[0-9a-zA-Z]+")]
+    Int,
+    #[regex(r"\s+")]
+    Whitespaces,
+}
+
+#[cfg(feature = "logos")]
+fn longest_match(target_string: &str) -> Option<(TokenType, usize)> {
+    use logos::Logos;
+    let mut lexer = Lexeme::lexer(target_string);
+    let token_type = match lexer.next()?.ok()? {
+        Lexeme::OpenedParenthesis => TokenType::OpenedParenthesis,
+        Lexeme::ClosedParenthesis => TokenType::ClosedParenthesis,
+        Lexeme::OpenedBracket => TokenType::OpenedBracket,
+        Lexeme::ClosedBracket => TokenType::ClosedBracket,
+        Lexeme::ArgumentSeparator => TokenType::ArgumentSeparator,
+        Lexeme::Bar => TokenType::Bar,
+        Lexeme::Name => TokenType::Function,
+        Lexeme::Operator => TokenType::BinaryOperator,
+        Lexeme::Date => TokenType::Date,
+        Lexeme::Float => TokenType::NumberFloat,
+        Lexeme::Int => TokenType::NumberInt,
+        Lexeme::Whitespaces => TokenType::Whitespaces,
+    };
+    Some((token_type, lexer.span().end))
+}
+
+// Самое длинное совпадение в начале строки без регулярных выражений; правила те же,
+// что у шаблонов KNOWNS_TOKENS, но цифрами считаются только ASCII-цифры
+#[cfg(not(any(feature = "regex", feature = "logos")))]
+fn longest_match(target_string: &str) -> Option<(TokenType, usize)> {
+    let bytes = target_string.as_bytes();
+    // Длина последовательности подходящих байтов, начиная с позиции from
+    let count = |from: usize, accept: fn(&u8) -> bool| bytes.get(from..).map_or(0, |rest| rest.iter().take_while(|b| accept(b)).count());
+    let first = target_string.chars().next()?;
+    let found = match first {
+        '(' => (TokenType::OpenedParenthesis, 1),
+        ')' => (TokenType::ClosedParenthesis, 1),
+        '[' => (TokenType::OpenedBracket, 1),
+        ']' => (TokenType::ClosedBracket, 1),
+        ',' => (TokenType::ArgumentSeparator, 1),
+        '|' => (TokenType::Bar, 1),
+        '+' | '-' | '/' | '*' | '%' | '×' | '÷' | '−' | '·' | '±' => (TokenType::BinaryOperator, first.len_utf8()),
+        '<' | '>' if bytes.get(1) == Some(&bytes[0]) => (TokenType::BinaryOperator, 2),
+        '<' | '>' | '=' | '!' if bytes.get(1) == Some(&b'=') => (TokenType::BinaryOperator, 2),
+        '<' | '>' | '=' => (TokenType::BinaryOperator, 1),
+        c if c.is_ascii_alphabetic() => (TokenType::Function, count(0, u8::is_ascii_alphabetic)),
+        c if c.is_ascii_digit() => {
+            let int = count(0, u8::is_ascii_digit);
+            let mut found = (TokenType::NumberInt, int);
+            // Дата ГГГГ-ММ-ДД
+            if int == 4 && bytes.get(4) == Some(&b'-') && count(5, u8::is_ascii_digit) == 2
+                && bytes.get(7) == Some(&b'-') && count(8, u8::is_ascii_digit) >= 2 {
+                found = (TokenType::Date, 10);
+            }
+            // Дробное число
+            let fraction = count(int + 1, u8::is_ascii_digit);
+            let mut number = int;
+            if bytes.get(int) == Some(&b'.') && fraction > 0 {
+                number = int + 1 + fraction;
+                if number > found.1 {
+                    found = (TokenType::NumberFloat, number);
+                }
+            }
+            // Угол с суффиксом: 90°, 1.5deg
+            let angle = KNOWNS_ANGLE_SUFFIXES.iter()
+                .find(|suffix| target_string[number..].starts_with(suffix.0))
+                .map(|suffix| number + suffix.0.len());
+            if let Some(angle) = angle.filter(|angle| *angle > found.1) {
+                found = (TokenType::NumberFloat, angle);
+            }
+            // Число с основанием: 16#ff
+            let digits = count(int + 1, u8::is_ascii_alphanumeric);
+            if bytes.get(int) == Some(&b'#') && digits > 0 && int + 1 + digits > found.1 {
+                found = (TokenType::NumberInt, int + 1 + digits);
+            }
+            found
+        },
+        c if c.is_whitespace() => (TokenType::Whitespaces,
+            target_string.find(|c: char| !c.is_whitespace()).unwrap_or(target_string.len())),
+        _ => return None,
+    };
+    Some(found)
+}
+
+// Выделение лексем по принципу самого длинного совпадения.
+// Строка просматривается один раз: все шаблоны привязаны к началу (^), поэтому
+// сопоставление в каждой позиции затрагивает только саму лексему, и время разбора
+// линейно зависит от длины входа.
+// Если передан `errors`, нераспознанные символы не прерывают разбор: подряд идущие
+// нераспознанные символы дают одну ошибку, а разбор продолжается со следующей известной
+// лексемы или пробела
+fn scan_lexemes(in_string: &str, limits: &Limits, errors: Option<&mut Vec<CalcError>>) -> Result<Vec<Token>, CalcError> {
+    // Лексема занимает хотя бы один байт, поэтому список выделяется один раз
+    // и не перевыделяется по мере роста
+    let mut lexemes: Vec<Token> = Vec::with_capacity(in_string.len().min(limits.max_tokens));
+    scan_from(in_string, 0, &mut lexemes, limits, errors, |_| false)?;
+    Ok(lexemes)
+}
+
+// Выделение лексем строки с позиции `position` в конец списка `lexemes` до конца строки
+// или до первой позиции между лексемами, для которой `stop` вернёт true. Возвращает
+// позицию, на которой выделение остановилось
+fn scan_from<F>(in_string: &str, mut position: usize, lexemes: &mut Vec<Token>, limits: &Limits,
+    mut errors: Option<&mut Vec<CalcError>>, stop: F) -> Result<usize, CalcError>
+where
+    F: Fn(usize) -> bool,
+{
+    while position < in_string.len() && !stop(position) {
+        check_cancelled(limits)?;
+        let target_string = &in_string[position..];
+        match longest_match(target_string) {
+            Some((token_type, len)) if len > 0 => {
+                // Число токенов ограничено (пробелы не считаются), чтобы не разбирать целиком
+                // заведомо слишком сложное выражение
+                if token_type != TokenType::Whitespaces {
+                    if lexemes.len() >= limits.max_tokens {
+                        return Err(too_many_tokens_error(limits));
+                    }
+                    let lexeme = &target_string[..len];
+                    let value = match token_type {
+                        TokenType::NumberInt | TokenType::NumberFloat | TokenType::Date => Cow::Owned(lexeme.to_string()),
+                        _ => intern(lexeme),
+                    };
+                    lexemes.push((token_type, value, (position, position + len)));
+                }
+                position += len;
+            },
+            _ => {
+                let len = unknown_length(target_string);
+                let error = CalcError::new(ErrorKind::UnknownToken, "неизвестная лексема!")
+                    .with_span(position, &target_string[..len])
+                    .with_note("допустимы числа, латинские имена, операторы, скобки, ',' и '|'");
+                match errors.as_mut() {
+                    Some(errors) => errors.push(error),
+                    None => return Err(error),
+                }
+                position += len;
+            },
+        }
+    }
+
+    Ok(position)
+}
+
+fn too_many_tokens_error(limits: &Limits) -> CalcError {
+    CalcError::new(ErrorKind::TooComplex, &format!("выражение слишком сложное: больше {} токенов", limits.max_tokens))
+}
+
+// Длина в байтах нераспознанного участка в начале строки: до первой известной лексемы
+fn unknown_length(target_string: &str) -> usize {
+    target_string.char_indices()
+        .find(|&(index, _)| index > 0 && longest_match(&target_string[index..]).is_some_and(|(_, len)| len > 0))
+        .map_or(target_string.len(), |(index, _)| index)
+}
+
+// Замена черт модуля на вызов abs: "|x - 3|" -> "abs(x - 3)". Черта закрывает модуль,
+// если перед ней стоит операнд и есть незакрытая черта, иначе открывает новый
+// ("||x| - |y||" - модуль разности модулей). Новые токены получают место черты в строке,
+// поэтому ошибки указывают на саму черту
+fn expand_bars(lexemes: Vec<Token>) -> Vec<Token> {
+    if lexemes.iter().all(|lexeme| lexeme.0 != TokenType::Bar) {
+        return lexemes;
+    }
+    let mut tokens: Vec<Token> = Vec::with_capacity(2 * lexemes.len());
+    let mut opened = 0;
+    for lexeme in lexemes {
+        if lexeme.0 != TokenType::Bar {
+            tokens.push(lexeme);
+            continue;
+        }
+        // Имя перед чертой не может быть вызовом функции, т.е. это переменная или единица
+        let after_operand = tokens.last()
+            .is_some_and(|prev| prev.0 == TokenType::Function || ends_operand(Some(prev.0)));
+        if opened > 0 && after_operand {
+            opened -= 1;
+            tokens.push((TokenType::ClosedParenthesis, lexeme.1, lexeme.2));
+        } else {
+            opened += 1;
+            tokens.push((TokenType::Function, Cow::Borrowed("abs"), lexeme.2));
+            tokens.push((TokenType::OpenedParenthesis, lexeme.1, lexeme.2));
+        }
+    }
+
+    tokens
+}
+
+// Черта модуля без парной: модуль |x| записывается двумя чертами
+fn unpaired_bar(input: &str, span: Span) -> CalcError {
+    CalcError::new(ErrorKind::MissingParenthesis, "у черты модуля '|' нет парной").with_source_span(input, span)
+}
+
+// Уточнение типов лексем с учётом контекста. Пробелы отброшены ещё при выделении
+// лексем, поэтому каждая лексема остаётся одним токеном и типы уточняются на месте,
+// без второго списка
+fn classify_lexemes(mut tokens: Vec<Token>) -> Vec<Token> {
+    for index in 0..tokens.len() {
+        let before_parenthesis = tokens.get(index + 1).is_some_and(|next| next.0 == TokenType::OpenedParenthesis);
+        let after_operand = ends_operand(index.checked_sub(1).map(|previous| tokens[previous].0));
+        let tok = &mut tokens[index];
+        match tok.0 {
+            // Имя, за которым не следует открывающая скобка, - единица измерения
+            // (так "min" остаётся функцией в "min(1, 2)" и единицей в "5 min")
+            TokenType::Function if get_unit_info(&tok.1).is_some() && !before_parenthesis => tok.0 = TokenType::Unit,
+            // Неизвестное имя без скобки после него - переменная
+            TokenType::Function if get_function_arity(&tok.1).is_none() && !before_parenthesis => tok.0 = TokenType::Variable,
+            TokenType::BinaryOperator => {
+                tok.1 = normalize_operator(core::mem::take(&mut tok.1));
+                // Т.к. унарные + и - не отличимы при разборе от бинарных, то
+                //  необходимы дополнительные проверки:
+                //  если последний разобранный токен завершает операнд (число или закрывающая скобка), то
+                //  данный токен это бинарный оператор, иначе (начало строки, открывающая скобка,
+                //  разделитель аргументов, другой оператор) - унарный, в том числе перед скобкой
+                //  и перед именем функции
+                if (tok.1 == "+" || tok.1 == "-") && !after_operand {
+                    // Дополнительно, чтобы при вычислении выражения отличать бинарные + и -
+                    // от унарных переименуем унарные в соответствующие операторы
+                    tok.1 = Cow::Borrowed(if tok.1 == "+" { "POS" } else { "NEG" });
+                    tok.0 = TokenType::UnaryOperator;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    tokens
+}
+
+// Проверка токенов на конструкции, запрещённые параметрами разбора
+fn check_options(tokens: &[Token], input: &str, options: &ParserOptions) -> Result<(), CalcError> {
+    if let Some(max_tokens) = options.max_tokens.filter(|max_tokens| tokens.len() > *max_tokens) {
+        return Err(CalcError::new(ErrorKind::TooComplex, &format!("выражение слишком сложное: больше {} токенов", max_tokens)));
+    }
+    for tok in tokens {
+        let lexeme = &input[tok.2.0..tok.2.1];
+        // Модуль |x| записывается как вызов abs с местом черты в строке
+        let bar = lexeme == "|";
+        let message = match tok.0 {
+            TokenType::Function if !options.functions && !bar => format!("функции запрещены: '{}'", lexeme),
+            TokenType::BinaryOperator if !options.bit_operators && (tok.1 == "<<" || tok.1 == ">>") =>
+                format!("оператор '{}' запрещён", lexeme),
+            TokenType::Function | TokenType::Unit | TokenType::Variable if !bar && !lexeme.chars().all(options.identifiers) =>
+                format!("недопустимое имя '{}'", lexeme),
+            _ => continue,
+        };
+        return Err(CalcError::new(ErrorKind::Forbidden, &message).with_span(tok.2.0, lexeme));
+    }
+
+    Ok(())
+}
+
+// Проверяем, что между двумя значениями (числами, скобочными выражениями, функциями)
+// всегда стоит оператор
+fn missing_operators(tokens: &[Token], input: &str) -> Vec<CalcError> {
+    let missing_operator = |left: &Token, right: &Token| {
+        let (left_lexeme, right_lexeme) = (&input[left.2.0..left.2.1], &input[right.2.0..right.2.1]);
+        CalcError::new(ErrorKind::MissingOperator, &format!("пропущен оператор между '{}' и '{}'", left_lexeme, right_lexeme))
+            .with_span(right.2.0, right_lexeme)
+            .with_label(left.2, "значение без оператора после него")
+            .with_note(&format!("например, '{} * {}'", left_lexeme, right_lexeme))
+    };
+
+    let value_end = [TokenType::NumberInt, TokenType::NumberFloat, TokenType::Date, TokenType::ClosedParenthesis,
+        TokenType::ClosedBracket, TokenType::Unit, TokenType::Variable];
+    let value_start = [TokenType::NumberInt, TokenType::NumberFloat, TokenType::Date, TokenType::OpenedParenthesis,
+        TokenType::OpenedBracket, TokenType::Function, TokenType::Unit, TokenType::Variable];
+    let mut errors = Vec::new();
+    for pair in tokens.windows(2) {
+        let (left, right) = (&pair[0], &pair[1]);
+        // Единица измерения после числа или скобки - множитель, а не пропущенный оператор
+        if right.0 == TokenType::Unit && left.0 != TokenType::Unit {
+            continue;
+        }
+        if value_end.contains(&left.0) && value_start.contains(&right.0) {
+            errors.push(missing_operator(left, right));
+        }
+    }
+
+    errors
+}
+
+// Проверяем, что у каждого оператора есть операнды: бинарному оператору нужен операнд слева,
+// любому оператору - операнд справа
+fn dangling_operators(tokens: &[Token], input: &str) -> Vec<CalcError> {
+    let is_operator = |tok: &Token| tok.0 == TokenType::BinaryOperator || tok.0 == TokenType::UnaryOperator;
+
+    let mut errors = Vec::new();
+    for (index, tok) in tokens.iter().enumerate() {
+        if !is_operator(tok) {
+            continue;
+        }
+        let lexeme = &input[tok.2.0..tok.2.1];
+        let message = if tok.0 == TokenType::BinaryOperator && index == 0 {
+            format!("выражение начинается с бинарного оператора '{}'", lexeme)
+        } else if tok.0 == TokenType::BinaryOperator && !ends_operand(tokens.get(index - 1).map(|tok| tok.0)) {
+            format!("пропущен операнд перед оператором '{}'", lexeme)
+        } else {
+            match tokens.get(index + 1) {
+                None => format!("выражение заканчивается оператором '{}'", lexeme),
+                Some(next) if [TokenType::ClosedParenthesis, TokenType::ClosedBracket, TokenType::ArgumentSeparator].contains(&next.0) =>
+                    format!("пропущен операнд после оператора '{}'", lexeme),
+                _ => continue,
+            }
+        };
+        errors.push(CalcError::new(ErrorKind::DanglingOperator, &message).with_source_span(input, tok.2));
+    }
+
+    errors
+}
+
+fn is_opening(tok: &Token) -> bool {
+    tok.0 == TokenType::OpenedParenthesis || tok.0 == TokenType::OpenedBracket
+}
+
+// Число элементов списка по токену составления списка ("[3]" - список из трёх элементов)
+fn list_length(tok: &Token) -> Option<usize> {
+    tok.1.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+// Сравнения, которые записываются цепочкой: 1 < x < 10
+fn is_comparison(tok: &Token) -> bool {
+    tok.0 == TokenType::BinaryOperator && ["<", ">", "<=", ">=", "==", "!="].contains(&&*tok.1)
+}
+
+// Изменение числа значений на стеке вычисления токеном записи ОПН
+fn token_stack_effect(tok: &Token) -> isize {
+    match tok.0 {
+        TokenType::BinaryOperator => -1,
+        TokenType::UnaryOperator => 0,
+        TokenType::List => 1 - list_length(tok).unwrap_or(0) as isize,
+        // Неизвестная функция забирает список аргументов
+        TokenType::Function => get_function_arity(&tok.1).map_or(0, |arity| 1 - arity as isize),
+        _ => 1,
+    }
+}
+
+// Начало записи последнего операнда в выходной очереди: самый короткий конец очереди,
+// добавляющий на стек ровно одно значение
+fn operand_start(output: &Queue<Token>) -> Option<usize> {
+    let mut depth = 0;
+    for index in (0..output.len()).rev() {
+        depth += token_stack_effect(output.get(index)?);
+        if depth == 1 {
+            return Some(index);
+        }
+    }
+    None
+}
+
+// Выполняем преобразования списка входных токенов в запись ОПН согласно алгоритму
+// сортировочной станции Дейкстры
+pub fn convert_to_rpn(token_list: Vec<Token>, input: &str, limits: &Limits) -> Result<Queue<Token>, CalcError> {
+    // Размер выходной очереди не превышает числа входных токенов
+    let mut output: Queue<Token> = Queue::with_capacity(token_list.len());
+    // Глубина стека операторов ограничена, чтобы не разбирать заведомо патологические выражения
+    let mut stack: BoundedStack<Token> = BoundedStack::new(limits.max_depth);
+    let mut arguments: Stack<Option<usize>> = Stack::new();
+    convert_into(token_list, input, limits, &mut output, &mut stack, &mut arguments)?;
+    Ok(output)
+}
+
+// Преобразование в ОПН с выходной очередью и стеками, переданными извне (см. Workspace);
+// они должны быть пустыми
+fn convert_into(token_list: Vec<Token>, input: &str, limits: &Limits, output: &mut Queue<Token>,
+    stack: &mut BoundedStack<Token>, arguments: &mut Stack<Option<usize>>) -> Result<(), CalcError> {
+    let too_deep = |_: Full| CalcError::new(ErrorKind::TooDeep,
+        &format!("превышена максимальная глубина выражения ({})", limits.max_depth));
+    // Для каждой открытой скобки в `arguments` - число аргументов, если это скобка вызова
+    // функции (или число элементов, если это квадратная скобка списка)
+    // От предыдущего токена нужны только тип и место в строке, поэтому сам токен
+    // (а с ним и строка числа) не копируется
+    let mut previous: Option<(TokenType, Span)> = None;
+    let lexeme = |span: Span| &input[span.0..span.1];
+    for tok in token_list {
+        check_cancelled(limits)?;
+        // За именем функции обязательно следует открывающая скобка
+        if let Some(function) = previous.filter(|prev| prev.0 == TokenType::Function) {
+            if tok.0 != TokenType::OpenedParenthesis {
+                let name = lexeme(function.1);
+                return Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+                    &format!("после имени функции '{}' ожидается '('", name)).with_span(function.1.0, name));
+            }
+        }
+        // С обеих сторон от разделителя обязательно стоят аргументы функции или элементы списка
+        if let Some(separator) = previous.filter(|prev| prev.0 == TokenType::ArgumentSeparator) {
+            if [TokenType::ClosedParenthesis, TokenType::ClosedBracket, TokenType::ArgumentSeparator].contains(&tok.0) {
+                return Err(CalcError::new(ErrorKind::InvalidFunctionCall, "пропущено значение после разделителя ','")
+                    .with_span(separator.1.0, lexeme(separator.1)));
+            }
+        }
+        if tok.0 == TokenType::ArgumentSeparator
+            && previous.is_some_and(|prev| [TokenType::OpenedParenthesis, TokenType::OpenedBracket].contains(&prev.0)) {
+            return Err(CalcError::new(ErrorKind::InvalidFunctionCall, "пропущено значение перед разделителем ','")
+                .with_span(tok.2.0, &tok.1));
+        }
+        let after_opened_parenthesis = previous.map(|prev| prev.0) == Some(TokenType::OpenedParenthesis);
+        let after_opened_bracket = previous.map(|prev| prev.0) == Some(TokenType::OpenedBracket);
+        let after_function = previous.map(|prev| prev.0) == Some(TokenType::Function);
+        let previous_operand = previous.replace((tok.0, tok.2));
+
+        match tok.0 {
+            TokenType::NumberInt | TokenType::NumberFloat | TokenType::Date | TokenType::Variable => {
+                // Если токен — число (дата или переменная), то добавить его в очередь вывода
+                output.enqueue(tok);
+            },
+            TokenType::Unit => {
+                // Единица после операнда умножает его с наивысшим приоритетом, поэтому
+                //   умножение сразу попадает в выходную очередь: "5 km" -> "5 km *"
+                let after_operand = ends_operand(previous_operand.map(|prev| prev.0));
+                let span = tok.2;
+                output.enqueue(tok);
+                if after_operand {
+                    output.enqueue((TokenType::BinaryOperator, Cow::Borrowed("*"), span));
+                }
+            },
+            TokenType::Function => {
+                // Если токен — функция, то поместить его в стек (неизвестные функции
+                //   вычисляет Resolver)
+                stack.push(tok).map_err(too_deep)?;
+            },
+            TokenType::ArgumentSeparator => {
+                // Если токен — разделитель аргументов функции или элементов списка (например запятая):
+                //     Пока токен на вершине стека не открывающая скобка:
+                //         Переложить оператор из стека в выходную очередь.
+                while stack.peek().is_some_and(|last| !is_opening(last)) {
+                    let op = stack.pop().unwrap();
+                    output.enqueue(op);
+                }
+                // Если стек закончился до того, как был встречен токен открывающая скобка,
+                //   то в выражении пропущен разделитель аргументов функции (запятая),
+                //   либо пропущена открывающая скобка.
+                if stack.is_empty() {
+                    return Err(CalcError::new(ErrorKind::MissingArgumentSeparator,
+                        "в выражении пропущен разделитель аргументов функции (запятая), либо пропущена открывающая скобка")
+                        .with_source_span(input, tok.2));
+                }
+                // Разделитель допустим только внутри скобок вызова функции и внутри списка
+                match arguments.peek_mut() {
+                    Some(Some(count)) => *count += 1,
+                    _ => return Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+                        "разделитель аргументов вне вызова функции или списка").with_span(tok.2.0, &tok.1)),
+                }
+            },
+            TokenType::BinaryOperator | TokenType::UnaryOperator => {
+                // Если токен — оператор op1, то:
+                //     Пока присутствует на вершине стека токен оператор op2,
+                //       чей приоритет выше или равен приоритету op1,
+                //       и при равенстве приоритетов op1 является левоассоциативным:
+                //         Переложить op2 из стека в выходную очередь;
+                let mut chained = false;
+                while stack.peek().is_some_and(|last| need_op_pop_from_stack(&tok, last)) {
+                    let op = stack.pop().unwrap();
+                    // Цепочка сравнений 1 < x < 10 - это 1 < x AND x < 10: за первым сравнением
+                    //   в очередь ещё раз записывается его правый операнд, а результаты обоих
+                    //   сравнений объединяет AND
+                    let middle = match is_comparison(&tok) && is_comparison(&op) {
+                        true => operand_start(output),
+                        false => None,
+                    };
+                    let repeated: Vec<Token> = match middle {
+                        Some(start) => (start..output.len()).filter_map(|index| output.get(index).cloned()).collect(),
+                        None => Vec::new(),
+                    };
+                    chained |= middle.is_some();
+                    output.enqueue(op);
+                    repeated.into_iter().for_each(|tok| output.enqueue(tok));
+                }
+                if chained {
+                    stack.push((TokenType::BinaryOperator, Cow::Borrowed("AND"), tok.2)).map_err(too_deep)?;
+                }
+                // Положить op1 в стек.
+                stack.push(tok).map_err(too_deep)?;
+            },
+            TokenType::OpenedParenthesis | TokenType::OpenedBracket => {
+                let is_list = tok.0 == TokenType::OpenedBracket;
+                arguments.push(if after_function || is_list { Some(1) } else { None });
+                if arguments.len() > limits.max_nesting {
+                    return Err(CalcError::new(ErrorKind::TooComplex,
+                        &format!("выражение слишком сложное: вложенность скобок больше {}", limits.max_nesting))
+                        .with_source_span(input, tok.2));
+                }
+                // Если токен — открывающая скобка, то положить его в стек
+                stack.push(tok).map_err(too_deep)?;
+            },
+            TokenType::ClosedParenthesis => {
+                // Если токен — закрывающая скобка:
+                //     Пока токен на вершине стека не открывающая скобка
+                //         Переложить оператор из стека в выходную очередь.
+                while stack.peek().is_some_and(|last| !is_opening(last)) {
+                    let op = stack.pop().unwrap();
+                    output.enqueue(op);
+                }
+
+                // Если стек закончился до того, как был встречен токен открывающая скобка, то в выражении пропущена скобка.
+                if stack.is_empty() {
+                    return Err(CalcError::new(ErrorKind::MissingParenthesis, "в выражении пропущена открывающая скобка")
+                        .with_source_span(input, tok.2)
+                        .with_note("у этой скобки нет парной '(' перед ней"));
+                } else if stack.peek().unwrap().0 == TokenType::OpenedBracket {
+                    return Err(CalcError::new(ErrorKind::MissingParenthesis, "в выражении пропущена закрывающая скобка ']'")
+                        .with_source_span(input, tok.2)
+                        .with_label(stack.peek().unwrap().2, "список начинается здесь"));
+                } else {
+                    // Выкинуть открывающую скобку из стека, но не добавлять в очередь вывода.
+                    let opened = stack.pop().unwrap();
+                    // Черта модуля закрывается только чертой, скобка - только скобкой
+                    if opened.1 == "|" && tok.1 != "|" {
+                        return Err(unpaired_bar(input, opened.2).with_label(tok.2, "скобка закрывается раньше модуля"));
+                    }
+                    if opened.1 != "|" && tok.1 == "|" {
+                        return Err(CalcError::new(ErrorKind::MissingParenthesis, "в выражении пропущена закрывающая скобка ')'")
+                            .with_source_span(input, tok.2)
+                            .with_label(opened.2, "скобка открыта здесь"));
+                    }
+                    let count = arguments.pop().flatten();
+                    // Пустые скобки допустимы только при вызове функции без аргументов
+                    if after_opened_parenthesis && count.is_none() {
+                        let position = input[..opened.2.0].chars().count() + 1;
+                        return Err(CalcError::new(ErrorKind::EmptyParentheses, &format!("пустые скобки в позиции {}", position))
+                            .with_source_span(input, (opened.2.0, tok.2.1))
+                            .with_note("пустые скобки допустимы только при вызове функции без аргументов, например now()"));
+                    }
+                    // Если токен на вершине стека — функция, переложить её в выходную очередь.
+                    if !stack.is_empty() && stack.peek().unwrap().0 == TokenType::Function {
+                        let op = stack.pop().unwrap();
+                        // Проверяем число переданных функции аргументов
+                        let count = if after_opened_parenthesis { 0 } else { count.unwrap_or(0) };
+                        let mut op = op;
+                        if op.1 == "sum" && count == 4 {
+                            op.1 = Cow::Borrowed("SUM");
+                        }
+                        let arity = match get_function_arity(&op.1) {
+                            Some(arity) => arity,
+                            // Аргументы неизвестной функции собираются в список: "f(1, 2)" -> "1 2 [2] f"
+                            None => {
+                                output.enqueue((TokenType::List, Cow::Owned(format!("[{}]", count)), (op.2.0, tok.2.1)));
+                                output.enqueue(op);
+                                continue;
+                            },
+                        };
+                        if count != arity {
+                            let error = CalcError::new(ErrorKind::InvalidFunctionCall,
+                                &format!("функция '{}' принимает аргументов: {}, передано: {}", op.1, arity, count))
+                                .with_span(op.2.0, &op.1);
+                            return Err(match describe_function(&op.1) {
+                                Some(description) => error.with_note(&description),
+                                None => error,
+                            });
+                        }
+                        output.enqueue(op);
+                    }
+                }
+            },
+            TokenType::ClosedBracket => {
+                // Закрывающая квадратная скобка завершает список: все операторы внутри списка
+                //   перекладываются в выходную очередь, а за ними - составление списка
+                while stack.peek().is_some_and(|last| !is_opening(last)) {
+                    let op = stack.pop().unwrap();
+                    output.enqueue(op);
+                }
+                match stack.pop() {
+                    Some(opened) if opened.0 == TokenType::OpenedBracket => {
+                        // Пустой список допустим
+                        let count = arguments.pop().flatten().unwrap_or(0);
+                        let count = if after_opened_bracket { 0 } else { count };
+                        output.enqueue((TokenType::List, Cow::Owned(format!("[{}]", count)), (opened.2.0, tok.2.1)));
+                    },
+                    Some(opened) => return Err(CalcError::new(ErrorKind::MissingParenthesis, "в выражении пропущена закрывающая скобка ')'")
+                        .with_source_span(input, tok.2)
+                        .with_label(opened.2, "скобка открыта здесь")),
+                    None => return Err(CalcError::new(ErrorKind::MissingParenthesis, "в выражении пропущена открывающая скобка '['")
+                        .with_source_span(input, tok.2)
+                        .with_note("у этой скобки нет парной '[' перед ней")),
+                }
+            },
+            // Пробелы отбрасываются ещё при разбиении строки на токены,
+            // а составление списка появляется только в выходной очереди
+            TokenType::Whitespaces | TokenType::List | TokenType::Bar => continue,
+        }
+    }
+
+    if let Some(function) = previous.filter(|prev| prev.0 == TokenType::Function) {
+        let name = lexeme(function.1);
+        return Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+            &format!("после имени функции '{}' ожидается '('", name)).with_span(function.1.0, name));
+    }
+
+    // Если больше не осталось токенов на входе:
+    // Пока есть токены операторы в стеке:
+    for op in stack.drain() {
+        // Если токен оператор на вершине стека — открывающая скобка, то в выражении пропущена скобка.
+        if op.0 == TokenType::OpenedParenthesis && op.1 == "|" {
+            return Err(unpaired_bar(input, op.2).with_note("модуль не закрыт до конца выражения"));
+        }
+        if op.0 == TokenType::OpenedParenthesis {
+            return Err(CalcError::new(ErrorKind::MissingParenthesis, "в выражении пропущена закрывающая скобка")
+                .with_source_span(input, op.2)
+                .with_note("эта скобка не закрыта до конца выражения"));
+        }
+        if op.0 == TokenType::OpenedBracket {
+            return Err(CalcError::new(ErrorKind::MissingParenthesis, "в выражении пропущена закрывающая скобка ']'")
+                .with_source_span(input, op.2)
+                .with_note("этот список не закрыт до конца выражения"));
+        }
+
+        // Переложить оператор из стека в выходную очередь.
+        output.enqueue(op);
+    }
+
+    Ok(())
+}
+
+
+// Значение, получаемое при вычислении: число, величина с единицей измерения
+// (в основных единицах), дата (число дней от 1970-01-01), целое число в заданной
+// системе счисления, интервал или список значений
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f32),
+    Quantity(f32, Dimension),
+    Date(i64),
+    // Целое число, выводимое в системе счисления с заданным основанием
+    Radix(i64, u32),
+    // Интервал [нижняя граница, верхняя граница] - число с погрешностью
+    Interval(f32, f32),
+    List(Vec<Value>),
+    // Результат сравнения - условие для if
+    Bool(bool),
+}
+
+// Число дней от 1970-01-01 по дате григорианского календаря
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Дата григорианского календаря (год, месяц, день) по числу дней от 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Дата из записи вида 2024-05-01; несуществующие даты (2024-02-30) считаются ошибкой
+fn date_value(tok: &Token) -> Result<i64, CalcError> {
+    let invalid_date = || CalcError::new(ErrorKind::InvalidDate, &format!("некорректная дата '{}'", tok.1))
+        .with_span(tok.2.0, &tok.1);
+    let parts = tok.1.split('-').map(|part| part.parse::<i64>()).collect::<Result<Vec<i64>, _>>()
+        .map_err(|_| invalid_date())?;
+    let (year, month, day) = match parts[..] {
+        [year, month, day] => (year, month, day),
+        _ => return Err(invalid_date()),
+    };
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) {
+        return Err(invalid_date());
+    }
+
+    Ok(days)
+}
+
+// Текущая дата (UTC); без стандартной библиотеки часов нет
+#[cfg(feature = "std")]
+fn today() -> Option<i64> {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    Some((seconds / 86400) as i64)
+}
+
+#[cfg(not(feature = "std"))]
+fn today() -> Option<i64> {
+    None
+}
+
+// Ошибка применения операции к дате
+fn date_operation_error(op: Option<&Token>) -> CalcError {
+    match op {
+        Some(op) => CalcError::new(ErrorKind::InvalidDate, &format!("операция '{}' не применима к дате", op.1))
+            .with_span(op.2.0, &op.1),
+        None => CalcError::new(ErrorKind::InvalidDate, "операция не применима к дате"),
+    }
+}
+
+// Длительность в целых днях: к дате прибавляются только величины времени (3 d, 2 w, 48 h)
+fn duration_days(op: &Token, value: &Value) -> Result<i64, CalcError> {
+    let days = match value {
+        Value::Quantity(seconds, dimension) if *dimension == [0, 0, 1, 0] => seconds / 86400.0,
+        _ => return Err(CalcError::new(ErrorKind::InvalidDate,
+            &format!("к дате можно прибавить или вычесть только длительность (например, 3 d), получено {}", value))
+            .with_span(op.2.0, &op.1)),
+    };
+    if (days - days.round()).abs() > 1e-3 {
+        return Err(CalcError::new(ErrorKind::InvalidDate,
+            &format!("длительность должна быть целым числом дней, получено {}", value)).with_span(op.2.0, &op.1));
+    }
+
+    Ok(days.round() as i64)
+}
+
+// Операции с датами: дата ± длительность - дата, разность дат - число дней между ними
+fn calc_date_operator(op: &Token, arg1: Value, arg2: Value) -> Result<Value, CalcError> {
+    match (&*op.1, arg1, arg2) {
+        ("-", Value::Date(date), Value::Date(other)) => Ok(Value::Number((date - other) as f32)),
+        ("-", Value::Date(date), duration) => Ok(Value::Date(date - duration_days(op, &duration)?)),
+        ("+", Value::Date(date), duration) | ("+", duration, Value::Date(date)) =>
+            Ok(Value::Date(date + duration_days(op, &duration)?)),
+        _ => Err(date_operation_error(Some(op))),
+    }
+}
+
+// Обозначение размерности через основные единицы, например "m/s^2"
+pub fn dimension_name(dimension: Dimension) -> String {
+    let mut base_units = BASE_UNITS;
+    if let Some(currency) = extra_units().iter().find(|unit| unit.1 == 1.0 && unit.2 == CURRENCY) {
+        base_units[3] = currency.0.as_str();
+    }
+    let power = |unit: &str, power: i8| if power == 1 { unit.to_string() } else { format!("{}^{}", unit, power) };
+    let numerator: Vec<String> = base_units.iter().zip(dimension)
+        .filter(|(_, power)| *power > 0)
+        .map(|(unit, p)| power(unit, p))
+        .collect();
+    let denominator: Vec<String> = base_units.iter().zip(dimension)
+        .filter(|(_, power)| *power < 0)
+        .map(|(unit, p)| power(unit, -p))
+        .collect();
+    let numerator = if numerator.is_empty() { "1".to_string() } else { numerator.join("*") };
+    if denominator.is_empty() {
+        numerator
+    } else {
+        format!("{}/{}", numerator, denominator.join("*"))
+    }
+}
+
+// Запись чисел в результате вычисления
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Notation {
+    // Два знака после точки: 12345678.00
+    #[default]
+    Fixed,
+    // Научная: 1.2345678e7
+    Scientific,
+    // Инженерная - показатель степени кратен трём: 12.345678e6
+    Engineering,
+    // Научная для очень больших и очень малых по модулю чисел, для остальных - Fixed
+    Auto,
+}
+
+// Округление последнего записываемого знака результата
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Rounding {
+    // Половина - к чётному: 0.125 -> 0.12, 0.375 -> 0.38
+    #[default]
+    HalfEven,
+    // Половина - от нуля: 0.125 -> 0.13, -0.125 -> -0.13
+    HalfUp,
+    // Отбрасывание лишних знаков: 0.129 -> 0.12
+    Truncate,
+}
+
+impl Rounding {
+    // Округление до целого
+    fn apply(self, value: f64) -> f64 {
+        match self {
+            Rounding::HalfEven => value.round_ties_even(),
+            Rounding::HalfUp => value.round(),
+            Rounding::Truncate => value.trunc(),
+        }
+    }
+}
+
+// Параметры записи чисел результата
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NumberFormat {
+    pub notation: Notation,
+    pub rounding: Rounding,
+    // Число значащих цифр вместо двух знаков после точки: 3 -> 1230000.00 записывается как 1.23e6
+    pub significant: Option<usize>,
+    // Разделитель групп по три цифры в целой части: ' ' -> 1 234 567.50, ',' -> 1,234,567.50.
+    // Дробная часть всегда отделяется точкой, поэтому разделитель группы не должен быть точкой
+    pub grouping: Option<char>,
+    // Запись простейшей дробью, равной числу с точностью вычисления: 7/3 вместо 2.33
+    pub fraction: bool,
+    // Неправильная дробь при записи дробью выделяет целую часть: 2 1/3 вместо 7/3
+    pub mixed: bool,
+}
+
+// Допустимое относительное отклонение дроби от записываемого числа: 7/3 хранится в f32 как
+// 2.3333333, то есть с погрешностью в младшем бите
+const FRACTION_PRECISION: f64 = f32::EPSILON as f64;
+// Наибольший знаменатель дроби; числа, близкие только к дробям с большим знаменателем
+// (sqrt(2)), записываются десятичной дробью
+const FRACTION_MAX_DENOMINATOR: u64 = 1000;
+
+// Допустимое число значащих цифр; у f32 их не больше девяти
+pub const SIGNIFICANT_RANGE: core::ops::RangeInclusive<usize> = 1..=9;
+
+// Числа с модулем из этого диапазона (и ноль) в нотации Auto записываются как в Fixed
+const AUTO_FIXED_RANGE: core::ops::Range<f32> = 0.01..1e7;
+
+// Запись числа в заданном формате; все числа результата записываются только здесь
+fn write_number(f: &mut fmt::Formatter, number: f32, format: NumberFormat) -> fmt::Result {
+    // Прибавление нуля превращает -0 в 0: sin(180°) даёт -0.00000009, что после
+    // округления записывалось бы как "-0.00"
+    let number = number + 0.0;
+    if let Some((numerator, denominator)) = Some(number).filter(|_| format.fraction).and_then(fraction) {
+        let sign = if number < 0.0 && numerator != 0 { "-" } else { "" };
+        return match denominator {
+            1 => write!(f, "{}{}", sign, numerator),
+            _ if format.mixed && numerator > denominator =>
+                write!(f, "{}{} {}/{}", sign, numerator / denominator, numerator % denominator, denominator),
+            _ => write!(f, "{}{}/{}", sign, numerator, denominator),
+        };
+    }
+    let notation = match format.notation {
+        Notation::Auto if number != 0.0 && number.is_finite() && !AUTO_FIXED_RANGE.contains(&number.abs()) => Notation::Scientific,
+        Notation::Auto => Notation::Fixed,
+        notation => notation,
+    };
+    match notation {
+        Notation::Scientific => write!(f, "{:e}", number),
+        Notation::Engineering => write_engineering(f, number),
+        _ if format.significant.is_some() => write_grouped(f, &significant(number, format), format.grouping),
+        _ => {
+            // Число f32, умноженное на 100, точно представимо в f64 (24 + 7 значащих бит),
+            // поэтому половина определяется точно, а не по двоичному приближению
+            let hundredths = format.rounding.apply(number as f64 * 100.0);
+            write_grouped(f, &format!("{0:.2}", hundredths / 100.0 + 0.0), format.grouping)
+        },
+    }
+}
+
+// Запись числа с разделением целой части на группы по три цифры; запись с показателем
+// степени не разделяется
+fn write_grouped(f: &mut fmt::Formatter, number: &str, grouping: Option<char>) -> fmt::Result {
+    let separator = match grouping {
+        Some(separator) if separator != '.' && !number.contains('e') => separator,
+        _ => return f.write_str(number),
+    };
+    let (sign, unsigned) = number.strip_prefix('-').map_or(("", number), |unsigned| ("-", unsigned));
+    let (integer, fraction) = unsigned.split_at(unsigned.find(|c: char| !c.is_ascii_digit()).unwrap_or(unsigned.len()));
+    f.write_str(sign)?;
+    for (index, digit) in integer.char_indices() {
+        if index > 0 && (integer.len() - index) % 3 == 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{}", digit)?;
+    }
+    f.write_str(fraction)
+}
+
+// Запись с заданным числом значащих цифр. Как и %g в C, числа с порядком от -5 до числа
+// цифр записываются без показателя степени: 0.0000123, 123.0, 1.23e6
+fn significant(number: f32, format: NumberFormat) -> String {
+    let digits = format.significant.unwrap_or(1).clamp(*SIGNIFICANT_RANGE.start(), *SIGNIFICANT_RANGE.end());
+    if number == 0.0 || !number.is_finite() {
+        return format!("{:.*}", digits - 1, number);
+    }
+    // Порядок старшей цифры берётся из научной записи, чтобы не зависеть от log10
+    let scientific = format!("{:e}", number);
+    let mut exponent = scientific.split_once('e').and_then(|(_, exponent)| exponent.parse::<i32>().ok()).unwrap_or(0);
+    // Сдвиг до целого из digits цифр; половина определяется точно, когда она представима в f64
+    let shift = digits as i32 - 1 - exponent;
+    let scaled = if shift >= 0 { number as f64 * power_of_ten(shift) } else { number as f64 / power_of_ten(-shift) };
+    let mut scaled = format.rounding.apply(scaled).abs();
+    // Перенос при округлении: 9.99 с двумя цифрами даёт 10
+    if scaled >= power_of_ten(digits as i32) {
+        scaled /= 10.0;
+        exponent += 1;
+    }
+    let mantissa = format!("{:.0}", scaled);
+    let sign = if number < 0.0 { "-" } else { "" };
+    if (0..digits as i32).contains(&exponent) {
+        let (integer, fraction) = mantissa.split_at(exponent as usize + 1);
+        match fraction.is_empty() {
+            true => format!("{}{}", sign, integer),
+            false => format!("{}{}.{}", sign, integer, fraction),
+        }
+    } else if (-5..0).contains(&exponent) {
+        format!("{}0.{}{}", sign, "0".repeat((-exponent - 1) as usize), mantissa)
+    } else {
+        let (integer, fraction) = mantissa.split_at(1);
+        match fraction.is_empty() {
+            true => format!("{}{}e{}", sign, integer, exponent),
+            false => format!("{}{}.{}e{}", sign, integer, fraction, exponent),
+        }
+    }
+}
+
+// Модуль числа простейшей дробью (числитель, знаменатель); для бесконечности, NaN, очень
+// больших чисел и чисел без дроби с небольшим знаменателем дроби нет
+fn fraction(number: f32) -> Option<(u64, u64)> {
+    let value = (number as f64).abs();
+    if !value.is_finite() || value >= 1e15 {
+        return None;
+    }
+    let tolerance = value * FRACTION_PRECISION;
+    simplest_fraction(value - tolerance, value + tolerance, 32)
+        .filter(|(_, denominator)| *denominator <= FRACTION_MAX_DENOMINATOR)
+}
+
+// Дробь с наименьшим знаменателем из отрезка [low, high], 0 <= low <= high, разложением
+// в цепную дробь: x = a + 1/y, где y ищется на отрезке с перевёрнутыми концами
+fn simplest_fraction(low: f64, high: f64, depth: usize) -> Option<(u64, u64)> {
+    let integer = low.trunc();
+    if integer == low {
+        return Some((integer as u64, 1));
+    }
+    if integer + 1.0 <= high {
+        return Some((integer as u64 + 1, 1));
+    }
+    if depth == 0 {
+        return None;
+    }
+    let (numerator, denominator) = simplest_fraction(1.0 / (high - integer), 1.0 / (low - integer), depth - 1)?;
+    Some((integer as u64 * numerator + denominator, numerator))
+}
+
+// Степень десяти; до 10^22 точна в f64
+fn power_of_ten(exponent: i32) -> f64 {
+    (0..exponent).fold(1.0, |power, _| power * 10.0)
+}
+
+// Инженерная запись получается из научной переносом точки на 0-2 разряда вправо
+fn write_engineering(f: &mut fmt::Formatter, number: f32) -> fmt::Result {
+    let scientific = format!("{:e}", number);
+    let (mantissa, exponent) = match scientific.split_once('e').and_then(|(mantissa, exponent)| Some((mantissa, exponent.parse::<i32>().ok()?))) {
+        Some(parts) => parts,
+        // Бесконечность и NaN
+        None => return f.write_str(&scientific),
+    };
+    let shift = exponent.rem_euclid(3) as usize;
+    let (sign, mantissa) = mantissa.strip_prefix('-').map_or(("", mantissa), |mantissa| ("-", mantissa));
+    let digits = mantissa.replace('.', "");
+    let digits = format!("{:0<width$}", digits, width = shift + 1);
+    let (integer, fraction) = digits.split_at(shift + 1);
+    write!(f, "{}{}", sign, integer)?;
+    if !fraction.is_empty() {
+        write!(f, ".{}", fraction)?;
+    }
+    write!(f, "e{}", exponent - shift as i32)
+}
+
+// Значение, записываемое в заданном формате: value.formatted(number_format).to_string()
+pub struct Formatted<'a>(&'a Value, NumberFormat);
+
+impl Value {
+    pub fn formatted(&self, format: NumberFormat) -> Formatted<'_> {
+        Formatted(self, format)
+    }
+}
+
+impl fmt::Display for Formatted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format = self.1;
+        match self.0 {
+            Value::Number(number) => write_number(f, *number, format),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Quantity(number, dimension) => {
+                write_number(f, *number, format)?;
+                write!(f, " {}", dimension_name(*dimension))
+            },
+            Value::Date(days) => {
+                let (year, month, day) = civil_from_days(*days);
+                write!(f, "{:04}-{:02}-{:02}", year, month, day)
+            },
+            Value::Radix(number, radix) => write!(f, "{}", radix_string(*number, *radix)),
+            Value::Interval(low, high) => {
+                write_number(f, (low + high) / 2.0, format)?;
+                write!(f, "±")?;
+                write_number(f, (high - low) / 2.0, format)
+            },
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item.formatted(format))?;
+                }
+                write!(f, "]")
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.formatted(NumberFormat::default()))
+    }
+}
+
+// Числовое значение операнда
+fn token_value(tok: &Token) -> Result<f32, CalcError> {
+    let invalid_number = || CalcError::new(ErrorKind::InvalidNumber, &format!("некорректное число '{}'", tok.1))
+        .with_span(tok.2.0, &tok.1);
+    if let Some((radix, digits)) = tok.1.split_once('#') {
+        return match radix.parse::<u32>() {
+            Ok(radix) if (2..=36).contains(&radix) => i64::from_str_radix(digits, radix)
+                .map(|value| value as f32)
+                .map_err(|_| invalid_number()),
+            _ => Err(CalcError::new(ErrorKind::InvalidNumber,
+                &format!("основание системы счисления должно быть от 2 до 36, получено {}", radix))
+                .with_span(tok.2.0, &tok.1)),
+        };
+    }
+
+    if let Some((number, factor)) = KNOWNS_ANGLE_SUFFIXES.iter()
+        .find_map(|suffix| tok.1.strip_suffix(suffix.0).map(|number| (number, suffix.1))) {
+        return number.parse::<f32>().map(|number| number * factor).map_err(|_| invalid_number());
+    }
+
+    tok.1.parse::<f32>().map_err(|_| invalid_number())
+}
+
+// Запись целого числа в системе счисления с основанием radix (в виде литерала 16#ff)
+fn radix_string(value: i64, radix: u32) -> String {
+    let mut digits: Vec<char> = Vec::new();
+    let mut rest = value.unsigned_abs();
+    loop {
+        digits.push(core::char::from_digit((rest % radix as u64) as u32, radix).unwrap_or('?'));
+        rest /= radix as u64;
+        if rest == 0 {
+            break;
+        }
+    }
+    let sign = if value < 0 { "-" } else { "" };
+    format!("{}{}#{}", sign, radix, digits.iter().rev().collect::<String>())
+}
+
+// Перевод числа в систему счисления: base(255, 16) = 16#ff
+fn calc_base(function: &Token, value: &Value, radix: &Value) -> Result<Value, CalcError> {
+    let invalid = |message: String| CalcError::new(ErrorKind::InvalidNumber, &message).with_span(function.2.0, &function.1);
+    let value = match value {
+        Value::Number(number) if number.fract() == 0.0 && number.abs() < i64::MAX as f32 => *number as i64,
+        Value::Radix(number, _) => *number,
+        _ => return Err(invalid(format!("функция 'base' переводит только целые числа, получено {}", value))),
+    };
+    let radix = match radix {
+        Value::Number(number) if (2.0..=36.0).contains(number) && number.fract() == 0.0 => *number as u32,
+        _ => return Err(invalid(format!("основание системы счисления должно быть целым от 2 до 36, получено {}", radix))),
+    };
+
+    Ok(Value::Radix(value, radix))
+}
+
+// Ошибка вычисления неизвестного оператора или функции
+fn unsupported_operation_error(tok: &Token) -> CalcError {
+    CalcError::new(ErrorKind::InvalidOutputQueue, &format!("неподдерживаемая операция '{}'", tok.1))
+        .with_span(tok.2.0, &tok.1)
+}
+
+// Величина сдвига: неотрицательное целое число меньше разрядности (64 бита)
+fn shift_count(op: &Token, count: f32) -> Result<u32, CalcError> {
+    if !(0.0..64.0).contains(&count) || count.fract() != 0.0 {
+        return Err(CalcError::new(ErrorKind::InvalidShift,
+            &format!("величина сдвига должна быть неотрицательным целым числом меньше 64, получено {}", count))
+            .with_span(op.2.0, &op.1));
+    }
+
+    Ok(count as u32)
+}
+
+// Сдвиг целого числа; сдвиг влево переполняет, если обратный сдвиг не восстанавливает число
+fn shift_integer(op: &Token, value: i64, count: f32) -> Result<i64, CalcError> {
+    let count = shift_count(op, count)?;
+    let shifted = if op.1 == "<<" {
+        value.checked_shl(count).filter(|shifted| shifted >> count == value)
+    } else {
+        value.checked_shr(count)
+    };
+
+    shifted.ok_or_else(|| CalcError::new(ErrorKind::Overflow, &format!("переполнение в '{}'", op.1)).with_span(op.2.0, &op.1))
+}
+
+// Сдвиг дробного значения: сдвигается только целое число из диапазона i64
+fn shift_float(op: &Token, value: f32, count: f32) -> Result<f32, CalcError> {
+    if value.fract() != 0.0 {
+        return Err(CalcError::new(ErrorKind::InvalidShift, &format!("сдвигать можно только целое число, получено {}", value))
+            .with_span(op.2.0, &op.1));
+    }
+    // i64::MIN как f32 равно -2^63 точно, а 2^63 уже за пределами i64
+    if !(i64::MIN as f32..-(i64::MIN as f32)).contains(&value) {
+        return Err(CalcError::new(ErrorKind::Overflow, &format!("число {} вне диапазона 64-битных целых", value))
+            .with_span(op.2.0, &op.1));
+    }
+
+    Ok(shift_integer(op, value as i64, count)? as f32)
+}
+
+// Отдельное (не списочное) значение: число и его размерность
+type Scalar = (f32, Dimension);
+
+// Значение из отдельного числа с размерностью: безразмерная величина - обычное число
+fn scalar_value((number, dimension): Scalar) -> Value {
+    if dimension == DIMENSIONLESS {
+        Value::Number(number)
+    } else {
+        Value::Quantity(number, dimension)
+    }
+}
+
+// Число с размерностью для значений, не являющихся списками
+fn as_scalar(value: &Value) -> Option<Scalar> {
+    match value {
+        Value::Number(number) => Some((*number, DIMENSIONLESS)),
+        Value::Quantity(number, dimension) => Some((*number, *dimension)),
+        Value::Radix(number, _) => Some((*number as f32, DIMENSIONLESS)),
+        Value::Date(_) | Value::Interval(..) | Value::List(_) | Value::Bool(_) => None,
+    }
+}
+
+// Проверка совместимости единиц измерения (для сложения, вычитания, сравнения)
+fn same_dimension(op: &Token, left: Dimension, right: Dimension) -> Result<Dimension, CalcError> {
+    if left != right {
+        return Err(CalcError::new(ErrorKind::UnitMismatch,
+            &format!("несовместимые единицы измерения для '{}': {} и {}", op.1, dimension_name(left), dimension_name(right)))
+            .with_span(op.2.0, &op.1));
+    }
+
+    Ok(left)
+}
+
+// Проверка, что величина безразмерная (для функций и сдвигов)
+fn no_dimension(op: &Token, dimension: Dimension) -> Result<(), CalcError> {
+    if dimension != DIMENSIONLESS {
+        return Err(CalcError::new(ErrorKind::UnitMismatch,
+            &format!("операция '{}' применима только к безразмерным величинам, получено {}", op.1, dimension_name(dimension)))
+            .with_span(op.2.0, &op.1));
+    }
+
+    Ok(())
+}
+
+// Сложение размерностей (при умножении величин) или вычитание (при делении)
+fn combine_dimensions(left: Dimension, right: Dimension, sign: i8) -> Dimension {
+    let mut dimension = left;
+    for (power, right) in dimension.iter_mut().zip(right) {
+        *power += sign * right;
+    }
+    dimension
+}
+
+// Поэлементное применение операции к значению: для списка - к каждому элементу
+fn map_value<F>(value: Value, f: &F) -> Result<Value, CalcError>
+where F: Fn(Scalar) -> Result<Scalar, CalcError> {
+    match value {
+        Value::Number(number) => Ok(scalar_value(f((number, DIMENSIONLESS))?)),
+        Value::Quantity(number, dimension) => Ok(scalar_value(f((number, dimension))?)),
+        Value::Radix(number, _) => Ok(scalar_value(f((number as f32, DIMENSIONLESS))?)),
+        Value::Date(_) => Err(date_operation_error(None)),
+        Value::Interval(..) => Err(interval_operation_error(None)),
+        Value::Bool(_) => Err(bool_operation_error(None)),
+        Value::List(items) => Ok(Value::List(items.into_iter().map(|item| map_value(item, f)).collect::<Result<_, _>>()?)),
+    }
+}
+
+// Поэлементное применение операции к паре значений: число применяется к каждому элементу
+// списка, а списки должны быть одной длины
+fn zip_values<F>(op: &Token, left: Value, right: Value, f: &F) -> Result<Value, CalcError>
+where F: Fn(Scalar, Scalar) -> Result<Scalar, CalcError> {
+    match (left, right) {
+        (Value::List(left), Value::List(right)) => {
+            if left.len() != right.len() {
+                return Err(CalcError::new(ErrorKind::ShapeMismatch,
+                    &format!("размеры списков не совпадают: {} и {}", left.len(), right.len()))
+                    .with_span(op.2.0, &op.1));
+            }
+            let items = left.into_iter().zip(right)
+                .map(|(x, y)| zip_values(op, x, y, f))
+                .collect::<Result<_, _>>()?;
+            Ok(Value::List(items))
+        },
+        (left, right) => match (as_scalar(&left), as_scalar(&right)) {
+            (Some(x), Some(y)) => Ok(scalar_value(f(x, y)?)),
+            (Some(x), None) => map_value(right, &|y| f(x, y)),
+            (_, Some(y)) => map_value(left, &|x| f(x, y)),
+            // Оба значения - списки, этот случай разобран выше
+            (None, None) => Err(unsupported_operation_error(op)),
+        },
+    }
+}
+
+// Ошибка применения операции к логическому значению
+fn bool_operation_error(op: Option<&Token>) -> CalcError {
+    match op {
+        Some(op) => CalcError::new(ErrorKind::InvalidFunctionCall, &format!("операция '{}' не применима к логическому значению", op.1))
+            .with_span(op.2.0, &op.1),
+        None => CalcError::new(ErrorKind::InvalidFunctionCall, "операция не применима к логическому значению"),
+    }
+}
+
+// Сравнение чисел, величин одной размерности и дат; логические значения сравниваются
+// только на равенство. Списки сравниваются поэлементно, как в арифметических операциях
+fn calc_comparison(op: &Token, arg1: Value, arg2: Value) -> Result<Value, CalcError> {
+    match (arg1, arg2) {
+        (Value::List(left), Value::List(right)) => {
+            if left.len() != right.len() {
+                return Err(CalcError::new(ErrorKind::ShapeMismatch,
+                    &format!("размеры списков не совпадают: {} и {}", left.len(), right.len()))
+                    .with_span(op.2.0, &op.1));
+            }
+            let items = left.into_iter().zip(right).map(|(x, y)| calc_comparison(op, x, y)).collect::<Result<_, _>>()?;
+            Ok(Value::List(items))
+        },
+        (Value::List(items), other) => Ok(Value::List(items.into_iter().map(|item| calc_comparison(op, item, other.clone())).collect::<Result<_, _>>()?)),
+        (other, Value::List(items)) => Ok(Value::List(items.into_iter().map(|item| calc_comparison(op, other.clone(), item)).collect::<Result<_, _>>()?)),
+        (Value::Bool(x), Value::Bool(y)) if op.1 == "==" || op.1 == "!=" => compare(op, x, y),
+        (Value::Date(x), Value::Date(y)) => compare(op, x, y),
+        (left, right) => match (as_scalar(&left), as_scalar(&right)) {
+            (Some((x, dim1)), Some((y, dim2))) => {
+                same_dimension(op, dim1, dim2)?;
+                compare(op, x, y)
+            },
+            _ => Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+                &format!("'{}' сравнивает числа, величины и даты, получено {} и {}", op.1, left, right))
+                .with_span(op.2.0, &op.1)),
+        },
+    }
+}
+
+// Объединение результатов цепочки сравнений (1 < x < 10); списки - поэлементно
+fn calc_and(op: &Token, arg1: Value, arg2: Value) -> Result<Value, CalcError> {
+    match (arg1, arg2) {
+        (Value::Bool(x), Value::Bool(y)) => Ok(Value::Bool(x && y)),
+        (Value::List(left), Value::List(right)) => {
+            if left.len() != right.len() {
+                return Err(CalcError::new(ErrorKind::ShapeMismatch,
+                    &format!("размеры списков не совпадают: {} и {}", left.len(), right.len()))
+                    .with_span(op.2.0, &op.1));
+            }
+            let items = left.into_iter().zip(right).map(|(x, y)| calc_and(op, x, y)).collect::<Result<_, _>>()?;
+            Ok(Value::List(items))
+        },
+        (Value::List(items), other) => Ok(Value::List(items.into_iter().map(|item| calc_and(op, item, other.clone())).collect::<Result<_, _>>()?)),
+        (other, Value::List(items)) => Ok(Value::List(items.into_iter().map(|item| calc_and(op, other.clone(), item)).collect::<Result<_, _>>()?)),
+        _ => Err(bool_operation_error(Some(op))),
+    }
+}
+
+// Результат оператора сравнения
+fn compare<T: PartialOrd>(op: &Token, x: T, y: T) -> Result<Value, CalcError> {
+    Ok(Value::Bool(match &*op.1 {
+        "<" => x < y,
+        ">" => x > y,
+        "<=" => x <= y,
+        ">=" => x >= y,
+        "==" => x == y,
+        "!=" => x != y,
+        _ => return Err(unsupported_operation_error(op)),
+    }))
+}
+
+// Ошибка применения операции к интервалу
+fn interval_operation_error(op: Option<&Token>) -> CalcError {
+    match op {
+        Some(op) => CalcError::new(ErrorKind::InvalidInterval, &format!("операция '{}' не применима к интервалу", op.1))
+            .with_span(op.2.0, &op.1),
+        None => CalcError::new(ErrorKind::InvalidInterval, "операция не применима к интервалу"),
+    }
+}
+
+// Границы интервала; число - интервал нулевой ширины
+fn interval_bounds(op: &Token, value: &Value) -> Result<(f32, f32), CalcError> {
+    match value {
+        Value::Interval(low, high) => Ok((*low, *high)),
+        Value::Number(number) => Ok((*number, *number)),
+        Value::Radix(number, _) => Ok((*number as f32, *number as f32)),
+        _ => Err(interval_operation_error(Some(op))),
+    }
+}
+
+// Наименьший интервал, содержащий все значения
+fn interval_hull(values: &[f32]) -> Value {
+    let low = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let high = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    Value::Interval(low, high)
+}
+
+// Интервальная арифметика: результат - наименьший интервал, содержащий результаты
+// операции для всех значений из интервалов операндов
+fn calc_interval_operator(op: &Token, arg1: &Value, arg2: &Value) -> Result<Value, CalcError> {
+    let (low1, high1) = interval_bounds(op, arg1)?;
+    let (low2, high2) = interval_bounds(op, arg2)?;
+    match &*op.1 {
+        "±" => {
+            if !matches!(arg2, Value::Number(_) | Value::Radix(..)) || low2 < 0.0 {
+                return Err(CalcError::new(ErrorKind::InvalidInterval,
+                    &format!("погрешность должна быть неотрицательным числом, получено {}", arg2)).with_span(op.2.0, &op.1));
+            }
+            Ok(Value::Interval(low1 - low2, high1 + low2))
+        },
+        "+" => Ok(Value::Interval(low1 + low2, high1 + high2)),
+        "-" => Ok(Value::Interval(low1 - high2, high1 - low2)),
+        "*" => Ok(interval_hull(&[low1 * low2, low1 * high2, high1 * low2, high1 * high2])),
+        "/" => {
+            if low2 <= 0.0 && high2 >= 0.0 {
+                return Err(CalcError::new(ErrorKind::InvalidInterval,
+                    &format!("деление на интервал, содержащий ноль: {}", arg2)).with_span(op.2.0, &op.1));
+            }
+            Ok(interval_hull(&[low1 / low2, low1 / high2, high1 / low2, high1 / high2]))
+        },
+        _ => Err(interval_operation_error(Some(op))),
+    }
+}
+
+// Содержит ли интервал точку вида offset + k * period
+fn interval_contains_periodic(low: f32, high: f32, offset: f32, period: f32) -> bool {
+    ((low - offset) / period).ceil() <= ((high - offset) / period).floor()
+}
+
+// Функции от интервалов: монотонные функции применяются к границам, для остальных
+// учитываются экстремумы внутри интервала
+fn calc_interval_function(function: &Token, args: &[Value]) -> Result<Value, CalcError> {
+    use core::f32::consts::{FRAC_PI_2, PI};
+    let bounds = args.iter().map(|arg| interval_bounds(function, arg)).collect::<Result<Vec<_>, _>>()?;
+    match (&*function.1, &bounds[..]) {
+        ("sqrt", [(low, high)]) => Ok(Value::Interval(low.sqrt(), high.sqrt())),
+        ("exp", [(low, high)]) => Ok(Value::Interval(low.exp(), high.exp())),
+        ("ln", [(low, high)]) => Ok(Value::Interval(low.ln(), high.ln())),
+        ("log", [(low, high)]) => Ok(Value::Interval(low.log10(), high.log10())),
+        ("abs", [(low, high)]) => {
+            if *low >= 0.0 {
+                Ok(Value::Interval(*low, *high))
+            } else if *high <= 0.0 {
+                Ok(Value::Interval(-high, -low))
+            } else {
+                Ok(Value::Interval(0.0, (-low).max(*high)))
+            }
+        },
+        ("sin" | "cos", [(low, high)]) => {
+            // cos(x) = sin(x + π/2)
+            let shift = if function.1 == "cos" { FRAC_PI_2 } else { 0.0 };
+            let (low, high) = (low + shift, high + shift);
+            let max = if interval_contains_periodic(low, high, FRAC_PI_2, 2.0 * PI) { 1.0 } else { low.sin().max(high.sin()) };
+            let min = if interval_contains_periodic(low, high, -FRAC_PI_2, 2.0 * PI) { -1.0 } else { low.sin().min(high.sin()) };
+            Ok(Value::Interval(min, max))
+        },
+        ("tan", [(low, high)]) => {
+            if interval_contains_periodic(*low, *high, FRAC_PI_2, PI) {
+                return Err(CalcError::new(ErrorKind::InvalidInterval,
+                    "тангенс не определён внутри интервала").with_span(function.2.0, &function.1));
+            }
+            Ok(Value::Interval(low.tan(), high.tan()))
+        },
+        ("min", [(low1, high1), (low2, high2)]) => Ok(Value::Interval(low1.min(*low2), high1.min(*high2))),
+        ("max", [(low1, high1), (low2, high2)]) => Ok(Value::Interval(low1.max(*low2), high1.max(*high2))),
+        // При положительном основании степень монотонна по каждому аргументу
+        ("pow", [(low1, high1), (low2, high2)]) if *low1 > 0.0 =>
+            Ok(interval_hull(&[low1.powf(*low2), low1.powf(*high2), high1.powf(*low2), high1.powf(*high2)])),
+        _ => Err(interval_operation_error(Some(function))),
+    }
+}
+
+// Вычисление известных бинарных операторов
+fn calc_binary_operator(op: &Token, arg1: Value, arg2: Value) -> Result<Value, CalcError> {
+    if op.1 == "=" {
+        return Err(CalcError::new(ErrorKind::InvalidFunctionCall, "знак '=' допустим только в уравнении solve(a = b, x)")
+            .with_span(op.2.0, &op.1));
+    }
+    if ["<", ">", "<=", ">=", "==", "!="].contains(&&*op.1) {
+        return calc_comparison(op, arg1, arg2);
+    }
+    if op.1 == "AND" {
+        return calc_and(op, arg1, arg2);
+    }
+    if matches!(arg1, Value::Bool(_)) || matches!(arg2, Value::Bool(_)) {
+        return Err(bool_operation_error(Some(op)));
+    }
+    if matches!(arg1, Value::Date(_)) || matches!(arg2, Value::Date(_)) {
+        return calc_date_operator(op, arg1, arg2);
+    }
+    if op.1 == "±" || matches!(arg1, Value::Interval(..)) || matches!(arg2, Value::Interval(..)) {
+        return calc_interval_operator(op, &arg1, &arg2);
+    }
+    zip_values(op, arg1, arg2, &|(arg1, dim1), (arg2, dim2)| {
+        let result = match &*op.1 {
+            "+" => (arg1 + arg2, same_dimension(op, dim1, dim2)?),
+            "-" => (arg1 - arg2, same_dimension(op, dim1, dim2)?),
+            "/" => (arg1 / arg2, combine_dimensions(dim1, dim2, -1)),
+            "*" => (arg1 * arg2, combine_dimensions(dim1, dim2, 1)),
+            // Остаток имеет знак делимого, как и в целочисленном режиме
+            "%" if arg2 == 0.0 => return Err(CalcError::new(ErrorKind::DivisionByZero, "остаток от деления на ноль")
+                .with_span(op.2.0, &op.1)),
+            "%" => (arg1 % arg2, same_dimension(op, dim1, dim2)?),
+            "<<" | ">>" => {
+                no_dimension(op, dim1)?;
+                no_dimension(op, dim2)?;
+                (shift_float(op, arg1, arg2)?, DIMENSIONLESS)
+            },
+            _ => return Err(unsupported_operation_error(op)),
+        };
+        Ok(result)
+    })
+}
+
+// Вычисление известных унарных операторов
+fn calc_unary_operator(op: &Token, arg: Value) -> Result<Value, CalcError> {
+    if let Value::Interval(low, high) = arg {
+        return match &*op.1 {
+            "POS" => Ok(Value::Interval(low, high)),
+            "NEG" => Ok(Value::Interval(-high, -low)),
+            _ => Err(unsupported_operation_error(op)),
+        };
+    }
+    map_value(arg, &|(arg, dimension)| {
+        match &*op.1 {
+            "POS" => Ok((arg, dimension)),
+            "NEG" => Ok((-arg, dimension)),
+            _ => Err(unsupported_operation_error(op)),
+        }
+    })
+}
+
+// Матрица - список строк одинаковой длины, каждая строка - непустой список чисел
+type Matrix = Vec<Vec<f32>>;
+
+// Проверка, что значение является матрицей, и её извлечение
+fn to_matrix(function: &Token, value: Value) -> Result<Matrix, CalcError> {
+    let not_matrix = || CalcError::new(ErrorKind::ShapeMismatch,
+        &format!("функция '{}' ожидает матрицу (список строк одинаковой длины)", function.1))
+        .with_span(function.2.0, &function.1);
+
+    let rows = match value {
+        Value::List(rows) if !rows.is_empty() => rows,
+        _ => return Err(not_matrix()),
+    };
+    let mut matrix: Matrix = Vec::with_capacity(rows.len());
+    for row in rows {
+        let row = match row {
+            Value::List(items) if !items.is_empty() => items,
+            _ => return Err(not_matrix()),
+        };
+        let row = row.into_iter()
+            .map(|item| match item {
+                Value::Number(number) => Ok(number),
+                _ => Err(not_matrix()),
+            })
+            .collect::<Result<Vec<f32>, CalcError>>()?;
+        if matrix.first().is_some_and(|first| first.len() != row.len()) {
+            return Err(not_matrix());
+        }
+        matrix.push(row);
+    }
+
+    Ok(matrix)
+}
+
+// Обратное преобразование матрицы в значение
+fn from_matrix(matrix: Matrix) -> Value {
+    Value::List(matrix.into_iter()
+        .map(|row| Value::List(row.into_iter().map(Value::Number).collect()))
+        .collect())
+}
+
+// Транспонирование матрицы
+fn transpose(matrix: &Matrix) -> Matrix {
+    (0..matrix[0].len())
+        .map(|column| matrix.iter().map(|row| row[column]).collect())
+        .collect()
+}
+
+// Определитель квадратной матрицы методом Гаусса с выбором главного элемента
+fn determinant(function: &Token, mut matrix: Matrix) -> Result<f32, CalcError> {
+    let size = matrix.len();
+    if matrix[0].len() != size {
+        return Err(CalcError::new(ErrorKind::ShapeMismatch,
+            &format!("определитель вычисляется только для квадратной матрицы, получена {}x{}", size, matrix[0].len()))
+            .with_span(function.2.0, &function.1));
+    }
+
+    let mut det = 1.0;
+    for column in 0..size {
+        let pivot = (column..size)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))
+            .unwrap_or(column);
+        if matrix[pivot][column] == 0.0 {
+            return Ok(0.0);
+        }
+        if pivot != column {
+            matrix.swap(pivot, column);
+            det = -det;
+        }
+        det *= matrix[column][column];
+        let pivot_row = matrix[column].clone();
+        for row in matrix.iter_mut().skip(column + 1) {
+            let factor = row[column] / pivot_row[column];
+            for (item, pivot_item) in row.iter_mut().zip(&pivot_row).skip(column) {
+                *item -= factor * pivot_item;
+            }
+        }
+    }
+
+    Ok(det)
+}
+
+// Произведение матриц: число столбцов левой матрицы равно числу строк правой
+fn multiply(function: &Token, left: &Matrix, right: &Matrix) -> Result<Matrix, CalcError> {
+    if left[0].len() != right.len() {
+        return Err(CalcError::new(ErrorKind::ShapeMismatch,
+            &format!("размеры матриц не согласованы для умножения: {}x{} и {}x{}",
+                left.len(), left[0].len(), right.len(), right[0].len()))
+            .with_span(function.2.0, &function.1));
+    }
+
+    Ok(left.iter()
+        .map(|row| (0..right[0].len())
+            .map(|column| row.iter().zip(right).map(|(x, right_row)| x * right_row[column]).sum())
+            .collect())
+        .collect())
+}
+
+// Все числа значения (с раскрытием вложенных списков); число считается списком из одного элемента
+fn flatten(function: &Token, value: Value, numbers: &mut Vec<f32>) -> Result<(), CalcError> {
+    match value {
+        Value::Number(number) => numbers.push(number),
+        Value::Quantity(_, dimension) => no_dimension(function, dimension)?,
+        Value::Radix(number, _) => numbers.push(number as f32),
+        Value::Date(_) => return Err(date_operation_error(Some(function))),
+        Value::Interval(..) => return Err(interval_operation_error(Some(function))),
+        Value::Bool(_) => return Err(bool_operation_error(Some(function))),
+        Value::List(items) => {
+            for item in items {
+                flatten(function, item, numbers)?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+// Статистические функции над всеми числами списка. Дисперсия и стандартное
+// отклонение вычисляются по генеральной совокупности (деление на n)
+fn aggregate(function: &Token, value: Value) -> Result<f32, CalcError> {
+    let mut numbers: Vec<f32> = Vec::new();
+    flatten(function, value, &mut numbers)?;
+    let count = numbers.len() as f32;
+    match &*function.1 {
+        "count" => return Ok(count),
+        "sum" => return Ok(numbers.iter().fold(0.0, |sum, x| sum + x)),
+        _ => {},
+    }
+
+    if numbers.is_empty() {
+        return Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+            &format!("функция '{}' не определена для пустого списка", function.1))
+            .with_span(function.2.0, &function.1));
+    }
+    let mean = numbers.iter().sum::<f32>() / count;
+    let variance = numbers.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / count;
+    match &*function.1 {
+        "mean" => Ok(mean),
+        "median" => {
+            numbers.sort_by(f32::total_cmp);
+            let middle = numbers.len() / 2;
+            if numbers.len().is_multiple_of(2) {
+                Ok((numbers[middle - 1] + numbers[middle]) / 2.0)
+            } else {
+                Ok(numbers[middle])
+            }
+        },
+        "variance" => Ok(variance),
+        "stddev" => Ok(variance.sqrt()),
+        _ => Err(unsupported_operation_error(function)),
+    }
+}
+
+// Вычисление известных функций (к спискам функции применяются поэлементно,
+// кроме матричных и статистических функций)
+fn calc_function(function: &Token, args: &mut Vec<Value>) -> Result<Value, CalcError> {
+    let name = &*function.1;
+    match (name, args.len()) {
+        ("now", 0) => return today().map(Value::Date).ok_or_else(|| CalcError::new(ErrorKind::InvalidFunctionCall,
+            "текущая дата недоступна без стандартной библиотеки").with_span(function.2.0, &function.1)),
+        ("base", 2) => return calc_base(function, &args[0], &args[1]),
+        _ if args.iter().any(|arg| matches!(arg, Value::Interval(..))) => return calc_interval_function(function, args),
+        ("transpose", 1) => return Ok(from_matrix(transpose(&to_matrix(function, args.remove(0))?))),
+        ("det", 1) => return Ok(Value::Number(determinant(function, to_matrix(function, args.remove(0))?)?)),
+        ("sum" | "mean" | "median" | "variance" | "stddev" | "count", 1) =>
+            return Ok(Value::Number(aggregate(function, args.remove(0))?)),
+        ("matmul", 2) => {
+            let right = to_matrix(function, args.remove(1))?;
+            let left = to_matrix(function, args.remove(0))?;
+            return Ok(from_matrix(multiply(function, &left, &right)?));
+        },
+        _ => {},
+    }
+
+    match args.len() {
+        1 => map_value(args.remove(0), &|(x, dimension)| {
+            // Модуль сохраняет единицы измерения, остальные функции определены для чисел
+            if name == "abs" {
+                return Ok((x.abs(), dimension));
+            }
+            no_dimension(function, dimension)?;
+            let result = match name {
+                "sqrt" => x.sqrt(),
+                "exp" => x.exp(),
+                "ln" => x.ln(),
+                "log" => x.log10(),
+                "sin" => x.sin(),
+                "cos" => x.cos(),
+                "tan" => x.tan(),
+                _ => return Err(unsupported_operation_error(function)),
+            };
+            Ok((result, DIMENSIONLESS))
+        }),
+        2 => {
+            let y = args.remove(1);
+            zip_values(function, args.remove(0), y, &|(x, dim1), (y, dim2)| Ok(match name {
+                "min" => (x.min(y), same_dimension(function, dim1, dim2)?),
+                "max" => (x.max(y), same_dimension(function, dim1, dim2)?),
+                "pow" => {
+                    no_dimension(function, dim1)?;
+                    no_dimension(function, dim2)?;
+                    (x.powf(y), DIMENSIONLESS)
+                },
+                _ => return Err(unsupported_operation_error(function)),
+            }))
+        },
+        _ => Err(unsupported_operation_error(function)),
+    }
+}
+
+// Снятие со стека последних `count` значений в порядке их записи; values - буфер,
+// прежнее содержимое которого отбрасывается
+fn pop_values(calculate_stack: &mut Stack<Value>, count: usize, values: &mut Vec<Value>) -> Result<(), CalcError> {
+    if calculate_stack.len() < count {
+        return Err(invalid_output_queue());
+    }
+    values.clear();
+    while values.len() < count {
+        values.extend(calculate_stack.pop());
+    }
+    values.reverse();
+    Ok(())
+}
+
+// Значения переменных и функций, неизвестных калькулятору, от использующего его приложения
+// (например, ячеек электронной таблицы); None - имя неизвестно и приложению
+pub trait Resolver {
+    fn variable(&self, name: &str) -> Option<Value>;
+    fn call(&self, name: &str, args: &[Value]) -> Option<Value>;
+
+    // Функция пользователя (см. Definition); её вызовы выполняет сам калькулятор
+    fn definition(&self, _name: &str) -> Option<&Definition> {
+        None
+    }
+
+    // То же, что variable, для переменных, значение которых само вычисляется и может не
+    // вычислиться (псевдонимы REPL): ошибка вычисления вместо "неизвестная переменная"
+    fn try_variable(&self, name: &str) -> Result<Option<Value>, CalcError> {
+        Ok(self.variable(name))
+    }
+
+    // То же, что call, с ошибкой вызова известной приложению функции (out[N] без результата N)
+    fn try_call(&self, name: &str, args: &[Value]) -> Result<Option<Value>, CalcError> {
+        Ok(self.call(name, args))
+    }
+}
+
+// Resolver без переменных и функций
+pub struct NoResolver;
+
+impl Resolver for NoResolver {
+    fn variable(&self, _name: &str) -> Option<Value> {
+        None
+    }
+
+    fn call(&self, _name: &str, _args: &[Value]) -> Option<Value> {
+        None
+    }
+}
+
+// Resolver со значением одной переменной поверх другого: выражение от переменной
+// вычисляется в окружении приложения (псевдонимы REPL, ячейки электронной таблицы)
+pub struct Binding<'a> {
+    pub name: &'a str,
+    pub value: Value,
+    pub outer: &'a dyn Resolver,
+}
+
+impl Resolver for Binding<'_> {
+    fn variable(&self, name: &str) -> Option<Value> {
+        match name == self.name {
+            true => Some(self.value.clone()),
+            false => self.outer.variable(name),
+        }
+    }
+
+    fn call(&self, name: &str, args: &[Value]) -> Option<Value> {
+        self.outer.call(name, args)
+    }
+
+    fn definition(&self, name: &str) -> Option<&Definition> {
+        self.outer.definition(name)
+    }
+
+    fn try_variable(&self, name: &str) -> Result<Option<Value>, CalcError> {
+        match name == self.name {
+            true => Ok(Some(self.value.clone())),
+            false => self.outer.try_variable(name),
+        }
+    }
+
+    fn try_call(&self, name: &str, args: &[Value]) -> Result<Option<Value>, CalcError> {
+        self.outer.try_call(name, args)
+    }
+}
+
+// Значения аргументов вызова функции пользователя поверх другого Resolver: для выражений
+// функций со связанной переменной в теле функции пользователя
+struct Arguments<'a> {
+    names: &'a [String],
+    values: &'a [Value],
+    outer: &'a dyn Resolver,
+}
+
+impl Resolver for Arguments<'_> {
+    fn variable(&self, name: &str) -> Option<Value> {
+        match self.names.iter().position(|param| param == name) {
+            Some(index) => self.values.get(index).cloned(),
+            None => self.outer.variable(name),
+        }
+    }
+
+    fn call(&self, name: &str, args: &[Value]) -> Option<Value> {
+        self.outer.call(name, args)
+    }
+
+    fn definition(&self, name: &str) -> Option<&Definition> {
+        self.outer.definition(name)
+    }
+
+    fn try_variable(&self, name: &str) -> Result<Option<Value>, CalcError> {
+        match self.names.iter().position(|param| param == name) {
+            Some(index) => Ok(self.values.get(index).cloned()),
+            None => self.outer.try_variable(name),
+        }
+    }
+
+    fn try_call(&self, name: &str, args: &[Value]) -> Result<Option<Value>, CalcError> {
+        self.outer.try_call(name, args)
+    }
+}
+
+// Выражение функции со связанной переменной. Его вычисления расходуют шаги и время
+// вычисления, в котором вызвана функция (см. Budget)
+struct BoundExpression<'a> {
+    ops: &'a [Op],
+    variable: &'a str,
+    limits: &'a Limits,
+    resolver: &'a dyn Resolver,
+    slots: &'a [Value],
+    budget: &'a Budget,
+}
+
+impl BoundExpression<'_> {
+    fn eval(&self, value: Value) -> Result<Value, CalcError> {
+        let binding = Binding { name: self.variable, value, outer: self.resolver };
+        let mut calculate_stack: Stack<Value> = Stack::with_capacity(self.ops.len());
+        run_ops(self.ops, self.limits, &binding, self.slots, self.budget, &mut calculate_stack, &mut Vec::new())
+    }
+
+    // Значение выражения при числовом значении переменной для численных методов
+    fn number(&self, function: &Token, x: f64) -> Result<f64, CalcError> {
+        match self.eval(Value::Number(x as f32))? {
+            Value::Number(y) => Ok(f64::from(y)),
+            Value::Radix(y, _) => Ok(y as f64),
+            value => Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+                &format!("выражение в '{}' должно давать число, получено {}", function.1, value)).with_span(function.2.0, &function.1)),
+        }
+    }
+}
+
+// Числовой аргумент функции со связанной переменной
+fn bound_argument(function: &Token, arg: &Value) -> Result<f32, CalcError> {
+    let (number, dimension) = as_scalar(arg).ok_or_else(|| CalcError::new(ErrorKind::InvalidFunctionCall,
+        &format!("аргументы '{}' после имени переменной - числа, получено {}", function.1, arg)).with_span(function.2.0, &function.1))?;
+    no_dimension(function, dimension)?;
+    Ok(number)
+}
+
+// Вычисление функций со связанной переменной; args - аргументы после имени переменной
+fn calc_bound_function(function: &Token, expression: &BoundExpression, args: &[Value]) -> Result<Value, CalcError> {
+    let numbers = args.iter().map(|arg| bound_argument(function, arg)).collect::<Result<Vec<f32>, CalcError>>()?;
+    match (&*function.1, &numbers[..]) {
+        ("table", [start, stop, step]) => table(function, expression, *start, *stop, *step),
+        ("integrate", [a, b]) => integrate(function, expression, *a, *b),
+        ("root", [guess]) => root(function, expression, *guess),
+        ("solve", []) => solve(function, expression),
+        ("SUM", [start, stop]) => fold(function, expression, *start, *stop, "+", 0.0),
+        ("prod", [start, stop]) => fold(function, expression, *start, *stop, "*", 1.0),
+        _ => Err(unsupported_operation_error(function)),
+    }
+}
+
+// Сумма или произведение (оператор op) значений выражения при целых значениях переменной
+// от start до stop; при start > stop - нейтральный элемент empty
+fn fold(function: &Token, expression: &BoundExpression, start: f32, stop: f32, op: &'static str, empty: f32) -> Result<Value, CalcError> {
+    if start.fract() != 0.0 || stop.fract() != 0.0 {
+        let name = written_name(&function.1);
+        return Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+            &format!("границы '{}' - целые числа, получено {} и {}", name, start, stop)).with_span(function.2.0, name));
+    }
+    let op: Token = (TokenType::BinaryOperator, Cow::Borrowed(op), function.2);
+    // Первое значение не складывается с нейтральным элементом: сумма величин сохраняет единицу
+    let mut result: Option<Value> = None;
+    let mut index = start;
+    while index <= stop {
+        let value = expression.eval(Value::Number(index))?;
+        result = Some(match result {
+            Some(result) => calc_binary_operator(&op, result, value)?,
+            None => value,
+        });
+        index += 1.0;
+    }
+    Ok(result.unwrap_or(Value::Number(empty)))
+}
+
+// Таблица [[x, f(x)], ...] для x от start до stop с шагом step; stop входит в таблицу,
+// если на него попадает шаг
+fn table(function: &Token, expression: &BoundExpression, start: f32, stop: f32, step: f32) -> Result<Value, CalcError> {
+    let steps = (stop - start) / step;
+    if step == 0.0 || steps.is_nan() || steps < 0.0 {
+        return Err(CalcError::new(ErrorKind::InvalidFunctionCall, &format!("шагом {} нельзя пройти от {} до {}", step, start, stop))
+            .with_span(function.2.0, &function.1));
+    }
+    // Допуск на погрешность деления: table(x, x, 0, 0.3, 0.1) включает 0.3
+    let count = (steps + 1e-3).floor() as usize + 1;
+    let mut rows: Vec<Value> = Vec::new();
+    for index in 0..count {
+        let x = start + index as f32 * step;
+        rows.push(Value::List(vec![Value::Number(x), expression.eval(Value::Number(x))?]));
+    }
+    Ok(Value::List(rows))
+}
+
+// Глубина деления отрезка в integrate. Отрезок делится хотя бы на 2^INTEGRATE_MIN_DEPTH
+// частей: по трём точкам sin(x) на [0, 2 pi] неотличим от нуля. Оценка погрешности может
+// так и не опуститься до допустимой - тогда деление останавливается на наибольшей глубине
+const INTEGRATE_MIN_DEPTH: usize = 4;
+const INTEGRATE_MAX_DEPTH: usize = 10;
+
+// Точка графика выражения: значение переменной и значение выражения
+type Point = (f64, f64);
+
+// Интеграл выражения по переменной от a до b адаптивным методом Симпсона: отрезок делится
+// пополам, пока оценка погрешности больше limits.tolerance от интеграла модуля выражения.
+// Погрешность относительная, чтобы малые интегралы (x * x от 0 до 0.1) не терялись
+fn integrate(function: &Token, expression: &BoundExpression, a: f32, b: f32) -> Result<Value, CalcError> {
+    let point = |x: f64| match expression.number(function, x)? {
+        y if y.is_finite() => Ok((x, y)),
+        _ => Err(CalcError::new(ErrorKind::NoResult, &format!("выражение в '{}' не определено при {} = {}", function.1, expression.variable, x))
+            .with_span(function.2.0, &function.1)),
+    };
+    let (a, b) = (f64::from(a), f64::from(b));
+    let (left, middle, right) = (point(a)?, point((a + b) / 2.0)?, point(b)?);
+    let whole = simpson(left, middle, right);
+    let magnitude = simpson((left.0, left.1.abs()), (middle.0, middle.1.abs()), (right.0, right.1.abs())).abs();
+    let tolerance = f64::from(expression.limits.tolerance) * magnitude;
+    let result = adaptive_simpson(&point, (left, middle, right), whole, tolerance, 0)?;
+    Ok(Value::Number(result as f32))
+}
+
+// Формула Симпсона по концам и середине отрезка
+fn simpson(left: Point, middle: Point, right: Point) -> f64 {
+    (right.0 - left.0) / 6.0 * (left.1 + 4.0 * middle.1 + right.1)
+}
+
+// Интеграл по отрезку (концы и середина) на глубине деления `depth`, для которого формула
+// Симпсона дала `whole`: сумма по половинам отличается от `whole` в 15 раз больше, чем
+// от точного значения
+fn adaptive_simpson<F>(point: &F, (left, middle, right): (Point, Point, Point), whole: f64, tolerance: f64, depth: usize) -> Result<f64, CalcError>
+where F: Fn(f64) -> Result<Point, CalcError> {
+    let left_middle = point((left.0 + middle.0) / 2.0)?;
+    let right_middle = point((middle.0 + right.0) / 2.0)?;
+    let left_half = simpson(left, left_middle, middle);
+    let right_half = simpson(middle, right_middle, right);
+    let delta = left_half + right_half - whole;
+    if depth >= INTEGRATE_MAX_DEPTH || (depth >= INTEGRATE_MIN_DEPTH && delta.abs() <= 15.0 * tolerance) {
+        return Ok(left_half + right_half + delta / 15.0);
+    }
+    Ok(adaptive_simpson(point, (left, left_middle, middle), left_half, tolerance / 2.0, depth + 1)?
+        + adaptive_simpson(point, (middle, right_middle, right), right_half, tolerance / 2.0, depth + 1)?)
+}
+
+// Наибольшее число шагов каждого этапа поиска корня в root
+const ROOT_ITERATIONS: usize = 60;
+// Наименьший модуль приближения, относительно которого оценивается шаг в root: корень
+// ближе к нулю (x * x от 1) находится с абсолютной погрешностью limits.tolerance * ROOT_SCALE
+const ROOT_SCALE: f64 = f32::EPSILON as f64;
+
+// Корень выражения по переменной рядом с начальным приближением. Сначала - метод Ньютона
+// с производной по центральной разности; если он не сходится, то от приближения в обе
+// стороны ищется отрезок со сменой знака, который делится пополам. Корень найден, когда
+// значение выражения равно нулю или шаг меньше limits.tolerance от приближения
+fn root(function: &Token, expression: &BoundExpression, guess: f32) -> Result<Value, CalcError> {
+    let f = |x: f64| expression.number(function, x);
+    let tolerance = f64::from(expression.limits.tolerance);
+    let close = |x: f64, next: f64| (next - x).abs() < tolerance * x.abs().max(next.abs()).max(ROOT_SCALE);
+    let mut x = f64::from(guess);
+    for _ in 0..ROOT_ITERATIONS {
+        let y = f(x)?;
+        if y == 0.0 {
+            return Ok(Value::Number(x as f32));
+        }
+        // Шаг производной пропорционален приближению, но не слишком мал: значения
+        // выражения вычисляются в f32
+        let h = 1e-3 * x.abs().max(1e-3);
+        let next = x - y * 2.0 * h / (f(x + h)? - f(x - h)?);
+        if !next.is_finite() {
+            break;
+        }
+        if close(x, next) {
+            return Ok(Value::Number(next as f32));
+        }
+        x = next;
+    }
+
+    // Отрезок со сменой знака между приближением и точкой справа или слева от него;
+    // расстояние до точки удваивается
+    let guess = f64::from(guess);
+    let y_guess = f(guess)?;
+    let mut width = 0.1 * guess.abs().max(1.0);
+    let mut bracket = None;
+    for _ in 0..ROOT_ITERATIONS {
+        for end in [guess + width, guess - width] {
+            let y_end = f(end)?;
+            if y_guess.is_finite() && y_end.is_finite() && y_guess.signum() != y_end.signum() {
+                bracket = Some(((guess, y_guess), (end, y_end)));
+                break;
+            }
+        }
+        if bracket.is_some() {
+            break;
+        }
+        width *= 2.0;
+    }
+    let ((mut low, mut y_low), (mut high, y_high)) = match bracket {
+        Some(bracket) => bracket,
+        None => return Err(no_convergence_error(function, expression, x)),
+    };
+    // У разрыва (1 / x в нуле) знак тоже меняется, но значение не убывает к концу деления
+    let largest = y_low.abs().max(y_high.abs());
+    for _ in 0..ROOT_ITERATIONS {
+        let middle = (low + high) / 2.0;
+        let y = f(middle)?;
+        if !y.is_finite() || (close(low, middle) && y.abs() > largest) {
+            return Err(no_convergence_error(function, expression, middle));
+        }
+        if y == 0.0 || close(low, middle) {
+            return Ok(Value::Number(middle as f32));
+        }
+        if y.signum() == y_low.signum() {
+            low = middle;
+            y_low = y;
+        } else {
+            high = middle;
+        }
+    }
+    Err(no_convergence_error(function, expression, (low + high) / 2.0))
+}
+
+// Точки, по значениям в которых solve находит коэффициенты уравнения, и точки проверки.
+// Точки далеко от нуля: ошибка вычисления в f32 мало меняет коэффициенты
+const SOLVE_STEP: f64 = 10.0;
+const SOLVE_CHECKS: [f64; 3] = [-20.0, 5.0, 20.0];
+// Относительная погрешность значений выражения в solve: значения вычисляются в f32
+const SOLVE_PRECISION: f64 = 1e-5;
+
+// Решение уравнения (левая часть минус правая равна нулю), если его левая часть - многочлен
+// степени не выше второй: коэффициенты a * x^2 + b * x + c находятся по значениям в точках
+// -SOLVE_STEP, 0 и SOLVE_STEP и проверяются в точках SOLVE_CHECKS. Два корня квадратного
+// уравнения - список по возрастанию
+fn solve(function: &Token, expression: &BoundExpression) -> Result<Value, CalcError> {
+    let f = |x: f64| expression.number(function, x);
+    let (left, zero, right) = (f(-SOLVE_STEP)?, f(0.0)?, f(SOLVE_STEP)?);
+    let a = ((left + right) / 2.0 - zero) / (SOLVE_STEP * SOLVE_STEP);
+    let b = (right - left) / (2.0 * SOLVE_STEP);
+    let c = zero;
+    let polynomial = |x: f64| a * x * x + b * x + c;
+    // Величина значений выражения, относительно которой оцениваются погрешности
+    let scale = left.abs().max(zero.abs()).max(right.abs());
+    let mut solvable = a.is_finite() && b.is_finite() && c.is_finite();
+    for x in SOLVE_CHECKS {
+        let y = f(x)?;
+        solvable = solvable && y.is_finite() && (y - polynomial(x)).abs() <= 10.0 * SOLVE_PRECISION * y.abs().max(scale);
+    }
+    if !solvable {
+        return Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+            &format!("'{}' решает только линейные и квадратные уравнения относительно {}", function.1, expression.variable))
+            .with_span(function.2.0, &function.1));
+    }
+    let no_solution = |message: &str| CalcError::new(ErrorKind::NoResult, message).with_span(function.2.0, &function.1);
+    // Члены, малые на отрезке [-SOLVE_STEP, SOLVE_STEP] по сравнению со значениями
+    // выражения, - погрешность вычисления
+    let negligible = |term: f64| term.abs() <= SOLVE_PRECISION * scale;
+    if negligible(a * SOLVE_STEP * SOLVE_STEP) {
+        return match (negligible(b * SOLVE_STEP), negligible(c)) {
+            (false, _) => Ok(Value::Number((-c / b) as f32)),
+            (true, true) => Err(no_solution("уравнение верно при любом значении переменной")),
+            (true, false) => Err(no_solution("уравнение не имеет решений")),
+        };
+    }
+    // Дискриминант кратного корня из-за погрешности может оказаться чуть больше или меньше нуля
+    let discriminant = match b * b - 4.0 * a * c {
+        discriminant if discriminant.abs() <= SOLVE_PRECISION * (b * b).max((4.0 * a * c).abs()) => 0.0,
+        discriminant => discriminant,
+    };
+    if discriminant < 0.0 {
+        return Err(no_solution("уравнение не имеет действительных решений"));
+    }
+    let (first, second) = ((-b - discriminant.sqrt()) / (2.0 * a), (-b + discriminant.sqrt()) / (2.0 * a));
+    let (low, high) = (first.min(second) as f32, first.max(second) as f32);
+    match low == high {
+        true => Ok(Value::Number(low)),
+        false => Ok(Value::List(vec![Value::Number(low), Value::Number(high)])),
+    }
+}
+
+// Ошибка численного метода с последним найденным приближением
+fn no_convergence_error(function: &Token, expression: &BoundExpression, last: f64) -> CalcError {
+    CalcError::new(ErrorKind::NoConvergence, &format!("'{}' не сошёлся, последнее приближение {} = {}",
+        function.1, expression.variable, Value::Number(last as f32)))
+        .with_span(function.2.0, &function.1)
+        .with_note("попробуйте другое начальное приближение")
+}
+
+// Вычисление выражения, записанного в ОПН
+pub fn calculate(output: Queue<Token>, limits: &Limits) -> Result<String, CalcError> {
+    evaluate(output, limits).map(|value| value.to_string())
+}
+
+// Вычисление выражения, записанного в ОПН, с результатом в виде значения
+pub fn evaluate(output: Queue<Token>, limits: &Limits) -> Result<Value, CalcError> {
+    evaluate_with(output, limits, &NoResolver)
+}
+
+// Вычисление выражения, записанного в ОПН; неизвестные имена разрешает resolver
+pub fn evaluate_with(output: Queue<Token>, limits: &Limits, resolver: &dyn Resolver) -> Result<Value, CalcError> {
+    Program::from_rpn(output)?.run(limits, resolver)
+}
+
+// Деление в целочисленном режиме
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IntegerDivision {
+    // Ошибка, если делимое не делится нацело: 7 / 2
+    #[default]
+    Exact,
+    // Отбрасывание дробной части, как в Rust и C: 7 / 2 = 3, -7 / 2 = -3
+    Truncate,
+}
+
+// Вычисление в целочисленном режиме (калькулятор программиста): все значения - i64,
+// дробные числа запрещены, а переполнение - ошибка. Доступны + - * / % << >>, унарные
+// + и -, |x| и функции abs, min, max и pow
+pub fn evaluate_integer(output: Queue<Token>, limits: &Limits, division: IntegerDivision) -> Result<i64, CalcError> {
+    let mut calculate_stack: Stack<i64> = Stack::with_capacity(output.len());
+    #[cfg(feature = "std")]
+    let started = limits.timeout.map(|timeout| (Instant::now(), timeout));
+    for (steps, tok) in output.into_iter().enumerate() {
+        if steps >= limits.max_steps {
+            return Err(CalcError::new(ErrorKind::BudgetExceeded,
+                &format!("превышен лимит вычисления: больше {} операций", limits.max_steps)));
+        }
+        check_cancelled(limits)?;
+        #[cfg(feature = "std")]
+        if let Some((started, timeout)) = started {
+            if started.elapsed() > timeout {
+                return Err(CalcError::new(ErrorKind::Timeout,
+                    &format!("превышено время вычисления: больше {} мс", timeout.as_millis())));
+            }
+        }
+
+        let overflow = || CalcError::new(ErrorKind::Overflow, &format!("переполнение в '{}'", tok.1)).with_span(tok.2.0, &tok.1);
+        let value = match tok.0 {
+            TokenType::NumberInt => integer_value(&tok)?,
+            TokenType::NumberFloat => return Err(CalcError::new(ErrorKind::InvalidNumber,
+                &format!("дробное число '{}' в целочисленном режиме", tok.1)).with_span(tok.2.0, &tok.1)),
+            TokenType::UnaryOperator => {
+                let arg = calculate_stack.pop().ok_or_else(invalid_output_queue)?;
+                match &*tok.1 {
+                    "POS" => arg,
+                    "NEG" => arg.checked_neg().ok_or_else(overflow)?,
+                    _ => return Err(integer_mode_error(&tok)),
+                }
+            },
+            TokenType::BinaryOperator => {
+                let arg2 = calculate_stack.pop().ok_or_else(invalid_output_queue)?;
+                let arg1 = calculate_stack.pop().ok_or_else(invalid_output_queue)?;
+                match &*tok.1 {
+                    "+" => arg1.checked_add(arg2).ok_or_else(overflow)?,
+                    "-" => arg1.checked_sub(arg2).ok_or_else(overflow)?,
+                    "*" => arg1.checked_mul(arg2).ok_or_else(overflow)?,
+                    "/" if arg2 == 0 => return Err(CalcError::new(ErrorKind::DivisionByZero, "деление на ноль")
+                        .with_span(tok.2.0, &tok.1)),
+                    "/" if division == IntegerDivision::Exact && arg1.checked_rem(arg2).ok_or_else(overflow)? != 0 =>
+                        return Err(CalcError::new(ErrorKind::InexactDivision, &format!("{} не делится нацело на {}", arg1, arg2))
+                            .with_span(tok.2.0, &tok.1)),
+                    "/" => arg1.checked_div(arg2).ok_or_else(overflow)?,
+                    "%" if arg2 == 0 => return Err(CalcError::new(ErrorKind::DivisionByZero, "остаток от деления на ноль")
+                        .with_span(tok.2.0, &tok.1)),
+                    "%" => arg1.checked_rem(arg2).ok_or_else(overflow)?,
+                    "<<" | ">>" => shift_integer(&tok, arg1, arg2 as f32)?,
+                    _ => return Err(integer_mode_error(&tok)),
+                }
+            },
+            TokenType::Function => {
+                let arity = get_function_arity(&tok.1).filter(|_| ["abs", "min", "max", "pow"].contains(&&*tok.1))
+                    .ok_or_else(|| integer_mode_error(&tok))?;
+                let mut args: Vec<i64> = Vec::with_capacity(arity);
+                for _ in 0..arity {
+                    args.push(calculate_stack.pop().ok_or_else(invalid_output_queue)?);
+                }
+                args.reverse();
+                match (&*tok.1, &args[..]) {
+                    ("abs", [arg]) => arg.checked_abs().ok_or_else(overflow)?,
+                    ("min", [arg1, arg2]) => *arg1.min(arg2),
+                    ("max", [arg1, arg2]) => *arg1.max(arg2),
+                    ("pow", [base, exponent]) => match u32::try_from(*exponent) {
+                        Ok(exponent) => base.checked_pow(exponent).ok_or_else(overflow)?,
+                        Err(_) => return Err(CalcError::new(ErrorKind::InvalidNumber,
+                            &format!("отрицательная степень {} в целочисленном режиме", exponent)).with_span(tok.2.0, &tok.1)),
+                    },
+                    _ => return Err(invalid_output_queue()),
+                }
+            },
+            _ => return Err(integer_mode_error(&tok)),
+        };
+        calculate_stack.push(value);
+    }
+
+    match (calculate_stack.pop(), calculate_stack.is_empty()) {
+        (Some(result), true) => Ok(result),
+        _ => Err(CalcError::new(ErrorKind::NoResult, "не удалось вычислить выражение")),
+    }
+}
+
+// Целое число из десятичной записи или записи с основанием (16#ff)
+fn integer_value(tok: &Token) -> Result<i64, CalcError> {
+    let parsed = match tok.1.split_once('#') {
+        Some((radix, digits)) => match radix.parse::<u32>() {
+            Ok(radix) if (2..=36).contains(&radix) => i64::from_str_radix(digits, radix),
+            _ => return token_value(tok).map(|value| value as i64),
+        },
+        None => tok.1.parse::<i64>(),
+    };
+    parsed.map_err(|_| CalcError::new(ErrorKind::Overflow, &format!("число '{}' не помещается в i64", tok.1))
+        .with_span(tok.2.0, &tok.1))
+}
+
+// Ошибка для значения или операции, которых нет в целочисленном режиме
+fn integer_mode_error(tok: &Token) -> CalcError {
+    CalcError::new(ErrorKind::Forbidden, &format!("'{}' недоступно в целочисленном режиме", tok.1)).with_span(tok.2.0, &tok.1)
+}
+
+// Ошибка некорректной записи ОПН
+fn invalid_output_queue() -> CalcError {
+    CalcError::new(ErrorKind::InvalidOutputQueue, "Выходная очередь сформирована неправильно")
+}
+
+// Команда скомпилированного выражения; токены хранятся для сообщений об ошибках
+#[derive(Debug, Clone)]
+enum Op {
+    // Значение, вычисленное при компиляции: число, дата или единица измерения
+    Push(Value),
+    // Переменная, значение которой сообщает Resolver
+    Variable(Token),
+    // Переменная с заранее известным номером в списке значений (см. Compiled)
+    Slot(usize, Token),
+    Unary(Token),
+    Binary(Token),
+    // Известная функция и число её аргументов
+    Function(Token, usize),
+    // Функция приложения, аргументы которой собраны в список
+    Call(Token),
+    // Составление списка из заданного числа значений
+    List(usize),
+    // Функция со связанной переменной (см. BOUND_FUNCTIONS): команды выражения и имя
+    // переменной; остальные аргументы вычисляются заранее и лежат на стеке
+    Bound(Token, Vec<Op>, Token),
+    // Функция if: команды ветвей then и else; вычисляется только ветвь, выбранная
+    // условием с вершины стека
+    If(Token, Vec<Op>, Vec<Op>),
+    // Начало общего подвыражения с номером n (см. share_subexpressions): если его значение
+    // уже вычислено в этом кадре, оно кладётся на стек и следующие skip команд пропускаются
+    Shared(usize, usize),
+    // Конец общего подвыражения n: значение с вершины стека запоминается в кадре
+    Store(usize),
+}
+
+// Скомпилированное выражение: последовательность команд стековой машины. Числа и
+// единицы измерения разбираются один раз при компиляции, поэтому одно выражение
+// можно многократно вычислять с разными значениями переменных
+#[derive(Debug, Clone)]
+pub struct Program {
+    ops: Vec<Op>,
+    // Число различных подвыражений, которые встречаются в выражении несколько раз
+    shared: usize,
+}
+
+// Статистика вычисления для --trace: выполнено команд, общих подвыражений в выражении и
+// повторных вычислений, вместо которых взято запомненное значение
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Trace {
+    pub steps: usize,
+    pub shared: usize,
+    pub hits: usize,
+}
+
+// Функция пользователя "fact(n) = if(n <= 1, 1, n * fact(n - 1))": имена параметров и
+// скомпилированное выражение. Выражение может вызывать функции пользователя, в том числе
+// себя: вызовы выполняются на стеке кадров в куче (см. run_ops), а не на стеке потока,
+// поэтому глубину рекурсии ограничивает только limits.max_recursion
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub params: Vec<String>,
+    pub expression: String,
+    ops: Vec<Op>,
+}
+
+impl Definition {
+    pub fn new(params: Vec<String>, expression: &str, limits: &Limits) -> Result<Definition, CalcError> {
+        let Program { ops, .. } = compile(expression, limits)?;
+        Ok(Definition { params, expression: expression.to_string(), ops })
+    }
+}
+
+// Разбор выражения и компиляция в последовательность команд
+pub fn compile(input: &str, limits: &Limits) -> Result<Program, CalcError> {
+    Program::from_rpn(parse(input, limits)?)
+}
+
+impl Program {
+    // Компиляция записи ОПН
+    pub fn from_rpn(mut output: Queue<Token>) -> Result<Program, CalcError> {
+        let mut ops: Vec<Op> = Vec::with_capacity(output.len());
+        compile_into(&mut output, &mut ops)?;
+        let (ops, shared) = share_subexpressions(ops);
+        Ok(Program { ops, shared })
+    }
+
+    // Число команд
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    // Вычисление; неизвестные имена разрешает resolver
+    pub fn run(&self, limits: &Limits, resolver: &dyn Resolver) -> Result<Value, CalcError> {
+        self.run_with_slots(limits, resolver, &[]).map(|(value, _)| value)
+    }
+
+    // То же, что run, со статистикой вычисления
+    pub fn run_traced(&self, limits: &Limits, resolver: &dyn Resolver) -> Result<(Value, Trace), CalcError> {
+        self.run_with_slots(limits, resolver, &[])
+    }
+
+    fn run_with_slots(&self, limits: &Limits, resolver: &dyn Resolver, slots: &[Value]) -> Result<(Value, Trace), CalcError> {
+        let mut calculate_stack: Stack<Value> = Stack::with_capacity(self.ops.len());
+        // Аргументы функций: один буфер на всё вычисление вместо списка на каждый вызов
+        let mut args: Vec<Value> = Vec::new();
+        let budget = Budget::new(limits);
+        let value = run_ops(&self.ops, limits, resolver, slots, &budget, &mut calculate_stack, &mut args)?;
+        let steps = limits.max_steps - budget.steps.get();
+        Ok((value, Trace { steps, shared: self.shared, hits: budget.hits.get() }))
+    }
+}
+
+// Подвыражение в списке команд: номера первой и последней команды, отпечаток и признак,
+// что его значение не зависит от того, когда оно вычислено
+type Segment = (usize, usize, u64, bool);
+
+// Отпечаток строки (FNV-1a) поверх отпечатка `hash`
+fn fingerprint(hash: u64, text: &str) -> u64 {
+    text.bytes().fold(hash, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Отпечаток команды без мест в строке: одинаковые подвыражения в разных местах выражения
+// получают один отпечаток. Вложенные команды (выражение функции, ветви if) входят в отпечаток
+fn op_fingerprint(op: &Op) -> u64 {
+    let seed = 0xcbf2_9ce4_8422_2325;
+    let nested = |hash: u64, ops: &[Op]| ops.iter().fold(hash, |hash, op| fingerprint(hash ^ op_fingerprint(op), "."));
+    match op {
+        Op::Push(value) => fingerprint(seed, &format!("push {:?}", value)),
+        Op::Variable(tok) => fingerprint(seed, &format!("var {}", tok.1)),
+        Op::Slot(index, _) => fingerprint(seed, &format!("slot {}", index)),
+        Op::Binary(tok) => fingerprint(seed, &format!("binary {}", tok.1)),
+        Op::Unary(tok) => fingerprint(seed, &format!("unary {}", tok.1)),
+        Op::Function(tok, arity) => fingerprint(seed, &format!("function {} {}", tok.1, arity)),
+        Op::Call(tok) => fingerprint(seed, &format!("call {}", tok.1)),
+        Op::List(count) => fingerprint(seed, &format!("list {}", count)),
+        Op::Bound(tok, ops, variable) => nested(fingerprint(seed, &format!("bound {} {}", tok.1, variable.1)), ops),
+        Op::If(_, then, otherwise) => nested(nested(fingerprint(seed, "if"), then), otherwise),
+        Op::Shared(index, skip) => fingerprint(seed, &format!("shared {} {}", index, skip)),
+        Op::Store(index) => fingerprint(seed, &format!("store {}", index)),
+    }
+}
+
+// Одинаковые команды без учёта мест в строке
+fn same_op(left: &Op, right: &Op) -> bool {
+    let same = |left: &Token, right: &Token| left.0 == right.0 && left.1 == right.1;
+    let same_ops = |left: &[Op], right: &[Op]| left.len() == right.len() && left.iter().zip(right).all(|(left, right)| same_op(left, right));
+    match (left, right) {
+        (Op::Push(left), Op::Push(right)) => left == right,
+        (Op::Variable(left), Op::Variable(right)) | (Op::Binary(left), Op::Binary(right))
+            | (Op::Unary(left), Op::Unary(right)) | (Op::Call(left), Op::Call(right)) => same(left, right),
+        (Op::Slot(left, _), Op::Slot(right, _)) => left == right,
+        (Op::Function(left, left_arity), Op::Function(right, right_arity)) => same(left, right) && left_arity == right_arity,
+        (Op::List(left), Op::List(right)) | (Op::Store(left), Op::Store(right)) => left == right,
+        (Op::Bound(left, left_ops, left_variable), Op::Bound(right, right_ops, right_variable)) =>
+            same(left, right) && same(left_variable, right_variable) && same_ops(left_ops, right_ops),
+        (Op::If(_, left_then, left_otherwise), Op::If(_, right_then, right_otherwise)) =>
+            same_ops(left_then, right_then) && same_ops(left_otherwise, right_otherwise),
+        (Op::Shared(left, left_skip), Op::Shared(right, right_skip)) => left == right && left_skip == right_skip,
+        _ => false,
+    }
+}
+
+// Значение команды не зависит от момента вычисления: now() меняется со временем, а функции
+// приложения (Call) могут возвращать разные значения
+fn pure_op(op: &Op) -> bool {
+    match op {
+        Op::Call(_) => false,
+        Op::Function(tok, _) => tok.1 != "now",
+        Op::Bound(_, ops, _) => ops.iter().all(pure_op),
+        Op::If(_, then, otherwise) => then.iter().chain(otherwise).all(pure_op),
+        _ => true,
+    }
+}
+
+// Подвыражения, которые вычисляют команды ops, в порядке их последних команд; None -
+// команды не складываются в выражения
+fn segments(ops: &[Op]) -> Option<Vec<Segment>> {
+    let mut segments: Vec<Segment> = Vec::with_capacity(ops.len());
+    // Подвыражения, значения которых лежат на стеке: номера в segments
+    let mut stack: Vec<usize> = Vec::new();
+    for (index, op) in ops.iter().enumerate() {
+        let inputs = usize::try_from(1 - stack_effect(op)).ok()?;
+        let operands = stack.split_off(stack.len().checked_sub(inputs)?);
+        let start = operands.first().map_or(index, |operand| segments[*operand].0);
+        let hash = operands.iter().fold(op_fingerprint(op), |hash, operand| fingerprint(hash ^ segments[*operand].2, ","));
+        let pure = pure_op(op) && operands.iter().all(|operand| segments[*operand].3);
+        stack.push(segments.len());
+        segments.push((start, index, hash, pure));
+    }
+    Some(segments)
+}
+
+// Исключение общих подвыражений: подвыражение, которое встречается в ops несколько раз
+// (sin(x) в pow(sin(x), 2) + pow(sin(x), 2) * 2), вычисляется один раз. Каждое его вхождение
+// обрамляется командами Shared и Store: первое вычисляет и запоминает значение, следующие
+// берут запомненное. Возвращает новые команды и число общих подвыражений
+fn share_subexpressions(ops: Vec<Op>) -> (Vec<Op>, usize) {
+    let segments = match segments(&ops) {
+        Some(segments) => segments,
+        None => return (ops, 0),
+    };
+    // Кандидаты - чистые подвыражения хотя бы из двух команд
+    let mut candidates: Vec<&Segment> = segments.iter().filter(|segment| segment.1 > segment.0 && segment.3).collect();
+    candidates.sort_by_key(|segment| (segment.2, segment.0));
+    let mut classes: Vec<Vec<&Segment>> = Vec::new();
+    for group in candidates.chunk_by(|left, right| left.2 == right.2) {
+        // Одинаковый отпечаток у разных подвыражений проверяется сравнением команд
+        let mut group_classes: Vec<Vec<&Segment>> = Vec::new();
+        for segment in group {
+            let commands = &ops[segment.0..=segment.1];
+            let same = |other: &&Segment| {
+                let other = &ops[other.0..=other.1];
+                other.len() == commands.len() && other.iter().zip(commands).all(|(left, right)| same_op(left, right))
+            };
+            match group_classes.iter_mut().find(|class| same(&class[0])) {
+                Some(class) => class.push(segment),
+                None => group_classes.push(vec![segment]),
+            }
+        }
+        classes.extend(group_classes.into_iter().filter(|class| class.len() > 1));
+    }
+    // Сначала длинные, чтобы вложенные в уже общие подвыражения не отмечались без нужды
+    classes.sort_by_key(|class| core::cmp::Reverse(class[0].1 - class[0].0));
+    let mut marked: Vec<(usize, usize, usize)> = Vec::new();
+    for class in classes {
+        // Если все вхождения внутри уже общих подвыражений, они и так вычисляются один раз.
+        // Иначе отмечаются все: вхождение вне общих берёт значение, вычисленное внутри них
+        let outside = class.iter().any(|segment| !marked.iter().any(|mark| mark.0 <= segment.0 && segment.1 <= mark.1));
+        if outside {
+            let number = marked.last().map_or(0, |mark| mark.2 + 1);
+            marked.extend(class.iter().map(|segment| (segment.0, segment.1, number)));
+        }
+    }
+    let shared = marked.last().map_or(0, |mark| mark.2 + 1);
+    if shared == 0 {
+        return (ops, 0);
+    }
+    // Вставка команд: Shared перед первой командой вхождения (внешние раньше вложенных),
+    // Store после последней (вложенные раньше внешних)
+    marked.sort_by_key(|mark| (mark.0, core::cmp::Reverse(mark.1)));
+    let mut result: Vec<Op> = Vec::with_capacity(ops.len() + 2 * marked.len());
+    let mut open: Vec<(usize, usize, usize)> = Vec::new();
+    let mut next_mark = 0;
+    for (index, op) in ops.into_iter().enumerate() {
+        while marked.get(next_mark).is_some_and(|mark| mark.0 == index) {
+            open.push((marked[next_mark].1, marked[next_mark].2, result.len()));
+            result.push(Op::Shared(marked[next_mark].2, 0));
+            next_mark += 1;
+        }
+        result.push(op);
+        while open.last().is_some_and(|mark| mark.0 == index) {
+            let (_, number, position) = open.pop().unwrap_or_default();
+            result.push(Op::Store(number));
+            result[position] = Op::Shared(number, result.len() - 1 - position);
+        }
+    }
+    (result, shared)
+}
+
+// Компиляция записи ОПН в команды в конец списка `ops`; очередь при этом опустошается,
+// но сохраняет выделенную память
+fn compile_into(output: &mut Queue<Token>, ops: &mut Vec<Op>) -> Result<(), CalcError> {
+    ops.reserve(output.len());
+    while !output.is_empty() {
+        let out = output.dequeue();
+    let op = match out.0 {
+            TokenType::NumberFloat | TokenType::NumberInt => Op::Push(Value::Number(token_value(&out)?)),
+            TokenType::Date => Op::Push(Value::Date(date_value(&out)?)),
+            TokenType::Unit => match get_unit_info(&out.1) {
+                Some((number, dimension)) => Op::Push(Value::Quantity(number, dimension)),
+                None => return Err(CalcError::new(ErrorKind::UnknownUnit, &format!("неизвестная единица измерения '{}'", out.1))
+                    .with_span(out.2.0, &out.1)),
+            },
+            TokenType::Variable => Op::Variable(out),
+            TokenType::BinaryOperator => Op::Binary(out),
+            TokenType::UnaryOperator => Op::Unary(out),
+            TokenType::Function => match get_function_arity(&out.1) {
+                Some(arity) if BOUND_FUNCTIONS.iter().any(|bound| bound.0 == out.1) => bound_function(ops, out, arity)?,
+                Some(_) if out.1 == "if" => conditional(ops, out)?,
+                Some(arity) => Op::Function(out, arity),
+                None => Op::Call(out),
+            },
+            TokenType::List => Op::List(list_length(&out).ok_or_else(invalid_output_queue)?),
+            _ => return Err(invalid_output_queue()),
+        };
+        ops.push(op);
+    }
+
+    Ok(())
+}
+
+// Изменение числа значений на стеке после выполнения команды
+fn stack_effect(op: &Op) -> isize {
+    match op {
+        Op::Push(_) | Op::Variable(_) | Op::Slot(..) => 1,
+        Op::Unary(_) | Op::Call(_) | Op::If(..) | Op::Shared(..) | Op::Store(_) => 0,
+        Op::Binary(_) => -1,
+        Op::Function(_, arity) => 1 - *arity as isize,
+        Op::List(count) => 1 - *count as isize,
+        // Выражение и имя переменной не лежат на стеке
+        Op::Bound(tok, ..) => 3 - get_function_arity(&tok.1).unwrap_or(2) as isize,
+    }
+}
+
+// Начало команд, вычисляющих значение, которое оказывается на вершине стека перед командой
+// с номером `end`: самый короткий конец списка, добавляющий ровно одно значение
+fn segment_start(ops: &[Op], end: usize) -> Option<usize> {
+    let mut depth = 0;
+    for index in (0..end).rev() {
+        depth += stack_effect(&ops[index]);
+        if depth == 1 {
+            return Some(index);
+        }
+    }
+    None
+}
+
+// Команда функции со связанной переменной: команды выражения и имя переменной (первые два
+// аргумента) забираются из конца `ops`, а команды остальных аргументов остаются на месте
+fn bound_function(ops: &mut Vec<Op>, tok: Token, arity: usize) -> Result<Op, CalcError> {
+    if INDEX_FUNCTIONS.contains(&&*tok.1) {
+        return index_function(ops, tok, arity);
+    }
+    let mut end = ops.len();
+    for _ in 2..arity {
+        end = segment_start(ops, end).ok_or_else(invalid_output_queue)?;
+    }
+    let variable = segment_start(ops, end).ok_or_else(invalid_output_queue)?;
+    let start = segment_start(ops, variable).ok_or_else(invalid_output_queue)?;
+    let mut expression: Vec<Op> = ops.drain(start..end).collect();
+    let name = match expression.pop() {
+        Some(Op::Variable(name)) if variable + 1 == end => name,
+        _ => return Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+            &format!("второй аргумент '{}' - имя переменной, не занятое функцией или единицей измерения", tok.1))
+            .with_span(tok.2.0, &tok.1)),
+    };
+    // Уравнение a = b в solve решается как a - b = 0
+    if tok.1 == "solve" {
+        match expression.last_mut() {
+            Some(Op::Binary(equals)) if equals.1 == "=" => equals.1 = Cow::Borrowed("-"),
+            _ => return Err(CalcError::new(ErrorKind::InvalidFunctionCall, "первый аргумент 'solve' - уравнение вида a = b")
+                .with_span(tok.2.0, &tok.1)),
+        }
+    }
+    Ok(Op::Bound(tok, expression, name))
+}
+
+// Команда функции в индексной форме (sum(i, 1, 100, i * i)): команды выражения (последний
+// аргумент) и имя переменной (первый) забираются из `ops`, а команды границ остаются на месте
+fn index_function(ops: &mut Vec<Op>, tok: Token, arity: usize) -> Result<Op, CalcError> {
+    let expression = segment_start(ops, ops.len()).ok_or_else(invalid_output_queue)?;
+    let mut end = expression;
+    for _ in 2..arity {
+        end = segment_start(ops, end).ok_or_else(invalid_output_queue)?;
+    }
+    let variable = segment_start(ops, end).ok_or_else(invalid_output_queue)?;
+    if variable + 1 != end || !matches!(ops[variable], Op::Variable(_)) {
+        let name = written_name(&tok.1);
+        return Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+            &format!("первый аргумент '{}' - имя переменной, не занятое функцией или единицей измерения", name))
+            .with_span(tok.2.0, name));
+    }
+    let expression = ops.split_off(expression);
+    match ops.remove(variable) {
+        Op::Variable(name) => Ok(Op::Bound(tok, expression, name)),
+        _ => Err(invalid_output_queue()),
+    }
+}
+
+// Имя функции, как оно записано во входной строке: SUM - это sum с четырьмя аргументами
+fn written_name(name: &str) -> &str {
+    match name {
+        "SUM" => "sum",
+        name => name,
+    }
+}
+
+// Команда if: команды ветвей (второй и третий аргументы) забираются из конца `ops`, а
+// команды условия остаются на месте и вычисляются до выбора ветви
+fn conditional(ops: &mut Vec<Op>, tok: Token) -> Result<Op, CalcError> {
+    let otherwise = segment_start(ops, ops.len()).ok_or_else(invalid_output_queue)?;
+    let then = segment_start(ops, otherwise).ok_or_else(invalid_output_queue)?;
+    segment_start(ops, then).ok_or_else(invalid_output_queue)?;
+    let otherwise_ops = ops.split_off(otherwise);
+    let then_ops = ops.split_off(then);
+    Ok(Op::If(tok, then_ops, otherwise_ops))
+}
+
+// Шаги и время одного вычисления верхнего уровня. Вложенные вычисления (ветви if, выражения
+// функций со связанной переменной) расходуют их же, поэтому вложенность не даёт ни новых
+// limits.max_steps команд, ни нового limits.timeout
+struct Budget {
+    // Сколько команд ещё можно выполнить
+    steps: Cell<usize>,
+    // Начало вычисления и ограничение времени. Время засекается только при заданном
+    // ограничении: на wasm32 часов может не быть, а без стандартной библиотеки
+    // ограничение времени не проверяется
+    #[cfg(feature = "std")]
+    started: Option<(Instant, Duration)>,
+    // Глубина вложенных вызовов функций пользователя и вызов в командах текущего run_ops,
+    // внутри которого идёт вычисление
+    calls: Cell<usize>,
+    site: Cell<Option<Token>>,
+    // Сколько раз значение общего подвыражения взято из запомненных
+    hits: Cell<usize>,
+}
+
+impl Budget {
+    fn new(limits: &Limits) -> Budget {
+        Budget {
+            steps: Cell::new(limits.max_steps),
+            calls: Cell::new(0),
+            site: Cell::new(None),
+            hits: Cell::new(0),
+            #[cfg(feature = "std")]
+            started: limits.timeout.map(|timeout| (Instant::now(), timeout)),
+        }
+    }
+
+    // Один шаг вычисления: ошибка, если шаги или время закончились или вычисление отменено
+    fn step(&self, limits: &Limits) -> Result<(), CalcError> {
+        let steps = self.steps.get().checked_sub(1).ok_or_else(|| CalcError::new(ErrorKind::BudgetExceeded,
+            &format!("превышен лимит вычисления: больше {} операций", limits.max_steps)))?;
+        self.steps.set(steps);
+        check_cancelled(limits)?;
+        #[cfg(feature = "std")]
+        if let Some((started, timeout)) = self.started {
+            if started.elapsed() > timeout {
+                return Err(CalcError::new(ErrorKind::Timeout,
+                    &format!("превышено время вычисления: больше {} мс", timeout.as_millis())));
+            }
+        }
+        Ok(())
+    }
+
+    // Вход в функцию пользователя: ошибка, если вложенных вызовов больше limits.max_recursion
+    fn enter(&self, limits: &Limits, function: &Token) -> Result<(), CalcError> {
+        if self.calls.get() >= limits.max_recursion {
+            return Err(CalcError::new(ErrorKind::TooDeep,
+                &format!("превышена глубина вызовов функций пользователя: больше {}", limits.max_recursion))
+                .with_span(function.2.0, &function.1));
+        }
+        self.calls.set(self.calls.get() + 1);
+        Ok(())
+    }
+}
+
+// Кадр вычисления: команды выражения, ветви if или функции пользователя, номер следующей
+// команды и число значений на стеке до начала кадра. У кадра вызова функции пользователя
+// есть аргументы, их видят команды кадра и кадров его ветвей. Общие подвыражения
+// запоминаются в кадре: в другом вызове функции у них другие значения
+struct Frame<'a> {
+    ops: &'a [Op],
+    next: usize,
+    base: usize,
+    arguments: Option<(&'a [String], Vec<Value>)>,
+    // Значения общих подвыражений, вычисленные в кадре
+    shared: Vec<Option<Value>>,
+}
+
+// Аргументы ближайшего вызова функции пользователя: текущего кадра или отложенных под ним
+fn frame_arguments<'s>(frame: &'s Frame, frames: &'s Stack<Frame>) -> Option<(&'s [String], &'s [Value])> {
+    core::iter::once(frame).chain(frames.iter()).find_map(|frame| frame.arguments.as_ref())
+        .map(|(names, values)| (*names, values.as_slice()))
+}
+
+// Проверка, что команды кадра оставили на стеке ровно одно значение
+fn frame_result(calculate_stack: &Stack<Value>, base: usize) -> Result<(), CalcError> {
+    if calculate_stack.len() <= base {
+        return Err(CalcError::new(ErrorKind::NoResult, "не удалось вычислить выражение"));
+    }
+    // Если в стеке осталось больше одного значения, то для части из них не нашлось оператора
+    if calculate_stack.len() > base + 1 {
+        let values: Vec<String> = calculate_stack.iter().take(calculate_stack.len() - base).map(|value| value.to_string()).collect();
+        return Err(CalcError::new(ErrorKind::NoResult,
+            &format!("не удалось вычислить выражение: в стеке осталось значений - {} ({})", values.len(), values.join(", "))));
+    }
+    Ok(())
+}
+
+// Выполнение команд со стеком значений и буфером аргументов, переданными извне (см. Workspace).
+// Ветви if и вызовы функций пользователя - кадры на стеке кадров, поэтому рекурсия функций
+// пользователя не расходует стек потока
+fn run_ops<'a>(ops: &'a [Op], limits: &Limits, resolver: &'a dyn Resolver, slots: &[Value], budget: &Budget,
+    calculate_stack: &mut Stack<Value>, args: &mut Vec<Value>) -> Result<Value, CalcError> {
+    // При ошибке кадры вызовов не закрываются, поэтому глубина восстанавливается здесь
+    let calls = budget.calls.get();
+    let outer_site = budget.site.take();
+    let result = run_frames(ops, limits, resolver, slots, budget, calculate_stack, args);
+    budget.calls.set(calls);
+    let site = budget.site.replace(outer_site);
+    // Места в выражении функции пользователя не относятся к вычисляемой строке, поэтому
+    // ошибка в нём указывает на вызов функции
+    result.map_err(|why| match site {
+        Some(call) => at_site(why, &call, &format!("ошибка при вычислении функции пользователя '{}'", call.1)),
+        None => why,
+    })
+}
+
+// Ошибка вычисления другого выражения (функции пользователя, переменной приложения) с местом
+// там, где оно использовано: места в том выражении не относятся к вычисляемой строке.
+// Пояснение `note` не повторяется при вложенных вычислениях
+fn at_site(mut why: CalcError, site: &Token, note: &str) -> CalcError {
+    why.notes.retain(|note| note.0.is_none());
+    let why = why.with_span(site.2.0, &site.1);
+    match why.notes.iter().any(|known| known.1 == note) {
+        true => why,
+        false => why.with_note(note),
+    }
+}
+
+fn run_frames<'a>(ops: &'a [Op], limits: &Limits, resolver: &'a dyn Resolver, slots: &[Value], budget: &Budget,
+    calculate_stack: &mut Stack<Value>, args: &mut Vec<Value>) -> Result<Value, CalcError> {
+    let entry = budget.calls.get();
+    // Текущий кадр и отложенные кадры под ним. Выражение без if и функций пользователя
+    // выполняется в одном кадре, и стек кадров не выделяет память
+    let mut frame = Frame { ops, next: 0, base: calculate_stack.len(), arguments: None, shared: Vec::new() };
+    let mut frames: Stack<Frame<'a>> = Stack::new();
+    loop {
+        let op = match frame.ops.get(frame.next) {
+            Some(op) => {
+                frame.next += 1;
+                op
+            },
+            // Значение кадра остаётся на стеке для команд кадра под ним
+            None => {
+                frame_result(calculate_stack, frame.base)?;
+                if frame.arguments.is_some() {
+                    budget.calls.set(budget.calls.get() - 1);
+                    if budget.calls.get() == entry {
+                        budget.site.set(None);
+                    }
+                }
+                match frames.pop() {
+                    Some(outer) => frame = outer,
+                    None => return calculate_stack.pop().ok_or_else(invalid_output_queue),
+                }
+                continue;
+            },
+        };
+        // Каждая команда - один шаг вычисления
+        budget.step(limits)?;
+
+        let value = match op {
+            Op::Push(value) => value.clone(),
+            Op::Variable(tok) => {
+                let argument = frame_arguments(&frame, &frames)
+                    .and_then(|(names, values)| values.get(names.iter().position(|name| *name == tok.1)?).cloned());
+                match argument {
+                    Some(value) => value,
+                    None => resolver.try_variable(&tok.1)
+                        .map_err(|why| at_site(why, tok, &format!("ошибка при вычислении переменной '{}'", tok.1)))?
+                        .ok_or_else(|| unknown_variable_error(tok))?,
+                }
+            },
+            Op::Slot(index, tok) => slots.get(*index).cloned().ok_or_else(|| CalcError::new(ErrorKind::UnknownVariable,
+                &format!("не задано значение переменной '{}'", tok.1)).with_span(tok.2.0, &tok.1))?,
+            Op::Binary(tok) => {
+                // Два операнда снимаются со стека без промежуточного списка
+                let arg2 = calculate_stack.pop().ok_or_else(invalid_output_queue)?;
+                let arg1 = calculate_stack.pop().ok_or_else(invalid_output_queue)?;
+                calc_binary_operator(tok, arg1, arg2)?
+            },
+            Op::Unary(tok) => {
+                let arg = calculate_stack.pop().ok_or_else(invalid_output_queue)?;
+                calc_unary_operator(tok, arg)?
+            },
+            Op::Function(tok, arity) => {
+                pop_values(calculate_stack, *arity, args)?;
+                calc_function(tok, args)?
+            },
+            Op::Call(tok) => match (calculate_stack.pop(), resolver.definition(&tok.1)) {
+                (Some(Value::List(values)), Some(definition)) => {
+                    if values.len() != definition.params.len() {
+                        return Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+                            &format!("функция '{}' принимает аргументов: {}, передано: {}", tok.1, definition.params.len(), values.len()))
+                            .with_span(tok.2.0, &tok.1));
+                    }
+                    budget.enter(limits, tok)?;
+                    if budget.calls.get() == entry + 1 {
+                        budget.site.set(Some(tok.clone()));
+                    }
+                    let arguments = Some((definition.params.as_slice(), values));
+                    let call = Frame { ops: &definition.ops, next: 0, base: calculate_stack.len(), arguments, shared: Vec::new() };
+                    frames.push(core::mem::replace(&mut frame, call));
+                    continue;
+                },
+                // Места в ошибке приложения не относятся к вычисляемой строке
+                (Some(Value::List(args)), None) => resolver.try_call(&tok.1, &args)
+                    .map_err(|why| why.with_span(tok.2.0, &tok.1))?
+                    .ok_or_else(|| unknown_function_error(tok))?,
+                _ => return Err(invalid_output_queue()),
+            },
+            Op::List(count) => {
+                let mut items: Vec<Value> = Vec::with_capacity(*count);
+                pop_values(calculate_stack, *count, &mut items)?;
+                Value::List(items)
+            },
+            Op::Bound(tok, ops, variable) => {
+                let arity = get_function_arity(&tok.1).ok_or_else(invalid_output_queue)?;
+                pop_values(calculate_stack, arity - 2, args)?;
+                // В теле функции пользователя выражение видит её аргументы
+                let arguments = frame_arguments(&frame, &frames).map(|(names, values)| Arguments { names, values, outer: resolver });
+                let resolver: &dyn Resolver = match &arguments {
+                    Some(arguments) => arguments,
+                    None => resolver,
+                };
+                let expression = BoundExpression { ops, variable: &variable.1, limits, resolver, slots, budget };
+                calc_bound_function(tok, &expression, args)?
+            },
+            Op::If(tok, then, otherwise) => {
+                let branch = match calculate_stack.pop() {
+                    Some(Value::Bool(true)) => then,
+                    Some(Value::Bool(false)) => otherwise,
+                    Some(condition) => return Err(CalcError::new(ErrorKind::InvalidFunctionCall,
+                        &format!("условие '{}' - результат сравнения (true или false), получено {}", tok.1, condition))
+                        .with_span(tok.2.0, &tok.1)),
+                    None => return Err(invalid_output_queue()),
+                };
+                let branch = Frame { ops: branch, next: 0, base: calculate_stack.len(), arguments: None, shared: Vec::new() };
+                frames.push(core::mem::replace(&mut frame, branch));
+                continue;
+            },
+            Op::Shared(index, skip) => match frame.shared.get(*index) {
+                Some(Some(value)) => {
+                    budget.hits.set(budget.hits.get() + 1);
+                    frame.next += skip;
+                    value.clone()
+                },
+                _ => continue,
+            },
+            Op::Store(index) => {
+                let value = calculate_stack.peek().cloned().ok_or_else(invalid_output_queue)?;
+                if frame.shared.len() <= *index {
+                    frame.shared.resize(*index + 1, None);
+                }
+                frame.shared[*index] = Some(value);
+                continue;
+            },
+        };
+        calculate_stack.push(value);
+    }
+}
+
+// Память для разбора и вычисления многих выражений подряд: выходная очередь, стеки и
+// команды после каждого выражения очищаются, но сохраняют выделенную память. Когда её
+// хватает, преобразование токенов в ОПН и вычисление не выделяют память (кроме значений-
+// списков и сообщений об ошибках)
+#[derive(Default)]
+pub struct Workspace {
+    output: Queue<Token>,
+    operators: Stack<Token>,
+    arguments: Stack<Option<usize>>,
+    ops: Vec<Op>,
+    values: Stack<Value>,
+    args: Vec<Value>,
+}
+
+impl Workspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Вычисление выражения по его токенам (см. tokerize); неизвестные имена разрешает resolver
+    pub fn evaluate(&mut self, tokens: Vec<Token>, input: &str, limits: &Limits, resolver: &dyn Resolver) -> Result<Value, CalcError> {
+        self.output.clear();
+        self.output.reserve(tokens.len());
+        self.arguments.clear();
+        self.ops.clear();
+        self.values.clear();
+        // Стек операторов на время разбора получает ограничение глубины из limits
+        let mut operators = BoundedStack::from_stack(mem::take(&mut self.operators), limits.max_depth);
+        operators.clear();
+        let converted = convert_into(tokens, input, limits, &mut self.output, &mut operators, &mut self.arguments);
+        self.operators = operators.into_stack();
+        converted?;
+        compile_into(&mut self.output, &mut self.ops)?;
+        self.values.reserve(self.ops.len());
+        run_ops(&self.ops, limits, resolver, &[], &Budget::new(limits), &mut self.values, &mut self.args)
+    }
+}
+
+// Обратное преобразование записи в ОПН в инфиксную форму с полной расстановкой скобок.
+// Разбор полученной строки даёт ту же запись в ОПН
+pub fn rpn_to_infix(rpn: &Queue<Token>) -> Option<String> {
+    let mut operands: Stack<String> = Stack::with_capacity(rpn.len());
+    for tok in rpn {
+        let operand = match tok.0 {
+            TokenType::NumberInt | TokenType::NumberFloat | TokenType::Date | TokenType::Unit | TokenType::Variable => tok.1.to_string(),
+            TokenType::UnaryOperator => {
+                let arg = operands.pop()?;
+                let op = if tok.1 == "NEG" { "-" } else { "+" };
+                format!("({}{})", op, arg)
+            },
+            TokenType::BinaryOperator => {
+                let arg2 = operands.pop()?;
+                let arg1 = operands.pop()?;
+                format!("({} {} {})", arg1, tok.1, arg2)
+            },
+            // Аргументы неизвестной функции уже собраны в список "[...]"
+            TokenType::Function if get_function_arity(&tok.1).is_none() => {
+                let args = operands.pop()?;
+                format!("{}({})", tok.1, args.strip_prefix('[')?.strip_suffix(']')?)
+            },
+            TokenType::Function => {
+                let arity = get_function_arity(&tok.1)?;
+                if operands.len() < arity {
+                    return None;
+                }
+                let mut args = vec![String::new(); arity];
+                for arg in args.iter_mut().rev() {
+                    *arg = operands.pop()?;
+                }
+                format!("{}({})", tok.1, args.join(", "))
+            },
+            TokenType::List => {
+                let count = list_length(tok)?;
+                if operands.len() < count {
+                    return None;
+                }
+                let mut items = vec![String::new(); count];
+                for item in items.iter_mut().rev() {
+                    *item = operands.pop()?;
+                }
+                format!("[{}]", items.join(", "))
+            },
+            _ => return None,
+        };
+        operands.push(operand);
+    }
+
+    let infix = operands.pop()?;
+    if !operands.is_empty() {
+        return None;
+    }
+    Some(infix)
+}
+
+// Скомпилированная формула с переменными: переменные нумеруются при компиляции, и
+// при вычислении их значения берутся по номеру, без повторного разбора выражения
+pub struct Compiled {
+    program: Program,
+    variables: Vec<String>,
+    limits: Limits,
+}
+
+impl Compiled {
+    pub fn new(input: &str) -> Result<Compiled, CalcError> {
+        Self::with_limits(input, Limits::default())
+    }
+
+    pub fn with_limits(input: &str, limits: Limits) -> Result<Compiled, CalcError> {
+        let mut program = compile(input, &limits)?;
+        let mut variables: Vec<String> = Vec::new();
+        assign_slots(&mut program.ops, &mut Vec::new(), &mut variables);
+        Ok(Compiled { program, variables, limits })
+    }
+
+    // Имена переменных в порядке первого появления в выражении
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    // Вычисление с числовыми значениями переменных, заданными по именам
+    pub fn eval(&self, values: &[(&str, f32)]) -> Result<Value, CalcError> {
+        let mut slots: Vec<Value> = Vec::with_capacity(self.variables.len());
+        for name in &self.variables {
+            match values.iter().find(|value| value.0 == name) {
+                Some(value) => slots.push(Value::Number(value.1)),
+                None => break,
+            }
+        }
+        self.program.run_with_slots(&self.limits, &NoResolver, &slots).map(|(value, _)| value)
+    }
+
+    // Вычисление со значениями переменных в порядке variables()
+    pub fn eval_values(&self, values: &[Value]) -> Result<Value, CalcError> {
+        self.program.run_with_slots(&self.limits, &NoResolver, values).map(|(value, _)| value)
+    }
+}
+
+// Замена переменных командами Slot с номерами в `variables`; переменные, связанные
+// функциями вроде table (`bound`), остаются именами - их значения задаёт сама функция
+fn assign_slots(ops: &mut [Op], bound: &mut Vec<String>, variables: &mut Vec<String>) {
+    for op in ops.iter_mut() {
+        match op {
+            Op::Variable(tok) if !bound.iter().any(|name| *name == tok.1) => {
+                let index = match variables.iter().position(|name| *name == tok.1) {
+                    Some(index) => index,
+                    None => {
+                        variables.push(tok.1.to_string());
+                        variables.len() - 1
+                    },
+                };
+                *op = Op::Slot(index, tok.clone());
+            },
+            Op::Bound(_, expression, variable) => {
+                bound.push(variable.1.to_string());
+                assign_slots(expression, bound, variables);
+                bound.pop();
+            },
+            Op::If(_, then, otherwise) => {
+                assign_slots(then, bound, variables);
+                assign_slots(otherwise, bound, variables);
+            },
+            _ => {},
+        }
+    }
+}
+
+// Ограничения на сложность обрабатываемых выражений
+pub struct Limits {
+    // Максимальная глубина стека операторов при преобразовании в ОПН
+    pub max_depth: usize,
+    // Максимальная вложенность скобок
+    pub max_nesting: usize,
+    // Максимальное число токенов в выражении
+    pub max_tokens: usize,
+    // Максимальное число операций при вычислении
+    pub max_steps: usize,
+    // Максимальное время вычисления (без ограничения, если не задано)
+    pub timeout: Option<Duration>,
+    // Отмена вычисления из другого потока
+    pub cancel: Option<CancelToken>,
+    // Допустимая относительная погрешность численных методов (integrate, root)
+    pub tolerance: f32,
+    // Максимальная глубина вложенных вызовов функций пользователя
+    pub max_recursion: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { max_depth: 256, max_nesting: 64, max_tokens: 10_000, max_steps: 100_000, timeout: None, cancel: None, tolerance: 1e-3, max_recursion: 1000 }
+    }
+}
+
+// Допустимые конструкции входного языка, чтобы встраивающая калькулятор программа могла
+// ограничить ввод - например, только арифметикой для непроверенного ввода:
+// ParserOptions { functions: false, bit_operators: false, identifiers: |_| false, ..Default::default() }
+#[derive(Clone, Copy)]
+pub struct ParserOptions {
+    // Вызовы функций (модуль |x| разрешён и без них)
+    pub functions: bool,
+    // Сдвиги << и >>
+    pub bit_operators: bool,
+    // Допустимые символы имён функций, единиц измерения и переменных
+    pub identifiers: fn(char) -> bool,
+    // Максимальное число токенов в этом разборе, дополнительно к Limits::max_tokens
+    pub max_tokens: Option<usize>,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        ParserOptions { functions: true, bit_operators: true, identifiers: |_| true, max_tokens: None }
+    }
+}
+
+// Прерывание разбора или вычисления, если оно отменено через Limits::cancel
+fn check_cancelled(limits: &Limits) -> Result<(), CalcError> {
+    match &limits.cancel {
+        Some(token) if token.is_cancelled() => Err(CalcError::new(ErrorKind::Cancelled, "вычисление отменено")),
+        _ => Ok(()),
+    }
+}
+
+// Признак отмены вычисления: копии токена разделяют один флаг, поэтому разбор и
+// вычисление, получившие токен через Limits, можно прервать из другого потока вызовом cancel()
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// Результат обработки одного выражения
+pub struct Evaluation {
+    pub rpn: String,
+    // Результат, записанный в нотации Notation::Fixed
+    pub result: String,
+    pub value: Value,
+    pub trace: Trace,
+}
+
+// Переменная, относительно которой решено уравнение, если выражение (запись ОПН) - вызов
+// solve: результат тогда выводится как "x = 4"
+pub fn solved_variable(rpn: &str) -> Option<&str> {
+    let mut tokens = rpn.rsplit(' ');
+    match (tokens.next(), tokens.next()) {
+        (Some("solve"), Some(variable)) => Some(variable),
+        _ => None,
+    }
+}
+
+// Процесс преобразования состоит из 3 основных этапов
+pub fn process(input : &str, limits: &Limits) -> Result<Evaluation, CalcError> {
+    process_with(input, limits, &NoResolver)
+}
+
+// То же, что process, с переменными и функциями приложения
+pub fn process_with(input : &str, limits: &Limits, resolver: &dyn Resolver) -> Result<Evaluation, CalcError> {
+    // 1-2. Разбиваем входную строку на токены и преобразуем их в запись ОПН
+    let output = parse(input, limits)?;
+    let mut rpn = String::with_capacity(input.len() + output.len());
+    for (index, tok) in output.iter().enumerate() {
+        if index > 0 {
+            rpn.push(' ');
+        }
+        rpn.push_str(&tok.1);
+    }
+
+    // 3. Вычисляем результат выражения
+    let (value, trace) = Program::from_rpn(output)?.run_traced(limits, resolver)?;
+    let result = value.to_string();
+
+    Ok(Evaluation { rpn, result, value, trace })
+}
+
+// Разбор выражения: разбиение на токены, проверки и преобразование в запись ОПН
+pub fn parse(input: &str, limits: &Limits) -> Result<Queue<Token>, CalcError> {
+    parse_with_options(input, limits, &ParserOptions::default())
+}
+
+// То же, что parse, но только для конструкций, разрешённых options
+pub fn parse_with_options(input: &str, limits: &Limits, options: &ParserOptions) -> Result<Queue<Token>, CalcError> {
+    if input.trim().is_empty() {
+        return Err(CalcError::new(ErrorKind::EmptyExpression, "пустое выражение"));
+    }
+
+    // 1. Разбиваем входную строку на токены (лексемы)
+    let tokens = tokerize(input, limits)?;
+
+    check_options(&tokens, input, options)?;
+    first_error(missing_operators(&tokens, input))?;
+    first_error(dangling_operators(&tokens, input))?;
+
+    // 2. Преобразуем список входных токенов в список в ОПН
+    convert_to_rpn(tokens, input, limits)
+}
+
+// Первая из найденных ошибок
+fn first_error(errors: Vec<CalcError>) -> Result<(), CalcError> {
+    match errors.into_iter().next() {
+        Some(why) => Err(why),
+        None => Ok(()),
+    }
+}
+
+// Все ошибки разбора выражения за один проход, по порядку мест в строке (пустой список -
+// выражение разбирается). Нераспознанные лексемы пропускаются, и разбор продолжается;
+// ошибки рядом с пропущенной лексемой не сообщаются, так как скорее всего вызваны ею.
+// Ошибки расстановки скобок ищутся, только если операторы расставлены верно
+pub fn parse_errors(input: &str, limits: &Limits) -> Vec<CalcError> {
+    if input.trim().is_empty() {
+        return vec![CalcError::new(ErrorKind::EmptyExpression, "пустое выражение")];
+    }
+
+    let mut errors = Vec::new();
+    let tokens = match scan_lexemes(input, limits, Some(&mut errors)) {
+        Ok(lexemes) => classify_lexemes(expand_bars(lexemes)),
+        Err(why) => {
+            errors.push(why);
+            return errors;
+        },
+    };
+    let skipped = errors.iter().filter_map(|why| why.span).collect::<Vec<Span>>();
+    let caused_by_skipped = |why: &CalcError| match why.span {
+        Some(span) => {
+            // Окрестность ошибки в лексеме - от конца предыдущей лексемы до начала следующей;
+            // пропущенный оператор ищется только между лексемой и предыдущей
+            let (start, end) = match tokens.iter().position(|tok| tok.2 == span) {
+                Some(index) => (
+                    index.checked_sub(1).map_or(0, |index| tokens[index].2.1),
+                    match why.kind {
+                        ErrorKind::MissingOperator => span.1,
+                        _ => tokens.get(index + 1).map_or(input.len(), |tok| tok.2.0),
+                    },
+                ),
+                None => span,
+            };
+            skipped.iter().any(|skipped| skipped.0 >= start && skipped.1 <= end)
+        },
+        None => !skipped.is_empty(),
+    };
+
+    let mut syntax = missing_operators(&tokens, input);
+    syntax.extend(dangling_operators(&tokens, input));
+    syntax.retain(|why| !caused_by_skipped(why));
+    if syntax.is_empty() {
+        if let Err(why) = convert_to_rpn(tokens.clone(), input, limits) {
+            if !caused_by_skipped(&why) {
+                syntax.push(why);
+            }
+        }
+    }
+    errors.extend(syntax);
+    errors.sort_by_key(|why| why.span.map_or(usize::MAX, |span| span.0));
+    errors
+}
+
+// Значение и размерность составной единицы измерения вида "km/h" или "kg*m/s"
+pub fn parse_unit(unit: &str) -> Option<(f32, Dimension)> {
+    let mut factor = 1.0;
+    let mut dimension = DIMENSIONLESS;
+    for (index, part) in unit.split('/').enumerate() {
+        // Первая часть - числитель, остальные - знаменатели
+        let sign = if index == 0 { 1 } else { -1 };
+        for name in part.split('*') {
+            let (value, unit_dimension) = get_unit_info(name.trim())?;
+            factor = if sign > 0 { factor * value } else { factor / value };
+            dimension = combine_dimensions(dimension, unit_dimension, sign);
+        }
+    }
+
+    Some((factor, dimension))
+}
+
+// Вычисление выражения с переводом результата в заданную единицу измерения
+pub fn convert(input: &str, unit: &str, limits: &Limits) -> Result<String, CalcError> {
+    Ok(format!("{} {}", convert_value(input, unit, limits)?, unit.trim()))
+}
+
+// То же, что convert, но результат - число в заданных единицах без их обозначения
+pub fn convert_value(input: &str, unit: &str, limits: &Limits) -> Result<Value, CalcError> {
+    let (factor, dimension) = match parse_unit(unit) {
+        Some(info) => info,
+        None => {
+            let similar = similar_names(unit.trim(), unit_names());
+            let message = with_suggestions(format!("неизвестная единица измерения '{}'", unit), &similar);
+            return Err(CalcError::new(ErrorKind::UnknownUnit, &message));
+        },
+    };
+
+    let value = evaluate(parse(input, limits)?, limits)?;
+    map_value(value, &|(number, value_dimension)| {
+        if value_dimension != dimension {
+            return Err(CalcError::new(ErrorKind::UnitMismatch,
+                &format!("нельзя перевести {} в {}", dimension_name(value_dimension), unit)));
+        }
+        Ok((number / factor, DIMENSIONLESS))
+    })
+}
+
+// Дерево выражения для упрощения: строится из записи ОПН
+#[derive(Clone, PartialEq)]
+enum Expr {
+    Number(f32),
+    // Переменная, единица измерения, дата и число в особой записи (0x1f, 90°) - по тексту
+    Atom(TokenType, String),
+    Unary(Cow<'static, str>, Box<Expr>),
+    Binary(Cow<'static, str>, Box<Expr>, Box<Expr>),
+    Call(Cow<'static, str>, Vec<Expr>),
+    List(Vec<Expr>),
+}
+
+impl Expr {
+    // Дерево выражения по записи ОПН
+    fn from_rpn(output: Queue<Token>) -> Result<Expr, CalcError> {
+        let mut operands: Stack<Expr> = Stack::with_capacity(output.len());
+        let pop = |operands: &mut Stack<Expr>, count: usize| -> Result<Vec<Expr>, CalcError> {
+            if operands.len() < count {
+                return Err(invalid_output_queue());
+            }
+            let mut args: Vec<Expr> = (0..count).filter_map(|_| operands.pop()).collect();
+            args.reverse();
+            Ok(args)
+        };
+        for tok in output.into_vec() {
+            let expr = match tok.0 {
+                TokenType::NumberInt | TokenType::NumberFloat => match tok.1.parse::<f32>() {
+                    Ok(number) => Expr::Number(number),
+                    Err(_) => Expr::Atom(tok.0, tok.1.to_string()),
+                },
+                TokenType::UnaryOperator => Expr::Unary(tok.1, Box::new(pop(&mut operands, 1)?.remove(0))),
+                TokenType::BinaryOperator => {
+                    let mut args = pop(&mut operands, 2)?;
+                    let right = args.pop().ok_or_else(invalid_output_queue)?;
+                    Expr::Binary(tok.1, Box::new(args.remove(0)), Box::new(right))
+                },
+                TokenType::List => Expr::List(pop(&mut operands, list_length(&tok).unwrap_or(0))?),
+                TokenType::Function => match get_function_arity(&tok.1) {
+                    Some(arity) => Expr::Call(tok.1, pop(&mut operands, arity)?),
+                    // Аргументы неизвестной функции собраны в список
+                    None => match pop(&mut operands, 1)?.remove(0) {
+                        Expr::List(args) => Expr::Call(tok.1, args),
+                        _ => return Err(invalid_output_queue()),
+                    },
+                },
+                _ => Expr::Atom(tok.0, tok.1.to_string()),
+            };
+            operands.push(expr);
+        }
+        match (operands.pop(), operands.is_empty()) {
+            (Some(expr), true) => Ok(expr),
+            _ => Err(invalid_output_queue()),
+        }
+    }
+
+    // Число, если выражение - число
+    fn number(&self) -> Option<f32> {
+        match self {
+            Expr::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    // Есть ли в выражении единицы измерения: 0 * x не упрощается до 0, если x - величина
+    fn has_units(&self) -> bool {
+        match self {
+            Expr::Atom(token_type, _) => *token_type == TokenType::Unit,
+            Expr::Number(_) => false,
+            Expr::Unary(_, arg) => arg.has_units(),
+            Expr::Binary(_, left, right) => left.has_units() || right.has_units(),
+            Expr::Call(_, args) | Expr::List(args) => args.iter().any(Expr::has_units),
+        }
+    }
+
+    // Число с единицей измерения: 5 km записывается без знака умножения
+    fn is_quantity(&self) -> bool {
+        match self {
+            Expr::Binary(op, left, right) => op == "*" && left.number().is_some_and(|number| number >= 0.0)
+                && matches!(&**right, Expr::Atom(TokenType::Unit, _)),
+            _ => false,
+        }
+    }
+
+    fn binary(op: &'static str, left: Expr, right: Expr) -> Expr {
+        Expr::Binary(Cow::Borrowed(op), Box::new(left), Box::new(right))
+    }
+}
+
+// Приоритет оператора при записи дерева; неизвестные операторы записываются в скобках
+fn expr_priority(expr: &Expr) -> OperatorOrder {
+    match expr {
+        Expr::Number(number) if *number < 0.0 => 1,
+        expr if expr.is_quantity() => 0,
+        Expr::Unary(op, _) | Expr::Binary(op, ..) => get_op_info(op).map_or(OperatorOrder::MAX, |info| info.0),
+        _ => 0,
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Операнд в скобках, если его оператор связывает слабее (правый - и при равенстве)
+        let operand = |f: &mut fmt::Formatter, expr: &Expr, priority: OperatorOrder, right: bool| {
+            let own = expr_priority(expr);
+            match own > priority || (right && own == priority && own > 0) {
+                true => write!(f, "({})", expr),
+                false => write!(f, "{}", expr),
+            }
+        };
+        let items = |f: &mut fmt::Formatter, items: &[Expr]| {
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}", item)?;
+            }
+            Ok(())
+        };
+        match self {
+            Expr::Number(number) => write!(f, "{}", number),
+            Expr::Atom(_, text) => f.write_str(text),
+            Expr::Unary(op, arg) => {
+                f.write_str(if op == "NEG" { "-" } else { "+" })?;
+                // Под унарным оператором в скобках любое выражение с оператором: -(x + 1), -(-x)
+                operand(f, arg, 0, true)
+            },
+            Expr::Binary(_, left, right) if self.is_quantity() => write!(f, "{} {}", left, right),
+            Expr::Binary(op, left, right) => {
+                let priority = expr_priority(self);
+                operand(f, left, priority, false)?;
+                write!(f, " {} ", op)?;
+                operand(f, right, priority, true)
+            },
+            Expr::Call(name, args) => {
+                write!(f, "{}(", written_name(name))?;
+                items(f, args)?;
+                f.write_str(")")
+            },
+            Expr::List(args) => {
+                f.write_str("[")?;
+                items(f, args)?;
+                f.write_str("]")
+            },
+        }
+    }
+}
+
+// Правило упрощения: имя, пример и его упрощение, функция правила. Функция получает узел
+// дерева с уже упрощёнными операндами и возвращает замену; выражения, которые замена
+// требует считать ненулевыми, она добавляет в conditions
+type SimplifyRule = (&'static str, &'static str, &'static str, fn(&Expr, &mut Vec<Expr>) -> Option<Expr>);
+
+// Правила упрощения в порядке применения
+static SIMPLIFY_RULES: &[SimplifyRule] = &[
+    ("fold", "2 * 3 + 1", "7", fold_constants),
+    ("negation", "-(-x)", "x", remove_double_negation),
+    ("identity", "(x + 0) * 1", "x", remove_identity),
+    ("zero-product", "0 * sin(x)", "0", zero_product),
+    ("cancel", "sin(x) - sin(x)", "0", cancel_difference),
+    ("like-terms", "2 * x + 3 * x - x + 1 + 2", "4 * x + 3", combine_like_terms),
+    ("quotient", "(a * b) / b", "a", cancel_quotient),
+    ("divide-self", "(x + 1) / (x + 1)", "1", divide_self),
+];
+
+// Вычисление операций над числами: 2 * 3 -> 6
+fn fold_constants(expr: &Expr, _conditions: &mut Vec<Expr>) -> Option<Expr> {
+    let result = match expr {
+        Expr::Unary(op, arg) => match (&**op, arg.number()?) {
+            ("NEG", number) => -number,
+            ("POS", number) => number,
+            _ => return None,
+        },
+        Expr::Binary(op, left, right) => match (&**op, left.number()?, right.number()?) {
+            ("+", x, y) => x + y,
+            ("-", x, y) => x - y,
+            ("*", x, y) => x * y,
+            ("/", x, y) if y != 0.0 => x / y,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(Expr::Number(result)).filter(|_| result.is_finite())
+}
+
+// Двойная смена знака и унарный плюс: -(-x) -> x, +x -> x
+fn remove_double_negation(expr: &Expr, _conditions: &mut Vec<Expr>) -> Option<Expr> {
+    match expr {
+        Expr::Unary(op, arg) if op == "POS" => Some((**arg).clone()),
+        Expr::Unary(op, arg) if op == "NEG" => match &**arg {
+            Expr::Unary(inner, arg) if inner == "NEG" => Some((**arg).clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Нейтральные операнды: x + 0, x - 0, x * 1, x / 1 -> x; 0 - x -> -x
+fn remove_identity(expr: &Expr, _conditions: &mut Vec<Expr>) -> Option<Expr> {
+    let (op, left, right) = match expr {
+        Expr::Binary(op, left, right) => (&**op, &**left, &**right),
+        _ => return None,
+    };
+    match (op, left.number(), right.number()) {
+        ("+", _, Some(0.0)) | ("-", _, Some(0.0)) | ("*", _, Some(1.0)) | ("/", _, Some(1.0)) => Some(left.clone()),
+        ("+", Some(0.0), _) | ("*", Some(1.0), _) => Some(right.clone()),
+        ("-", Some(0.0), _) => Some(Expr::Unary(Cow::Borrowed("NEG"), Box::new(right.clone()))),
+        _ => None,
+    }
+}
+
+// Произведение на ноль: 0 * x -> 0, если x - не величина с единицами
+fn zero_product(expr: &Expr, _conditions: &mut Vec<Expr>) -> Option<Expr> {
+    match expr {
+        Expr::Binary(op, left, right) if op == "*" && !expr.has_units()
+            && (left.number() == Some(0.0) || right.number() == Some(0.0)) => Some(Expr::Number(0.0)),
+        _ => None,
+    }
+}
+
+// Разность одинаковых выражений: x - x -> 0
+fn cancel_difference(expr: &Expr, _conditions: &mut Vec<Expr>) -> Option<Expr> {
+    match expr {
+        Expr::Binary(op, left, right) if op == "-" && left == right && !left.has_units() => Some(Expr::Number(0.0)),
+        _ => None,
+    }
+}
+
+// Слагаемые суммы: коэффициент и множитель (None - число)
+fn collect_terms(expr: &Expr, sign: f32, terms: &mut Vec<(f32, Option<Expr>)>) {
+    match expr {
+        Expr::Binary(op, left, right) if op == "+" || op == "-" => {
+            collect_terms(left, sign, terms);
+            collect_terms(right, if op == "+" { sign } else { -sign }, terms);
+        },
+        Expr::Unary(op, arg) if op == "NEG" => collect_terms(arg, -sign, terms),
+        Expr::Number(number) => terms.push((sign * number, None)),
+        // Числовой множитель переходит в коэффициенты: 2 * (x + 1) - это 2 * x и 2
+        Expr::Binary(op, left, right) if op == "*" && left.number().is_some() => collect_terms(right, sign * left.number().unwrap_or(1.0), terms),
+        Expr::Binary(op, left, right) if op == "*" && right.number().is_some() => collect_terms(left, sign * right.number().unwrap_or(1.0), terms),
+        expr => terms.push((sign, Some(expr.clone()))),
+    }
+}
+
+// Приведение подобных слагаемых: 2 * x + 3 * x - x -> 4 * x; числа складываются в конце
+fn combine_like_terms(expr: &Expr, _conditions: &mut Vec<Expr>) -> Option<Expr> {
+    if !matches!(expr, Expr::Binary(op, ..) if op == "+" || op == "-") {
+        return None;
+    }
+    let mut terms: Vec<(f32, Option<Expr>)> = Vec::new();
+    collect_terms(expr, 1.0, &mut terms);
+    let mut combined: Vec<(f32, Option<Expr>)> = Vec::with_capacity(terms.len());
+    for (coefficient, factor) in terms.iter().cloned() {
+        match combined.iter_mut().find(|term| term.1 == factor) {
+            Some(term) => term.0 += coefficient,
+            None => combined.push((coefficient, factor)),
+        }
+    }
+    // Упрощать нечего, если подобных слагаемых нет
+    if combined.len() == terms.len() {
+        return None;
+    }
+    // Величины с единицами не обращаются в число 0: 5 km - 5 km остаётся величиной
+    if combined.iter().any(|term| term.0 == 0.0 && term.1.as_ref().is_some_and(Expr::has_units)) {
+        return None;
+    }
+    combined.sort_by_key(|term| term.1.is_none());
+    let mut sum: Option<Expr> = None;
+    for (coefficient, factor) in combined.into_iter().filter(|term| term.0 != 0.0) {
+        let term = |coefficient: f32| match (coefficient, factor.clone()) {
+            (coefficient, None) => Expr::Number(coefficient),
+            (1.0, Some(factor)) => factor,
+            (coefficient, Some(factor)) => Expr::binary("*", Expr::Number(coefficient), factor),
+        };
+        sum = Some(match sum {
+            None if coefficient == -1.0 && factor.is_some() => Expr::Unary(Cow::Borrowed("NEG"), Box::new(term(1.0))),
+            None => term(coefficient),
+            Some(sum) if coefficient < 0.0 => Expr::binary("-", sum, term(-coefficient)),
+            Some(sum) => Expr::binary("+", sum, term(coefficient)),
+        });
+    }
+    Some(sum.unwrap_or(Expr::Number(0.0)))
+}
+
+// Делитель, на который можно сократить: ненулевое число или выражение, которое с этого
+// момента считается ненулевым
+fn nonzero_divisor(divisor: &Expr, conditions: &mut Vec<Expr>) -> bool {
+    match divisor.number() {
+        Some(number) => number != 0.0,
+        None => {
+            if !conditions.contains(divisor) {
+                conditions.push(divisor.clone());
+            }
+            true
+        },
+    }
+}
+
+// Сокращение множителя: (a * b) / b -> a при b != 0
+fn cancel_quotient(expr: &Expr, conditions: &mut Vec<Expr>) -> Option<Expr> {
+    let (numerator, divisor) = match expr {
+        Expr::Binary(op, numerator, divisor) if op == "/" => (&**numerator, &**divisor),
+        _ => return None,
+    };
+    let rest = match numerator {
+        Expr::Binary(op, left, right) if op == "*" && **right == *divisor => (**left).clone(),
+        Expr::Binary(op, left, right) if op == "*" && **left == *divisor => (**right).clone(),
+        _ => return None,
+    };
+    Some(rest).filter(|_| nonzero_divisor(divisor, conditions))
+}
+
+// Частное одинаковых выражений: x / x -> 1 при x != 0
+fn divide_self(expr: &Expr, conditions: &mut Vec<Expr>) -> Option<Expr> {
+    match expr {
+        Expr::Binary(op, numerator, divisor) if op == "/" && numerator == divisor && !divisor.has_units() =>
+            Some(Expr::Number(1.0)).filter(|_| nonzero_divisor(divisor, conditions)),
+        _ => None,
+    }
+}
+
+// Упрощение узла дерева и его операндов; каждое применение правила - шаг вычисления
+fn simplify_expr(expr: Expr, limits: &Limits, budget: &Budget, conditions: &mut Vec<Expr>) -> Result<Expr, CalcError> {
+    let mut expr = match expr {
+        Expr::Unary(op, arg) => Expr::Unary(op, Box::new(simplify_expr(*arg, limits, budget, conditions)?)),
+        Expr::Binary(op, left, right) => Expr::Binary(op,
+            Box::new(simplify_expr(*left, limits, budget, conditions)?),
+            Box::new(simplify_expr(*right, limits, budget, conditions)?)),
+        Expr::Call(name, args) => Expr::Call(name, args.into_iter().map(|arg| simplify_expr(arg, limits, budget, conditions)).collect::<Result<_, _>>()?),
+        Expr::List(items) => Expr::List(items.into_iter().map(|item| simplify_expr(item, limits, budget, conditions)).collect::<Result<_, _>>()?),
+        expr => expr,
+    };
+    // Замена может дать узел, к которому применимо другое правило: 0 - x -> -x, -(-y) -> y
+    while let Some(simplified) = SIMPLIFY_RULES.iter().find_map(|rule| rule.3(&expr, conditions)) {
+        budget.step(limits)?;
+        expr = match simplified {
+            Expr::Unary(..) | Expr::Binary(..) => simplify_expr(simplified, limits, budget, conditions)?,
+            simplified => simplified,
+        };
+    }
+    Ok(expr)
+}
+
+// Упрощённое выражение и условия, при которых оно равно исходному
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simplified {
+    pub expression: String,
+    // Выражения, которые при упрощении считались ненулевыми: (a * b) / b -> a при b != 0
+    pub nonzero: Vec<String>,
+}
+
+// Алгебраическое упрощение выражения по правилам SIMPLIFY_RULES: вычисление операций над
+// числами, приведение подобных слагаемых, сокращение дробей
+pub fn simplify(input: &str, limits: &Limits) -> Result<Simplified, CalcError> {
+    let expr = Expr::from_rpn(parse(input, limits)?)?;
+    let mut conditions: Vec<Expr> = Vec::new();
+    let expr = simplify_expr(expr, limits, &Budget::new(limits), &mut conditions)?;
+    Ok(Simplified {
+        expression: expr.to_string(),
+        nonzero: conditions.iter().map(Expr::to_string).collect(),
+    })
+}
+
+// Правила упрощения для справки: имя, пример и его упрощение
+pub fn simplify_rules() -> impl Iterator<Item = (&'static str, &'static str, &'static str)> {
+    SIMPLIFY_RULES.iter().map(|rule| (rule.0, rule.1, rule.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Числа результата (число или список чисел) с относительной погрешностью precision:
+    // результаты вычислений не округляются
+    fn assert_numbers(value: Value, expected: &[f32], precision: f32) {
+        let numbers: Vec<f32> = match &value {
+            Value::Number(number) => vec![*number],
+            Value::List(items) => items.iter().map(|item| match item {
+                Value::Number(number) => *number,
+                _ => panic!("ожидались числа, получено {}", value),
+            }).collect(),
+            _ => panic!("ожидались числа, получено {}", value),
+        };
+        assert_eq!(numbers.len(), expected.len(), "{}", value);
+        for (number, expected) in numbers.iter().zip(expected) {
+            assert!((number - expected).abs() <= precision * expected.abs().max(1e-3), "{} вместо {}", number, expected);
+        }
+    }
+
+    #[test]
+    fn interned_names_cover_tables() {
+        assert!(INTERNED.windows(2).all(|pair| pair[0] < pair[1]));
+        let names = ["(", ")", "[", "]", ",", "|", "×", "÷", "−", "·"].iter().copied()
+            .chain(KNOWNS_OPERATORS.iter().map(|op| op.0))
+            .chain(KNOWNS_FUNCTIONS.iter().map(|function| function.0))
+            .chain(KNOWNS_UNITS.iter().map(|unit| unit.0));
+        for name in names {
+            assert!(matches!(intern(name), Cow::Borrowed(interned) if interned == name), "'{}' не в INTERNED", name);
+        }
+        assert!(matches!(intern("x"), Cow::Owned(_)));
+    }
+
+    #[test]
+    fn diagnostic_render_snapshots() {
+        let rendered = |input: &str| process(input, &Limits::default()).map(|_| ()).unwrap_err().to_text(input);
+        // Основное место и связанное с подписью; подсказка без места - после строки с ошибкой
+        assert_eq!(rendered("2 3 + 4"), [
+            "ошибка[E0010]: пропущен оператор между '2' и '3'",
+            "  | 2 3 + 4",
+            "  | - ^",
+            "  | |",
+            "  | значение без оператора после него",
+            "  = подсказка: например, '2 * 3'",
+        ].join("\n"));
+        // Столбец и ширина подчёркивания - в символах, а не в байтах
+        assert_eq!(rendered("sin(90°) × ж"), [
+            "ошибка[E0001]: неизвестная лексема!",
+            "  | sin(90°) × ж",
+            "  |            ^",
+            "  = подсказка: допустимы числа, латинские имена, операторы, скобки, ',' и '|'",
+        ].join("\n"));
+        assert_eq!(rendered("−5 + foo"), "ошибка[E0023]: неизвестная переменная 'foo'\n  | −5 + foo\n  |      ^^^");
+        assert_eq!(rendered("[1, 2) + 3"), [
+            "ошибка[E0002]: в выражении пропущена закрывающая скобка ']'",
+            "  | [1, 2) + 3",
+            "  | -    ^",
+            "  | |",
+            "  | список начинается здесь",
+        ].join("\n"));
+        assert_eq!(rendered("2024-02-30 + 1 d"), "ошибка[E0021]: некорректная дата '2024-02-30'\n  | 2024-02-30 + 1 d\n  | ^^^^^^^^^^");
+
+        // Несколько подписей: самая правая - в строке подчёркивания, остальные - ниже, с линиями
+        // к своим местам; выводится только строка входа с основным местом
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            code: None,
+            message: "подозрительное выражение".to_string(),
+            span: Some((19, 21)),
+            labels: vec![((10, 11), "первая"), ((14, 16), "вторая"), ((24, 26), "третья"), ((0, 3), "в другой строке")]
+                .into_iter().map(|(span, label)| (span, label.to_string())).collect(),
+            notes: vec!["первая подсказка".to_string(), "вторая подсказка".to_string()],
+        };
+        assert_eq!(diagnostic.render("x = 1\ny = 2 * ab + cd + ef"), [
+            "предупреждение: подозрительное выражение",
+            "  | y = 2 * ab + cd + ef",
+            "  |     -   --   ^^   -- третья",
+            "  |     |   |",
+            "  |     |   вторая",
+            "  |     |",
+            "  |     первая",
+            "  = подсказка: первая подсказка",
+            "  = подсказка: вторая подсказка",
+        ].join("\n"));
+        // Без места - только сообщение и подсказки
+        let diagnostic = Diagnostic { severity: Severity::Note, code: Some("E0007"), message: "пусто".to_string(), span: None, labels: Vec::new(), notes: Vec::new() };
+        assert_eq!(diagnostic.render("1"), "примечание[E0007]: пусто");
+    }
+
+    #[test]
+    fn parse_errors_reports_independent_errors() {
+        let errors = |input: &str| parse_errors(input, &Limits::default()).into_iter()
+            .map(|why| (why.kind, why.span, why.message))
+            .collect::<Vec<(ErrorKind, Option<Span>, String)>>();
+        let error = |kind: ErrorKind, span: Span, message: &str| (kind, Some(span), message.to_string());
+        assert_eq!(errors("2 3 + 4 5"), [
+            error(ErrorKind::MissingOperator, (2, 3), "пропущен оператор между '2' и '3'"),
+            error(ErrorKind::MissingOperator, (8, 9), "пропущен оператор между '4' и '5'"),
+        ]);
+        assert_eq!(errors("1 $ 2 @ 3"), [
+            error(ErrorKind::UnknownToken, (2, 3), "неизвестная лексема!"),
+            error(ErrorKind::UnknownToken, (6, 7), "неизвестная лексема!"),
+        ]);
+        assert_eq!(errors("1 + * 2 - / 3"), [
+            error(ErrorKind::DanglingOperator, (4, 5), "пропущен операнд перед оператором '*'"),
+            error(ErrorKind::DanglingOperator, (10, 11), "пропущен операнд перед оператором '/'"),
+        ]);
+        assert_eq!(errors("[1, 2 3] + (4 5)"), [
+            error(ErrorKind::MissingOperator, (6, 7), "пропущен оператор между '2' и '3'"),
+            error(ErrorKind::MissingOperator, (14, 15), "пропущен оператор между '4' и '5'"),
+        ]);
+        // Ошибки разных видов - по порядку мест в строке; места многобайтовых лексем - в байтах
+        assert_eq!(errors("1 2 ж 3 4"), [
+            error(ErrorKind::MissingOperator, (2, 3), "пропущен оператор между '1' и '2'"),
+            error(ErrorKind::UnknownToken, (4, 6), "неизвестная лексема!"),
+            error(ErrorKind::MissingOperator, (9, 10), "пропущен оператор между '3' и '4'"),
+        ]);
+        assert_eq!(errors("2 3 + $"), [
+            error(ErrorKind::MissingOperator, (2, 3), "пропущен оператор между '2' и '3'"),
+            error(ErrorKind::UnknownToken, (6, 7), "неизвестная лексема!"),
+        ]);
+
+        // Ошибки, вызванные пропущенной лексемой, не сообщаются: между 2 и 3 оператор был
+        // бы на месте '$', а '+' перед '$' не остался бы без операнда
+        assert_eq!(errors("2 $ 3 + 4 5"), [
+            error(ErrorKind::UnknownToken, (2, 3), "неизвестная лексема!"),
+            error(ErrorKind::MissingOperator, (10, 11), "пропущен оператор между '4' и '5'"),
+        ]);
+        assert_eq!(errors("1 + $ + 3"), [error(ErrorKind::UnknownToken, (4, 5), "неизвестная лексема!")]);
+        assert_eq!(errors("$3 + 4 5"), [
+            error(ErrorKind::UnknownToken, (0, 1), "неизвестная лексема!"),
+            error(ErrorKind::MissingOperator, (7, 8), "пропущен оператор между '4' и '5'"),
+        ]);
+
+        // Скобки проверяются, только если операторы расставлены верно
+        assert_eq!(errors("(1 + 2"), [error(ErrorKind::MissingParenthesis, (0, 1), "в выражении пропущена закрывающая скобка")]);
+        assert_eq!(errors("(1 + 2 3"), [error(ErrorKind::MissingOperator, (7, 8), "пропущен оператор между '2' и '3'")]);
+        assert_eq!(errors("1 +"), [error(ErrorKind::DanglingOperator, (2, 3), "выражение заканчивается оператором '+'")]);
+        assert_eq!(errors("   "), [(ErrorKind::EmptyExpression, None, "пустое выражение".to_string())]);
+        assert!(errors("(1 + 2) * 3").is_empty());
+    }
+
+    #[test]
+    fn operator_stack_depth_is_limited() {
+        let limits = Limits { max_depth: 8, ..Limits::default() };
+        let value = |input: &str, limits: &Limits| process(input, limits).map(|evaluation| evaluation.value);
+        // Цепочка правоассоциативных унарных минусов держит все операторы в стеке до конца выражения
+        let chain = |count: usize| format!("{}1", "-".repeat(count));
+        assert_eq!(value(&chain(8), &limits).unwrap(), Value::Number(1.0));
+        let why = value(&chain(9), &limits).expect_err("девять операторов не помещаются в стек");
+        assert_eq!((why.kind, why.message.as_str()), (ErrorKind::TooDeep, "превышена максимальная глубина выражения (8)"));
+        // Скобки тоже занимают место в стеке
+        assert_eq!(value("((-(-(-(1)))))", &limits).unwrap(), Value::Number(-1.0));
+        assert_eq!(value("(((-(-(-(1)))))))", &limits).expect_err("скобки и минусы").kind, ErrorKind::TooDeep);
+        assert_eq!(value("1 + (2 * (3 + (4 * (5 + (6 * -7)))))", &limits).expect_err("операторы между скобками").kind, ErrorKind::TooDeep);
+        // То же ограничение и при вычислении в Workspace, который переиспользует стек операторов
+        let mut workspace = Workspace::new();
+        let input = chain(9);
+        let why = workspace.evaluate(tokerize(&input, &limits).unwrap(), &input, &limits, &NoResolver).expect_err("глубже 8");
+        assert_eq!(why.kind, ErrorKind::TooDeep);
+        let input = chain(4);
+        assert_eq!(workspace.evaluate(tokerize(&input, &limits).unwrap(), &input, &limits, &NoResolver).unwrap(), Value::Number(1.0));
+        // Ограничение по умолчанию пропускает обычные выражения, но не тысячи вложенных операторов
+        assert!(value(&chain(200), &Limits::default()).is_ok());
+        assert_eq!(value(&chain(1000), &Limits::default()).expect_err("глубже 256").kind, ErrorKind::TooDeep);
+    }
+
+    // Типы и значения токенов выражения
+    fn tokens(input: &str) -> Vec<(TokenType, String)> {
+        tokerize(input, &Limits::default()).unwrap().into_iter()
+            .filter(|tok| tok.0 != TokenType::Whitespaces)
+            .map(|tok| (tok.0, tok.1.into_owned()))
+            .collect()
+    }
+
+    // Запись ОПН и результат выражения
+    fn rpn_and_result(input: &str) -> (String, String) {
+        let evaluation = process(input, &Limits::default()).unwrap();
+        (evaluation.rpn, evaluation.result)
+    }
+
+    #[test]
+    fn minus_before_number_is_unary_operator() {
+        use TokenType::*;
+        let number = |value: &str| (NumberInt, value.to_string());
+        let op = |token_type: TokenType, value: &str| (token_type, value.to_string());
+        assert_eq!(tokens("(-5)*2"), vec![op(OpenedParenthesis, "("), op(UnaryOperator, "NEG"), number("5"),
+            op(ClosedParenthesis, ")"), op(BinaryOperator, "*"), number("2")]);
+        assert_eq!(tokens("3*-2"), vec![number("3"), op(BinaryOperator, "*"), op(UnaryOperator, "NEG"), number("2")]);
+        assert_eq!(tokens("2--3"), vec![number("2"), op(BinaryOperator, "-"), op(UnaryOperator, "NEG"), number("3")]);
+        assert_eq!(tokens("-(-2)"), vec![op(UnaryOperator, "NEG"), op(OpenedParenthesis, "("), op(UnaryOperator, "NEG"),
+            number("2"), op(ClosedParenthesis, ")")]);
+        assert_eq!(tokens("2 - -3"), vec![number("2"), op(BinaryOperator, "-"), op(UnaryOperator, "NEG"), number("3")]);
+        assert_eq!(tokens("+2"), vec![op(UnaryOperator, "POS"), number("2")]);
+        assert_eq!(tokens("2−3"), vec![number("2"), op(BinaryOperator, "-"), number("3")]);
+    }
+
+    #[test]
+    fn minus_cases_evaluate() {
+        let expected = |rpn: &str, result: &str| (rpn.to_string(), result.to_string());
+        assert_eq!(rpn_and_result("(-5)*2"), expected("5 NEG 2 *", "-10.00"));
+        assert_eq!(rpn_and_result("3*-2"), expected("3 2 NEG *", "-6.00"));
+        assert_eq!(rpn_and_result("2--3"), expected("2 3 NEG -", "5.00"));
+        assert_eq!(rpn_and_result("-(-2)"), expected("2 NEG NEG", "2.00"));
+        assert_eq!(rpn_and_result("--2"), expected("2 NEG NEG", "2.00"));
+        assert_eq!(rpn_and_result("-2*-3"), expected("2 NEG 3 NEG *", "6.00"));
+        assert_eq!(rpn_and_result("2-(-3)"), expected("2 3 NEG -", "5.00"));
+        assert_eq!(rpn_and_result("-3-2"), expected("3 NEG 2 -", "-5.00"));
+        assert_eq!(rpn_and_result("abs(-2.5)"), expected("2.5 NEG abs", "2.50"));
+    }
+
+    #[test]
+    fn absolute_value_bars() {
+        let limits = Limits::default();
+        assert_eq!(process("||-3| - |5|| * 2", &limits).unwrap().value, Value::Number(4.0));
+        let error = |input: &str| {
+            let why = process(input, &limits).map(|evaluation| evaluation.value).expect_err("черта без пары");
+            (why.kind, why.message, why.span)
+        };
+        // Черта без пары - своя ошибка с местом черты, а не пропущенная скобка, которой нет в строке
+        let unpaired = |start: usize| (ErrorKind::MissingParenthesis, "у черты модуля '|' нет парной".to_string(), Some((start, start + 1)));
+        assert_eq!(error("|2 + 1"), unpaired(0));
+        assert_eq!(error("2 + |3"), unpaired(4));
+        assert_eq!(error("(|2)"), unpaired(1));
+        assert_eq!(error("|(2|"), (ErrorKind::MissingParenthesis, "в выражении пропущена закрывающая скобка ')'".to_string(), Some((3, 4))));
+    }
+
+    // Число, записанное с заданным округлением и, если задано, числом значащих цифр
+    fn rounded(number: f32, rounding: Rounding, significant: Option<usize>) -> String {
+        let format = NumberFormat { rounding, significant, ..NumberFormat::default() };
+        Value::Number(number).formatted(format).to_string()
+    }
+
+    #[test]
+    fn rounding_modes_on_ties() {
+        let cases = [
+            (Rounding::HalfEven, ["2", "4", "-2"]),
+            (Rounding::HalfUp, ["3", "4", "-3"]),
+            (Rounding::Truncate, ["2", "3", "-2"]),
+        ];
+        for (rounding, expected) in cases {
+            let written = [2.5, 3.5, -2.5].map(|number| rounded(number, rounding, Some(1)));
+            assert_eq!(written, expected, "{:?}", rounding);
+        }
+    }
+
+    #[test]
+    fn results_are_rounded_only_when_written() {
+        let limits = Limits::default();
+        let value = |input: &str| process(input, &limits).unwrap().value;
+        // Промежуточные результаты не округляются до сотых
+        assert_eq!(value("1 / 3 * 3"), Value::Number(1.0));
+        assert_eq!(value("0.004 + 0.004"), Value::Number(0.008));
+        assert_eq!(value("-(0.001)"), Value::Number(-0.001));
+        assert_eq!(process("1 / 3", &limits).unwrap().result, "0.33");
+        let significant = NumberFormat { significant: Some(3), ..NumberFormat::default() };
+        assert_eq!(value("1 / 3").formatted(significant).to_string(), "0.333");
+        assert_eq!(value("sqrt(2) / 1000").formatted(significant).to_string(), "0.00141");
+    }
+
+    #[test]
+    fn scientific_and_engineering_notation() {
+        let limits = Limits::default();
+        let written = |input: &str, notation: Notation| {
+            let format = NumberFormat { notation, ..NumberFormat::default() };
+            process(input, &limits).unwrap().value.formatted(format).to_string()
+        };
+        assert_eq!(written("12345678", Notation::Scientific), "1.2345678e7");
+        assert_eq!(written("12345678", Notation::Engineering), "12.345678e6");
+        // Малые результаты вычислений не обращаются в ноль
+        assert_eq!(written("3 / 1000", Notation::Scientific), "3e-3");
+        assert_eq!(written("47 / 1000000", Notation::Engineering), "47e-6");
+        assert_eq!(written("0.5 * 0.005", Notation::Engineering), "2.5e-3");
+        assert_eq!(written("-1 / 4000", Notation::Engineering), "-250e-6");
+        assert_eq!(written("0.001 / 2", Notation::Auto), "5e-4");
+        assert_eq!(written("0", Notation::Engineering), "0e0");
+    }
+
+    #[test]
+    fn grouped_integer_part() {
+        let grouped = |value: f32, grouping: char| {
+            Value::Number(value).formatted(NumberFormat { grouping: Some(grouping), ..NumberFormat::default() }).to_string()
+        };
+        // 1234567.5 записывается в f32 точно, в отличие от 1234567.89
+        assert_eq!(grouped(1_234_567.5, ' '), "1 234 567.50");
+        assert_eq!(grouped(1_234_567.5, ','), "1,234,567.50");
+        assert_eq!(grouped(-1000.0, ','), "-1,000.00");
+        assert_eq!(grouped(999.0, ','), "999.00");
+    }
+
+    #[test]
+    fn fraction_display() {
+        let limits = Limits::default();
+        let written = |input: &str, mixed: bool| {
+            let format = NumberFormat { fraction: true, mixed, ..NumberFormat::default() };
+            process(input, &limits).unwrap().value.formatted(format).to_string()
+        };
+        assert_eq!(written("1 / 3", false), "1/3");
+        assert_eq!(written("7 / 3", false), "7/3");
+        assert_eq!(written("1 / 7 + 1 / 7", false), "2/7");
+        assert_eq!(written("(1 / 3 + 1 / 6) * 3", false), "3/2");
+        assert_eq!(written("2 / 3 * 3 / 7 - 1 / 9", false), "11/63");
+        assert_eq!(written("0.1 + 0.2", false), "3/10");
+        assert_eq!(written("6 / 3", false), "2");
+        assert_eq!(written("0", false), "0");
+        // Дробь точно равна числу, а не его сотым
+        assert_eq!(written("0.33", false), "33/100");
+        assert_eq!(written("1 / 300", false), "1/300");
+        assert_eq!(written("sqrt(2)", false), "1.41");
+        assert_eq!(written("1 / 0", false), "inf");
+    }
+
+    #[test]
+    fn mixed_number_display() {
+        let limits = Limits::default();
+        let written = |input: &str| {
+            let format = NumberFormat { fraction: true, mixed: true, ..NumberFormat::default() };
+            process(input, &limits).unwrap().value.formatted(format).to_string()
+        };
+        assert_eq!(written("7 / 3"), "2 1/3");
+        assert_eq!(written("-7 / 3"), "-2 1/3");
+        assert_eq!(written("10 / 4 + 1 / 100"), "2 51/100");
+        assert_eq!(written("2 / 3"), "2/3");
+        assert_eq!(written("9 / 3"), "3");
+    }
+
+    #[test]
+    fn rounding_modes_on_hundredths() {
+        let cases = [
+            (Rounding::HalfEven, ["0.12", "0.38", "-0.12", "2.50"]),
+            (Rounding::HalfUp, ["0.13", "0.38", "-0.13", "2.50"]),
+            (Rounding::Truncate, ["0.12", "0.37", "-0.12", "2.50"]),
+        ];
+        for (rounding, expected) in cases {
+            let written = [0.125, 0.375, -0.125, 2.5].map(|number| rounded(number, rounding, None));
+            assert_eq!(written, expected, "{:?}", rounding);
+        }
+    }
+
+    // Вид и место ошибки разбора с заданными ограничениями
+    fn rejected(input: &str, options: ParserOptions) -> (ErrorKind, Option<Span>) {
+        let why = parse_with_options(input, &Limits::default(), &options).expect_err("выражение должно быть отклонено");
+        (why.kind, why.span)
+    }
+
+    #[test]
+    fn parser_options_reject_disabled_classes() {
+        let defaults = ParserOptions::default();
+        let no_functions = ParserOptions { functions: false, ..defaults };
+        assert_eq!(rejected("1 + sqrt(4)", no_functions), (ErrorKind::Forbidden, Some((4, 8))));
+        assert!(parse_with_options("|-2| + 1", &Limits::default(), &no_functions).is_ok());
+
+        let no_bit_operators = ParserOptions { bit_operators: false, ..defaults };
+        assert_eq!(rejected("1 << 2", no_bit_operators), (ErrorKind::Forbidden, Some((2, 4))));
+        assert_eq!(rejected("8 >> 1", no_bit_operators), (ErrorKind::Forbidden, Some((2, 4))));
+        assert!(parse_with_options("8 * 2", &Limits::default(), &no_bit_operators).is_ok());
+
+        let lowercase = ParserOptions { identifiers: |c| c.is_ascii_lowercase(), ..defaults };
+        assert_eq!(rejected("abs(2) + Abs(3)", lowercase), (ErrorKind::Forbidden, Some((9, 12))));
+        assert!(parse_with_options("5 km", &Limits::default(), &lowercase).is_ok());
+
+        let three_tokens = ParserOptions { max_tokens: Some(3), ..defaults };
+        assert_eq!(rejected("1 + 2 + 3", three_tokens), (ErrorKind::TooComplex, None));
+        assert!(parse_with_options("1 + 2", &Limits::default(), &three_tokens).is_ok());
+    }
+
+    // Результат выражения в целочисленном режиме или вид ошибки
+    fn integer(input: &str, division: IntegerDivision) -> Result<i64, ErrorKind> {
+        let limits = Limits::default();
+        parse(input, &limits).and_then(|output| evaluate_integer(output, &limits, division)).map_err(|why| why.kind)
+    }
+
+    #[test]
+    fn integer_mode_division() {
+        assert_eq!(integer("7/2", IntegerDivision::Exact), Err(ErrorKind::InexactDivision));
+        assert_eq!(integer("8/2", IntegerDivision::Exact), Ok(4));
+        assert_eq!(integer("7/2", IntegerDivision::Truncate), Ok(3));
+        assert_eq!(integer("-7/2", IntegerDivision::Truncate), Ok(-3));
+        assert_eq!(integer("7/0", IntegerDivision::Truncate), Err(ErrorKind::DivisionByZero));
+        assert_eq!(integer("-7 % 3", IntegerDivision::Exact), Ok(-1));
+        assert_eq!(integer("7 % 0", IntegerDivision::Exact), Err(ErrorKind::DivisionByZero));
+    }
+
+    #[test]
+    fn integer_mode_rejects_fractions() {
+        assert_eq!(integer("1.5", IntegerDivision::Exact), Err(ErrorKind::InvalidNumber));
+        assert_eq!(integer("2 * 1.5", IntegerDivision::Truncate), Err(ErrorKind::InvalidNumber));
+    }
+
+    #[test]
+    fn integer_mode_overflow() {
+        let max = i64::MAX.to_string();
+        assert_eq!(integer(&max, IntegerDivision::Exact), Ok(i64::MAX));
+        assert_eq!(integer(&format!("{} + 1", max), IntegerDivision::Exact), Err(ErrorKind::Overflow));
+        assert_eq!(integer(&format!("-{} - 2", max), IntegerDivision::Exact), Err(ErrorKind::Overflow));
+        assert_eq!(integer("1 << 62", IntegerDivision::Exact), Ok(1 << 62));
+        assert_eq!(integer("1 << 63", IntegerDivision::Exact), Err(ErrorKind::Overflow));
+        assert_eq!(integer("1 << 64", IntegerDivision::Exact), Err(ErrorKind::InvalidShift));
+        assert_eq!(integer("pow(2, 63)", IntegerDivision::Exact), Err(ErrorKind::Overflow));
+    }
+
+    #[test]
+    fn float_shift_overflow() {
+        let limits = Limits::default();
+        let kind = |input: &str| process(input, &limits).map(|evaluation| evaluation.result).map_err(|why| why.kind);
+        assert_eq!(kind("1 << 62"), Ok("4611686018427387904.00".to_string()));
+        assert_eq!(kind("1 << 63"), Err(ErrorKind::Overflow));
+        assert_eq!(kind("1.5 << 1"), Err(ErrorKind::InvalidShift));
+    }
+
+    #[test]
+    fn table_evaluates_expression_per_row() {
+        let limits = Limits::default();
+        let row = |x: f32, y: f32| Value::List(vec![Value::Number(x), Value::Number(y)]);
+        assert_eq!(process("table(x * x, x, 0, 1, 0.5)", &limits).unwrap().value,
+            Value::List(vec![row(0.0, 0.0), row(0.5, 0.25), row(1.0, 1.0)]));
+        assert_eq!(process("sum(table(x, x, 1, 3, 1))", &limits).unwrap().value, Value::Number(12.0));
+        let kind = |input: &str| process(input, &limits).map_err(|why| why.kind).err();
+        assert_eq!(kind("table(x + y, x, 0, 1, 1)"), Some(ErrorKind::UnknownVariable));
+        assert_eq!(kind("table(x, 2, 0, 1, 1)"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("table(x, x, 0, 1, 0)"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("table(x, x, 0, 1000000, 1)"), Some(ErrorKind::BudgetExceeded));
+        // Строки таблицы вычисляются из оставшихся шагов выражения, а не из нового лимита
+        let limits = Limits { max_steps: 300, ..Limits::default() };
+        assert!(process("sum(table(x * x, x, 0, 50, 1))", &limits).is_ok());
+        // Таблицы разные: одинаковая вычислилась бы один раз как общее подвыражение
+        assert_eq!(process("sum(table(x * x, x, 0, 50, 1)) + sum(table(x * x, x, 1, 51, 1))", &limits)
+            .map_err(|why| why.kind).err(), Some(ErrorKind::BudgetExceeded));
+        assert!(process("sum(table(x * x, x, 0, 50, 1)) + sum(table(x * x, x, 0, 50, 1))", &limits).is_ok());
+    }
+
+    #[test]
+    fn nested_bound_functions_share_budget() {
+        // Каждая строка внутренней таблицы укладывается в лимит, вся вложенная таблица - нет
+        let input = "sum(table(sum(table(x * y, y, 0, 50, 1)), x, 0, 50, 1))";
+        let limits = Limits { max_steps: 1_000, ..Limits::default() };
+        assert_eq!(process(input, &limits).map_err(|why| why.kind).err(), Some(ErrorKind::BudgetExceeded));
+        assert!(process(input, &Limits::default()).is_ok());
+    }
+
+    #[test]
+    fn sum_and_prod_index_forms() {
+        let limits = Limits::default();
+        let evaluation = process("sum(i, 1, 100, pow(i, 2))", &limits).unwrap();
+        assert_eq!(evaluation.rpn, "i 1 100 i 2 pow SUM");
+        assert_eq!(evaluation.value, Value::Number(338_350.0));
+        let value = |input: &str| process(input, &limits).unwrap().value;
+        assert_eq!(value("prod(k, 1, 10, k)"), Value::Number(3_628_800.0));
+        assert_eq!(value("sum(i, 1, 3, sum(j, 1, i, j))"), Value::Number(10.0));
+        assert_eq!(value("sum(i, 1, 3, i km)"), Value::Quantity(6000.0, [1, 0, 0, 0]));
+        // Пустой диапазон - нейтральный элемент
+        assert_eq!(value("sum(i, 5, 1, i) + prod(i, 5, 1, i)"), Value::Number(1.0));
+        // Статистика по списку не изменилась
+        assert_eq!(value("sum([1, 2, 3])"), Value::Number(6.0));
+
+        let kind = |input: &str| process(input, &limits).map_err(|why| why.kind).err();
+        assert_eq!(kind("sum(i, 1.5, 3, i)"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("sum(m, 1, 3, 2)"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("prod(i, 1, 3)"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("sum(i, 1, 3, i * y)"), Some(ErrorKind::UnknownVariable));
+        // Вложенные суммы расходуют шаги одного вычисления
+        let small = Limits { max_steps: 1_000, ..Limits::default() };
+        assert_eq!(process("sum(i, 1, 30, sum(j, 1, 30, i * j))", &small).map_err(|why| why.kind).err(), Some(ErrorKind::BudgetExceeded));
+    }
+
+    // Функции пользователя для тестов: имя, параметры и выражение
+    struct Functions(Vec<(&'static str, Definition)>);
+
+    impl Functions {
+        fn new(definitions: &[(&'static str, &[&str], &str)]) -> Functions {
+            let limits = Limits::default();
+            Functions(definitions.iter().map(|(name, params, expression)| {
+                let params = params.iter().map(|param| param.to_string()).collect();
+                (*name, Definition::new(params, expression, &limits).unwrap())
+            }).collect())
+        }
+    }
+
+    impl Resolver for Functions {
+        fn variable(&self, _name: &str) -> Option<Value> {
+            None
+        }
+
+        fn call(&self, _name: &str, _args: &[Value]) -> Option<Value> {
+            None
+        }
+
+        fn definition(&self, name: &str) -> Option<&Definition> {
+            self.0.iter().find(|function| function.0 == name).map(|function| &function.1)
+        }
+    }
+
+    #[test]
+    fn recursive_user_functions() {
+        let functions = Functions::new(&[
+            ("fact", &["n"], "if(n <= 1, 1, n * fact(n - 1))"),
+            ("fib", &["n"], "if(n < 2, n, fib(n - 1) + fib(n - 2))"),
+            ("depth", &["n"], "if(n <= 0, 0, 1 + depth(n - 1))"),
+            ("loop", &["n"], "loop(n)"),
+            // Аргументы видны и в выражениях функций со связанной переменной
+            ("tri", &["n"], "sum(i, 1, n, i * n)"),
+        ]);
+        let limits = Limits::default();
+        let value = |input: &str, limits: &Limits| process_with(input, limits, &functions).map(|evaluation| evaluation.value);
+        assert_eq!(value("fact(10)", &limits).unwrap(), Value::Number(3_628_800.0));
+        assert_eq!(value("fib(15)", &limits).unwrap(), Value::Number(610.0));
+        assert_eq!(value("tri(3)", &limits).unwrap(), Value::Number(18.0));
+        assert_eq!(value("fact(1, 2)", &limits).expect_err("лишний аргумент").kind, ErrorKind::InvalidFunctionCall);
+        // Ошибка в теле функции указывает на её вызов во вводе
+        let why = value("2 + loop(1)", &limits).expect_err("бесконечная рекурсия");
+        assert_eq!((why.kind, why.span), (ErrorKind::TooDeep, Some((4, 8))));
+        let shallow = Limits { max_recursion: 5, ..Limits::default() };
+        assert_eq!(value("fact(5)", &shallow).unwrap(), Value::Number(120.0));
+        assert_eq!(value("fact(6)", &shallow).expect_err("глубже 5").kind, ErrorKind::TooDeep);
+        // Вызовы - кадры в куче: глубокая рекурсия не переполняет стек потока теста
+        let deep = Limits { max_recursion: 200_000, max_steps: usize::MAX, ..Limits::default() };
+        assert_eq!(value("depth(100000)", &deep).unwrap(), Value::Number(100_000.0));
+    }
+
+    #[test]
+    fn shares_common_subexpressions() {
+        let limits = Limits::default();
+        let evaluation = process("pow(sin(2), 2) + pow(sin(2), 2) * 2", &limits).unwrap();
+        assert_eq!(evaluation.value, process("3 * pow(sin(2), 2)", &limits).unwrap().value);
+        assert_eq!(evaluation.trace, Trace { steps: 10, shared: 1, hits: 1 });
+        // Вложенное в общее подвыражение отмечается, только если встречается и вне его
+        let trace = |input: &str| process(input, &limits).unwrap().trace;
+        assert_eq!(trace("sqrt(2 + 1) * sqrt(2 + 1) + (2 + 1)"), Trace { steps: 12, shared: 2, hits: 2 });
+        assert_eq!(trace("sqrt(2 + 1) * sqrt(2 + 1)").shared, 1);
+        assert_eq!(trace("1 + 2 + 3").shared, 0);
+        // now() меняется со временем и не запоминается
+        #[cfg(feature = "std")]
+        assert_eq!(trace("(now() - now()) + (now() - now())").shared, 0);
+        // Запомненные значения - свои у каждого вызова функции пользователя
+        let functions = Functions::new(&[("fib", &["n"], "if(n < 2, n, fib(n - 1) + fib(n - 2)) + 0 * (n - 1) * (n - 1)")]);
+        let evaluation = process_with("fib(10)", &limits, &functions).unwrap();
+        assert_eq!(evaluation.value, Value::Number(55.0));
+        // В каждом вызове fib повторное n - 1 берётся из запомненного
+        assert!(evaluation.trace.hits > 100);
+        // Компилированное выражение со слотами переменных
+        let compiled = Compiled::new("pow(x * y, 2) - pow(x * y, 2) + x * y").unwrap();
+        assert_eq!(compiled.eval(&[("x", 2.0), ("y", 3.0)]).unwrap(), Value::Number(6.0));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn nested_bound_functions_share_deadline() {
+        let input = "sum(table(sum(table(x * y, y, 0, 2000, 1)), x, 0, 2000, 1))";
+        let limits = Limits { max_steps: usize::MAX, timeout: Some(Duration::from_millis(10)), ..Limits::default() };
+        let started = Instant::now();
+        assert_eq!(process(input, &limits).map_err(|why| why.kind).err(), Some(ErrorKind::Timeout));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn integrate_uses_adaptive_simpson() {
+        let limits = Limits::default();
+        let number = |input: &str| process(input, &limits).unwrap().value;
+        assert_eq!(number("integrate(x * x, x, 0, 3)"), Value::Number(9.0));
+        assert_eq!(number("integrate(sin(x), x, 0, 3.14159)"), Value::Number(2.0));
+        assert_eq!(number("integrate(x, x, 1, 0)"), Value::Number(-0.5));
+        // Малые интегралы не теряются: погрешность оценивается относительно значения интеграла
+        assert_numbers(number("integrate(x * x, x, 0, 0.1)"), &[3.333_333e-4], limits.tolerance);
+        assert_numbers(number("integrate(sin(x), x, 0, 0.01)"), &[4.999_958e-5], limits.tolerance);
+        assert_numbers(number("integrate(sqrt(x), x, 0, 0.001)"), &[2.108_185e-5], limits.tolerance);
+        assert_numbers(number("integrate(exp(-1000 * x * x), x, 0, 1)"), &[0.028_025_0], limits.tolerance);
+        let kind = |input: &str| process(input, &limits).map_err(|why| why.kind).err();
+        assert_eq!(kind("integrate(x * y, x, 0, 1)"), Some(ErrorKind::UnknownVariable));
+        assert_eq!(kind("integrate(1 / x, x, 0, 1)"), Some(ErrorKind::NoResult));
+        assert_eq!(kind("integrate(x * 5 km, x, 0, 1)"), Some(ErrorKind::InvalidFunctionCall));
+        // Точки интеграла расходуют шаги вызвавшего его выражения
+        let limits = Limits { max_steps: 1_000, ..Limits::default() };
+        assert!(process("integrate(x * x, x, 0, 1)", &limits).is_ok());
+        assert_eq!(process("sum(table(integrate(x * y, y, 0, 1), x, 0, 20, 1))", &limits).map_err(|why| why.kind).err(),
+            Some(ErrorKind::BudgetExceeded));
+    }
+
+    #[test]
+    fn root_uses_newton_with_bisection_fallback() {
+        let limits = Limits::default();
+        let number = |input: &str| process(input, &limits).unwrap().value;
+        assert_numbers(number("root(x * x - 2, x, 1)"), &[core::f32::consts::SQRT_2], limits.tolerance);
+        // В нуле производная равна нулю, и корень находит деление пополам
+        assert_numbers(number("root(x * x - 2, x, 0)"), &[core::f32::consts::SQRT_2], limits.tolerance);
+        assert_numbers(number("root(cos(x) - x, x, 1)"), &[0.739_085_1], limits.tolerance);
+        // Малые корни находятся с той же относительной точностью
+        assert_numbers(number("root(x - 0.001, x, 1)"), &[0.001], limits.tolerance);
+        assert_numbers(number("root(x * x - 0.000001, x, 1)"), &[0.001], limits.tolerance);
+        assert_numbers(number("root(1000 * x - 0.002, x, 0)"), &[2e-6], limits.tolerance);
+        match number("root(x * x, x, 1)") {
+            Value::Number(x) => assert!(x.abs() < 1e-6, "{}", x),
+            value => panic!("ожидалось число, получено {}", value),
+        }
+        let why = process("root(x * x + 1, x, 1)", &limits).err().expect("у x * x + 1 нет корней");
+        assert_eq!(why.kind, ErrorKind::NoConvergence);
+        assert!(why.message.contains("последнее приближение x = "), "{}", why.message);
+        let kind = |input: &str| process(input, &limits).map_err(|why| why.kind).err();
+        assert_eq!(kind("root(1 / x, x, 1)"), Some(ErrorKind::NoConvergence));
+        assert_eq!(kind("root(x, 2, 1)"), Some(ErrorKind::InvalidFunctionCall));
+        let limits = Limits { max_steps: 500, ..Limits::default() };
+        assert!(process("root(cos(x) - x, x, 1)", &limits).is_ok());
+        assert_eq!(process("sum(table(root(cos(x) - x * y, x, 1), y, 1, 50, 1))", &limits).map_err(|why| why.kind).err(),
+            Some(ErrorKind::BudgetExceeded));
+    }
+
+    #[test]
+    fn solve_linear_and_quadratic_equations() {
+        let limits = Limits::default();
+        let number = |input: &str| process(input, &limits).unwrap().value;
+        assert_numbers(number("solve(2 * x + 3 = 11, x)"), &[4.0], 1e-5);
+        assert_numbers(number("solve(x / 3 = 1, x)"), &[3.0], 1e-5);
+        assert_numbers(number("solve(x * x = 4, x)"), &[-2.0, 2.0], 1e-5);
+        assert_numbers(number("solve(x * x - 2 * x + 1 = 0, x)"), &[1.0], 1e-5);
+        assert_numbers(number("solve(3 = x - 1, x) * 2"), &[8.0], 1e-5);
+        assert_numbers(number("solve(x * x / 3 - 2 * x / 3 + 1 / 3 = 0, x)"), &[1.0], 1e-5);
+        assert_numbers(number("solve(0.001 * x = 0.002, x)"), &[2.0], 1e-5);
+        let kind = |input: &str| process(input, &limits).map_err(|why| why.kind).err();
+        assert_eq!(kind("solve(sin(x) = 0, x)"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("solve(1 / x = 2, x)"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("solve(2 * x + 3, x)"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("solve(x * x = -1, x)"), Some(ErrorKind::NoResult));
+        assert_eq!(kind("solve(x = x + 1, x)"), Some(ErrorKind::NoResult));
+        assert_eq!(kind("solve(x + y = 1, x)"), Some(ErrorKind::UnknownVariable));
+        assert_eq!(kind("1 = 1"), Some(ErrorKind::InvalidFunctionCall));
+        let limits = Limits { max_steps: 300, ..Limits::default() };
+        assert!(process("solve(x * x = 4, x)", &limits).is_ok());
+        assert_eq!(process("sum(table(solve(x * x = y, x), y, 1, 20, 1))", &limits).map_err(|why| why.kind).err(),
+            Some(ErrorKind::BudgetExceeded));
+        let rpn = process("solve(2 * x = 4, x)", &limits).unwrap().rpn;
+        assert_eq!(solved_variable(&rpn), Some("x"));
+        assert_eq!(solved_variable("2 x *"), None);
+    }
+
+    #[test]
+    fn simplify_rules_examples() {
+        let limits = Limits::default();
+        for (name, example, expected) in simplify_rules() {
+            assert_eq!(simplify(example, &limits).unwrap().expression, expected, "правило {}", name);
+        }
+    }
+
+    #[test]
+    fn simplify_keeps_meaning() {
+        let limits = Limits::default();
+        let simplified = |input: &str| simplify(input, &limits).unwrap();
+        let expression = |input: &str| simplified(input).expression;
+        assert_eq!(expression("x - x + y"), "y");
+        assert_eq!(expression("2 * (x + 1) - (x + 1)"), "x + 1");
+        assert_eq!(expression("x - 2 * x"), "-x");
+        assert_eq!(expression("a - (b - c)"), "a - (b - c)");
+        assert_eq!(expression("-(x + 1) * 2"), "-(x + 1) * 2");
+        assert_eq!(expression("sqrt(x * 1 + 0) + max(2 * 3, y)"), "sqrt(x) + max(6, y)");
+        assert_eq!(expression("[x - x, 1 + 1]"), "[0, 2]");
+        assert_eq!(expression("sum(i, 1, 3, i * 1)"), "sum(i, 1, 3, i)");
+        // Величины с единицами не теряют размерность
+        assert_eq!(expression("0 * 5 km"), "0 * 5 km");
+        assert_eq!(expression("2 km + 3 km"), "5 km");
+        assert_eq!(expression("2 km - 2 km"), "2 km - 2 km");
+        // Сокращение требует ненулевого делителя
+        assert_eq!(simplified("(x * y) / y"), Simplified { expression: "x".to_string(), nonzero: vec!["y".to_string()] });
+        assert_eq!(simplified("(x * 2) / 2").nonzero, Vec::<String>::new());
+        assert_eq!(expression("(x * 0) / 0"), "0 / 0");
+        assert!(simplify("1 +", &limits).is_err());
+    }
+
+    #[test]
+    fn lists_apply_operators_elementwise() {
+        assert_eq!(rpn_and_result("[1, 2, 3] + [10, 20, 30]"), ("1 2 3 [3] 10 20 30 [3] +".to_string(), "[11.00, 22.00, 33.00]".to_string()));
+        assert_eq!(rpn_and_result("[1, 2, 3] * 2").1, "[2.00, 4.00, 6.00]");
+        assert_eq!(rpn_and_result("10 - [1, 2]").1, "[9.00, 8.00]");
+        assert_eq!(rpn_and_result("-[1, 2]").1, "[-1.00, -2.00]");
+        assert_eq!(rpn_and_result("sin([0, 1])").1, "[0.00, 0.84]");
+        assert_eq!(rpn_and_result("[1, 2] < [2, 1]").1, "[true, false]");
+        assert_eq!(rpn_and_result("[1, 2] km").1, "[1000.00 m, 2000.00 m]");
+        // Вложенные списки обрабатываются на каждом уровне
+        assert_eq!(rpn_and_result("[1, [2, 3]] * 2"), ("1 2 3 [2] [2] 2 *".to_string(), "[2.00, [4.00, 6.00]]".to_string()));
+        assert_eq!(rpn_and_result("[]"), ("[0]".to_string(), "[]".to_string()));
+
+        // Списки разной длины - ошибка с местом оператора
+        let why = process("[1, 2] + [1, 2, 3]", &Limits::default()).map(|evaluation| evaluation.value).expect_err("длины 2 и 3");
+        assert_eq!((why.kind, why.span, why.message.as_str()), (ErrorKind::ShapeMismatch, Some((7, 8)), "размеры списков не совпадают: 2 и 3"));
+        let why = process("[[1, 2], [3, 4]] + [[1], [2]]", &Limits::default()).map(|evaluation| evaluation.value).expect_err("строки длины 2 и 1");
+        assert_eq!((why.kind, why.span, why.message.as_str()), (ErrorKind::ShapeMismatch, Some((17, 18)), "размеры списков не совпадают: 2 и 1"));
+        assert_eq!(why.kind.code(), "E0018");
+    }
+
+    #[test]
+    fn matrix_functions() {
+        let value = |input: &str| process(input, &Limits::default()).map(|evaluation| evaluation.value);
+        assert_eq!(rpn_and_result("transpose([[1, 2, 3], [4, 5, 6]])").1, "[[1.00, 4.00], [2.00, 5.00], [3.00, 6.00]]");
+        assert_eq!(rpn_and_result("matmul([[1, 2], [3, 4]], [[5, 6], [7, 8]])").1, "[[19.00, 22.00], [43.00, 50.00]]");
+        assert_eq!(rpn_and_result("matmul([[1, 2, 3]], [[1], [2], [3]])").1, "[[14.00]]");
+        assert_numbers(value("det([[1, 2], [3, 4]])").unwrap(), &[-2.0], 1e-6);
+        assert_eq!(value("det([[2, 0, 0], [0, 3, 0], [0, 0, 4]])").unwrap(), Value::Number(24.0));
+        // Перестановка строк при выборе ведущего элемента меняет знак
+        assert_eq!(value("det([[0, 1], [1, 0]])").unwrap(), Value::Number(-1.0));
+
+        // Вырожденная матрица: определитель 0, а не ошибка или NaN
+        assert_eq!(value("det([[1, 2], [2, 4]])").unwrap(), Value::Number(0.0));
+        assert_eq!(value("det([[0, 0], [1, 2]])").unwrap(), Value::Number(0.0));
+        match value("det([[1, 2, 3], [4, 5, 6], [7, 8, 9]])").unwrap() {
+            Value::Number(det) => assert!(det.abs() < 1e-5, "{}", det),
+            other => panic!("ожидалось число, получено {}", other),
+        }
+
+        let error = |input: &str| value(input).map(|_| ()).unwrap_err();
+        let why = error("det([[1, 2, 3], [4, 5, 6]])");
+        assert_eq!((why.kind, why.span, why.message.as_str()),
+            (ErrorKind::ShapeMismatch, Some((0, 3)), "определитель вычисляется только для квадратной матрицы, получена 2x3"));
+        let why = error("matmul([[1, 2]], [[1, 2]])");
+        assert_eq!((why.kind, why.message.as_str()), (ErrorKind::ShapeMismatch, "размеры матриц не согласованы для умножения: 1x2 и 1x2"));
+        for input in ["det([1, 2])", "det(5)", "det([])", "transpose([[1, 2], [3]])"] {
+            assert_eq!(error(input).kind, ErrorKind::ShapeMismatch, "{}", input);
+        }
+    }
+
+    #[test]
+    fn list_statistics() {
+        let value = |input: &str| process(input, &Limits::default()).map(|evaluation| evaluation.value);
+        let cases = [
+            ("sum([1, 2, 3, 4])", 10.0), ("mean([1, 2, 3, 4])", 2.5),
+            ("median([3, 1, 2])", 2.0), ("median([4, 1, 3, 2])", 2.5),
+            ("variance([2, 4, 4, 4, 5, 5, 7, 9])", 4.0), ("stddev([2, 4, 4, 4, 5, 5, 7, 9])", 2.0),
+            ("variance([5])", 0.0), ("sum(5)", 5.0),
+            // Статистика - по всем числам вложенных списков
+            ("count([[1, 2], [3]])", 3.0), ("sum([[1, 2], [3]])", 6.0),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(value(input).unwrap(), Value::Number(expected), "{}", input);
+        }
+
+        // У пустого списка есть сумма и количество, но не среднее, медиана и разброс
+        assert_eq!(value("sum([])").unwrap(), Value::Number(0.0));
+        assert_eq!(value("count([])").unwrap(), Value::Number(0.0));
+        for function in ["mean", "median", "variance", "stddev"] {
+            let why = value(&format!("{}([])", function)).expect_err("пустой список");
+            assert_eq!((why.kind, why.span), (ErrorKind::InvalidFunctionCall, Some((0, function.len()))), "{}", function);
+            assert_eq!(why.message, format!("функция '{}' не определена для пустого списка", function));
+        }
+        let why = value("mean([1 km, 500 m])").expect_err("величины с размерностью");
+        assert_eq!(why.kind, ErrorKind::UnitMismatch);
+    }
+
+    #[test]
+    fn units_convert_and_check_dimensions() {
+        assert_eq!(rpn_and_result("5 km + 300 m"), ("5 km * 300 m * +".to_string(), "5300.00 m".to_string()));
+        assert_eq!(rpn_and_result("1 h + 30 min").1, "5400.00 s");
+        assert_eq!(rpn_and_result("10 m / 2 s").1, "5.00 m/s");
+        assert_eq!(rpn_and_result("100 mph").1, "44.70 m/s");
+        assert_eq!(rpn_and_result("2 kg * 3 m").1, "6.00 m*kg");
+        assert_eq!(rpn_and_result("3 ft + 1 in").1, "0.94 m");
+        // Отношение величин одной размерности - обычное число
+        assert_eq!(process("1 km / 1 m", &Limits::default()).unwrap().value, Value::Number(1000.0));
+
+        let limits = Limits::default();
+        assert_eq!(convert("100 mph", "km/h", &limits).unwrap(), "160.93 km/h");
+        assert_eq!(convert("1 h + 30 min", "min", &limits).unwrap(), "90.00 min");
+        assert_eq!(convert_value("[1, 2] km", "m", &limits).unwrap(), Value::List(vec![Value::Number(1000.0), Value::Number(2000.0)]));
+
+        // Несовместимые размерности - ошибка с местом оператора или функции
+        let error = |input: &str| process(input, &limits).map(|evaluation| evaluation.value).unwrap_err();
+        let why = error("1 km + 1 s");
+        assert_eq!((why.kind, why.span, why.message.as_str()), (ErrorKind::UnitMismatch, Some((5, 6)), "несовместимые единицы измерения для '+': m и s"));
+        assert_eq!(error("1 km < 1 s").kind, ErrorKind::UnitMismatch);
+        let why = error("sqrt(4 m)");
+        assert_eq!((why.kind, why.span, why.message.as_str()),
+            (ErrorKind::UnitMismatch, Some((0, 4)), "операция 'sqrt' применима только к безразмерным величинам, получено m"));
+        assert_eq!(error("(10 m) << 2").kind, ErrorKind::UnitMismatch);
+        let why = convert("5 kg", "m", &limits).unwrap_err();
+        assert_eq!((why.kind, why.message.as_str()), (ErrorKind::UnitMismatch, "нельзя перевести kg в m"));
+        assert_eq!(convert("5 m", "parsec", &limits).unwrap_err().kind, ErrorKind::UnknownUnit);
+    }
+
+    #[test]
+    fn date_arithmetic() {
+        let cases = [
+            ("2024-05-01 + 3 w", "2024-05-22"), ("2024-05-01 + 10 d", "2024-05-11"),
+            ("2024-05-01 - 2 w", "2024-04-17"), ("2024-12-31 + 1 d", "2025-01-01"),
+            // Високосный год
+            ("2024-03-01 - 1 d", "2024-02-29"), ("2024-03-01 - 2024-02-01", "29.00"),
+            ("2023-03-01 - 2023-02-01", "28.00"), ("2024-05-01 < 2024-06-01", "true"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(rpn_and_result(input).1, expected, "{}", input);
+        }
+        assert_eq!(rpn_and_result("2024-05-01 + 3 w").0, "2024-05-01 3 w * +");
+        assert_eq!(process("2024-05-01 + 10 d", &Limits::default()).unwrap().value, Value::Date(19854));
+
+        let error = |input: &str| process(input, &Limits::default()).map(|evaluation| evaluation.value).unwrap_err();
+        let why = error("2024-05-01 + 5");
+        assert_eq!((why.kind, why.span, why.message.as_str()),
+            (ErrorKind::InvalidDate, Some((11, 12)), "к дате можно прибавить или вычесть только длительность (например, 3 d), получено 5.00"));
+        assert_eq!(error("2024-05-01 + 2024-05-02").kind, ErrorKind::InvalidDate);
+        assert_eq!(error("2024-05-01 + 36 h").message, "длительность должна быть целым числом дней, получено 129600.00 s");
+        assert_eq!(error("2024-05-01 * 2").message, "операция '*' не применима к дате");
+        for input in ["2024-02-30", "2024-13-01", "2023-02-29"] {
+            let why = error(input);
+            assert_eq!((why.kind, why.span), (ErrorKind::InvalidDate, Some((0, 10))), "{}", input);
+        }
+    }
+
+    #[test]
+    fn radix_round_trip() {
+        let limits = Limits::default();
+        let value = |input: &str| process(input, &limits).map(|evaluation| evaluation.value);
+        assert_eq!(value("16#ff").unwrap(), Value::Number(255.0));
+        assert_eq!(value("16#FF").unwrap(), Value::Number(255.0));
+        assert_eq!(value("2#1010 + 8#17").unwrap(), Value::Number(25.0));
+        assert_eq!(rpn_and_result("base(255, 16)"), ("255 16 base".to_string(), "16#ff".to_string()));
+        assert_eq!(rpn_and_result("base(-10, 16)").1, "-16#a");
+        assert_eq!(rpn_and_result("base(0, 2)").1, "2#0");
+
+        // Запись числа в системе счисления читается обратно в то же число
+        for radix in 2..=36 {
+            for number in [0, 1, 35, 255, 1000, 65_535, 1_234_567, -42] {
+                let written = process(&format!("base({}, {})", number, radix), &limits).unwrap().result;
+                assert_eq!(value(&written).unwrap(), Value::Number(number as f32), "{} по основанию {}: {}", number, radix, written);
+            }
+        }
+
+        let error = |input: &str| value(input).unwrap_err();
+        let why = error("base(2.5, 16)");
+        assert_eq!((why.kind, why.span, why.message.as_str()),
+            (ErrorKind::InvalidNumber, Some((0, 4)), "функция 'base' переводит только целые числа, получено 2.50"));
+        assert_eq!(error("base(10, 1)").message, "основание системы счисления должно быть целым от 2 до 36, получено 1.00");
+        assert_eq!(error("base(10, 37)").kind, ErrorKind::InvalidNumber);
+        assert_eq!(error("37#1").message, "основание системы счисления должно быть от 2 до 36, получено 37");
+        let why = error("2#102");
+        assert_eq!((why.kind, why.span), (ErrorKind::InvalidNumber, Some((0, 5))));
+    }
+
+    #[test]
+    fn interval_arithmetic() {
+        let value = |input: &str| process(input, &Limits::default()).map(|evaluation| evaluation.value);
+        // Границы результата: интервал содержит все значения операции над точками интервалов
+        let cases = [
+            ("2.0±0.1", (1.9, 2.1)), ("(2±0.1) + (3±0.2)", (4.7, 5.3)), ("(2±0.1) - (3±0.2)", (-1.3, -0.7)),
+            ("(2.0±0.1) * (3±0.2)", (5.32, 6.72)), ("(-1±0.5) * (2±1)", (-4.5, -0.5)), ("(2±1) / (4±1)", (0.2, 1.0)),
+            ("(1±2) / (1±0.5)", (-2.0, 6.0)), ("-(2±0.1)", (-2.1, -1.9)), ("(2±0.1) * 2", (3.8, 4.2)),
+            ("sqrt(4±1)", (3.0f32.sqrt(), 5.0f32.sqrt())),
+        ];
+        for (input, (low, high)) in cases {
+            match value(input).unwrap() {
+                Value::Interval(result_low, result_high) => {
+                    assert!((result_low - low).abs() < 1e-5 && (result_high - high).abs() < 1e-5, "{}: [{}, {}]", input, result_low, result_high);
+                },
+                other => panic!("{}: ожидался интервал, получено {}", input, other),
+            }
+        }
+        assert_eq!(rpn_and_result("(2.0±0.1) * (3±0.2)"), ("2.0 0.1 ± 3 0.2 ± *".to_string(), "6.02±0.70".to_string()));
+
+        let error = |input: &str| value(input).unwrap_err();
+        let why = error("1 / (0±1)");
+        assert_eq!((why.kind, why.span, why.message.as_str()),
+            (ErrorKind::InvalidInterval, Some((2, 3)), "деление на интервал, содержащий ноль: 0.00±1.00"));
+        assert_eq!(error("2±-1").message, "погрешность должна быть неотрицательным числом, получено -1.00");
+        assert_eq!(error("(2±0.1) km").kind, ErrorKind::InvalidInterval);
+        assert_eq!(error("(2±0.1) < 3").kind, ErrorKind::InvalidFunctionCall);
+    }
+
+    #[test]
+    fn chained_comparisons() {
+        let limits = Limits::default();
+        let rpn_and_value = |input: &str| process(input, &limits).map(|evaluation| (evaluation.rpn, evaluation.value)).unwrap();
+        assert_eq!(rpn_and_value("1 < 2 < 3"), ("1 2 < 2 3 < AND".to_string(), Value::Bool(true)));
+        assert_eq!(rpn_and_value("1 < 2 + 3 < 6"), ("1 2 3 + < 2 3 + 6 < AND".to_string(), Value::Bool(true)));
+        let value = |input: &str| process(input, &limits).unwrap().value;
+        assert_eq!(value("3 > 2 > 2"), Value::Bool(false));
+        assert_eq!(value("1 < 2 < 3 <= 3"), Value::Bool(true));
+        assert_eq!(value("1 < 3 < 2 < 4"), Value::Bool(false));
+        assert_eq!(value("0 <= sin(0) < 1"), Value::Bool(true));
+        assert_eq!(value("if(0 < 5 < 10, 1, 2)"), Value::Number(1.0));
+        assert_eq!(value("[1, 5, 9] > 2 > 0"), Value::List(vec![Value::Bool(false), Value::Bool(true), Value::Bool(true)]));
+        // Сравнение в скобках - не цепочка
+        assert_eq!(process("(1 < 2) < 3", &limits).map_err(|why| why.kind).err(), Some(ErrorKind::InvalidFunctionCall));
+    }
+
+    #[test]
+    fn comparisons_give_booleans() {
+        let limits = Limits::default();
+        let value = |input: &str| process(input, &limits).unwrap().value;
+        assert_eq!(value("1 + 1 < 3"), Value::Bool(true));
+        assert_eq!(value("2 >= 3"), Value::Bool(false));
+        assert_eq!(value("0.1 + 0.2 == 0.3"), Value::Bool(true));
+        assert_eq!(value("1 << 2 != 4"), Value::Bool(false));
+        assert_eq!(value("5 km > 300 m"), Value::Bool(true));
+        assert_eq!(value("2024-05-01 <= 2024-05-02"), Value::Bool(true));
+        assert_eq!(value("(1 < 2) == (3 < 4)"), Value::Bool(true));
+        assert_eq!(value("[1, 2, 3] > 1"), Value::List(vec![Value::Bool(false), Value::Bool(true), Value::Bool(true)]));
+        assert_eq!(value("3 > 2").to_string(), "true");
+        let kind = |input: &str| process(input, &limits).map_err(|why| why.kind).err();
+        assert_eq!(kind("5 km < 3 s"), Some(ErrorKind::UnitMismatch));
+        assert_eq!(kind("(1 < 2) + 1"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("(1 < 2) < (3 < 4)"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("sqrt(1 < 2)"), Some(ErrorKind::InvalidFunctionCall));
+    }
+
+    #[test]
+    fn if_evaluates_only_taken_branch() {
+        let limits = Limits::default();
+        let value = |input: &str| process(input, &limits).unwrap().value;
+        assert_eq!(value("if(2 > 1, 10, 20)"), Value::Number(10.0));
+        assert_eq!(value("if(2 < 1, 10, 20) + 1"), Value::Number(21.0));
+        // Ветвь с неизвестной переменной и ошибочная ветвь не вычисляются
+        assert_eq!(value("if(1 < 2, 5, y)"), Value::Number(5.0));
+        assert_eq!(value("if(1 > 2, 1 % 0, 7)"), Value::Number(7.0));
+        assert_eq!(value("if(1 < 2, if(2 < 1, 1, 2), 3)"), Value::Number(2.0));
+        assert_eq!(value("table(if(x < 1, 0, x), x, 0, 2, 1)"), Value::List(vec![
+            Value::List(vec![Value::Number(0.0), Value::Number(0.0)]),
+            Value::List(vec![Value::Number(1.0), Value::Number(1.0)]),
+            Value::List(vec![Value::Number(2.0), Value::Number(2.0)]),
+        ]));
+        let kind = |input: &str| process(input, &limits).map_err(|why| why.kind).err();
+        assert_eq!(kind("if(1, 2, 3)"), Some(ErrorKind::InvalidFunctionCall));
+        assert_eq!(kind("if(1 > 2, 1, 1 % 0)"), Some(ErrorKind::DivisionByZero));
+
+        let compiled = Compiled::new("if(a > b, a, b)").unwrap();
+        assert_eq!(compiled.variables(), ["a", "b"]);
+        assert_eq!(compiled.eval(&[("a", 3.0), ("b", 5.0)]).unwrap(), Value::Number(5.0));
+    }
+
+    #[test]
+    fn compiled_table_keeps_bound_variable() {
+        let compiled = Compiled::new("table(x * a, x, 1, 2, 1)").unwrap();
+        assert_eq!(compiled.variables(), ["a"]);
+        let row = |x: f32, y: f32| Value::List(vec![Value::Number(x), Value::Number(y)]);
+        assert_eq!(compiled.eval(&[("a", 3.0)]).unwrap(), Value::List(vec![row(1.0, 3.0), row(2.0, 6.0)]));
+    }
+
+    // Добавленные единицы задаются один раз на процесс, поэтому только в этом тесте
+    #[cfg(feature = "std")]
+    #[test]
+    fn extra_units_extend_lookup() {
+        let units = vec![
+            ("furlong".to_string(), 201.168, [1, 0, 0, 0]),
+            ("m".to_string(), 2.0, [1, 0, 0, 0]),
+            ("EUR".to_string(), 1.0, CURRENCY),
+            ("USD".to_string(), 0.9, CURRENCY),
+        ];
+        assert!(set_extra_units(units).is_ok());
+        let limits = Limits::default();
+        assert_eq!(process("2 furlong + 1 m", &limits).unwrap().value, Value::Quantity(403.336, [1, 0, 0, 0]));
+        assert_eq!(process("1 m", &limits).unwrap().value, Value::Quantity(1.0, [1, 0, 0, 0]));
+        assert_eq!(convert("1006 m", "furlong", &limits).unwrap(), "5.00 furlong");
+        assert!(describe("furlong").is_some());
+        assert!(unit_names().any(|name| name == "furlong"));
+
+        // Валюты - единицы размерности CURRENCY, суммы переводятся в основную валюту
+        let sum = process("12.50 USD + 3 EUR", &limits).unwrap().value;
+        assert_eq!(sum, Value::Quantity(14.25, CURRENCY));
+        assert_eq!(sum.to_string(), "14.25 EUR");
+        assert_eq!(convert("9 EUR", "USD", &limits).unwrap(), "10.00 USD");
+        assert_eq!(process("5 USD + 1 m", &limits).err().map(|why| why.kind), Some(ErrorKind::UnitMismatch));
+        assert_eq!(dimension_name([0, 0, -1, 1]), "EUR/s");
+    }
+
+    // Свойства разбора на случайных выражениях; proptest требует стандартную библиотеку
+    #[cfg(feature = "std")]
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        // Генератор заведомо корректных выражений в инфиксной форме; операнды унарного
+        // минуса берутся в скобки, чтобы выражение оставалось корректным
+        fn expression() -> impl Strategy<Value = String> {
+            let leaf = prop_oneof![
+                (0u32..1000).prop_map(|value| value.to_string()),
+                (0u32..1000, 0u32..100).prop_map(|(int, frac)| format!("{}.{}", int, frac)),
+            ];
+            leaf.prop_recursive(6, 48, 2, |inner| prop_oneof![
+                inner.clone().prop_map(|arg| format!("-({})", arg)),
+                (inner.clone(), prop::sample::select(vec!["+", "-", "*", "/", "%"]), inner.clone())
+                    .prop_map(|(arg1, op, arg2)| format!("{} {} {}", arg1, op, arg2)),
+                (prop::sample::select(vec!["abs", "sqrt"]), inner.clone())
+                    .prop_map(|(function, arg)| format!("{}({})", function, arg)),
+                (prop::sample::select(vec!["min", "max"]), inner.clone(), inner.clone())
+                    .prop_map(|(function, arg1, arg2)| format!("{}({}, {})", function, arg1, arg2)),
+                inner.prop_map(|arg| format!("({})", arg)),
+            ])
+        }
+
+        proptest! {
+            // Выражение, восстановленное из ОПН, вычисляется в тот же результат
+            #[test]
+            fn rpn_to_infix_preserves_result(infix in expression()) {
+                let limits = Limits::default();
+                let rpn = parse(&infix, &limits).expect("корректное выражение должно разбираться");
+                let printed = rpn_to_infix(&rpn).expect("ОПН корректного выражения должна восстанавливаться");
+                // Восстановленное выражение может содержать больше скобок, чем исходное
+                let relaxed = Limits { max_depth: usize::MAX, max_nesting: usize::MAX, ..Limits::default() };
+                let expected = process(&infix, &limits).map(|evaluation| evaluation.result).map_err(|why| why.kind);
+                let again = process(&printed, &relaxed).map(|evaluation| evaluation.result).map_err(|why| why.kind);
+                prop_assert_eq!(expected, again, "{} -> {}", infix, printed);
+            }
+
+            // Разбор произвольной строки заканчивается результатом или ошибкой, но не паникой
+            #[test]
+            fn tokenizer_never_panics(input in any::<String>()) {
+                let limits = Limits::default();
+                let _ = tokerize(&input, &limits);
+                let _ = process(&input, &limits);
+                let _ = parse_errors(&input, &limits);
+            }
+
+            // То же для строк из символов, которые лексер распознаёт, - они доходят до разбора
+            #[test]
+            fn parser_never_panics(input in "[0-9a-z()\\[\\],|+*/%<>±×·÷− .#-]{0,40}") {
+                let limits = Limits::default();
+                let _ = process(&input, &limits);
+                let _ = parse_errors(&input, &limits);
+            }
+
+            // Токены строки после правки - вставки `inserted` вместо символов с `start` по `end` -
+            // совпадают с полным разбором новой строки; при ошибке разбора остаётся прежняя строка
+            #[test]
+            fn incremental_tokens_match_tokerize(
+                original in "[0-9a-z()\\[\\],|+*/%<>=±×·÷−° .#-]{0,30}",
+                start in 0usize..32,
+                length in 0usize..8,
+                inserted in "[0-9a-z()\\[\\],|+*/%<>=±×·÷−° .#-]{0,6}",
+            ) {
+                let limits = Limits::default();
+                let mut tokens = match IncrementalTokens::new(&original, &limits) {
+                    Ok(tokens) => tokens,
+                    Err(_) => return Ok(()),
+                };
+                let chars: Vec<char> = original.chars().collect();
+                let start = start.min(chars.len());
+                let end = (start + length).min(chars.len());
+                let edited: String = chars[..start].iter().chain(inserted.chars().collect::<Vec<char>>().iter()).chain(&chars[end..]).collect();
+                match (tokens.update(&edited, &limits), tokerize(&edited, &limits)) {
+                    (Ok(_), Ok(expected)) => {
+                        prop_assert_eq!(tokens.input(), edited.as_str());
+                        prop_assert_eq!(tokens.tokens(), expected, "{:?} -> {:?}", original, edited);
+                    },
+                    (Err(why), Err(expected)) => {
+                        prop_assert_eq!((why.kind, why.span), (expected.kind, expected.span), "{:?} -> {:?}", original, edited);
+                        prop_assert_eq!(tokens.input(), original.as_str());
+                        prop_assert_eq!(tokens.tokens(), tokerize(&original, &limits).unwrap());
+                    },
+                    (updated, expected) => prop_assert!(false, "{:?} -> {:?}: {:?} и {:?}", original, edited, updated.map(|_| ()), expected.map(|_| ())),
+                }
+            }
+
+            // То же при наборе строки по одному символу, как в редакторе строки
+            #[test]
+            fn incremental_tokens_follow_typing(input in "[0-9a-z()\\[\\],|+*/%<>=±×·÷−° .#-]{1,30}") {
+                let limits = Limits::default();
+                let mut tokens = IncrementalTokens::new("", &limits).unwrap();
+                for (index, c) in input.char_indices() {
+                    let typed = &input[..index + c.len_utf8()];
+                    if let Ok(expected) = tokerize(typed, &limits) {
+                        prop_assert!(tokens.update(typed, &limits).is_ok(), "{:?}", typed);
+                        prop_assert_eq!(tokens.tokens(), expected, "{:?}", typed);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,14 @@
+// Копирование результата в системный буфер обмена (feature "clipboard"). В X11 содержимое
+// буфера принадлежит программе, поэтому после её завершения оно сохраняется, только если
+// запущен менеджер буфера обмена
+#[cfg(feature = "clipboard")]
+pub fn copy(text: &str) -> Result<(), String> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .map_err(|why| why.to_string())
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn copy(_text: &str) -> Result<(), String> {
+    Err("программа собрана без поддержки буфера обмена: cargo build --features clipboard".to_string())
+}
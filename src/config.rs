@@ -0,0 +1,238 @@
+// Настройки из файла и переменных окружения. Файл - строки "ключ = значение", пустые строки
+// и строки с '#' в начале пропускаются. Ключи совпадают с флагами ограничений без "--"
+// (max-depth, max-nesting, max-tokens, max-steps, max-recursion, timeout, tolerance) и с
+// параметрами :set (notation, rounding, sigfigs, grouping, display, mixed, echo, integer). Переменная окружения
+// L2T_<КЛЮЧ> (например, L2T_MAX_STEPS) важнее строки файла, а флаги командной строки,
+// которые разбираются после загрузки, важнее обоих. Строки "unit ИМЯ = ЧИСЛО ЕДИНИЦА"
+// добавляют единицы измерения, например "unit furlong = 201.168 m" или "unit knot = 0.514 m/s".
+// Ключ rates - путь к файлу курсов валют в JSON: {"base": "EUR", "rates": {"USD": 1.08}},
+// то есть 1 EUR = 1.08 USD; валюты становятся единицами измерения размерности CURRENCY
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use less_2_task::calc::{describe, parse_unit, Dimension, Limits, CURRENCY};
+
+use crate::json::{self, Json};
+use crate::{parse_setting, parse_tolerance, Settings};
+
+// Ключи настроек; переменные окружения применяются в этом порядке
+const KEYS: [&str; 16] = [
+    "max-depth", "max-nesting", "max-tokens", "max-steps", "max-recursion", "timeout", "tolerance",
+    "notation", "rounding", "sigfigs", "grouping", "display", "mixed", "echo", "integer", "rates",
+];
+
+#[derive(Default)]
+pub struct Config {
+    pub limits: Limits,
+    pub settings: Settings,
+    // Единицы измерения из файла: обозначение, значение в основных единицах и размерность
+    pub units: Vec<(String, f32, Dimension)>,
+}
+
+// Путь к файлу настроек: L2T_CONFIG, иначе less_2_task/config в $XDG_CONFIG_HOME или ~/.config
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("L2T_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(base) if !base.is_empty() => PathBuf::from(base),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("less_2_task").join("config"))
+}
+
+// Настройки по умолчанию, файла и окружения; ошибки в них выводятся в stderr и пропускаются
+pub fn load() -> Config {
+    let text = match config_path().map(fs::read_to_string) {
+        Some(Ok(text)) => text,
+        Some(Err(why)) if why.kind() != io::ErrorKind::NotFound => {
+            eprintln!("Не удалось прочитать файл настроек: {}", why);
+            String::new()
+        },
+        _ => String::new(),
+    };
+    let (config, warnings) = parse_config(&text, |name| env::var(name).ok());
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+    config
+}
+
+// Имя переменной окружения для ключа: "max-steps" -> "L2T_MAX_STEPS"
+fn env_name(key: &str) -> String {
+    format!("L2T_{}", key.to_uppercase().replace('-', "_"))
+}
+
+// Разбор текста файла настроек со значениями из окружения поверх; возвращает настройки
+// и предупреждения о пропущенных строках и переменных
+fn parse_config(text: &str, env: impl Fn(&str) -> Option<String>) -> (Config, Vec<String>) {
+    let mut config = Config::default();
+    let mut warnings = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let applied = match line.strip_prefix("unit ") {
+            Some(definition) => parse_unit_definition(definition, &config.units).map(|unit| config.units.push(unit)),
+            None => line.split_once('=').and_then(|(key, value)| apply(&mut config, key.trim(), value.trim())),
+        };
+        if applied.is_none() {
+            warnings.push(format!("Строка {} файла настроек пропущена: '{}'", number + 1, line));
+        }
+    }
+    for key in KEYS.iter() {
+        let name = env_name(key);
+        if let Some(value) = env(&name) {
+            if apply(&mut config, key, value.trim()).is_none() {
+                warnings.push(format!("Переменная {} пропущена: '{}'", name, value));
+            }
+        }
+    }
+    (config, warnings)
+}
+
+// Применение значения ключа; None - неизвестный ключ или неподходящее значение
+fn apply(config: &mut Config, key: &str, value: &str) -> Option<()> {
+    let limits = &mut config.limits;
+    match key {
+        "max-depth" => limits.max_depth = value.parse().ok()?,
+        "max-nesting" => limits.max_nesting = value.parse().ok()?,
+        "max-tokens" => limits.max_tokens = value.parse().ok()?,
+        "max-steps" => limits.max_steps = value.parse().ok()?,
+        "max-recursion" => limits.max_recursion = value.parse().ok()?,
+        "timeout" => limits.timeout = Some(Duration::from_millis(value.parse().ok()?)),
+        "tolerance" => limits.tolerance = parse_tolerance(value)?,
+        "rates" => {
+            let rates = parse_rates(&fs::read_to_string(value).ok()?, &config.units)?;
+            config.units.extend(rates);
+        },
+        _ if KEYS.contains(&key) && !value.is_empty() =>
+            config.settings = parse_setting(&format!(":set {} {}", key, value), config.settings)?,
+        _ => return None,
+    }
+    Some(())
+}
+
+// Определение единицы "furlong = 201.168 m": имя из латинских букв, не занятое операторами,
+// функциями и единицами, и число с необязательной составной единицей из встроенных ("km/h")
+fn parse_unit_definition(definition: &str, units: &[(String, f32, Dimension)]) -> Option<(String, f32, Dimension)> {
+    let (name, value) = definition.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) || describe(name).is_some()
+        || units.iter().any(|unit| unit.0 == name) {
+        return None;
+    }
+    let value = value.trim();
+    let (number, unit) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+    let (factor, dimension) = match unit.trim() {
+        "" => (1.0, [0, 0, 0, 0]),
+        unit => parse_unit(unit)?,
+    };
+    let factor = number.parse::<f32>().ok()? * factor;
+    match factor.is_finite() && factor != 0.0 {
+        true => Some((name.to_string(), factor, dimension)),
+        false => None,
+    }
+}
+
+// Курсы валют из JSON: основная валюта с множителем 1 и остальные с множителем, обратным
+// курсу. Имена валют - заглавные латинские буквы, не занятые функциями и единицами
+fn parse_rates(text: &str, units: &[(String, f32, Dimension)]) -> Option<Vec<(String, f32, Dimension)>> {
+    let rates = json::parse(text).ok()?;
+    let base = rates.get("base")?.as_str()?;
+    let fields = match rates.get("rates")? {
+        Json::Object(fields) => fields,
+        _ => return None,
+    };
+    let mut currencies = vec![(base.to_string(), 1.0, CURRENCY)];
+    for (name, rate) in fields.iter().filter(|field| field.0 != base) {
+        match rate {
+            Json::Number(rate) if rate.is_finite() && *rate > 0.0 => currencies.push((name.clone(), (1.0 / rate) as f32, CURRENCY)),
+            _ => return None,
+        }
+    }
+    for (index, currency) in currencies.iter().enumerate() {
+        let name = currency.0.as_str();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_uppercase()) || describe(name).is_some()
+            || units.iter().chain(&currencies[..index]).any(|unit| unit.0 == name) {
+            return None;
+        }
+    }
+    Some(currencies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use less_2_task::calc::Notation;
+
+    #[test]
+    fn file_overrides_defaults() {
+        let (config, warnings) = parse_config("# ограничения\nmax-steps = 500\n\nnotation = sci\n", |_| None);
+        assert!(warnings.is_empty());
+        assert_eq!(config.limits.max_steps, 500);
+        assert_eq!(config.limits.max_depth, Limits::default().max_depth);
+        assert_eq!(config.settings.format.notation, Notation::Scientific);
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        let env = |name: &str| match name {
+            "L2T_MAX_STEPS" => Some("700".to_string()),
+            "L2T_TIMEOUT" => Some("250".to_string()),
+            "L2T_TOLERANCE" => Some("0.01".to_string()),
+            _ => None,
+        };
+        let (config, warnings) = parse_config("max-steps = 500\nmax-tokens = 40\n", env);
+        assert!(warnings.is_empty());
+        assert_eq!(config.limits.max_steps, 700);
+        assert_eq!(config.limits.max_tokens, 40);
+        assert_eq!(config.limits.timeout, Some(Duration::from_millis(250)));
+        assert_eq!(config.limits.tolerance, 0.01);
+    }
+
+    #[test]
+    fn invalid_values_are_reported_and_skipped() {
+        let env = |name: &str| match name {
+            "L2T_SIGFIGS" => Some("many".to_string()),
+            _ => None,
+        };
+        let (config, warnings) = parse_config("max-depth = deep\nsigfigs = 3\ncolour = red\n", env);
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(config.limits.max_depth, Limits::default().max_depth);
+        assert_eq!(config.settings.format.significant, Some(3));
+    }
+
+    #[test]
+    fn unit_definitions() {
+        let text = "unit furlong = 201.168 m\nunit knot = 0.5 m/s\nunit dozen = 12\nunit km = 2 m\nunit fathom = 2 yd\nunit dozen = 13\n";
+        let (config, warnings) = parse_config(text, |_| None);
+        assert_eq!(config.units, vec![
+            ("furlong".to_string(), 201.168, [1, 0, 0, 0]),
+            ("knot".to_string(), 0.5, [1, 0, -1, 0]),
+            ("dozen".to_string(), 12.0, [0, 0, 0, 0]),
+        ]);
+        assert_eq!(warnings.len(), 3);
+    }
+
+    #[test]
+    fn currency_rates() {
+        let rates = parse_rates(r#"{"base": "EUR", "rates": {"USD": 1.25, "GBP": 0.8, "EUR": 1}}"#, &[]).unwrap();
+        assert_eq!(rates, vec![
+            ("EUR".to_string(), 1.0, CURRENCY),
+            ("USD".to_string(), 0.8, CURRENCY),
+            ("GBP".to_string(), 1.25, CURRENCY),
+        ]);
+        assert!(parse_rates(r#"{"base": "EUR", "rates": {"USD": 0}}"#, &[]).is_none());
+        assert!(parse_rates(r#"{"base": "EUR", "rates": {"usd": 1.1}}"#, &[]).is_none());
+        assert!(parse_rates(r#"{"base": "EUR", "rates": {"USD": 1.1, "USD": 1.2}}"#, &[]).is_none());
+        assert!(parse_rates(r#"{"rates": {"USD": 1.1}}"#, &[]).is_none());
+
+        let (config, warnings) = parse_config("rates = /nonexistent/rates.json\n", |_| None);
+        assert!(config.units.is_empty());
+        assert_eq!(warnings.len(), 1);
+    }
+}
@@ -0,0 +1,39 @@
+// Ограничение числа одновременно обслуживаемых соединений в режимах сервера и демона:
+// сверх ограничения новые соединения не принимаются, пока не закроется одно из открытых,
+// и ожидают в очереди сокета операционной системы
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+// Число соединений по умолчанию, если не задано --max-connections
+pub const MAX_CONNECTIONS: usize = 64;
+
+pub struct ConnectionLimit {
+    open: Mutex<usize>,
+    closed: Condvar,
+    limit: usize,
+}
+
+// Разрешение на одно соединение; соединение считается закрытым, когда разрешение удаляется
+pub struct Permit(Arc<ConnectionLimit>);
+
+impl ConnectionLimit {
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(ConnectionLimit { open: Mutex::new(0), closed: Condvar::new(), limit: limit.max(1) })
+    }
+
+    // Ожидание, пока открытых соединений меньше ограничения
+    pub fn acquire(self: &Arc<Self>) -> Permit {
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        while *open >= self.limit {
+            open = self.closed.wait(open).unwrap_or_else(PoisonError::into_inner);
+        }
+        *open += 1;
+        Permit(Arc::clone(self))
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        *self.0.open.lock().unwrap_or_else(PoisonError::into_inner) -= 1;
+        self.0.closed.notify_one();
+    }
+}
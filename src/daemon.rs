@@ -0,0 +1,174 @@
+// Режим демона (--daemon ПУТЬ): выражения принимаются через Unix-сокет по одному в строке,
+// на каждое выражение отправляется одна строка ответа
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Arc;
+use std::thread;
+use less_2_task::calc::{json_string, process, ErrorKind, Limits};
+
+use crate::connections::ConnectionLimit;
+use crate::ErrorFormat;
+
+// Максимальная длина строки с выражением; на более длинную строку отправляется ошибка,
+// и соединение закрывается
+const MAX_LINE: usize = 64 * 1024;
+
+// Каждое соединение обслуживается в отдельном потоке, одновременно не больше
+// max_connections соединений
+pub fn run(path: &str, error_format: ErrorFormat, limits: Limits, max_connections: usize) -> io::Result<()> {
+    // Сокет, оставшийся от предыдущего запуска, заменяется; другие файлы не трогаем
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+    let listener = UnixListener::bind(path)?;
+    println!("Демон запущен: {}", path);
+    let limits = Arc::new(limits);
+    let connections = ConnectionLimit::new(max_connections);
+    loop {
+        let permit = connections.acquire();
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(why) => {
+                eprintln!("Не удалось принять соединение: {}", why);
+                continue;
+            },
+        };
+        let limits = Arc::clone(&limits);
+        thread::spawn(move || {
+            if let Err(why) = handle_connection(stream, error_format, &limits) {
+                eprintln!("Ошибка обработки соединения: {}", why);
+            }
+            drop(permit);
+        });
+    }
+}
+
+fn handle_connection(stream: UnixStream, error_format: ErrorFormat, limits: &Limits) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    loop {
+        // Строка читается не дальше MAX_LINE байт, чтобы клиент без перевода строки
+        // не заставил держать в памяти неограниченные данные
+        line.clear();
+        if reader.by_ref().take(MAX_LINE as u64 + 1).read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        } else if line.len() > MAX_LINE {
+            writeln!(writer, "{}", line_too_long(error_format))?;
+            return Ok(());
+        }
+        let line = String::from_utf8_lossy(&line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let answer = match process(&line, limits) {
+            Ok(evaluation) => evaluation.result,
+            Err(why) => match error_format {
+                ErrorFormat::Text => format!("ошибка: {}", why.message),
+                ErrorFormat::Json => why.to_json(),
+            },
+        };
+        writeln!(writer, "{}", answer)?;
+    }
+}
+
+// Ответ на строку длиннее MAX_LINE в формате ответов с ошибкой
+fn line_too_long(error_format: ErrorFormat) -> String {
+    let message = format!("строка длиннее {} байт", MAX_LINE);
+    match error_format {
+        ErrorFormat::Text => format!("ошибка: {}", message),
+        ErrorFormat::Json => format!("{{\"code\":\"{}\",\"kind\":\"{}\",\"message\":{},\"span\":null,\"lexeme\":null}}",
+            ErrorKind::TooComplex.code(), ErrorKind::TooComplex.name(), json_string(&message)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+    use std::time::Duration;
+
+    // Ответы на строки input в одном соединении
+    fn exchange(input: &[u8], error_format: ErrorFormat) -> String {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let handler = thread::spawn(move || handle_connection(server, error_format, &Limits::default()));
+        client.write_all(input).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        handler.join().unwrap().unwrap();
+        output
+    }
+
+    #[test]
+    fn answers_each_line() {
+        // Пустые строки пропускаются; последняя строка может быть без перевода строки
+        assert_eq!(exchange(b"2 + 3\n\n  \n1 / 4\n(1 + 2) * 3", ErrorFormat::Text), "5.00\n0.25\n9.00\n");
+        assert_eq!(exchange(b"2 +\n7\n", ErrorFormat::Text), "ошибка: выражение заканчивается оператором '+'\n7.00\n");
+        assert_eq!(exchange(b"2 +\n", ErrorFormat::Json),
+            "{\"code\":\"E0015\",\"kind\":\"dangling_operator\",\"message\":\"выражение заканчивается оператором '+'\",\"span\":{\"start\":2,\"end\":3},\"lexeme\":\"+\"}\n");
+        // Состояния между строками нет: ans не определён
+        assert_eq!(exchange(b"5\nans\n", ErrorFormat::Text), "5.00\nошибка: неизвестная переменная 'ans' (возможно, abs)\n");
+    }
+
+    #[test]
+    fn rejects_long_line() {
+        let mut input = b"1 + 1\n".to_vec();
+        input.extend(b"1".repeat(MAX_LINE + 1));
+        assert_eq!(exchange(&input, ErrorFormat::Text), format!("2.00\nошибка: строка длиннее {} байт\n", MAX_LINE));
+        let answer = exchange(&b"1".repeat(MAX_LINE + 1), ErrorFormat::Json);
+        assert!(answer.starts_with("{\"code\":\"E0") && answer.contains("\"kind\":\"too_complex\""), "{}", answer);
+        // Строка ровно в MAX_LINE байт ещё обрабатывается
+        let mut input = b" ".repeat(MAX_LINE - 1);
+        input.extend(b"8\n");
+        assert_eq!(exchange(&input, ErrorFormat::Text), "8.00\n");
+    }
+
+    #[test]
+    fn replaces_stale_socket_and_caps_connections() {
+        let path = std::env::temp_dir().join(format!("less_2_task_daemon_{}.sock", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        // Сокет от предыдущего запуска
+        drop(UnixListener::bind(&path).unwrap());
+        let daemon = path.clone();
+        thread::spawn(move || run(&daemon, ErrorFormat::Text, Limits::default(), 1));
+        let connect = || (0..100).find_map(|_| UnixStream::connect(&path).ok().or_else(|| {
+            thread::sleep(Duration::from_millis(10));
+            None
+        })).expect("демон принимает соединения");
+
+        let mut first = connect();
+        let mut second = connect();
+        second.write_all(b"2\n").unwrap();
+        second.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+        let mut byte = [0; 1];
+        let waiting = second.read(&mut byte).expect_err("второе соединение ещё не обслуживается");
+        assert!(matches!(waiting.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut), "{}", waiting);
+
+        first.write_all(b"1\n").unwrap();
+        first.shutdown(Shutdown::Write).unwrap();
+        let mut output = String::new();
+        first.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "1.00\n");
+        second.set_read_timeout(None).unwrap();
+        second.shutdown(Shutdown::Write).unwrap();
+        output.clear();
+        second.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "2.00\n");
+        let _ = fs::remove_file(&path);
+
+        // Обычный файл на месте сокета не удаляется
+        let file = format!("{}.txt", path);
+        fs::write(&file, "данные").unwrap();
+        assert!(run(&file, ErrorFormat::Text, Limits::default(), 1).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "данные");
+        fs::remove_file(&file).unwrap();
+    }
+}
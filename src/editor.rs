@@ -0,0 +1,276 @@
+// Чтение строк REPL. С feature "editor" - редактор строки rustyline: история введённых строк,
+// дополнение по Tab имён функций, единиц измерения и переменных сеанса и подсветка выражения
+// при вводе. Без неё, а также если редактор не удалось запустить, строки читаются из
+// стандартного ввода
+use std::io;
+
+#[cfg(feature = "editor")]
+use std::borrow::Cow;
+#[cfg(feature = "editor")]
+use std::cell::RefCell;
+#[cfg(feature = "editor")]
+use less_2_task::calc::{tokerize, IncrementalTokens, Limits, Token, TokenType};
+
+#[cfg(feature = "editor")]
+use rustyline::completion::Completer;
+#[cfg(feature = "editor")]
+use rustyline::error::ReadlineError;
+#[cfg(feature = "editor")]
+use rustyline::highlight::{CmdKind, Highlighter};
+#[cfg(feature = "editor")]
+use rustyline::hint::Hinter;
+#[cfg(feature = "editor")]
+use rustyline::history::DefaultHistory;
+#[cfg(feature = "editor")]
+use rustyline::validate::Validator;
+#[cfg(feature = "editor")]
+use rustyline::{Context, Editor};
+
+pub struct LineReader {
+    #[cfg(feature = "editor")]
+    editor: Option<Editor<Helper, DefaultHistory>>,
+}
+
+impl LineReader {
+    #[cfg(feature = "editor")]
+    pub fn new() -> LineReader {
+        let editor = Editor::new().ok().map(|mut editor: Editor<Helper, DefaultHistory>| {
+            editor.set_helper(Some(Helper::new(Vec::new())));
+            editor
+        });
+        LineReader { editor }
+    }
+
+    #[cfg(not(feature = "editor"))]
+    pub fn new() -> LineReader {
+        LineReader {}
+    }
+
+    // Имена для дополнения; переменные сеанса меняются, поэтому имена задаются перед каждой строкой
+    #[cfg(feature = "editor")]
+    pub fn set_names(&mut self, names: Vec<String>) {
+        if let Some(helper) = self.editor.as_mut().and_then(Editor::helper_mut) {
+            helper.names = names;
+        }
+    }
+
+    #[cfg(not(feature = "editor"))]
+    pub fn set_names(&mut self, _names: Vec<String>) {}
+
+    // Очередная строка; None - конец ввода (Ctrl+D) или прерывание (Ctrl+C)
+    pub fn read_line(&mut self) -> Option<String> {
+        #[cfg(feature = "editor")]
+        if let Some(editor) = &mut self.editor {
+            return match editor.readline("") {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = editor.add_history_entry(line.as_str());
+                    }
+                    Some(line)
+                },
+                Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => None,
+                Err(why) => {
+                    eprintln!("Не удалось прочитать строку: {}", why);
+                    None
+                },
+            };
+        }
+        let mut input = String::new();
+        match io::stdin().read_line(&mut input).expect("Не удалось прочитать строку") {
+            0 => None,
+            _ => Some(input),
+        }
+    }
+}
+
+// Дополнение имени перед курсором и подсветка строки
+#[cfg(feature = "editor")]
+struct Helper {
+    names: Vec<String>,
+    // Токены строки с прошлой подсветки: после нажатия клавиши лексемы выделяются заново
+    // только рядом с правкой
+    tokens: RefCell<Option<IncrementalTokens>>,
+    limits: Limits,
+}
+
+#[cfg(feature = "editor")]
+impl Helper {
+    fn new(names: Vec<String>) -> Helper {
+        Helper { names, tokens: RefCell::new(None), limits: Limits::default() }
+    }
+}
+
+#[cfg(feature = "editor")]
+impl Completer for Helper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _context: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].trim_end_matches(|c: char| c.is_ascii_alphabetic()).len();
+        let prefix = &line[start..pos];
+        if prefix.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+        let mut candidates: Vec<String> = self.names.iter().filter(|name| name.starts_with(prefix)).cloned().collect();
+        // min - и функция, и единица измерения
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+#[cfg(feature = "editor")]
+impl Hinter for Helper {
+    type Hint = String;
+}
+
+// Цвета подсветки (escape-последовательности ANSI)
+#[cfg(feature = "editor")]
+const NUMBER_COLOR: &str = "\x1b[36m";
+#[cfg(feature = "editor")]
+const OPERATOR_COLOR: &str = "\x1b[33m";
+#[cfg(feature = "editor")]
+const FUNCTION_COLOR: &str = "\x1b[34m";
+#[cfg(feature = "editor")]
+const UNIT_COLOR: &str = "\x1b[32m";
+#[cfg(feature = "editor")]
+const ERROR_COLOR: &str = "\x1b[1;31m";
+#[cfg(feature = "editor")]
+const RESET: &str = "\x1b[0m";
+
+#[cfg(feature = "editor")]
+impl Highlighter for Helper {
+    // Команды (:set, !N) не подсвечиваются. Если лексему выделить нельзя, строка от места
+    // ошибки до конца выделяется цветом ошибки
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if line.trim().is_empty() || line.trim_start().starts_with([':', '!']) {
+            return Cow::Borrowed(line);
+        }
+        let mut tokens = self.tokens.borrow_mut();
+        let scanned = match tokens.as_mut() {
+            Some(tokens) => tokens.update(line, &self.limits).map(|_| ()),
+            None => IncrementalTokens::new(line, &self.limits).map(|scanned| *tokens = Some(scanned)),
+        };
+        match (scanned, tokens.as_ref()) {
+            (Ok(()), Some(tokens)) => Cow::Owned(highlight_tokens(line, &tokens.tokens())),
+            (Ok(()), None) => Cow::Borrowed(line),
+            // До места ошибки лексемы выделяются
+            (Err(why), _) => {
+                let start = why.span.map_or(0, |span| span.0).min(line.len());
+                let valid = match tokerize(&line[..start], &self.limits) {
+                    Ok(tokens) => highlight_tokens(&line[..start], &tokens),
+                    Err(_) => line[..start].to_string(),
+                };
+                Cow::Owned(format!("{}{}{}{}", valid, ERROR_COLOR, &line[start..], RESET))
+            },
+        }
+    }
+
+    // Подсветка меняется при каждой правке строки
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        true
+    }
+}
+
+// Строка с цветами токенов: числа, операторы, функции и единицы измерения; скобки без пары -
+// цветом ошибки
+#[cfg(feature = "editor")]
+fn highlight_tokens(line: &str, tokens: &[Token]) -> String {
+    let mut unmatched = vec![false; tokens.len()];
+    let mut opened: Vec<usize> = Vec::new();
+    for (index, tok) in tokens.iter().enumerate() {
+        match tok.0 {
+            TokenType::OpenedParenthesis | TokenType::OpenedBracket => opened.push(index),
+            TokenType::ClosedParenthesis | TokenType::ClosedBracket => {
+                let pair = match tok.0 {
+                    TokenType::ClosedParenthesis => TokenType::OpenedParenthesis,
+                    _ => TokenType::OpenedBracket,
+                };
+                match opened.last() {
+                    Some(open) if tokens[*open].0 == pair => {
+                        opened.pop();
+                    },
+                    _ => unmatched[index] = true,
+                }
+            },
+            _ => {},
+        }
+    }
+    for index in opened {
+        unmatched[index] = true;
+    }
+
+    let mut highlighted = String::with_capacity(line.len() * 2);
+    let mut position = 0;
+    for (tok, unmatched) in tokens.iter().zip(unmatched) {
+        let (start, end) = tok.2;
+        // Черта модуля |x| даёт несколько токенов на одном месте
+        if start < position || end > line.len() {
+            continue;
+        }
+        let color = match tok.0 {
+            _ if unmatched => Some(ERROR_COLOR),
+            TokenType::NumberInt | TokenType::NumberFloat | TokenType::Date => Some(NUMBER_COLOR),
+            TokenType::UnaryOperator | TokenType::BinaryOperator => Some(OPERATOR_COLOR),
+            TokenType::Function => Some(FUNCTION_COLOR),
+            TokenType::Unit => Some(UNIT_COLOR),
+            _ => None,
+        };
+        highlighted.push_str(&line[position..start]);
+        match color {
+            Some(color) => highlighted.push_str(&format!("{}{}{}", color, &line[start..end], RESET)),
+            None => highlighted.push_str(&line[start..end]),
+        }
+        position = end;
+    }
+    highlighted.push_str(&line[position..]);
+    highlighted
+}
+
+#[cfg(feature = "editor")]
+impl Validator for Helper {}
+
+#[cfg(feature = "editor")]
+impl rustyline::Helper for Helper {}
+
+#[cfg(all(test, feature = "editor"))]
+mod tests {
+    use super::*;
+    use less_2_task::calc::{Limits, Value};
+    use crate::Session;
+
+    #[test]
+    fn completes_session_variables() {
+        let limits = Limits::default();
+        let mut session = Session::new(&limits);
+        session.aliases.push(("vat".to_string(), "ans * 0.2".to_string()));
+        let names = |session: &Session| session.completions("").into_iter().map(|(name, _)| name.to_string()).collect();
+        let history = DefaultHistory::new();
+        let context = Context::new(&history);
+
+        let helper = Helper::new(names(&session));
+        assert_eq!(helper.complete("1 + va", 6, &context).unwrap(), (4, vec!["variance".to_string(), "vat".to_string()]));
+        assert_eq!(helper.complete("2 * a", 5, &context).unwrap(), (4, vec!["abs".to_string()]));
+        assert_eq!(helper.complete("mi", 2, &context).unwrap(), (0, vec!["mi".to_string(), "min".to_string()]));
+
+        session.ans = Some(Value::Number(1.0));
+        let helper = Helper::new(names(&session));
+        assert_eq!(helper.complete("2 * a", 5, &context).unwrap(), (4, vec!["abs".to_string(), "ans".to_string()]));
+        assert_eq!(helper.complete("2 * ", 4, &context).unwrap(), (4, Vec::new()));
+    }
+
+    #[test]
+    fn highlights_tokens_while_typing() {
+        let helper = Helper::new(Vec::new());
+        let number = |text: &str| format!("{}{}{}", NUMBER_COLOR, text, RESET);
+        let operator = |text: &str| format!("{}{}{}", OPERATOR_COLOR, text, RESET);
+        let error = |text: &str| format!("{}{}{}", ERROR_COLOR, text, RESET);
+        assert_eq!(helper.highlight("2 + 3", 5), format!("{} {} {}", number("2"), operator("+"), number("3")));
+        // Строка меняется по одному символу, как при вводе
+        assert_eq!(helper.highlight("2 + 34", 6), format!("{} {} {}", number("2"), operator("+"), number("34")));
+        assert_eq!(helper.highlight("sin(x) km", 9), format!("{}sin{}(x) {}km{}", FUNCTION_COLOR, RESET, UNIT_COLOR, RESET));
+        assert_eq!(helper.highlight("(1 + 2))", 8), format!("({} {} {}){}", number("1"), operator("+"), number("2"), error(")")));
+        assert_eq!(helper.highlight("[1, (2", 6), format!("{}{}, {}{}", error("["), number("1"), error("("), number("2")));
+        assert_eq!(helper.highlight("2 $ 3", 5), format!("{} {}", number("2"), error("$ 3")));
+        assert_eq!(helper.highlight(":set echo all", 13), ":set echo all");
+    }
+}
@@ -0,0 +1,138 @@
+// Интерфейс калькулятора для C/C++ (см. include/l2t.h)
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic;
+use std::ptr;
+
+use crate::calc::{process, ErrorKind, Limits};
+
+// Код завершения l2t_eval; значения совпадают с перечислением l2t_status в include/l2t.h.
+// Статус ошибки выражения - номер её кода плюс 99: E0001 - 100, E0030 - 129
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum L2tStatus {
+    Ok = 0,
+    NullPointer = 1,
+    InvalidUtf8 = 2,
+    BufferTooSmall = 3,
+    Panic = 4,
+    UnknownToken = 100,
+    MissingParenthesis = 101,
+    MissingArgumentSeparator = 102,
+    InvalidOutputQueue = 103,
+    NoResult = 104,
+    TooDeep = 105,
+    TooComplex = 106,
+    BudgetExceeded = 107,
+    Timeout = 108,
+    MissingOperator = 109,
+    UnknownFunction = 110,
+    InvalidFunctionCall = 111,
+    EmptyExpression = 112,
+    EmptyParentheses = 113,
+    DanglingOperator = 114,
+    InvalidNumber = 115,
+    InvalidShift = 116,
+    ShapeMismatch = 117,
+    UnitMismatch = 118,
+    UnknownUnit = 119,
+    InvalidDate = 120,
+    InvalidInterval = 121,
+    UnknownVariable = 122,
+    Cancelled = 123,
+    Forbidden = 124,
+    Overflow = 125,
+    DivisionByZero = 126,
+    InexactDivision = 127,
+    NoConvergence = 128,
+    OutOfRange = 129,
+}
+
+impl From<ErrorKind> for L2tStatus {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::UnknownToken => L2tStatus::UnknownToken,
+            ErrorKind::MissingParenthesis => L2tStatus::MissingParenthesis,
+            ErrorKind::MissingArgumentSeparator => L2tStatus::MissingArgumentSeparator,
+            ErrorKind::InvalidOutputQueue => L2tStatus::InvalidOutputQueue,
+            ErrorKind::NoResult => L2tStatus::NoResult,
+            ErrorKind::TooDeep => L2tStatus::TooDeep,
+            ErrorKind::TooComplex => L2tStatus::TooComplex,
+            ErrorKind::BudgetExceeded => L2tStatus::BudgetExceeded,
+            ErrorKind::Timeout => L2tStatus::Timeout,
+            ErrorKind::MissingOperator => L2tStatus::MissingOperator,
+            ErrorKind::UnknownFunction => L2tStatus::UnknownFunction,
+            ErrorKind::InvalidFunctionCall => L2tStatus::InvalidFunctionCall,
+            ErrorKind::EmptyExpression => L2tStatus::EmptyExpression,
+            ErrorKind::EmptyParentheses => L2tStatus::EmptyParentheses,
+            ErrorKind::DanglingOperator => L2tStatus::DanglingOperator,
+            ErrorKind::InvalidNumber => L2tStatus::InvalidNumber,
+            ErrorKind::InvalidShift => L2tStatus::InvalidShift,
+            ErrorKind::ShapeMismatch => L2tStatus::ShapeMismatch,
+            ErrorKind::UnitMismatch => L2tStatus::UnitMismatch,
+            ErrorKind::UnknownUnit => L2tStatus::UnknownUnit,
+            ErrorKind::InvalidDate => L2tStatus::InvalidDate,
+            ErrorKind::InvalidInterval => L2tStatus::InvalidInterval,
+            ErrorKind::UnknownVariable => L2tStatus::UnknownVariable,
+            ErrorKind::Cancelled => L2tStatus::Cancelled,
+            ErrorKind::Forbidden => L2tStatus::Forbidden,
+            ErrorKind::Overflow => L2tStatus::Overflow,
+            ErrorKind::DivisionByZero => L2tStatus::DivisionByZero,
+            ErrorKind::InexactDivision => L2tStatus::InexactDivision,
+            ErrorKind::NoConvergence => L2tStatus::NoConvergence,
+            ErrorKind::OutOfRange => L2tStatus::OutOfRange,
+        }
+    }
+}
+
+// Запись строки с завершающим нулём в буфер вызывающей стороны; если строка не
+// помещается, в буфер записывается пустая строка
+unsafe fn write_out(text: &str, out: *mut c_char, cap: usize) -> bool {
+    if text.len() < cap {
+        ptr::copy_nonoverlapping(text.as_ptr() as *const c_char, out, text.len());
+        *out.add(text.len()) = 0;
+        true
+    } else {
+        if cap > 0 {
+            *out = 0;
+        }
+        false
+    }
+}
+
+/// Вычисляет выражение `expr` и записывает в `out` результат либо сообщение об ошибке
+/// (UTF-8 с завершающим нулём). Сообщение об ошибке при нехватке места обрезается,
+/// результат - нет: вместо него возвращается `BufferTooSmall`.
+///
+/// # Safety
+///
+/// `expr` - указатель на строку с завершающим нулём, `out` - указатель на буфер
+/// размером не меньше `cap` байт.
+#[no_mangle]
+pub unsafe extern "C" fn l2t_eval(expr: *const c_char, out: *mut c_char, cap: usize) -> L2tStatus {
+    if expr.is_null() || out.is_null() {
+        return L2tStatus::NullPointer;
+    }
+    let input = match CStr::from_ptr(expr).to_str() {
+        Ok(input) => input,
+        Err(_) => return L2tStatus::InvalidUtf8,
+    };
+    // Паника не должна выходить за границу FFI
+    let evaluation = match panic::catch_unwind(|| process(input, &Limits::default())) {
+        Ok(evaluation) => evaluation,
+        Err(_) => return L2tStatus::Panic,
+    };
+    match evaluation {
+        Ok(evaluation) if write_out(&evaluation.result, out, cap) => L2tStatus::Ok,
+        Ok(_) => L2tStatus::BufferTooSmall,
+        Err(error) => {
+            // Сообщение об ошибке обрезается до размера буфера по границе символа
+            let mut end = error.message.len().min(cap.saturating_sub(1));
+            while !error.message.is_char_boundary(end) {
+                end -= 1;
+            }
+            write_out(&error.message[..end], out, cap);
+            error.kind.into()
+        },
+    }
+}
@@ -0,0 +1,210 @@
+// Минимальный разбор и запись JSON для запросов к серверу и по протоколу JSON-RPC
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+use less_2_task::calc::json_string;
+
+// Максимальная вложенность массивов и объектов в запросе
+const MAX_NESTING: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    // Значение поля объекта
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|field| field.0 == key).map(|field| &field.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(value) => write!(f, "{}", json_string(value)),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", json_string(key), value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Json, String> {
+    let mut chars = input.chars().peekable();
+    let value = parse_value(&mut chars, 0)?;
+    skip_whitespaces(&mut chars);
+    match chars.next() {
+        None => Ok(value),
+        Some(c) => Err(format!("лишний символ '{}' после значения", c)),
+    }
+}
+
+fn skip_whitespaces(chars: &mut Peekable<Chars>) {
+    while matches!(chars.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+        chars.next();
+    }
+}
+
+fn expect_word(chars: &mut Peekable<Chars>, word: &str, value: Json) -> Result<Json, String> {
+    for expected in word.chars() {
+        if chars.next() != Some(expected) {
+            return Err(format!("ожидалось '{}'", word));
+        }
+    }
+    Ok(value)
+}
+
+fn parse_value(chars: &mut Peekable<Chars>, depth: usize) -> Result<Json, String> {
+    if depth > MAX_NESTING {
+        return Err(format!("превышена вложенность ({})", MAX_NESTING));
+    }
+    skip_whitespaces(chars);
+    match chars.peek() {
+        Some('n') => expect_word(chars, "null", Json::Null),
+        Some('t') => expect_word(chars, "true", Json::Bool(true)),
+        Some('f') => expect_word(chars, "false", Json::Bool(false)),
+        Some('"') => parse_string(chars).map(Json::String),
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            skip_whitespaces(chars);
+            if chars.peek() == Some(&']') {
+                chars.next();
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(parse_value(chars, depth + 1)?);
+                skip_whitespaces(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Json::Array(items)),
+                    _ => return Err("ожидалось ',' или ']'".to_string()),
+                }
+            }
+        },
+        Some('{') => {
+            chars.next();
+            let mut fields = Vec::new();
+            skip_whitespaces(chars);
+            if chars.peek() == Some(&'}') {
+                chars.next();
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                skip_whitespaces(chars);
+                if chars.peek() != Some(&'"') {
+                    return Err("ожидалось имя поля".to_string());
+                }
+                let key = parse_string(chars)?;
+                skip_whitespaces(chars);
+                if chars.next() != Some(':') {
+                    return Err("ожидалось ':'".to_string());
+                }
+                fields.push((key, parse_value(chars, depth + 1)?));
+                skip_whitespaces(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => return Ok(Json::Object(fields)),
+                    _ => return Err("ожидалось ',' или '}'".to_string()),
+                }
+            }
+        },
+        Some(c) if *c == '-' || c.is_ascii_digit() => {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            number.parse().map(Json::Number).map_err(|_| format!("некорректное число '{}'", number))
+        },
+        Some(c) => Err(format!("неожиданный символ '{}'", c)),
+        None => Err("неожиданный конец данных".to_string()),
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    chars.next();
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(value),
+            Some('\\') => match chars.next() {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('/') => value.push('/'),
+                Some('b') => value.push('\u{8}'),
+                Some('f') => value.push('\u{c}'),
+                Some('n') => value.push('\n'),
+                Some('r') => value.push('\r'),
+                Some('t') => value.push('\t'),
+                Some('u') => {
+                    let code = parse_hex4(chars)?;
+                    // Символы вне базовой плоскости записываются суррогатной парой
+                    let code = if (0xD800..0xDC00).contains(&code) {
+                        if chars.next() != Some('\\') || chars.next() != Some('u') {
+                            return Err("ожидалась вторая половина суррогатной пары".to_string());
+                        }
+                        let low = parse_hex4(chars)?;
+                        if !(0xDC00..0xE000).contains(&low) {
+                            return Err("некорректная суррогатная пара".to_string());
+                        }
+                        0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)
+                    } else {
+                        code
+                    };
+                    value.push(char::from_u32(code).ok_or_else(|| "некорректный символ в строке".to_string())?);
+                },
+                _ => return Err("некорректная escape-последовательность".to_string()),
+            },
+            Some(c) => value.push(c),
+            None => return Err("незакрытая строка".to_string()),
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+    let digits: String = chars.by_ref().take(4).collect();
+    match u32::from_str_radix(&digits, 16) {
+        Ok(code) if digits.len() == 4 => Ok(code),
+        _ => Err(format!("некорректный код символа '{}'", digits)),
+    }
+}
@@ -1,12 +1,95 @@
+// Без feature "std" библиотека использует только core и alloc
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("без feature \"std\" для математических функций нужна feature \"libm\"");
+
+extern crate alloc;
+
+pub mod calc;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "python")]
+pub mod python;
+
+use alloc::collections::{vec_deque, VecDeque};
+use alloc::rc::Rc;
+use alloc::vec::{self, Vec};
+use core::cmp::Ordering;
+use core::fmt;
+use core::iter::{FromIterator, Rev};
+use core::{mem, slice};
+#[cfg(feature = "std")]
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// Вывод элементов контейнера в виде `Имя[первый, ..., последний]`; стрелки с метками
+// `marks` указывают на первый и последний элементы непустого контейнера:
+// `Queue[front → 1, 2 ← back]`
+fn fmt_items<I, F>(f: &mut fmt::Formatter<'_>, name: &str, marks: (Option<&str>, Option<&str>), items: I, mut fmt_item: F) -> fmt::Result
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item, &mut fmt::Formatter<'_>) -> fmt::Result,
+{
+    write!(f, "{}[", name)?;
+    let mut empty = true;
+    for (index, item) in items.into_iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        } else if let Some(first) = marks.0 {
+            write!(f, "{} → ", first)?;
+        }
+        fmt_item(&item, f)?;
+        empty = false;
+    }
+    if let Some(last) = marks.1.filter(|_| !empty) {
+        write!(f, " ← {}", last)?;
+    }
+    write!(f, "]")
+}
+
+// Метки стека: вершина - последний элемент
+const STACK_MARKS: (Option<&str>, Option<&str>) = (None, Some("top"));
+// Метки очереди: начало, откуда извлекаются элементы, и конец, куда добавляются
+const QUEUE_MARKS: (Option<&str>, Option<&str>) = (Some("front"), Some("back"));
+
+// Стек сериализуется как последовательность элементов от дна к вершине
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
 pub struct Stack<T> {
     pub stack: Vec<T>,
 }
 
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> Stack<T> {
     pub fn new() -> Self {
         Stack { stack: Vec::new() }
     }
 
+    pub fn with_capacity(capacity: usize) -> Self {
+        Stack { stack: Vec::with_capacity(capacity) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.stack.capacity()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.stack.reserve(additional)
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.stack.shrink_to_fit()
+    }
+
     pub fn pop(&mut self) -> Option<T> {
         self.stack.pop()
     }
@@ -23,29 +106,1453 @@ impl<T> Stack<T> {
         self.stack.last()
     }
 
+    pub fn peek_mut(&mut self) -> Option<&mut T> {
+        self.stack.last_mut()
+    }
+
     pub fn size(&self) -> usize {
         self.stack.len()
     }
+
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    // Элемент на глубине `n` от вершины стека (0 - вершина)
+    pub fn get(&self, n: usize) -> Option<&T> {
+        let len = self.stack.len();
+        if n >= len {
+            return None;
+        }
+        self.stack.get(len - 1 - n)
+    }
+
+    // Обход элементов от вершины стека к его дну (в порядке извлечения)
+    pub fn iter(&self) -> Rev<slice::Iter<'_, T>> {
+        self.stack.iter().rev()
+    }
+
+    // Элементы в порядке от дна стека к его вершине
+    pub fn into_vec(self) -> Vec<T> {
+        self.stack
+    }
+
+    // Извлечение всех элементов от вершины к дну
+    pub fn drain(&mut self) -> Rev<vec::Drain<'_, T>> {
+        self.stack.drain(..).rev()
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear()
+    }
+
+    // Оставляет в стеке не более `len` элементов, считая от дна
+    pub fn truncate(&mut self, len: usize) {
+        self.stack.truncate(len)
+    }
+}
+
+impl<T> IntoIterator for Stack<T> {
+    type Item = T;
+    type IntoIter = Rev<vec::IntoIter<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.stack.into_iter().rev()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Stack<T> {
+    type Item = &'a T;
+    type IntoIter = Rev<slice::Iter<'a, T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// Элементы помещаются в стек по порядку: последний оказывается на вершине
+impl<T> FromIterator<T> for Stack<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Stack { stack: Vec::from_iter(iter) }
+    }
+}
+
+impl<T> Extend<T> for Stack<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.stack.extend(iter)
+    }
+}
+
+// Последний элемент вектора оказывается на вершине стека
+impl<T> From<Vec<T>> for Stack<T> {
+    fn from(stack: Vec<T>) -> Self {
+        Stack { stack }
+    }
+}
+
+impl<T> From<Stack<T>> for Vec<T> {
+    fn from(stack: Stack<T>) -> Self {
+        stack.into_vec()
+    }
+}
+
+// Стек выводится от дна к вершине
+impl<T: fmt::Debug> fmt::Debug for Stack<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_items(f, "Stack", STACK_MARKS, &self.stack, fmt::Debug::fmt)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Stack<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_items(f, "Stack", STACK_MARKS, &self.stack, fmt::Display::fmt)
+    }
 }
 
+// Очередь реализована на кольцевом буфере: извлечение из начала выполняется за O(1).
+// Несовместимо с версией 0.1: открытого поля `queue: Vec<T>` больше нет, элементы доступны
+// через iter(), get() и into_vec() (или Vec::from) в том же порядке - от начала к концу
 pub struct Queue<T> {
-    pub queue: Vec<T>,
+    buffer: Vec<Option<T>>,
+    head: usize,
+    len: usize,
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<T> Queue<T> {
     pub fn new() -> Self {
-        Queue { queue: Vec::new() }
+        Queue { buffer: Vec::new(), head: 0, len: 0 }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut queue = Self::new();
+        queue.resize_buffer(capacity);
+        queue
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        let required = self.len + additional;
+        if required > self.capacity() {
+            self.resize_buffer(required.max(2 * self.capacity()));
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.resize_buffer(self.len);
     }
 
     pub fn enqueue(&mut self, item: T) {
-        self.queue.push(item)
+        if self.len == self.capacity() {
+            self.resize_buffer((2 * self.capacity()).max(4));
+        }
+        let index = self.physical_index(self.len);
+        self.buffer[index] = Some(item);
+        self.len += 1;
     }
 
     pub fn dequeue(&mut self) -> T {
-        self.queue.remove(0)
+        assert!(!self.is_empty(), "извлечение из пустой очереди");
+        let item = self.buffer[self.head].take().unwrap();
+        self.head = (self.head + 1) % self.capacity();
+        self.len -= 1;
+        item
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        if self.is_empty() {
+            return None;
+        }
+        self.buffer[self.head].as_ref()
+    }
+
+    pub fn peek_mut(&mut self) -> Option<&mut T> {
+        if self.is_empty() {
+            return None;
+        }
+        self.buffer[self.head].as_mut()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Элемент с номером `n` от начала очереди (0 - начало)
+    pub fn get(&self, n: usize) -> Option<&T> {
+        if n >= self.len {
+            return None;
+        }
+        self.buffer[self.physical_index(n)].as_ref()
+    }
+
+    // Обход элементов от начала очереди к её концу (в порядке извлечения)
+    pub fn iter(&self) -> QueueIter<'_, T> {
+        QueueIter { queue: self, front: 0, back: self.len }
+    }
+
+    // Элементы в порядке от начала очереди к её концу
+    pub fn into_vec(self) -> Vec<T> {
+        self.into_iter().collect()
+    }
+
+    // Очередь из элементов стека в порядке их извлечения из стека (вершина - в начале очереди)
+    pub fn from_stack(stack: Stack<T>) -> Self {
+        Queue::from_iter(stack)
+    }
+
+    // Извлечение всех элементов от начала очереди к её концу
+    pub fn drain(&mut self) -> QueueIntoIter<T> {
+        mem::take(self).into_iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+        self.head = 0;
+    }
+
+    // Оставляет в очереди не более `len` элементов, считая от начала
+    pub fn truncate(&mut self, len: usize) {
+        for offset in len..self.len {
+            let index = self.physical_index(offset);
+            self.buffer[index] = None;
+        }
+        self.len = self.len.min(len);
+    }
+
+    // Индекс в буфере для элемента с порядковым номером `offset` от начала очереди
+    fn physical_index(&self, offset: usize) -> usize {
+        (self.head + offset) % self.capacity()
+    }
+
+    // Перенос элементов в новый буфер заданного размера (начало очереди - в нулевой ячейке)
+    fn resize_buffer(&mut self, capacity: usize) {
+        let mut buffer = Vec::with_capacity(capacity);
+        for offset in 0..self.len {
+            let index = self.physical_index(offset);
+            buffer.push(self.buffer[index].take());
+        }
+        buffer.resize_with(capacity, || None);
+        self.buffer = buffer;
+        self.head = 0;
+    }
+}
+
+// Очередь сериализуется как последовательность элементов от начала к концу
+#[cfg(feature = "serde")]
+impl<T: Serialize> Serialize for Queue<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Queue<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Queue::from_iter)
+    }
+}
+
+pub struct QueueIter<'a, T> {
+    queue: &'a Queue<T>,
+    front: usize,
+    back: usize,
+}
+
+impl<'a, T> Iterator for QueueIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        let index = self.queue.physical_index(self.front);
+        self.front += 1;
+        self.queue.buffer[index].as_ref()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for QueueIter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        let index = self.queue.physical_index(self.back);
+        self.queue.buffer[index].as_ref()
+    }
+}
+
+impl<'a, T> ExactSizeIterator for QueueIter<'a, T> {}
+
+pub struct QueueIntoIter<T> {
+    queue: Queue<T>,
+}
+
+impl<T> Iterator for QueueIntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.queue.is_empty() {
+            return None;
+        }
+        Some(self.queue.dequeue())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.queue.len, Some(self.queue.len))
+    }
+}
+
+impl<T> ExactSizeIterator for QueueIntoIter<T> {}
+
+impl<T> IntoIterator for Queue<T> {
+    type Item = T;
+    type IntoIter = QueueIntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        QueueIntoIter { queue: self }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Queue<T> {
+    type Item = &'a T;
+    type IntoIter = QueueIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T> FromIterator<T> for Queue<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let buffer: Vec<Option<T>> = iter.into_iter().map(Some).collect();
+        let len = buffer.len();
+        Queue { buffer, head: 0, len }
+    }
+}
+
+impl<T> Extend<T> for Queue<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for item in iter {
+            self.enqueue(item);
+        }
+    }
+}
+// Первый элемент вектора оказывается в начале очереди
+impl<T> From<Vec<T>> for Queue<T> {
+    fn from(vec: Vec<T>) -> Self {
+        Queue::from_iter(vec)
+    }
+}
+
+impl<T> From<Queue<T>> for Vec<T> {
+    fn from(queue: Queue<T>) -> Self {
+        queue.into_vec()
+    }
+}
+
+// Очередь выводится от начала к концу
+impl<T: fmt::Debug> fmt::Debug for Queue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_items(f, "Queue", QUEUE_MARKS, self, fmt::Debug::fmt)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Queue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_items(f, "Queue", QUEUE_MARKS, self, fmt::Display::fmt)
+    }
+}
+
+// Дек сериализуется как последовательность элементов от начала к концу
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct Deque<T> {
+    pub deque: VecDeque<T>,
+}
+
+impl<T> Default for Deque<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deque<T> {
+    pub fn new() -> Self {
+        Deque { deque: VecDeque::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Deque { deque: VecDeque::with_capacity(capacity) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.deque.capacity()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.deque.reserve(additional)
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.deque.shrink_to_fit()
+    }
+
+    pub fn push_front(&mut self, item: T) {
+        self.deque.push_front(item)
+    }
+
+    pub fn push_back(&mut self, item: T) {
+        self.deque.push_back(item)
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.deque.pop_front()
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        self.deque.pop_back()
+    }
+
+    pub fn peek_front(&self) -> Option<&T> {
+        self.deque.front()
+    }
+
+    pub fn peek_back(&self) -> Option<&T> {
+        self.deque.back()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deque.is_empty()
+    }
+
+    pub fn size(&self) -> usize {
+        self.deque.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.deque.len()
+    }
+
+    // Элемент с номером `n` от начала дека (0 - начало)
+    pub fn get(&self, n: usize) -> Option<&T> {
+        self.deque.get(n)
+    }
+
+    // Обход элементов от начала дека к его концу
+    pub fn iter(&self) -> vec_deque::Iter<'_, T> {
+        self.deque.iter()
+    }
+
+    // Элементы в порядке от начала дека к его концу
+    pub fn into_vec(self) -> Vec<T> {
+        self.deque.into()
+    }
+
+    // Извлечение всех элементов от начала дека к его концу
+    pub fn drain(&mut self) -> vec_deque::Drain<'_, T> {
+        self.deque.drain(..)
+    }
+
+    pub fn clear(&mut self) {
+        self.deque.clear()
+    }
+
+    // Оставляет в деке не более `len` элементов, считая от начала
+    pub fn truncate(&mut self, len: usize) {
+        self.deque.truncate(len)
+    }
+}
+
+impl<T> IntoIterator for Deque<T> {
+    type Item = T;
+    type IntoIter = vec_deque::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.deque.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Deque<T> {
+    type Item = &'a T;
+    type IntoIter = vec_deque::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// Элементы добавляются в конец дека по порядку
+impl<T> FromIterator<T> for Deque<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Deque { deque: VecDeque::from_iter(iter) }
+    }
+}
+
+impl<T> Extend<T> for Deque<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.deque.extend(iter)
+    }
+}
+
+// Первый элемент вектора оказывается в начале дека
+impl<T> From<Vec<T>> for Deque<T> {
+    fn from(vec: Vec<T>) -> Self {
+        Deque { deque: VecDeque::from(vec) }
+    }
+}
+
+impl<T> From<Deque<T>> for Vec<T> {
+    fn from(deque: Deque<T>) -> Self {
+        deque.into_vec()
+    }
+}
+
+// Дек выводится от начала к концу
+impl<T: fmt::Debug> fmt::Debug for Deque<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_items(f, "Deque", QUEUE_MARKS, self, fmt::Debug::fmt)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Deque<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_items(f, "Deque", QUEUE_MARKS, self, fmt::Display::fmt)
+    }
+}
+
+// Ошибка добавления элемента в заполненный ограниченный контейнер
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Full;
+
+impl fmt::Display for Full {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "контейнер заполнен")
+    }
+}
+
+// Стек с ограничением на число элементов
+pub struct BoundedStack<T> {
+    stack: Stack<T>,
+    limit: usize,
+}
+
+impl<T> BoundedStack<T> {
+    pub fn new(limit: usize) -> Self {
+        BoundedStack { stack: Stack::new(), limit }
+    }
+
+    // Стек с ограничением из обычного стека; элементы сверх ограничения остаются в нём
+    pub fn from_stack(stack: Stack<T>, limit: usize) -> Self {
+        BoundedStack { stack, limit }
+    }
+
+    // Обычный стек с теми же элементами и выделенной памятью
+    pub fn into_stack(self) -> Stack<T> {
+        self.stack
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.stack.size() >= self.limit
+    }
+
+    pub fn push(&mut self, item: T) -> Result<(), Full> {
+        if self.is_full() {
+            return Err(Full);
+        }
+        self.stack.push(item);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.stack.pop()
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        self.stack.peek()
+    }
+
+    pub fn peek_mut(&mut self) -> Option<&mut T> {
+        self.stack.peek_mut()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    pub fn size(&self) -> usize {
+        self.stack.size()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn get(&self, n: usize) -> Option<&T> {
+        self.stack.get(n)
+    }
+
+    pub fn iter(&self) -> Rev<slice::Iter<'_, T>> {
+        self.stack.iter()
+    }
+
+    pub fn drain(&mut self) -> Rev<vec::Drain<'_, T>> {
+        self.stack.drain()
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear()
+    }
+}
+
+// Очередь с ограничением на число элементов
+pub struct BoundedQueue<T> {
+    queue: Queue<T>,
+    limit: usize,
+}
+
+impl<T> BoundedQueue<T> {
+    pub fn new(limit: usize) -> Self {
+        BoundedQueue { queue: Queue::new(), limit }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.queue.len() >= self.limit
+    }
+
+    pub fn enqueue(&mut self, item: T) -> Result<(), Full> {
+        if self.is_full() {
+            return Err(Full);
+        }
+        self.queue.enqueue(item);
+        Ok(())
+    }
+
+    pub fn dequeue(&mut self) -> T {
+        self.queue.dequeue()
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        self.queue.peek()
+    }
+
+    pub fn peek_mut(&mut self) -> Option<&mut T> {
+        self.queue.peek_mut()
     }
 
     pub fn is_empty(&self) -> bool {
         self.queue.is_empty()
     }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn get(&self, n: usize) -> Option<&T> {
+        self.queue.get(n)
+    }
+
+    pub fn iter(&self) -> QueueIter<'_, T> {
+        self.queue.iter()
+    }
+
+    pub fn drain(&mut self) -> QueueIntoIter<T> {
+        self.queue.drain()
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear()
+    }
+
+    pub fn into_queue(self) -> Queue<T> {
+        self.queue
+    }
+}
+
+// Потокобезопасный стек: все операции выполняются под мьютексом
+#[cfg(feature = "std")]
+pub struct SyncStack<T> {
+    stack: Mutex<Stack<T>>,
+}
+
+#[cfg(feature = "std")]
+impl<T> Default for SyncStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> SyncStack<T> {
+    pub fn new() -> Self {
+        SyncStack { stack: Mutex::new(Stack::new()) }
+    }
+
+    pub fn push(&self, item: T) {
+        self.lock().push(item)
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.lock().pop()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub fn size(&self) -> usize {
+        self.lock().size()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn into_inner(self) -> Stack<T> {
+        self.stack.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Паника в другом потоке не портит сам стек, поэтому "отравление" мьютекса игнорируется
+    fn lock(&self) -> MutexGuard<'_, Stack<T>> {
+        self.stack.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Потокобезопасная очередь: извлечение может ожидать появления элемента
+#[cfg(feature = "std")]
+pub struct SyncQueue<T> {
+    queue: Mutex<Queue<T>>,
+    available: Condvar,
+}
+
+#[cfg(feature = "std")]
+impl<T> Default for SyncQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> SyncQueue<T> {
+    pub fn new() -> Self {
+        SyncQueue { queue: Mutex::new(Queue::new()), available: Condvar::new() }
+    }
+
+    pub fn enqueue(&self, item: T) {
+        self.lock().enqueue(item);
+        self.available.notify_one();
+    }
+
+    // Извлечение без ожидания: None, если очередь пуста
+    pub fn try_dequeue(&self) -> Option<T> {
+        let mut queue = self.lock();
+        if queue.is_empty() {
+            return None;
+        }
+        Some(queue.dequeue())
+    }
+
+    // Извлечение с ожиданием, пока в очереди не появится элемент
+    pub fn dequeue(&self) -> T {
+        let mut queue = self.lock();
+        while queue.is_empty() {
+            queue = self.available.wait(queue).unwrap_or_else(PoisonError::into_inner);
+        }
+        queue.dequeue()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn into_inner(self) -> Queue<T> {
+        self.queue.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Queue<T>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Неизменяемый (персистентный) стек: `push` и `pop` не меняют исходный стек, а возвращают
+// новый, разделяющий с исходным общие узлы
+pub struct PersistentStack<T> {
+    head: Option<Rc<PersistentNode<T>>>,
+    size: usize,
+}
+
+struct PersistentNode<T> {
+    item: T,
+    next: Option<Rc<PersistentNode<T>>>,
+}
+
+impl<T> Default for PersistentStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for PersistentStack<T> {
+    fn clone(&self) -> Self {
+        PersistentStack { head: self.head.clone(), size: self.size }
+    }
+}
+
+impl<T> PersistentStack<T> {
+    pub fn new() -> Self {
+        PersistentStack { head: None, size: 0 }
+    }
+
+    pub fn push(&self, item: T) -> Self {
+        let node = PersistentNode { item, next: self.head.clone() };
+        PersistentStack { head: Some(Rc::new(node)), size: self.size + 1 }
+    }
+
+    // Вершина стека и стек без неё
+    pub fn pop(&self) -> Option<(&T, Self)> {
+        self.head.as_ref().map(|node| {
+            (&node.item, PersistentStack { head: node.next.clone(), size: self.size - 1 })
+        })
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        self.head.as_ref().map(|node| &node.item)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    // Обход элементов от вершины стека к его дну
+    pub fn iter(&self) -> PersistentStackIter<'_, T> {
+        PersistentStackIter { next: self.head.as_deref(), remaining: self.size }
+    }
+}
+
+// Узлы освобождаются в цикле, а не рекурсивно, чтобы длинный стек не переполнил стек вызовов
+impl<T> Drop for PersistentStack<T> {
+    fn drop(&mut self) {
+        let mut next = self.head.take();
+        while let Some(node) = next {
+            match Rc::try_unwrap(node) {
+                Ok(mut node) => next = node.next.take(),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+pub struct PersistentStackIter<'a, T> {
+    next: Option<&'a PersistentNode<T>>,
+    remaining: usize,
+}
+
+impl<'a, T> Iterator for PersistentStackIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next.map(|node| {
+            self.next = node.next.as_deref();
+            self.remaining -= 1;
+            &node.item
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for PersistentStackIter<'a, T> {}
+
+impl<'a, T> IntoIterator for &'a PersistentStack<T> {
+    type Item = &'a T;
+    type IntoIter = PersistentStackIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// Очередь с приоритетом на двоичной куче: первым извлекается элемент с наибольшим
+// приоритетом, а при равных приоритетах - добавленный раньше
+pub struct PriorityQueue<T, P: Ord> {
+    heap: Vec<PriorityEntry<T, P>>,
+    sequence: u64,
+}
+
+struct PriorityEntry<T, P> {
+    item: T,
+    priority: P,
+    sequence: u64,
+}
+
+impl<T, P: Ord> Default for PriorityQueue<T, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, P: Ord> PriorityQueue<T, P> {
+    pub fn new() -> Self {
+        PriorityQueue { heap: Vec::new(), sequence: 0 }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        PriorityQueue { heap: Vec::with_capacity(capacity), sequence: 0 }
+    }
+
+    pub fn push(&mut self, item: T, priority: P) {
+        self.heap.push(PriorityEntry { item, priority, sequence: self.sequence });
+        self.sequence += 1;
+        self.sift_up(self.heap.len() - 1);
+    }
+
+    pub fn pop_max(&mut self) -> Option<T> {
+        if self.heap.is_empty() {
+            return None;
+        }
+        let entry = self.heap.swap_remove(0);
+        if !self.heap.is_empty() {
+            self.sift_down(0);
+        }
+        Some(entry.item)
+    }
+
+    pub fn peek_max(&self) -> Option<(&T, &P)> {
+        self.heap.first().map(|entry| (&entry.item, &entry.priority))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    // Элементы в порядке извлечения pop_max: по убыванию приоритета, при равном
+    // приоритете - в порядке добавления
+    pub fn into_sorted(mut self) -> Vec<T> {
+        self.heap.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.sequence.cmp(&b.sequence)));
+        self.heap.into_iter().map(|entry| entry.item).collect()
+    }
+
+    // Извлечение всех элементов в порядке pop_max
+    pub fn drain(&mut self) -> vec::IntoIter<T> {
+        mem::take(self).into_sorted().into_iter()
+    }
+
+    // Должен ли элемент с индексом `a` стоять в куче выше элемента с индексом `b`
+    fn higher(&self, a: usize, b: usize) -> bool {
+        let (a, b) = (&self.heap[a], &self.heap[b]);
+        match a.priority.cmp(&b.priority) {
+            Ordering::Equal => a.sequence < b.sequence,
+            ordering => ordering == Ordering::Greater,
+        }
+    }
+
+    fn sift_up(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if !self.higher(index, parent) {
+                break;
+            }
+            self.heap.swap(index, parent);
+            index = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let mut largest = index;
+            for child in [2 * index + 1, 2 * index + 2].iter().copied() {
+                if child < self.heap.len() && self.higher(child, largest) {
+                    largest = child;
+                }
+            }
+            if largest == index {
+                break;
+            }
+            self.heap.swap(index, largest);
+            index = largest;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{format, vec};
+
+    #[test]
+    fn stack_format_marks_top() {
+        let stack: Stack<i32> = Stack::from(vec![1, 2, 3]);
+        assert_eq!(format!("{:?}", stack), "Stack[1, 2, 3 ← top]");
+        assert_eq!(format!("{}", stack), "Stack[1, 2, 3 ← top]");
+        assert_eq!(format!("{:?}", Stack::<i32>::new()), "Stack[]");
+    }
+
+    #[test]
+    fn queue_format_marks_front_and_back() {
+        let queue: Queue<&str> = Queue::from(vec!["a", "b"]);
+        assert_eq!(format!("{:?}", queue), "Queue[front → \"a\", \"b\" ← back]");
+        assert_eq!(format!("{}", queue), "Queue[front → a, b ← back]");
+        assert_eq!(format!("{}", Queue::<i32>::new()), "Queue[]");
+    }
+
+    // Очередь с элементами 0..len, у которой начало сдвинуто на `shift` ячеек кольцевого
+    // буфера, так что при shift > 0 элементы переходят через конец буфера
+    fn wrapped_queue(len: usize, shift: usize) -> Queue<usize> {
+        let mut queue = Queue::with_capacity(len.max(1));
+        for _ in 0..shift {
+            queue.enqueue(usize::MAX);
+            queue.dequeue();
+        }
+        queue.extend(0..len);
+        queue
+    }
+
+    #[test]
+    fn stack_vec_round_trip_keeps_order() {
+        for len in 0..16 {
+            let items: Vec<usize> = (0..len).collect();
+            let stack = Stack::from(items.clone());
+            assert_eq!(stack.peek(), items.last());
+            assert_eq!(Vec::from(stack), items);
+        }
+    }
+
+    #[test]
+    fn queue_vec_round_trip_keeps_order() {
+        for len in 0..16 {
+            let items: Vec<usize> = (0..len).collect();
+            let queue = Queue::from(items.clone());
+            assert_eq!(queue.peek(), items.first());
+            assert_eq!(Vec::from(Queue::from(Vec::from(queue))), items);
+        }
+    }
+
+    #[test]
+    fn wrapped_queue_into_vec_keeps_order() {
+        for len in 1..16 {
+            for shift in 0..len {
+                let queue = wrapped_queue(len, shift);
+                assert_eq!(queue.capacity(), len);
+                assert_eq!(queue.head + queue.len > queue.capacity(), shift > 0);
+                assert_eq!(Vec::from(queue), (0..len).collect::<Vec<usize>>());
+            }
+        }
+    }
+
+    #[test]
+    fn queue_wraps_around_after_growth() {
+        let mut queue = Queue::with_capacity(4);
+        let mut model = VecDeque::new();
+        // Начало сдвигается к концу буфера, затем буфер растёт, пока элементы переходят через его конец
+        for step in 0..200usize {
+            if step % 3 == 2 {
+                assert_eq!(queue.dequeue(), model.pop_front().unwrap());
+            } else {
+                queue.enqueue(step);
+                model.push_back(step);
+            }
+            assert_eq!(queue.len(), model.len());
+            assert_eq!(queue.peek(), model.front());
+            assert_eq!(queue.iter().next_back(), model.back());
+        }
+        assert!(queue.capacity() >= queue.len() && queue.capacity() < 4 * queue.len());
+        while let Some(expected) = model.pop_front() {
+            assert_eq!(queue.dequeue(), expected);
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn queue_dequeue_does_not_move_items() {
+        let mut queue: Queue<usize> = (0..8).collect();
+        let buffer = queue.buffer.as_ptr();
+        for expected in 0..5 {
+            assert_eq!(queue.dequeue(), expected);
+            assert_eq!(queue.head, expected + 1);
+        }
+        // Свободные ячейки в начале буфера занимаются без роста и переноса элементов
+        queue.extend(8..13);
+        assert_eq!((queue.capacity(), queue.buffer.as_ptr()), (8, buffer));
+        assert_eq!(Vec::from(queue), (5..13).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn deque_format_marks_front_and_back() {
+        let deque: Deque<&str> = Deque::from(vec!["a", "b"]);
+        assert_eq!(format!("{:?}", deque), "Deque[front → \"a\", \"b\" ← back]");
+        assert_eq!(format!("{}", deque), "Deque[front → a, b ← back]");
+        assert_eq!(format!("{}", Deque::<i32>::new()), "Deque[]");
+    }
+
+    #[test]
+    fn deque_both_ends_and_conversions() {
+        let mut deque: Deque<usize> = (3..6).collect();
+        deque.push_front(2);
+        deque.extend([6, 7]);
+        deque.push_front(1);
+        assert_eq!((deque.len(), deque.size()), (7, 7));
+        assert_eq!((deque.peek_front(), deque.peek_back()), (Some(&1), Some(&7)));
+        assert_eq!((deque.get(0), deque.get(3), deque.get(7)), (Some(&1), Some(&4), None));
+        assert_eq!((deque.pop_front(), deque.pop_back()), (Some(1), Some(7)));
+        assert_eq!(deque.iter().copied().collect::<Vec<usize>>(), vec![2, 3, 4, 5, 6]);
+        assert_eq!(deque.drain().take(2).collect::<Vec<usize>>(), vec![2, 3]);
+        assert!(deque.is_empty());
+
+        for len in 0..16 {
+            let items: Vec<usize> = (0..len).collect();
+            let mut deque = Deque::from(items.clone());
+            assert_eq!(deque.peek_front(), items.first());
+            assert_eq!(Vec::from(Deque::from(Vec::from(deque))), items);
+            deque = Deque::from(items.clone());
+            deque.truncate(len / 2);
+            assert_eq!(deque.into_iter().collect::<Vec<usize>>(), items[..len / 2]);
+        }
+        let mut deque: Deque<usize> = (0..4).collect();
+        deque.clear();
+        assert_eq!((deque.pop_front(), deque.pop_back()), (None, None));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn sync_queue_dequeue_waits_for_item() {
+        use std::sync::{mpsc, Arc};
+        use std::{thread, time::Duration};
+
+        let queue = Arc::new(SyncQueue::new());
+        let (sender, receiver) = mpsc::channel();
+        let consumer = {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || sender.send(queue.dequeue()).unwrap())
+        };
+        // Пока очередь пуста, извлечение ожидает
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        assert_eq!(queue.try_dequeue(), None);
+        queue.enqueue(7);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(7));
+        consumer.join().unwrap();
+        assert!(queue.is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn sync_queue_producers_and_consumers() {
+        use std::sync::Arc;
+        use std::thread;
+
+        const PRODUCERS: usize = 4;
+        const CONSUMERS: usize = 2;
+        const ITEMS: usize = 1000;
+        let queue = Arc::new(SyncQueue::new());
+        let consumers: Vec<_> = (0..CONSUMERS).map(|_| {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || (0..PRODUCERS * ITEMS / CONSUMERS).map(|_| queue.dequeue()).collect::<Vec<(usize, usize)>>())
+        }).collect();
+        let producers: Vec<_> = (0..PRODUCERS).map(|producer| {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || (0..ITEMS).for_each(|item| queue.enqueue((producer, item))))
+        }).collect();
+        producers.into_iter().for_each(|producer| producer.join().unwrap());
+
+        let mut received: Vec<Vec<usize>> = vec![Vec::new(); PRODUCERS];
+        for consumer in consumers {
+            let items = consumer.join().unwrap();
+            // Каждый потребитель получает элементы одного производителя в порядке добавления
+            for (producer, received) in received.iter_mut().enumerate() {
+                let own: Vec<usize> = items.iter().filter(|(from, _)| *from == producer).map(|(_, item)| *item).collect();
+                assert!(own.windows(2).all(|pair| pair[0] < pair[1]));
+                received.extend(own);
+            }
+        }
+        // Ни один элемент не потерян и не получен дважды
+        for items in &mut received {
+            items.sort_unstable();
+            assert_eq!(*items, (0..ITEMS).collect::<Vec<usize>>());
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn sync_stack_concurrent_push_and_pop() {
+        use std::sync::Arc;
+        use std::thread;
+
+        const THREADS: usize = 4;
+        const ITEMS: usize = 1000;
+        let stack = Arc::new(SyncStack::new());
+        let pushers: Vec<_> = (0..THREADS).map(|thread| {
+            let stack = Arc::clone(&stack);
+            thread::spawn(move || (0..ITEMS).for_each(|item| stack.push(thread * ITEMS + item)))
+        }).collect();
+        pushers.into_iter().for_each(|pusher| pusher.join().unwrap());
+        assert_eq!(stack.len(), THREADS * ITEMS);
+
+        let poppers: Vec<_> = (0..THREADS).map(|_| {
+            let stack = Arc::clone(&stack);
+            thread::spawn(move || core::iter::from_fn(|| stack.pop()).collect::<Vec<usize>>())
+        }).collect();
+        let mut popped: Vec<usize> = poppers.into_iter().flat_map(|popper| popper.join().unwrap()).collect();
+        popped.sort_unstable();
+        assert_eq!(popped, (0..THREADS * ITEMS).collect::<Vec<usize>>());
+        assert!(stack.is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn sync_containers_survive_poisoning() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let stack = Arc::new(SyncStack::new());
+        stack.push(1);
+        let queue = Arc::new(SyncQueue::new());
+        queue.enqueue(1);
+        // Поток паникует, удерживая мьютексы, - они становятся "отравленными"
+        let panicked = {
+            let (stack, queue) = (Arc::clone(&stack), Arc::clone(&queue));
+            thread::spawn(move || {
+                let (_stack, _queue) = (stack.lock(), queue.lock());
+                panic!("паника под мьютексом");
+            }).join()
+        };
+        assert!(panicked.is_err());
+        assert!(stack.stack.is_poisoned() && queue.queue.is_poisoned());
+
+        stack.push(2);
+        assert_eq!((stack.len(), stack.pop()), (2, Some(2)));
+        queue.enqueue(2);
+        assert_eq!((queue.dequeue(), queue.try_dequeue(), queue.is_empty()), (1, Some(2), true));
+        let waiter = {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || queue.dequeue())
+        };
+        queue.enqueue(3);
+        assert_eq!(waiter.join().unwrap(), 3);
+
+        let stack = Arc::try_unwrap(stack).ok().unwrap();
+        assert_eq!(stack.into_inner().pop(), Some(1));
+        let queue = Arc::try_unwrap(queue).ok().unwrap();
+        assert!(queue.into_inner().is_empty());
+    }
+
+    #[test]
+    fn bounded_stack_rejects_push_when_full() {
+        let mut stack = BoundedStack::new(3);
+        for item in 1..=3 {
+            assert_eq!(stack.push(item), Ok(()));
+        }
+        assert!(stack.is_full());
+        assert_eq!(stack.push(4), Err(Full));
+        // Отказ не меняет стек
+        assert_eq!((stack.len(), stack.peek()), (3, Some(&3)));
+        assert_eq!(stack.pop(), Some(3));
+        assert_eq!(stack.push(5), Ok(()));
+        assert_eq!(stack.iter().copied().collect::<Vec<i32>>(), vec![5, 2, 1]);
+        assert_eq!(format!("{}", Full), "контейнер заполнен");
+
+        // Элементы обычного стека сверх ограничения остаются, но новые не добавляются
+        let mut stack = BoundedStack::from_stack(Stack::from(vec![1, 2, 3]), 2);
+        assert_eq!((stack.is_full(), stack.push(4)), (true, Err(Full)));
+        stack.clear();
+        assert_eq!(stack.push(4), Ok(()));
+        assert_eq!((stack.limit(), stack.into_stack().size()), (2, 1));
+
+        let mut empty: BoundedStack<i32> = BoundedStack::new(0);
+        assert_eq!((empty.is_full(), empty.push(1), empty.is_empty()), (true, Err(Full), true));
+    }
+
+    #[test]
+    fn bounded_queue_rejects_enqueue_when_full() {
+        let mut queue = BoundedQueue::new(2);
+        assert_eq!(queue.enqueue(1), Ok(()));
+        assert_eq!(queue.enqueue(2), Ok(()));
+        assert!(queue.is_full());
+        assert_eq!(queue.enqueue(3), Err(Full));
+        assert_eq!((queue.len(), queue.peek()), (2, Some(&1)));
+        // Освободившееся место снова доступно, порядок элементов сохраняется
+        assert_eq!(queue.dequeue(), 1);
+        assert_eq!(queue.enqueue(3), Ok(()));
+        assert_eq!(queue.enqueue(4), Err(Full));
+        assert_eq!(queue.drain().collect::<Vec<i32>>(), vec![2, 3]);
+        assert_eq!((queue.is_full(), queue.enqueue(5)), (false, Ok(())));
+        assert_eq!(queue.into_queue().len(), 1);
+    }
+
+    #[test]
+    fn persistent_stack_drops_long_chain() {
+        // Рекурсивное освобождение миллиона узлов переполнило бы стек вызовов потока теста
+        let mut stack = PersistentStack::new();
+        for item in 0..1_000_000 {
+            stack = stack.push(item);
+        }
+        assert_eq!((stack.len(), stack.peek()), (1_000_000, Some(&999_999)));
+        let older = stack.pop().map(|(_, rest)| rest).unwrap();
+        drop(stack);
+        assert_eq!((older.len(), older.peek()), (999_999, Some(&999_998)));
+        drop(older);
+    }
+
+    #[test]
+    fn persistent_stack_versions_share_nodes() {
+        use core::cell::Cell;
+
+        // Элемент, считающий освобождения
+        struct Counted<'a>(u32, &'a Cell<u32>);
+        impl Drop for Counted<'_> {
+            fn drop(&mut self) {
+                self.1.set(self.1.get() + 1);
+            }
+        }
+
+        fn tail<T>(stack: &PersistentStack<T>) -> Rc<PersistentNode<T>> {
+            stack.head.as_ref().and_then(|node| node.next.clone()).unwrap()
+        }
+
+        let dropped = Cell::new(0);
+        let base = PersistentStack::new().push(Counted(1, &dropped)).push(Counted(2, &dropped));
+        let left = base.push(Counted(3, &dropped));
+        let right = base.push(Counted(4, &dropped));
+        // Обе версии продолжают одни и те же узлы исходного стека
+        assert!(Rc::ptr_eq(&tail(&left), base.head.as_ref().unwrap()));
+        assert!(Rc::ptr_eq(&tail(&left), &tail(&right)));
+        assert_eq!(left.iter().map(|item| item.0).collect::<Vec<u32>>(), vec![3, 2, 1]);
+        assert_eq!(right.iter().map(|item| item.0).collect::<Vec<u32>>(), vec![4, 2, 1]);
+
+        // pop не меняет исходную версию
+        let (top, popped) = left.pop().unwrap();
+        assert_eq!((top.0, popped.len(), left.len()), (3, 2, 3));
+        assert!(Rc::ptr_eq(popped.head.as_ref().unwrap(), base.head.as_ref().unwrap()));
+        drop(popped);
+
+        // Общие узлы освобождаются только вместе с последней версией, которая их использует
+        drop(base);
+        drop(left);
+        assert_eq!(dropped.get(), 1);
+        assert_eq!(right.iter().map(|item| item.0).collect::<Vec<u32>>(), vec![4, 2, 1]);
+        drop(right);
+        assert_eq!(dropped.get(), 4);
+    }
+
+    #[test]
+    fn priority_queue_pops_highest_first() {
+        let mut queue = PriorityQueue::new();
+        // Псевдослучайный порядок приоритетов
+        let priorities: Vec<u32> = (0..100).map(|index| (index * 37 + 11) % 100).collect();
+        for priority in &priorities {
+            queue.push(*priority, *priority);
+        }
+        assert_eq!((queue.len(), queue.peek_max()), (100, Some((&99, &99))));
+        let popped: Vec<u32> = core::iter::from_fn(|| queue.pop_max()).collect();
+        assert_eq!(popped, (0..100).rev().collect::<Vec<u32>>());
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop_max(), None);
+    }
+
+    #[test]
+    fn priority_queue_keeps_fifo_order_for_equal_priorities() {
+        let mut queue = PriorityQueue::new();
+        for (item, priority) in [("a", 1), ("b", 2), ("c", 1), ("d", 2), ("e", 1), ("f", 3)] {
+            queue.push(item, priority);
+        }
+        assert_eq!(queue.pop_max(), Some("f"));
+        assert_eq!(queue.pop_max(), Some("b"));
+        // Добавленный позже с тем же приоритетом извлекается после ранее добавленных
+        queue.push("g", 2);
+        queue.push("h", 1);
+        let popped: Vec<&str> = core::iter::from_fn(|| queue.pop_max()).collect();
+        assert_eq!(popped, vec!["d", "g", "a", "c", "e", "h"]);
+
+        // Обратный приоритет (Reverse) - извлечение по возрастанию, как при выводе строк файла по номерам
+        let mut queue = PriorityQueue::new();
+        for index in [3, 0, 2, 1] {
+            queue.push(index, core::cmp::Reverse(index));
+        }
+        assert_eq!(queue.into_sorted(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn priority_queue_into_sorted_and_drain() {
+        let items = [("x", 5), ("y", 1), ("z", 5), ("w", 3), ("v", 1)];
+        let mut queue: PriorityQueue<&str, i32> = PriorityQueue::with_capacity(items.len());
+        for (item, priority) in items {
+            queue.push(item, priority);
+        }
+        let mut popped = PriorityQueue::new();
+        for (item, priority) in items {
+            popped.push(item, priority);
+        }
+        let popped: Vec<&str> = core::iter::from_fn(|| popped.pop_max()).collect();
+        assert_eq!(popped, vec!["x", "z", "w", "y", "v"]);
+        assert_eq!(queue.drain().collect::<Vec<&str>>(), popped);
+        assert!(queue.is_empty());
+        // После drain очередь снова используется
+        queue.push("u", 0);
+        assert_eq!(queue.into_sorted(), vec!["u"]);
+    }
+
+    #[test]
+    fn queue_from_stack_starts_at_top() {
+        for len in 0..16 {
+            let stack: Stack<usize> = (0..len).collect();
+            let queue = Queue::from_stack(stack);
+            assert_eq!(queue.peek(), len.checked_sub(1).as_ref());
+            assert_eq!(Vec::from(queue), (0..len).rev().collect::<Vec<usize>>());
+        }
+    }
+
+    #[test]
+    fn queue_from_stack_of_wrapped_queue() {
+        for len in 1..16 {
+            for shift in 0..len {
+                let stack: Stack<usize> = wrapped_queue(len, shift).into_iter().collect();
+                let mut queue = Queue::from_stack(stack);
+                let popped = (0..len).map(|_| queue.dequeue()).collect::<Vec<usize>>();
+                assert_eq!(popped, (0..len).rev().collect::<Vec<usize>>());
+            }
+        }
+    }
 }
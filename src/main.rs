@@ -1,339 +1,1008 @@
-use std::io;
-use regex::Regex;
-use less_2_task::{Stack, Queue};
-
-// Типы доступных токенов (лексем)
-#[derive(Debug, Copy, Clone, PartialEq)]
-enum TokenType {
-    NumberInt,
-    NumberFloat,
-    UnaryOperator,
-    BinaryOperator,
-    Function,
-    OpenedParenthesis,
-    ClosedParenthesis,
-    ArgumentSeparator,
-    Whitespaces,
-}
-
-// Определим кортеж для удобства работы - (Тип токена, "символьное представление")
-type Token = (TokenType, String);
-
-// Ассоциативность оператора
+use std::{env, io, panic, thread};
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process::exit;
+use std::sync::mpsc;
+use std::time::Duration;
+use less_2_task::{PriorityQueue, Stack, SyncQueue};
+use less_2_task::calc::{convert_value, describe, Definition, documented_names, evaluate_integer, parse, parse_errors, process, process_with, set_extra_units, simplify, simplify_rules, solved_variable, unit_names, CalcError, ErrorKind, IntegerDivision, Limits, Notation, NumberFormat, Resolver, Rounding, Value, KNOWNS_FUNCTIONS, SIGNIFICANT_RANGE};
+
+use crate::cache::ParseCache;
+use crate::editor::LineReader;
+use crate::plot::Plot;
+
+mod cache;
+mod clipboard;
+mod config;
+mod connections;
+#[cfg(unix)]
+mod daemon;
+mod editor;
+mod json;
+mod plot;
+mod rpc;
+mod server;
+
+// Строки файла раздаются потокам обработки блоками, чтобы потоки реже обращались
+// к общей очереди; на поток приходится не больше BLOCKS_PER_JOB прочитанных, но ещё
+// не выведенных блоков
+const BLOCK_LINES: usize = 256;
+const BLOCKS_PER_JOB: usize = 4;
+// Время вычисления одного запроса в режиме сервера, если не задано --timeout
+const SERVE_TIMEOUT: Duration = Duration::from_secs(1);
+// Наибольшая вложенность псевдонимов REPL, ссылающихся на другие псевдонимы
+const ALIAS_DEPTH: usize = 16;
+
+// Формат вывода результатов при обработке файла с выражениями
 #[derive(Clone, Copy, PartialEq)]
-enum OperatorAssociation {
-    LeftAssociation,
-    RightAssociatoin,
-}
-
-// Псевдоним для наглядности
-type OperatorOrder = u8;
-
-// Определим тип для определения действий
-type Operator<'a>= (&'a str, OperatorOrder, OperatorAssociation);
-
-// Список известных (поддерживаемых операторов)
-static KNOWNS_OPERATORS: &'static [Operator] = &[
-    ("POS", 1, OperatorAssociation::RightAssociatoin),
-    ("NEG", 1, OperatorAssociation::RightAssociatoin),
-    ("/", 2, OperatorAssociation::LeftAssociation),
-    ("*", 2, OperatorAssociation::LeftAssociation),
-    ("%", 2, OperatorAssociation::LeftAssociation),
-    ("+", 3, OperatorAssociation::LeftAssociation),
-    ("-", 3, OperatorAssociation::LeftAssociation),
-    ("<<", 4, OperatorAssociation::LeftAssociation),
-    (">>", 4, OperatorAssociation::LeftAssociation),
-];
-
-// Список известных токенов и соответствующих им шаблонов поиска в исходной строке
-static KNOWNS_TOKENS: &'static [(TokenType, &str)] = &[
-    (TokenType::OpenedParenthesis, r"^(\()"),
-    (TokenType::ClosedParenthesis, r"^(\))"),
-    (TokenType::Function, r"^[a-zA-Z]+"),
-    (TokenType::BinaryOperator, r"^([\+\-/\*]{1,1})|(<{2,2})|(>{2,2})"),
-    (TokenType::UnaryOperator, r"^([\+\-]{1,1})"),
-    (TokenType::NumberFloat, r"^(\d+\.\d+)"),
-    (TokenType::NumberInt, r"^(\d+)"),
-    (TokenType::ArgumentSeparator, r"^(,{1,1})"),
-    (TokenType::Whitespaces, r"^(\s+)"),
-];
-
-// Получаем информацию об операторе из таблицы
-fn get_op_info(op: &str) -> Option<(OperatorOrder, OperatorAssociation)> {
-    for operator in KNOWNS_OPERATORS {
-        if op == operator.0 {
-            return Some((operator.1, operator.2));
-        }
-    }
+enum OutputFormat {
+    Text,
+    Csv,
+}
 
-    None
+// Формат вывода ошибок
+#[derive(Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    Text,
+    Json,
 }
 
-// Определяем, нужно ли выталкивать из стека имеющийся там оператор
-fn need_op_pop_from_stack(op1: &str, op2: &str) -> bool {
-    let (op1_prio, op1_associo) = get_op_info(op1).unwrap();
-    let (op2_prio, _) = get_op_info(op2).unwrap();
-    // Если приоритет op2 выше или равен приоритету op1 и при этом op1 является левоассоциативным
-    if op2_prio < op1_prio ||
-        (op2_prio == op1_prio && op1_associo == OperatorAssociation::LeftAssociation) {
-            return true;
-        }
+// Экранирование поля CSV: поля с запятыми, кавычками и переводами строк заключаются в кавычки
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        return format!("\"{}\"", field.replace('"', "\"\""));
+    }
 
-    false
+    field.to_string()
 }
 
-// Разбиваем входную строку на токены (лексемы)
-fn tokerize(in_string: &str) -> Result<Vec<Token>, char> {
-    let permissible_tokens = [TokenType::NumberFloat, TokenType::NumberInt, TokenType::ClosedParenthesis];
-    let mut tokens : Vec<Token> = Vec::new();
-    let mut target_string = in_string;
-    let mut error : bool = false;
-    while !target_string.is_empty() && !error {
-        let strlen_before = target_string.len();
-        for tok in KNOWNS_TOKENS {
-            let rgx : Regex = Regex::new(tok.1).unwrap();
-            match rgx.captures(target_string) {
-                None => continue,
-                Some(captions) => {
-                    let mut value = &captions[0];
-                    // Т.к. унарные + и - не отличимы при разборе от бинарных, то
-                    //  необходимы дополнительные проверки:
-                    //  если есть последний разобранный токен и он число или закрывающая скобка, то
-                    //  данный токен это унарный оператор, иначе - бинарный
-                    if tok.0 == TokenType::BinaryOperator {
-                        let last = tokens.last();
-                        if last == None || !permissible_tokens.contains(&last.unwrap().0) {
-                            continue;
-                        }
-                    } else if tok.0 == TokenType::UnaryOperator {
-                        // Дополнительно, чтобы при вычислении выражения отличать бинарные + и -
-                        // от унарных переименуем унарные в соответствующие операторы
-                        value = match value {
-                            "+" => "POS",
-                            "-" => "NEG",
-                            _ => "",
-                        };
+// Результат строки файла: None - пустая строка, Ok - строка вывода и результат выражения
+// (для --copy), Err - строка вывода с ошибкой и вид первой ошибки (для кода возврата)
+type LineOutput = Option<Result<(String, String), (String, ErrorKind)>>;
+
+fn process_line(line: &str, format: OutputFormat, error_format: ErrorFormat, limits: &Limits) -> LineOutput {
+    let input = line.trim();
+    if input.is_empty() {
+        return None;
+    }
+
+    // Положения ошибок отсчитываются от начала исходной строки файла
+    let evaluation = process(line, limits).map_err(|why| all_errors(line, limits, why));
+    let result = evaluation.as_ref().map(|evaluation| solution(&evaluation.rpn, evaluation.result.clone())).map_err(|errors| errors[0].kind);
+    let output = match format {
+        OutputFormat::Text => match evaluation {
+            // Решение уравнения уже записано с переменной: "solve(2 * x = 8, x): x = 4.00"
+            Ok(evaluation) if solved_variable(&evaluation.rpn).is_some() =>
+                format!("{}: {}", input, solution(&evaluation.rpn, evaluation.result.clone())),
+            Ok(evaluation) => format!("{} = {}", input, evaluation.result),
+            Err(errors) => match error_format {
+                ErrorFormat::Text if errors.len() > 1 => {
+                    let mut output = format!("{}: найдено ошибок: {}", input, errors.len());
+                    for why in &errors {
+                        output.push('\n');
+                        output.push_str(&why.to_text(line));
                     }
-                    tokens.push((tok.0, value.to_string()));
-                    target_string = target_string.strip_prefix(&captions[0]).unwrap();
-                }
-            }
-        }
-        error = strlen_before == target_string.len();
+                    output
+                },
+                ErrorFormat::Text => format!("{}: {}", input, errors[0].message),
+                ErrorFormat::Json => errors.iter().map(CalcError::to_json).collect::<Vec<String>>().join("\n"),
+            },
+        },
+        OutputFormat::Csv => {
+            let (rpn, result, error) = match evaluation {
+                Ok(evaluation) => {
+                    let result = solution(&evaluation.rpn, evaluation.result);
+                    (evaluation.rpn, result, String::new())
+                },
+                Err(errors) => {
+                    let messages = errors.iter().map(|why| why.message.as_str()).collect::<Vec<&str>>();
+                    (String::new(), String::new(), messages.join("; "))
+                },
+            };
+            format!("{},{},{},{}", csv_field(input), csv_field(&rpn), csv_field(&result), csv_field(&error))
+        },
+    };
+    Some(match result {
+        Ok(result) => Ok((output, result)),
+        Err(kind) => Err((output, kind)),
+    })
+}
+
+// Запись результата выражения `rpn`; решение уравнения записывается с переменной: x = 4.00
+fn solution(rpn: &str, result: String) -> String {
+    match solved_variable(rpn) {
+        Some(variable) => format!("{} = {}", variable, result),
+        None => result,
     }
+}
 
-    if error {
-        return Err(target_string.chars().next().unwrap());
+// Все ошибки разбора строки, если их несколько; иначе только ошибка `why`, с которой
+// остановилось вычисление
+fn all_errors(input: &str, limits: &Limits, why: CalcError) -> Vec<CalcError> {
+    match parse_errors(input, limits) {
+        errors if errors.len() > 1 => errors,
+        _ => vec![why],
     }
+}
 
-    Ok(tokens)
+// Ход обработки файла: каждые `every` строк на stderr выводится число обработанных
+// строк и ошибок (0 - не выводится)
+struct Progress {
+    every: usize,
+    lines: usize,
+    errors: usize,
+    // Результат последнего вычисленного выражения
+    last: Option<String>,
+    // Вид ошибки в первой строке с ошибкой
+    failed: Option<ErrorKind>,
 }
 
-// Выполняем преобразования списка входных токенов в запись ОПН согласно алгоритму
-// сортировочной станции Дейкстры
-fn convert_to_rpn<'a>(token_list: Vec<Token>) -> Result<Queue<Token>, &'a str> {
-    let mut output: Queue<Token> = Queue::new();
-    let mut stack: Stack<Token> = Stack::new();
-    for tok in token_list {
-        match tok.0 {
-            TokenType::NumberInt | TokenType::NumberFloat => {
-                // Если токен — число, то добавить его в очередь вывода
-                output.enqueue(tok);
+impl Progress {
+    // Вывод результата строки файла
+    fn print(&mut self, output: LineOutput) {
+        match output {
+            Some(Ok((output, result))) => {
+                println!("{}", output);
+                self.last = Some(result);
             },
-            TokenType::Function => {
-                // Если токен — функция, то поместить его в стек
-                stack.push(tok);
+            Some(Err((output, kind))) => {
+                self.errors += 1;
+                self.failed = self.failed.or(Some(kind));
+                println!("{}", output);
             },
-            TokenType::ArgumentSeparator => {
-                // Если токен — разделитель аргументов функции (например запятая):
-                //     Пока токен на вершине стека не открывающая скобка:
-                //         Переложить оператор из стека в выходную очередь.
-                while !stack.is_empty() && stack.peek().unwrap().0 != TokenType::OpenedParenthesis {
-                    let op = stack.pop().unwrap();
-                    output.enqueue(op);
-                }
-                // Если стек закончился до того, как был встречен токен открывающая скобка,
-                //   то в выражении пропущен разделитель аргументов функции (запятая),
-                //   либо пропущена открывающая скобка.
-                if stack.is_empty() {
-                    return Err("в выражении пропущен разделитель аргументов функции (запятая), либо пропущена открывающая скобка");
-                }
-            },
-            TokenType::BinaryOperator | TokenType::UnaryOperator => {
-                // Если токен — оператор op1, то:
-                //     Пока присутствует на вершине стека токен оператор op2,
-                //       чей приоритет выше или равен приоритету op1,
-                //       и при равенстве приоритетов op1 является левоассоциативным:
-                //         Переложить op2 из стека в выходную очередь;
-                let mut last = stack.peek();
-                while last != None &&
-                    (last.unwrap().0 == TokenType::BinaryOperator) &&
-                    need_op_pop_from_stack(&tok.1, &last.unwrap().1) {
-                        let op = stack.pop().unwrap();
-                        output.enqueue(op);
-                        last = stack.peek();
+            None => (),
+        }
+        self.lines += 1;
+        if self.every > 0 && self.lines.is_multiple_of(self.every) {
+            self.report();
+        }
+    }
+
+    fn report(&self) {
+        eprintln!("Обработано строк: {}, ошибок: {}", self.lines, self.errors);
+    }
+}
+
+// Обработка файла с выражениями (по одному выражению в строке). Файл читается построчно,
+// поэтому его размер не ограничен доступной памятью. Возвращает результат последнего
+// вычисленного выражения
+fn process_file(path: &str, format: OutputFormat, error_format: ErrorFormat, limits: &Limits, jobs: usize, progress: usize) -> io::Result<Progress> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let mut progress = Progress { every: progress, lines: 0, errors: 0, last: None, failed: None };
+    if format == OutputFormat::Csv {
+        println!("input,rpn,result,error");
+    }
+
+    if jobs <= 1 {
+        for line in lines {
+            progress.print(process_line(&line?, format, error_format, limits));
+        }
+    } else {
+        process_parallel(&mut lines, jobs, &|line| process_line(line, format, error_format, limits), &mut progress)?;
+    }
+    if progress.every > 0 {
+        progress.report();
+    }
+    Ok(progress)
+}
+
+// Обработка строк в `jobs` потоках с выводом результатов в порядке строк. Паника потока
+// обработки - ошибка с номером первой строки блока, на котором он упал; остальные потоки
+// завершаются, как и при ошибке чтения
+fn process_parallel(lines: &mut dyn Iterator<Item = io::Result<String>>, jobs: usize,
+    process: &(dyn Fn(&str) -> LineOutput + Sync), progress: &mut Progress) -> io::Result<()> {
+    // Строки раздаются потокам блоками через общую очередь (None - сигнал завершения), а
+    // результаты выводятся в порядке строк файла по мере готовности. Прочитанных, но ещё
+    // не выведенных блоков не больше BLOCKS_PER_JOB на поток: иначе при медленной обработке
+    // очередь заданий и очередь готовых результатов разрастались бы до размера файла
+    let tasks: SyncQueue<Option<(usize, Vec<String>)>> = SyncQueue::new();
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let (tasks, sender) = (&tasks, sender.clone());
+            scope.spawn(move || {
+                while let Some((index, block)) = tasks.dequeue() {
+                    let outputs = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                        block.iter().map(|line| process(line)).collect::<Vec<_>>()
+                    }));
+                    if sender.send((index, outputs.ok())).is_err() {
+                        break;
                     }
-                // Положить op1 в стек.
-                stack.push(tok);
-            },
-            TokenType::OpenedParenthesis => {
-                // Если токен — открывающая скобка, то положить его в стек
-                stack.push(tok);
-            },
-            TokenType::ClosedParenthesis => {
-                // Если токен — закрывающая скобка:
-                //     Пока токен на вершине стека не открывающая скобка
-                //         Переложить оператор из стека в выходную очередь.
-                while !stack.is_empty() && stack.peek().unwrap().0 != TokenType::OpenedParenthesis {
-                    let op = stack.pop().unwrap();
-                    output.enqueue(op);
                 }
+            });
+        }
+        drop(sender);
 
-                // Если стек закончился до того, как был встречен токен открывающая скобка, то в выражении пропущена скобка.
-                if stack.is_empty() {
-                    return Err("в выражении пропущена скобка");
-                } else {
-                    // Выкинуть открывающую скобку из стека, но не добавлять в очередь вывода.
-                    let _ = stack.pop();
-                    // Если токен на вершине стека — функция, переложить её в выходную очередь.
-                    if !stack.is_empty() && stack.peek().unwrap().0 == TokenType::Function {
-                        let op = stack.pop().unwrap();
-                        output.enqueue(op);
+        let mut pending: PriorityQueue<Vec<LineOutput>, Reverse<usize>> = PriorityQueue::new();
+        let (mut read, mut next) = (0, 0);
+        let mut finished = false;
+        let mut run = || -> io::Result<()> {
+            loop {
+                while !finished && read - next < BLOCKS_PER_JOB * jobs {
+                    let block = (&mut *lines).take(BLOCK_LINES).collect::<io::Result<Vec<String>>>()?;
+                    finished = block.len() < BLOCK_LINES;
+                    if !block.is_empty() {
+                        tasks.enqueue(Some((read, block)));
+                        read += 1;
                     }
                 }
-            },
-            TokenType::Whitespaces => println!("А вот такого быть не должно"),
+                if next == read {
+                    return Ok(());
+                }
+                // Пока блоки в работе, хотя бы один поток отправит результат
+                let (index, outputs) = receiver.recv()
+                    .map_err(|_| io::Error::other("потоки обработки завершились раньше времени"))?;
+                let outputs = outputs.ok_or_else(|| io::Error::other(
+                    format!("поток обработки завершился аварийно на блоке строк с {}-й", index * BLOCK_LINES + 1)))?;
+                pending.push(outputs, Reverse(index));
+                while pending.peek_max().is_some_and(|(_, index)| index.0 == next) {
+                    for output in pending.pop_max().unwrap_or_default() {
+                        progress.print(output);
+                    }
+                    next += 1;
+                }
+            }
+        };
+        let result = run();
+        // Потоки завершаются и при ошибке чтения файла
+        for _ in 0..jobs {
+            tasks.enqueue(None);
         }
-    }
+        result
+    })
+}
 
-    // Если больше не осталось токенов на входе:
-    // Пока есть токены операторы в стеке:
-    let mut last = stack.peek();
-    while last != None {
-        // Если токен оператор на вершине стека — открывающая скобка, то в выражении пропущена скобка.
-        if last.unwrap().0 == TokenType::OpenedParenthesis {
-            return Err("в выражении пропущена скобка");
-        }
+// Команда REPL ":convert ВЫРАЖЕНИЕ to ЕДИНИЦА". Выражение дополняется слева пробелами
+// вместо команды, чтобы положения ошибок совпадали с введённой строкой
+fn run_convert(input: &str, limits: &Limits, format: NumberFormat) -> Result<String, CalcError> {
+    let command = input.trim_end();
+    let start = command.find(":convert").map_or(0, |start| start + ":convert".len());
+    let (expression, unit) = match command.rfind(" to ") {
+        Some(to) if to >= start => (&command[start..to], &command[to + " to ".len()..]),
+        _ => (&command[start..], ""),
+    };
+    let expression = format!("{}{}", " ".repeat(start), expression);
+    let value = convert_value(&expression, unit, limits)?;
+    Ok(format!("{} {}", value.formatted(format), unit.trim()))
+}
+
+// Команда REPL ":table ВЫРАЖЕНИЕ, ПЕРЕМЕННАЯ, НАЧАЛО, КОНЕЦ, ШАГ" - вызов table(...) с выводом
+// в два столбца. ":table " заменяется на " table(" той же длины, поэтому положения ошибок
+// совпадают с введённой строкой
+fn run_table(input: &str, limits: &Limits, session: &Session, format: NumberFormat) -> Result<Vec<String>, CalcError> {
+    let command = input.trim_end();
+    let expression = match command.contains(":table ") {
+        true => format!("{})", command.replacen(":table ", " table(", 1)),
+        false => format!("{})", command.replacen(":table", "table(", 1)),
+    };
+    let rows = match process_with(&expression, limits, session)?.value {
+        Value::List(rows) => rows,
+        value => return Ok(vec![value.formatted(format).to_string()]),
+    };
+    let cells: Vec<(String, String)> = rows.iter()
+        .filter_map(|row| match row {
+            Value::List(cells) if cells.len() == 2 =>
+                Some((cells[0].formatted(format).to_string(), cells[1].formatted(format).to_string())),
+            _ => None,
+        })
+        .collect();
+    let width = cells.iter().map(|cell| cell.0.chars().count()).max().unwrap_or(0);
+    Ok(cells.into_iter().map(|(x, y)| format!("{:>width$} | {}", x, y, width = width)).collect())
+}
+
+// Настройки REPL, изменяемые командой :set
+#[derive(Clone, Copy, Default)]
+struct Settings {
+    format: NumberFormat,
+    // Из выражений строки, разделённых ';', выводить результат только последнего
+    echo_last: bool,
+    // Целочисленный режим и способ деления в нём; None - обычные вычисления
+    integer: Option<IntegerDivision>,
+}
 
-        // Переложить оператор из стека в выходную очередь.
-        let op = stack.pop().unwrap();
-        output.enqueue(op);
-        last = stack.peek();
+// Команды REPL ":set notation fixed|sci|eng|auto", ":set rounding half-even|half-up|trunc",
+// ":set sigfigs N|off", ":set grouping space|comma|locale|off", ":set display fraction|decimal",
+// ":set mixed on|off", ":set echo all|last" и ":set integer off|exact|trunc"; возвращает
+// настройки с изменённым параметром
+fn parse_setting(input: &str, mut settings: Settings) -> Option<Settings> {
+    match input.split_whitespace().collect::<Vec<&str>>()[..] {
+        [":set", "notation", "fixed"] => settings.format.notation = Notation::Fixed,
+        [":set", "notation", "sci"] => settings.format.notation = Notation::Scientific,
+        [":set", "notation", "eng"] => settings.format.notation = Notation::Engineering,
+        [":set", "notation", "auto"] => settings.format.notation = Notation::Auto,
+        [":set", "rounding", "half-even"] => settings.format.rounding = Rounding::HalfEven,
+        [":set", "rounding", "half-up"] => settings.format.rounding = Rounding::HalfUp,
+        [":set", "rounding", "trunc"] => settings.format.rounding = Rounding::Truncate,
+        [":set", "sigfigs", "off"] => settings.format.significant = None,
+        [":set", "sigfigs", digits] => match digits.parse() {
+            Ok(digits) if SIGNIFICANT_RANGE.contains(&digits) => settings.format.significant = Some(digits),
+            _ => return None,
+        },
+        [":set", "grouping", "space"] => settings.format.grouping = Some(' '),
+        [":set", "grouping", "comma"] => settings.format.grouping = Some(','),
+        [":set", "grouping", "locale"] => settings.format.grouping = Some(locale_grouping()),
+        [":set", "grouping", "off"] => settings.format.grouping = None,
+        [":set", "display", "fraction"] => settings.format.fraction = true,
+        [":set", "display", "decimal"] => settings.format.fraction = false,
+        [":set", "mixed", "on"] => settings.format.mixed = true,
+        [":set", "mixed", "off"] => settings.format.mixed = false,
+        [":set", "echo", "all"] => settings.echo_last = false,
+        [":set", "echo", "last"] => settings.echo_last = true,
+        [":set", "integer", "off"] => settings.integer = None,
+        [":set", "integer", "exact"] => settings.integer = Some(IntegerDivision::Exact),
+        [":set", "integer", "trunc"] => settings.integer = Some(IntegerDivision::Truncate),
+        _ => return None,
     }
+    Some(settings)
+}
 
-    Ok(output)
+// Разделитель групп цифр по локали из LC_ALL, LC_NUMERIC или LANG: запятая для английской,
+// пробел для остальных - он однозначен и там, где запятая отделяет дробную часть
+fn locale_grouping() -> char {
+    let locale = ["LC_ALL", "LC_NUMERIC", "LANG"].iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    match locale.starts_with("en") {
+        true => ',',
+        false => ' ',
+    }
 }
 
+// Команды REPL "!N" и ":redo N" заменяются строкой с номером N из истории, начиная с 1,
+// которая выводится перед выполнением; остальные строки возвращаются без изменений
+fn resolve_redo(input: String, history: &[String]) -> Result<String, String> {
+    let trimmed = input.trim();
+    let number = match trimmed.strip_prefix('!').or_else(|| trimmed.strip_prefix(":redo")) {
+        Some(number) => number.trim(),
+        None => return Ok(input),
+    };
+    match number.parse::<usize>().ok().and_then(|number| history.get(number.checked_sub(1)?)) {
+        Some(line) => {
+            println!("{}", line.trim_end());
+            Ok(line.clone())
+        },
+        None => Err(format!("Нет строки '{}' в истории, номера строк выводит :history", number)),
+    }
+}
 
-// Вычисление известных бинарных операторов
-fn calc_binary_operator(op: &str, arg1: &Token, arg2: &Token) -> String {
-    let arg1 = arg1.1.parse::<f32>().unwrap();
-    let arg2 = arg2.1.parse::<f32>().unwrap();
-    match op {
-        "+" => return format!("{0:.2}", arg1 + arg2),
-        "-" => return format!("{0:.2}", arg1 - arg2),
-        "/" => return format!("{0:.2}", arg1 / arg2),
-        "*" => return format!("{0:.2}", arg1 * arg2),
-        "<<" => return format!("{0:.2}", ((arg1 as i32) << (arg2 as i32)) as f32),
-        ">>" => return format!("{0:.2}", ((arg1 as i32) >> (arg2 as i32)) as f32),
-        _ => "".to_string(),
+// Выражения строки, разделённые ';'. Каждое дополняется слева пробелами вместо предыдущих,
+// чтобы положения ошибок совпадали с введённой строкой; пустые выражения пропускаются
+fn split_statements(input: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    for statement in input.split(';') {
+        if !statement.trim().is_empty() {
+            statements.push(format!("{}{}", " ".repeat(start), statement));
+        }
+        start += statement.len() + 1;
     }
+    if statements.is_empty() {
+        statements.push(input.to_string());
+    }
+    statements
+}
+
+// Переменные сеанса REPL: ans - результат последнего вычисленного выражения, out(N) или out[N] -
+// N-й результат сеанса, начиная с 1, переменные, которым присвоено значение, и псевдонимы :alias
+struct Session<'a> {
+    ans: Option<Value>,
+    outs: Vec<Value>,
+    // Переменные "x = 5": имя и значение, вычисленное при присваивании
+    variables: Vec<(String, Value)>,
+    // Последний выведенный результат, в том числе целочисленного режима, для :copy
+    shown: Option<String>,
+    // Псевдонимы: имя и выражение, которое вычисляется заново при каждом использовании имени,
+    // поэтому ans и out[N] в нём берутся на момент использования
+    aliases: Vec<(String, String)>,
+    // Функции пользователя: имя и определение, которое вычисляется при каждом вызове со
+    // значениями аргументов вместо параметров; глубину рекурсии ограничивает limits.max_recursion
+    functions: Vec<(String, Definition)>,
+    limits: &'a Limits,
+    // Глубина вложенных вычислений псевдонимов, ссылающихся друг на друга
+    depth: Cell<usize>,
 }
 
-// Вычисление известных унарных операторов
-fn calc_unary_operator(op: &str, arg: &Token) -> String {
-    let arg = arg.1.parse::<f32>().unwrap();
-    match op {
-        "POS" => return format!("{0:.2}", arg),
-        "NEG" => return format!("{0:.2}", -1.0 * arg),
-        _ => "".to_string(),
+impl<'a> Session<'a> {
+    fn new(limits: &'a Limits) -> Self {
+        Session { ans: None, outs: Vec::new(), variables: Vec::new(), shown: None, aliases: Vec::new(), functions: Vec::new(), limits, depth: Cell::new(0) }
+    }
+
+    // Вычисление псевдонима; None - псевдонима с таким именем нет. При зацикливании
+    // псевдонимов глубина ограничена ALIAS_DEPTH
+    fn alias(&self, name: &str) -> Result<Option<Value>, CalcError> {
+        let expression = match self.aliases.iter().find(|alias| alias.0 == name) {
+            Some(alias) => &alias.1,
+            None => return Ok(None),
+        };
+        if self.depth.get() >= ALIAS_DEPTH {
+            let message = format!("псевдонимы ссылаются друг на друга глубже {} уровней", ALIAS_DEPTH);
+            return Err(CalcError { kind: ErrorKind::TooDeep, message, span: None, lexeme: None, notes: Vec::new() });
+        }
+        self.depth.set(self.depth.get() + 1);
+        let value = process_with(expression, self.limits, self).map(|evaluation| Some(evaluation.value));
+        self.depth.set(self.depth.get() - 1);
+        value
+    }
+
+    // Результат выражения: следующий out[N] и новое значение ans
+    fn record(&mut self, value: Value) {
+        self.outs.push(value.clone());
+        self.ans = Some(value);
+    }
+
+    // Имена, начинающиеся с prefix, с видом имени для дополнения ввода: функции, единицы
+    // измерения и переменные сеанса (ans и out, когда есть результаты, и псевдонимы)
+    fn completions(&self, prefix: &str) -> Vec<(&str, &'static str)> {
+        let functions = KNOWNS_FUNCTIONS.iter().map(|function| (function.0, "function"));
+        let units = unit_names().map(|name| (name, "unit"));
+        let ans = self.ans.as_ref().map(|_| ("ans", "variable"));
+        let out = self.outs.first().map(|_| ("out", "variable"));
+        let variables = self.variables.iter().map(|variable| (variable.0.as_str(), "variable"));
+        let aliases = self.aliases.iter().map(|alias| (alias.0.as_str(), "variable"));
+        let defined = self.functions.iter().map(|function| (function.0.as_str(), "function"));
+        functions.chain(units).chain(ans).chain(out).chain(variables).chain(aliases).chain(defined)
+            .filter(|item| item.0.starts_with(prefix))
+            .collect()
     }
 }
 
-// Вычисление выражения и вывод на консоль и самого выражения, и результата
-fn calc_and_print<'a>(mut output: Queue<Token>) -> Result<String, &'a str> {
-    let mut calculate_stack : Stack<Token> = Stack::new();
-    while !output.is_empty() {
-        let out = output.dequeue();
-        print!("{} ", out.1);
-        match out.0 {
-            TokenType::NumberFloat | TokenType::NumberInt => {calculate_stack.push(out);},
-            TokenType::BinaryOperator => {
-                if let Some(arg2) = calculate_stack.pop() {
-                    if let Some(arg1) = calculate_stack.pop() {
-                        let res = calc_binary_operator(&out.1, &arg1, &arg2);
-                        calculate_stack.push((TokenType::NumberFloat, res));
-                        continue;
-                    }
-                }
-                return Err("Выходная очередь сформирована неправильно");
-            },
-            TokenType::UnaryOperator => {
-                if let Some(arg) = calculate_stack.pop() {
-                    let res = calc_unary_operator(&out.1, &arg);
-                    calculate_stack.push((TokenType::NumberFloat, res));
-                    continue;
-                }
-                return Err("Выходная очередь сформирована неправильно");
-            },
-            TokenType::Function => {
-            },
-            _ => {
-                return Err("Выходная очередь сформирована неправильно");
-            },
+impl Resolver for Session<'_> {
+    fn variable(&self, name: &str) -> Option<Value> {
+        self.try_variable(name).ok().flatten()
+    }
+
+    fn try_variable(&self, name: &str) -> Result<Option<Value>, CalcError> {
+        match (name, self.variables.iter().find(|variable| variable.0 == name)) {
+            ("ans", _) => Ok(self.ans.clone()),
+            (_, Some(variable)) => Ok(Some(variable.1.clone())),
+            _ => self.alias(name),
         }
     }
 
-    if calculate_stack.is_empty() {
-        return Err("");
+    fn call(&self, name: &str, args: &[Value]) -> Option<Value> {
+        self.try_call(name, args).ok().flatten()
     }
 
-    let result = calculate_stack.pop().unwrap();
-    if !calculate_stack.is_empty() ||
-        result.0 != TokenType::NumberFloat {
-            return Err("");
+    fn try_call(&self, name: &str, args: &[Value]) -> Result<Option<Value>, CalcError> {
+        let number = match (name, args) {
+            ("out", [Value::Number(number)]) => *number,
+            _ => return Ok(None),
+        };
+        if number.fract() == 0.0 && number >= 1.0 && number as usize <= self.outs.len() {
+            return Ok(self.outs.get(number as usize - 1).cloned());
         }
+        let message = match self.outs.len() {
+            0 => format!("нет результата out[{}]: в сеансе ещё нет результатов", number),
+            1 => format!("нет результата out[{}]: в сеансе есть только out[1]", number),
+            count => format!("нет результата out[{}]: номер результата - целое число от 1 до {}", number, count),
+        };
+        Err(CalcError { kind: ErrorKind::OutOfRange, message, span: None, lexeme: None, notes: Vec::new() })
+    }
 
-    let result = result.1;
-    Ok(result)
+    fn definition(&self, name: &str) -> Option<&Definition> {
+        self.functions.iter().find(|function| function.0 == name).map(|function| &function.1)
+    }
 }
 
-// Процесс преобразования состоит из 3 основных этапов
-fn process(input : &String) -> Result<String,String> {
-    // для унификации удалим все пробелы из строки
-    let trimmed = &input.trim().replace(" ", "");
-    // 1. Разбиваем входную строку на токены (лексемы)
-    let tokens = match tokerize(trimmed) {
-        Ok(tokens) => tokens,
-        Err(why) => return Err(format!(" {1:>0$} неизвестная лексема!", input.find(why).unwrap(), "^")),
-    };
+// Состояние сеанса до команды, которую отменяет :undo: настройки :set, псевдонимы, функции
+// и переменные пользователя. Результаты ans и out[N] не отменяются
+struct Snapshot {
+    settings: Settings,
+    aliases: Vec<(String, String)>,
+    functions: Vec<(String, Definition)>,
+    variables: Vec<(String, Value)>,
+}
 
-    // 2. Преобразуем список входных токенов в список в ОПН
-    let output = match convert_to_rpn(tokens) {
-        Ok(output) => output,
-        Err(why) => return Err(format!("\r{}", why)),
-    };
+impl Session<'_> {
+    fn snapshot(&self, settings: Settings) -> Snapshot {
+        Snapshot { settings, aliases: self.aliases.clone(), functions: self.functions.clone(), variables: self.variables.clone() }
+    }
+
+    // Возврат к сохранённому состоянию; возвращает настройки на момент сохранения
+    fn restore(&mut self, snapshot: Snapshot) -> Settings {
+        self.aliases = snapshot.aliases;
+        self.functions = snapshot.functions;
+        self.variables = snapshot.variables;
+        snapshot.settings
+    }
+}
+
+// Вычисление выражения REPL в целочисленном режиме: запись ОПН и результат
+fn run_integer(input: &str, limits: &Limits, division: IntegerDivision) -> Result<(String, i64), CalcError> {
+    let output = parse(input, limits)?;
+    let rpn = output.iter().map(|tok| &*tok.1).collect::<Vec<&str>>().join(" ");
+    Ok((rpn, evaluate_integer(output, limits, division)?))
+}
+
+// Команда REPL ":alias ИМЯ = ВЫРАЖЕНИЕ": имя и выражение, дополненное слева пробелами,
+// чтобы положения ошибок совпадали с введённой строкой
+fn parse_alias(input: &str) -> Option<(&str, String)> {
+    let start = input.find(":alias")? + ":alias".len();
+    let (name, expression) = input[start..].split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some((name, format!("{}{}", " ".repeat(input.len() - expression.len()), expression)))
+}
 
-    // 3. Вычисляем результат выражения
-    let result = match calc_and_print(output) {
-        Ok(result) => format!("\nРезультат: {}", result),
-        Err(why) => return Err(format!("\r{}", why)),
+// Определение функции "ИМЯ(ПАРАМЕТРЫ) = ВЫРАЖЕНИЕ": имя, параметры и выражение, дополненное
+// слева пробелами, как у parse_alias. Имена только из латинских букв; сравнения "f(x) == 1",
+// "f(x) <= 1" определениями не считаются
+fn parse_definition(input: &str) -> Option<(&str, Vec<&str>, String)> {
+    let (head, expression) = input.split_once('=')?;
+    if expression.starts_with('=') {
+        return None;
+    }
+    let (name, params) = head.trim().strip_suffix(')')?.split_once('(')?;
+    let letters = |name: &str| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphabetic());
+    let name = name.trim();
+    let params: Vec<&str> = params.split(',').map(str::trim).collect();
+    if !letters(name) || !params.iter().all(|param| letters(param)) {
+        return None;
+    }
+    Some((name, params, format!("{}{}", " ".repeat(input.len() - expression.len()), expression)))
+}
+
+// Присваивание "ИМЯ = ВЫРАЖЕНИЕ" или "ИМЯ += ВЫРАЖЕНИЕ" (также -=, *=, /=): имя и выражение
+// для вычисления значения. "x += 5" вычисляется как "x +( 5)": положения в выражении не
+// меняются. Сравнения "x == 1", "x <= 1" присваиваниями не считаются
+fn parse_assignment(statement: &str) -> Option<(&str, String)> {
+    let equals = statement.find('=')?;
+    if statement[equals + 1..].starts_with('=') {
+        return None;
+    }
+    let head = &statement[..equals];
+    let compound = head.strip_suffix(['+', '-', '*', '/']);
+    let name = compound.unwrap_or(head).trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let expression = match compound {
+        Some(_) => format!("{}({})", head, statement[equals + 1..].trim_end()),
+        None => format!("{}{}", " ".repeat(equals + 1), &statement[equals + 1..]),
     };
+    Some((name, expression))
+}
 
-    Ok(result)
+// Ссылки out[N] вычисляются как вызов out(N). Скобки заменяются на круглые той же длины,
+// поэтому положения ошибок в строке не меняются
+fn rewrite_out_references(input: &str) -> String {
+    let mut bytes = input.as_bytes().to_vec();
+    for (start, reference) in input.match_indices("out[") {
+        let separate = !input[..start].chars().next_back().is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let digits = input[start + reference.len()..].bytes().take_while(u8::is_ascii_digit).count();
+        let close = start + reference.len() + digits;
+        if separate && digits > 0 && bytes.get(close) == Some(&b']') {
+            bytes[start + 3] = b'(';
+            bytes[close] = b')';
+        }
+    }
+    String::from_utf8(bytes).unwrap_or_else(|_| input.to_string())
+}
+
+fn print_usage() {
+    println!("Использование: less_2_task [--format text|csv] [--error-format text|json] [--max-depth N] [--max-nesting N] [--max-tokens N] [--max-steps N] [--max-recursion N] [--timeout МС] [--tolerance ЧИСЛО] [--stdio-rpc | --daemon СОКЕТ [--max-connections N]] [--jobs N] [--progress N] [--copy] [--trace] [ФАЙЛ]");
+    println!("               less_2_task serve [--port ПОРТ] [--max-connections N] [--max-depth N] [--max-nesting N] [--max-tokens N] [--max-steps N] [--max-recursion N] [--timeout МС] [--tolerance ЧИСЛО]");
+    println!("Настройки по умолчанию - из файла L2T_CONFIG или ~/.config/less_2_task/config (строки \"ключ = значение\") и переменных L2T_<КЛЮЧ>, например L2T_MAX_STEPS");
+    println!("Код возврата для ФАЙЛА по первой строке с ошибкой: 3 - ошибка в записи выражения, 4 - ошибка вычисления, 5 - превышено ограничение");
+}
+
+// Код возврата при ошибке в строке файла (1 и 2 - ошибки чтения файла и аргументов):
+// 3 - ошибка в записи выражения, 4 - ошибка вычисления, 5 - превышено ограничение
+// или вычисление прервано
+fn exit_code(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::UnknownToken | ErrorKind::MissingParenthesis | ErrorKind::MissingArgumentSeparator
+            | ErrorKind::MissingOperator | ErrorKind::InvalidFunctionCall | ErrorKind::EmptyExpression
+            | ErrorKind::EmptyParentheses | ErrorKind::DanglingOperator | ErrorKind::Forbidden => 3,
+        ErrorKind::TooDeep | ErrorKind::TooComplex | ErrorKind::BudgetExceeded | ErrorKind::Timeout
+            | ErrorKind::Cancelled => 5,
+        _ => 4,
+    }
+}
+
+// Допустимая погрешность численных методов: положительное число
+fn parse_tolerance(value: &str) -> Option<f32> {
+    value.parse().ok().filter(|tolerance: &f32| tolerance.is_finite() && *tolerance > 0.0)
+}
+
+// Значение ограничения из аргумента командной строки
+fn parse_limit(value: Option<String>) -> usize {
+    match value.and_then(|value| value.parse().ok()) {
+        Some(limit) => limit,
+        None => {
+            print_usage();
+            exit(2);
+        },
+    }
 }
 
 fn main() {
+    let mut format = OutputFormat::Text;
+    let mut error_format = ErrorFormat::Text;
+    // Настройки файла и окружения; флаги командной строки важнее них
+    let config = config::load();
+    // Единицы задаются один раз, до разбора первого выражения
+    set_extra_units(config.units).expect("единицы измерения уже заданы");
+    let mut limits = config.limits;
+    let mut path: Option<String> = None;
+    let mut stdio_rpc = false;
+    let mut jobs = 1;
+    let mut progress = 0;
+    let mut copy = false;
+    // Статистика вычисления после каждого результата REPL
+    let mut trace = false;
+    let mut daemon: Option<String> = None;
+    let mut port: u16 = 8080;
+    let mut max_connections = connections::MAX_CONNECTIONS;
+    let mut args = env::args().skip(1).peekable();
+    // Режим HTTP-сервера задаётся первым аргументом
+    let serve = args.peek().map(String::as_str) == Some("serve");
+    if serve {
+        args.next();
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("text") => OutputFormat::Text,
+                    Some("csv") => OutputFormat::Csv,
+                    _ => {
+                        print_usage();
+                        exit(2);
+                    },
+                };
+            },
+            "--error-format" => {
+                error_format = match args.next().as_deref() {
+                    Some("text") => ErrorFormat::Text,
+                    Some("json") => ErrorFormat::Json,
+                    _ => {
+                        print_usage();
+                        exit(2);
+                    },
+                };
+            },
+            "--max-depth" => limits.max_depth = parse_limit(args.next()),
+            "--max-nesting" => limits.max_nesting = parse_limit(args.next()),
+            "--max-tokens" => limits.max_tokens = parse_limit(args.next()),
+            "--max-steps" => limits.max_steps = parse_limit(args.next()),
+            "--max-recursion" => limits.max_recursion = parse_limit(args.next()),
+            "--timeout" => limits.timeout = Some(Duration::from_millis(parse_limit(args.next()) as u64)),
+            "--tolerance" => {
+                limits.tolerance = match args.next().as_deref().and_then(parse_tolerance) {
+                    Some(tolerance) => tolerance,
+                    None => {
+                        print_usage();
+                        exit(2);
+                    },
+                };
+            },
+            "--stdio-rpc" => stdio_rpc = true,
+            "--jobs" => jobs = parse_limit(args.next()),
+            "--progress" => progress = parse_limit(args.next()),
+            "--copy" => copy = true,
+            "--trace" => trace = true,
+            "--max-connections" => max_connections = parse_limit(args.next()),
+            "--daemon" => {
+                daemon = match args.next() {
+                    Some(socket) => Some(socket),
+                    None => {
+                        print_usage();
+                        exit(2);
+                    },
+                };
+            },
+            "--port" if serve => {
+                port = match args.next().and_then(|value| value.parse().ok()) {
+                    Some(port) => port,
+                    None => {
+                        print_usage();
+                        exit(2);
+                    },
+                };
+            },
+            "-h" | "--help" => {
+                print_usage();
+                return;
+            },
+            _ if !serve && path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => {
+                print_usage();
+                exit(2);
+            },
+        }
+    }
+
+    if serve {
+        // Запрос не должен занимать поток сервера неограниченно долго
+        limits.timeout = limits.timeout.or(Some(SERVE_TIMEOUT));
+        if let Err(why) = server::serve(port, limits, max_connections) {
+            eprintln!("Не удалось запустить сервер: {}", why);
+            exit(1);
+        }
+        return;
+    }
+
+    if let Some(socket) = daemon {
+        #[cfg(unix)]
+        let started = daemon::run(&socket, error_format, limits, max_connections);
+        #[cfg(not(unix))]
+        let started: io::Result<()> = Err(io::Error::new(io::ErrorKind::Unsupported, "Unix-сокеты недоступны на этой платформе"));
+        if let Err(why) = started {
+            eprintln!("Не удалось запустить демон: {}", why);
+            exit(1);
+        }
+        return;
+    }
+
+    if stdio_rpc {
+        if let Err(why) = rpc::run(&limits, config.settings) {
+            eprintln!("Ошибка ввода-вывода: {}", why);
+            exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = path {
+        let progress = match process_file(&path, format, error_format, &limits, jobs, progress) {
+            Ok(progress) => progress,
+            Err(why) => {
+                eprintln!("Не удалось обработать файл {}: {}", path, why);
+                exit(1);
+            },
+        };
+        if copy {
+            let copied = progress.last.ok_or_else(|| "нет вычисленного результата".to_string()).and_then(|last| clipboard::copy(&last));
+            if let Err(why) = copied {
+                eprintln!("Не удалось скопировать результат: {}", why);
+                exit(1);
+            }
+        }
+        if let Some(kind) = progress.failed {
+            exit(exit_code(kind));
+        }
+        return;
+    }
+
     print_help();
+    let mut cache = ParseCache::new();
+    let mut settings = config.settings;
+    let mut session = Session::new(&limits);
+    // Состояния до команд, изменивших настройки, псевдонимы, функции или переменные: :undo
+    // возвращает последнее
+    let mut undo: Stack<Snapshot> = Stack::new();
+    // Введённые строки, кроме :history, в том числе повторённые через !N
+    let mut history: Vec<String> = Vec::new();
+    let mut reader = LineReader::new();
     loop {
-        let stdin = io::stdin();
-        let mut input = String::new();
         println!("Введите выражение:");
-        stdin.read_line(&mut input).expect("Не удалось прочитать строку");
-        match process(&input) {
-            Ok(result) => println!("{}", result),
-            Err(why) => println!("{}", why),
+        reader.set_names(session.completions("").into_iter().map(|(name, _)| name.to_string()).collect());
+        let input = match reader.read_line() {
+            Some(input) => input,
+            None => break,
+        };
+        let input = match resolve_redo(input, &history) {
+            Ok(input) => input,
+            Err(why) => {
+                println!("{}", why);
+                match request_to_continue() {
+                    true => continue,
+                    false => break,
+                }
+            },
         };
+        if input.trim() == ":doc" {
+            print_doc_index();
+        } else if let Some(name) = input.trim().strip_prefix(":doc ") {
+            match describe(name.trim()) {
+                Some(description) => println!("{}", description),
+                None => println!("Нет описания для '{}', имена выводит :doc", name.trim()),
+            }
+        } else if input.trim() == ":history" {
+            for (index, line) in history.iter().enumerate() {
+                println!("{:>4}  {}", index + 1, line.trim_end());
+            }
+        } else if input.trim_start().starts_with(":convert") {
+            match run_convert(&input, &limits, settings.format) {
+                Ok(result) => println!("Результат: {}", result),
+                Err(why) => match error_format {
+                    ErrorFormat::Text => println!("{}", why.to_text(&input)),
+                    ErrorFormat::Json => println!("{}", why.to_json()),
+                },
+            };
+        } else if input.trim_start().starts_with(":table") {
+            match run_table(&input, &limits, &session, settings.format) {
+                Ok(rows) => rows.iter().for_each(|row| println!("{}", row)),
+                Err(why) => match error_format {
+                    ErrorFormat::Text => println!("{}", why.to_text(&input)),
+                    ErrorFormat::Json => println!("{}", why.to_json()),
+                },
+            };
+        } else if input.trim_start().starts_with(":plot") {
+            match Plot::parse(&input).map(|plot| plot.render(&limits, &session, settings.format)) {
+                Some(Ok(lines)) => lines.iter().for_each(|line| println!("{}", line)),
+                Some(Err(why)) => match error_format {
+                    ErrorFormat::Text => println!("{}", why.to_text(&input)),
+                    ErrorFormat::Json => println!("{}", why.to_json()),
+                },
+                None => println!("Использование: :plot ВЫРАЖЕНИЕ, ПЕРЕМЕННАЯ = НАЧАЛО..КОНЕЦ, например :plot sin(x), x = 0..6.28"),
+            };
+        } else if input.trim() == ":simplify" {
+            for (name, example, simplified) in simplify_rules() {
+                println!("  {}: {} -> {}", name, example, simplified);
+            }
+        } else if input.trim_start().starts_with(":simplify") {
+            // Выражение дополняется слева пробелами, чтобы положения ошибок совпадали с введённой строкой
+            let start = input.find(":simplify").unwrap_or(0) + ":simplify".len();
+            match simplify(&format!("{}{}", " ".repeat(start), &input[start..]), &limits) {
+                Ok(simplified) if simplified.nonzero.is_empty() => println!("Результат: {}", simplified.expression),
+                Ok(simplified) => println!("Результат: {} (при {} ≠ 0)", simplified.expression, simplified.nonzero.join(" ≠ 0, ")),
+                Err(why) => match error_format {
+                    ErrorFormat::Text => println!("{}", why.to_text(&input)),
+                    ErrorFormat::Json => println!("{}", why.to_json()),
+                },
+            };
+        } else if input.trim_start().starts_with(":set") {
+            match parse_setting(&input, settings) {
+                Some(selected) => {
+                    undo.push(session.snapshot(settings));
+                    settings = selected;
+                },
+                None => println!("Использование: :set notation fixed|sci|eng|auto, :set rounding half-even|half-up|trunc, :set sigfigs 1-9|off, :set grouping space|comma|locale|off, :set display fraction|decimal, :set mixed on|off, :set echo all|last или :set integer off|exact|trunc"),
+            }
+        } else if input.trim() == ":alias" {
+            for (name, expression) in &session.aliases {
+                println!("{} = {}", name, expression.trim());
+            }
+        } else if input.trim_start().starts_with(":alias") {
+            // Имена операторов, функций и единиц измерения распознаются раньше псевдонимов
+            match parse_alias(&input) {
+                Some((name, _)) if describe(name).is_some() || ["ans", "out"].contains(&name) =>
+                    println!("Имя '{}' уже занято, см. :doc {}", name, name),
+                Some((name, expression)) => match parse(&expression, &limits) {
+                    Ok(_) => {
+                        undo.push(session.snapshot(settings));
+                        session.variables.retain(|variable| variable.0 != name);
+                        session.aliases.retain(|alias| alias.0 != name);
+                        session.aliases.push((name.to_string(), expression));
+                    },
+                    Err(why) => match error_format {
+                        ErrorFormat::Text => println!("{}", why.to_text(&input)),
+                        ErrorFormat::Json => println!("{}", why.to_json()),
+                    },
+                },
+                None => println!("Использование: :alias ИМЯ = ВЫРАЖЕНИЕ, имя только из латинских букв"),
+            }
+        } else if input.trim() == ":functions" {
+            for (name, definition) in &session.functions {
+                println!("{}({}) = {}", name, definition.params.join(", "), definition.expression.trim());
+            }
+        } else if let Some((name, params, expression)) = parse_definition(&input) {
+            // Имена встроенных функций и единиц распознаются раньше, поэтому заняты и для параметров
+            match params.iter().chain([&name]).find(|name| describe(name).is_some() || ["ans", "out"].contains(name)) {
+                Some(taken) => println!("Имя '{}' уже занято, см. :doc {}", taken, taken),
+                None => match Definition::new(params.iter().map(|param| param.to_string()).collect(), &expression, &limits) {
+                    Ok(definition) => {
+                        undo.push(session.snapshot(settings));
+                        session.functions.retain(|function| function.0 != name);
+                        session.functions.push((name.to_string(), definition));
+                    },
+                    Err(why) => match error_format {
+                        ErrorFormat::Text => println!("{}", why.to_text(&input)),
+                        ErrorFormat::Json => println!("{}", why.to_json()),
+                    },
+                },
+            }
+        } else if input.trim() == ":undo" {
+            match undo.pop() {
+                Some(snapshot) => {
+                    settings = session.restore(snapshot);
+                    println!("Отменена последняя команда, изменившая настройки, псевдонимы, функции или переменные");
+                },
+                None => println!("Нечего отменять"),
+            }
+        } else if input.trim() == ":copy" {
+            match &session.shown {
+                Some(result) => match clipboard::copy(result) {
+                    Ok(()) => println!("Скопировано: {}", result),
+                    Err(why) => println!("Не удалось скопировать результат: {}", why),
+                },
+                None => println!("Нет результата для копирования"),
+            }
+        } else if input.trim() == ":outs" {
+            for (index, value) in session.outs.iter().enumerate() {
+                println!("out[{}] = {}", index + 1, value.formatted(settings.format));
+            }
+        } else if input.trim() == ":stats" {
+            println!("{}", cache.stats());
+        } else {
+            // Выражения вычисляются по порядку до первой ошибки; в каждом ans - результат
+            // предыдущего выражения, в первом - предыдущей строки
+            let statements = split_statements(&rewrite_out_references(&input));
+            // Присваивания строки отменяются вместе
+            let mut saved = false;
+            for (index, statement) in statements.iter().enumerate() {
+                let last = index + 1 == statements.len();
+                // Значение присваивания вычисляется без целочисленного режима: оно хранится как f32
+                let assignment = parse_assignment(statement);
+                if let Some((name, _)) = assignment.as_ref().filter(|(name, _)| describe(name).is_some() || ["ans", "out"].contains(name)) {
+                    println!("Имя '{}' уже занято, см. :doc {}", name, name);
+                    break;
+                }
+                let statement = assignment.as_ref().map_or(statement.as_str(), |assignment| &assignment.1);
+                let evaluated = match settings.integer.filter(|_| assignment.is_none()) {
+                    // Целые результаты не попадают в ans и out[N]: там они хранились бы как f32
+                    // и теряли бы точность
+                    Some(division) => run_integer(statement, &limits, division).map(|(rpn, result)| (rpn, result.to_string(), None, None)),
+                    None => cache.process(statement, &limits, &session).map(|evaluation| {
+                        let result = solution(&evaluation.rpn, evaluation.value.formatted(settings.format).to_string());
+                        let result = match &assignment {
+                            Some((name, _)) => format!("{} = {}", name, result),
+                            None => result,
+                        };
+                        (evaluation.rpn, result, Some(evaluation.value), Some(evaluation.trace))
+                    }),
+                };
+                match evaluated {
+                    Ok((rpn, result, value, evaluation_trace)) => {
+                        let out = match value {
+                            Some(_) => format!(" (out[{}])", session.outs.len() + 1),
+                            None => String::new(),
+                        };
+                        let shown = if statements.len() == 1 {
+                            println!("{}\nРезультат: {}{}", rpn, result, out);
+                            true
+                        } else if last || !settings.echo_last {
+                            println!("{}\n[{}] Результат: {}{}", rpn, index + 1, result, out);
+                            true
+                        } else {
+                            false
+                        };
+                        if shown {
+                            session.shown = Some(result);
+                            if let Some(evaluation_trace) = evaluation_trace.filter(|_| trace) {
+                                println!("Трассировка: шагов {}, общих подвыражений {}, повторных вычислений пропущено {}",
+                                    evaluation_trace.steps, evaluation_trace.shared, evaluation_trace.hits);
+                            }
+                        }
+                        if let (Some((name, _)), Some(value)) = (&assignment, &value) {
+                            if !saved {
+                                undo.push(session.snapshot(settings));
+                                saved = true;
+                            }
+                            session.aliases.retain(|alias| alias.0 != *name);
+                            session.variables.retain(|variable| variable.0 != *name);
+                            session.variables.push((name.to_string(), value.clone()));
+                        }
+                        if let Some(value) = value {
+                            session.record(value);
+                        }
+                    },
+                    Err(why) => {
+                        // Положения в выражении совпадают с положениями во всей строке
+                        let errors = all_errors(statement, &limits, why);
+                        if errors.len() > 1 && error_format == ErrorFormat::Text {
+                            println!("Найдено ошибок: {}", errors.len());
+                        }
+                        for why in errors {
+                            match error_format {
+                                ErrorFormat::Text => println!("{}", why.to_text(&input)),
+                                ErrorFormat::Json => println!("{}", why.to_json()),
+                            }
+                        }
+                        break;
+                    },
+                };
+            }
+        }
+        if input.trim() != ":history" && !input.trim().is_empty() {
+            history.push(input);
+        }
 
         match request_to_continue() {
             true => continue,
@@ -342,16 +1011,76 @@ fn main() {
     }
 }
 
+// Команда REPL ":doc": имена из таблиц операторов, функций и единиц измерения по категориям
+fn print_doc_index() {
+    for (category, names) in documented_names() {
+        println!("  {}: {}", category, names.join(" "));
+    }
+}
+
 fn print_help() {
     println!("Данная программа преобразует арифметическую операцию записанную в инфиксной форме в запись обратной польской нотации и вычисляет её.\nПоддерживаемые операции:");
-    println!("  унарные:");
-    println!("    '+'");
-    println!("    '-'");
-    println!("  бинарные:");
-    println!("    '+'");
-    println!("    '-'");
-    println!("    '/'");
-    println!("    '*'");
+    print_doc_index();
+    println!("  списки:");
+    println!("    [1, 2, 3] * 2 - операторы и функции применяются к каждому элементу");
+    println!("    [1, 2] + [3, 4] - списки одинаковой длины складываются поэлементно");
+    println!("    [[1, 2], [3, 4]] - матрица (список строк) для функций transpose, det и matmul");
+    println!("    sum, mean, median, variance, stddev, count - статистика по всем числам списка");
+    println!("  единицы измерения:");
+    println!("    5 km + 300 m - результат в основных единицах (m, kg, s)");
+    println!("    :convert 100 mph to km/h - перевод результата в заданную единицу");
+    println!("    12.50 USD + 3 EUR - валюты из файла курсов (ключ rates в файле настроек), сумма - в основной валюте");
+    println!("  даты:");
+    println!("    2024-05-01 + 3 w, now() - 90 d - дата, сдвинутая на длительность в днях (d) или неделях (w)");
+    println!("    2024-03-01 - 2024-02-01 - число дней между датами");
+    println!("  системы счисления:");
+    println!("    16#ff, 2#1010, 36#z - целые числа с основанием от 2 до 36");
+    println!("    base(255, 16) - запись числа в системе счисления с заданным основанием");
+    println!("  погрешности:");
+    println!("    (2.0±0.1) * (3±0.2) - интервальная арифметика: результат содержит все возможные значения");
+    println!("  сравнения:");
+    println!("    2 * 3 > 5, 5 km == 5000 m - результат true или false; сравниваются числа, величины и даты");
+    println!("    1 < x < 10 - цепочка сравнений: 1 < x и x < 10 (в записи ОПН: 1 x < x 10 < AND)");
+    println!("    if(x > 0, sqrt(x), 0) - значение выбранной ветви; другая ветвь не вычисляется");
+    println!("  функции пользователя:");
+    println!("    absv(x) = if(x < 0, -x, x) - определение функции, затем absv(-3); :functions - список функций");
+    println!("    fact(n) = if(n <= 1, 1, n * fact(n - 1)) - рекурсивная функция; глубину вызовов ограничивает --max-recursion (1000)");
+    println!("  выражения от переменной:");
+    println!("    table(x * x, x, 0, 1, 0.25) - значения выражения при x от 0 до 1 с шагом 0.25: [[0, 0], [0.25, 0.06], ...]");
+    println!("    integrate(x * x, x, 0, 3) - интеграл выражения по x от 0 до 3; относительную погрешность задаёт --tolerance (0.001)");
+    println!("    root(x * x - 2, x, 1) - корень выражения по x рядом с начальным приближением 1");
+    println!("    solve(2 * x + 3 = 11, x) - решение линейного или квадратного уравнения: x = 4");
+    println!("    sum(i, 1, 100, pow(i, 2)), prod(k, 1, 10, k) - сумма и произведение выражения при целых i от 1 до 100");
+    println!("  запись:");
+    println!("    |x| - модуль, то же, что abs(x)");
+    println!("    x = 5, x += 2 (также -=, *=, /=) - присваивание переменной сеанса; x без значения - ошибка");
+    println!("    1 + 2; ans * 3 - несколько выражений в строке (результат 9); ans - результат предыдущего выражения");
+    println!("    out[2] * 10 - результат с номером 2 из выведенных в сеансе (также out(2))");
+    println!("    sin(90°), sin(90deg), cos(100grad) - угол в градусах или градах (функции считают в радианах)");
+    println!("  команды:");
+    println!("    :doc [ИМЯ] - описание оператора, функции, единицы измерения или кода ошибки (:doc E0010); без имени - список имён");
+    println!("    :history - ранее введённые строки с номерами");
+    println!("    :outs - результаты сеанса с номерами для ссылок out[N]");
+    println!("    :alias [ИМЯ = ВЫРАЖЕНИЕ] - имя для выражения, которое вычисляется при каждом использовании: :alias vat = ans * 0.2");
+    println!("    :undo - отменить последнюю команду :set, :alias, определение функции или присваивание");
+    println!("    :copy - последний результат в буфер обмена (сборка с --features clipboard)");
+    println!("    --trace - после результата: шагов вычисления, общих подвыражений (вычисляются один раз) и пропущенных повторных вычислений");
+    println!("    !N или :redo N - выполнить строку с номером N из :history ещё раз");
+    println!("    :table ВЫРАЖЕНИЕ, ПЕРЕМЕННАЯ, НАЧАЛО, КОНЕЦ, ШАГ - таблица значений выражения в два столбца: :table sin(x), x, 0, 3, 0.5");
+    println!("    :plot ВЫРАЖЕНИЕ, ПЕРЕМЕННАЯ = НАЧАЛО..КОНЕЦ - график выражения в терминале: :plot sin(x), x = 0..6.28");
+    println!("    :simplify ВЫРАЖЕНИЕ - упрощение: (x * y) / y -> x (при y ≠ 0), 2 * x + 3 * x -> 5 * x; без выражения - список правил");
+    println!("    :stats - попадания и промахи кэша разбора повторно введённых выражений");
+    println!("    :set notation fixed|sci|eng|auto - запись результата: 12345678.00, 1.2345678e7, 12.345678e6");
+    println!("      или научная только для очень больших и очень малых чисел");
+    println!("    :set rounding half-even|half-up|trunc - округление до сотых: 0.125 -> 0.12, 0.13 или 0.12");
+    println!("    :set sigfigs 1-9|off - число значащих цифр вместо двух знаков после точки: 3 -> 0.000123, 1.23e6");
+    println!("    :set grouping space|comma|locale|off - разделение целой части: 1 234 567.50 или 1,234,567.50 (точность f32 - около 7 значащих цифр)");
+    println!("    :set display fraction|decimal - результат простой дробью: 7/3 вместо 2.33");
+    println!("    :set mixed on|off - дробь с выделенной целой частью: 2 1/3 вместо 7/3");
+    println!("    :set integer off|exact|trunc - целые числа i64 с проверкой переполнения; деление нацело или с отбрасыванием остатка");
+    println!("    :set echo all|last - результаты всех выражений строки через ';' или только последнего");
+    println!("В сборке с --features editor доступны история строк (стрелки вверх и вниз), дополнение по Tab имён функций,");
+    println!("единиц измерения, ans, out, переменных и псевдонимов и подсветка выражения при вводе: скобки без пары и место ошибки - красным");
     println!("Для выхода нажмите <Ctrl+C>");
 }
 
@@ -369,3 +1098,175 @@ fn request_to_continue() -> bool {
 
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Строка вывода обработки строки файла
+    fn output(line: &str, format: OutputFormat) -> String {
+        match process_line(line, format, ErrorFormat::Text, &Limits::default()) {
+            Some(Ok((output, _))) | Some(Err((output, _))) => output,
+            None => String::new(),
+        }
+    }
+
+    #[test]
+    fn piecewise_user_functions() {
+        let limits = Limits::default();
+        let mut session = Session::new(&limits);
+        let (name, params, expression) = parse_definition("absv(x) = if(x < 0, -x, x)").unwrap();
+        assert_eq!((name, params.clone()), ("absv", vec!["x"]));
+        assert_eq!(expression, "          if(x < 0, -x, x)");
+        let define = |params: &[&str], expression: &str| Definition::new(params.iter().map(|param| param.to_string()).collect(), expression, &limits).unwrap();
+        session.functions.push((name.to_string(), define(&params, &expression)));
+        session.functions.push(("clamp".to_string(), define(&["x", "lo", "hi"], "if(x < lo, lo, if(x > hi, hi, x))")));
+        let value = |input: &str| process_with(input, &limits, &session).map(|evaluation| evaluation.value);
+        assert_eq!(value("absv(-3) + absv(2)").unwrap(), Value::Number(5.0));
+        assert_eq!(value("clamp(15, 0, 10) + clamp(-1, 0, 10) + clamp(7, 0, 10)").unwrap(), Value::Number(17.0));
+        // Параметр виден только в теле функции
+        assert_eq!(value("x").expect_err("x не определена").kind, ErrorKind::UnknownVariable);
+        assert_eq!(value("absv(1, 2)").expect_err("лишний аргумент").kind, ErrorKind::InvalidFunctionCall);
+
+        assert!(parse_definition("f(x) == 1").is_none());
+        assert!(parse_definition("f(x) <= 1").is_none());
+        assert!(parse_definition("max(1, 2) = 2").is_none());
+        assert_eq!(parse_definition("g(a, b) = a * b").map(|definition| definition.1), Some(vec!["a", "b"]));
+    }
+
+    #[test]
+    fn assignments_and_compound_assignments() {
+        assert_eq!(parse_assignment("x = 5"), Some(("x", "    5".to_string())));
+        assert_eq!(parse_assignment("  total += 2 * 3\n"), Some(("total", "  total +( 2 * 3)".to_string())));
+        assert_eq!(parse_assignment("x /= 4").map(|assignment| assignment.1), Some("x /( 4)".to_string()));
+        assert!(parse_assignment("x == 5").is_none());
+        assert!(parse_assignment("x <= 5").is_none());
+        assert!(parse_assignment("solve(2 * x = 8, x)").is_none());
+
+        let limits = Limits::default();
+        let mut session = Session::new(&limits);
+        let assign = |session: &mut Session, statement: &str| {
+            let (name, expression) = parse_assignment(statement).unwrap();
+            let value = process_with(&expression, &limits, session)?.value;
+            session.variables.retain(|variable| variable.0 != name);
+            session.variables.push((name.to_string(), value));
+            Ok::<(), CalcError>(())
+        };
+        assign(&mut session, "x = 5").unwrap();
+        assign(&mut session, "x *= 2 + 1").unwrap();
+        assign(&mut session, "x -= 3").unwrap();
+        assert_eq!(process_with("x", &limits, &session).map(|evaluation| evaluation.value).unwrap(), Value::Number(12.0));
+        // Составное присваивание переменной без значения - ошибка, место ошибки - имя
+        let why = assign(&mut session, "y += 1").expect_err("y не определена");
+        assert_eq!((why.kind, why.span), (ErrorKind::UnknownVariable, Some((0, 1))));
+    }
+
+    #[test]
+    fn undo_restores_session_state() {
+        let limits = Limits::default();
+        let mut session = Session::new(&limits);
+        let mut undo: Stack<Snapshot> = Stack::new();
+        let settings = Settings::default();
+        undo.push(session.snapshot(settings));
+        let settings = parse_setting(":set echo last", settings).unwrap();
+        undo.push(session.snapshot(settings));
+        session.aliases.push(("vat".to_string(), "ans * 0.2".to_string()));
+        undo.push(session.snapshot(settings));
+        session.variables.push(("x".to_string(), Value::Number(5.0)));
+        session.functions.push(("f".to_string(), Definition::new(vec!["a".to_string()], "a * 2", &limits).unwrap()));
+
+        let settings = session.restore(undo.pop().unwrap());
+        assert!(settings.echo_last);
+        assert_eq!((session.aliases.len(), session.variables.len(), session.functions.len()), (1, 0, 0));
+        session.restore(undo.pop().unwrap());
+        assert!(session.aliases.is_empty());
+        let settings = session.restore(undo.pop().unwrap());
+        assert!(!settings.echo_last);
+        assert!(undo.pop().is_none());
+    }
+
+    #[test]
+    fn alias_errors_are_reported() {
+        let limits = Limits::default();
+        let mut session = Session::new(&limits);
+        session.aliases.push(("ratio".to_string(), "[1, 2] + [1, 2, 3]".to_string()));
+        session.aliases.push(("a".to_string(), "b + 1".to_string()));
+        session.aliases.push(("b".to_string(), "a".to_string()));
+        let error = |input: &str| process_with(input, &limits, &session).map(|evaluation| evaluation.value).expect_err("псевдоним не вычисляется");
+        // Ошибка выражения псевдонима, а не "неизвестная переменная", с местом имени псевдонима
+        let why = error("1 + ratio");
+        assert_eq!((why.kind, why.span), (ErrorKind::ShapeMismatch, Some((4, 9))));
+        assert_eq!(why.notes, vec![(None, "ошибка при вычислении переменной 'ratio'".to_string())]);
+        assert_eq!(error("2 * a").kind, ErrorKind::TooDeep);
+        assert_eq!(error("2 * c").kind, ErrorKind::UnknownVariable);
+        session.ans = Some(Value::Number(10.0));
+        session.aliases.push(("vat".to_string(), "ans * 0.2".to_string()));
+        assert_eq!(process_with("vat + 1", &limits, &session).unwrap().value, Value::Number(3.0));
+    }
+
+    #[test]
+    fn out_of_range_results() {
+        let limits = Limits::default();
+        let mut session = Session::new(&limits);
+        let error = |session: &Session, input: &str| process_with(&rewrite_out_references(input), &limits, session)
+            .map(|evaluation| evaluation.value).expect_err("нет такого результата");
+        let why = error(&session, "out[1]");
+        assert_eq!((why.kind, why.span), (ErrorKind::OutOfRange, Some((0, 3))));
+        assert_eq!(why.message, "нет результата out[1]: в сеансе ещё нет результатов");
+        session.outs = vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)];
+        let why = error(&session, "2 * out[4]");
+        assert_eq!((why.kind, why.span), (ErrorKind::OutOfRange, Some((4, 7))));
+        assert_eq!(why.message, "нет результата out[4]: номер результата - целое число от 1 до 3");
+        assert_eq!(error(&session, "out(0)").kind, ErrorKind::OutOfRange);
+        assert_eq!(error(&session, "out(1.5)").kind, ErrorKind::OutOfRange);
+        assert_eq!(process_with(&rewrite_out_references("out(3) - out[2]"), &limits, &session).unwrap().value, Value::Number(1.0));
+    }
+
+    #[test]
+    fn statements_see_previous_ans() {
+        let limits = Limits::default();
+        let mut session = Session::new(&limits);
+        let statements = split_statements("1 + 2; ans * 3;ans - 1");
+        assert_eq!(statements, vec!["1 + 2", "       ans * 3", "               ans - 1"]);
+        for statement in &statements {
+            let value = process_with(statement, &limits, &session).unwrap().value;
+            session.record(value);
+        }
+        assert_eq!(session.ans, Some(Value::Number(8.0)));
+        assert_eq!(session.outs, vec![Value::Number(3.0), Value::Number(9.0), Value::Number(8.0)]);
+        // В первом выражении первой строки ans ещё нет
+        let fresh = Session::new(&limits);
+        assert_eq!(process_with("ans * 3", &limits, &fresh).map(|evaluation| evaluation.value).expect_err("ans нет").kind, ErrorKind::UnknownVariable);
+    }
+
+    #[test]
+    fn parallel_lines_keep_order_and_report_worker_panics() {
+        let count = 10 * BLOCK_LINES + 7;
+        let lines = || (1..=count).map(|number| Ok(number.to_string()));
+        let progress = || Progress { every: 0, lines: 0, errors: 0, last: None, failed: None };
+        let echo = |line: &str| Some(Ok((line.to_string(), line.to_string())));
+        let mut done = progress();
+        process_parallel(&mut lines(), 4, &echo, &mut done).unwrap();
+        assert_eq!((done.lines, done.last), (count, Some(count.to_string())));
+
+        // Паника потока - ошибка, а не зависание или паника чтения результатов
+        let failing = |line: &str| match line == (3 * BLOCK_LINES + 5).to_string() {
+            true => panic!("сбой обработки строки"),
+            false => echo(line),
+        };
+        let mut failed = progress();
+        let why = process_parallel(&mut lines(), 4, &failing, &mut failed).expect_err("поток упал");
+        assert_eq!(why.to_string(), format!("поток обработки завершился аварийно на блоке строк с {}-й", 3 * BLOCK_LINES + 1));
+        assert_eq!(failed.lines, 3 * BLOCK_LINES);
+    }
+
+    #[test]
+    fn file_lines_show_solved_variable() {
+        assert_eq!(output("2 * 3", OutputFormat::Text), "2 * 3 = 6.00");
+        assert_eq!(output("solve(2 * x + 3 = 11, x)", OutputFormat::Text), "solve(2 * x + 3 = 11, x): x = 4.00");
+        assert_eq!(output("solve(x * x = 4, x)", OutputFormat::Text), "solve(x * x = 4, x): x = [-2.00, 2.00]");
+        assert_eq!(output("solve(2 * x = 8, x)", OutputFormat::Csv), "\"solve(2 * x = 8, x)\",2 x * 8 = x solve,x = 4.00,");
+        let copied = process_line("solve(2 * x = 8, x)", OutputFormat::Text, ErrorFormat::Text, &Limits::default());
+        assert_eq!(copied.and_then(Result::ok).map(|(_, result)| result), Some("x = 4.00".to_string()));
+    }
+}
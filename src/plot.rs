@@ -0,0 +1,144 @@
+// Команда REPL ":plot ВЫРАЖЕНИЕ, ПЕРЕМЕННАЯ = НАЧАЛО..КОНЕЦ" - график выражения символами
+// в терминале. Выражение компилируется один раз и вычисляется в точках отрезка; значение
+// переменной задаёт Binding поверх переменных сеанса (ans, псевдонимы)
+use less_2_task::calc::{compile, Binding, CalcError, Limits, NumberFormat, Resolver, Value};
+
+// Размер поля графика в символах: число точек отрезка и строк
+const WIDTH: usize = 60;
+const HEIGHT: usize = 15;
+
+pub struct Plot {
+    // Выражение, дополненное слева пробелами до своего места во введённой строке
+    expression: String,
+    variable: String,
+    low: f32,
+    high: f32,
+}
+
+impl Plot {
+    // Разбор команды; None - команда записана неправильно
+    pub fn parse(input: &str) -> Option<Plot> {
+        let command = input.trim_end();
+        let start = command.find(":plot")? + ":plot".len();
+        // Запятые могут быть и в выражении, поэтому отрезок - после последней
+        let comma = command.rfind(',').filter(|comma| *comma > start)?;
+        let (variable, range) = command[comma + 1..].split_once('=')?;
+        let variable = variable.trim();
+        if variable.is_empty() || !variable.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let (low, high) = range.split_once("..")?;
+        let (low, high) = (low.trim().parse::<f32>().ok()?, high.trim().parse::<f32>().ok()?);
+        if !low.is_finite() || !high.is_finite() || low >= high {
+            return None;
+        }
+        let expression = format!("{}{}", " ".repeat(start), &command[start..comma]);
+        Some(Plot { expression, variable: variable.to_string(), low, high })
+    }
+
+    // Строки графика: подписи наибольшего и наименьшего значений слева, ось значений
+    // переменной снизу. Точки, в которых выражение не вычисляется или не даёт числа,
+    // пропускаются; ошибка - только если таких точек нет
+    pub fn render(&self, limits: &Limits, resolver: &dyn Resolver, format: NumberFormat) -> Result<Vec<String>, CalcError> {
+        let program = compile(&self.expression, limits)?;
+        let mut points: Vec<Option<f32>> = Vec::with_capacity(WIDTH);
+        let mut first_error: Option<CalcError> = None;
+        for column in 0..WIDTH {
+            let x = self.low + (self.high - self.low) * column as f32 / (WIDTH - 1) as f32;
+            let binding = Binding { name: &self.variable, value: Value::Number(x), outer: resolver };
+            let y = match program.run(limits, &binding) {
+                Ok(Value::Number(y)) | Ok(Value::Quantity(y, _)) => Some(y),
+                Ok(Value::Radix(y, _)) => Some(y as f32),
+                Ok(_) => None,
+                Err(why) => {
+                    first_error.get_or_insert(why);
+                    None
+                },
+            };
+            points.push(y.filter(|y| y.is_finite()));
+        }
+        let values = points.iter().flatten();
+        let (min, max) = match (values.clone().copied().reduce(f32::min), values.copied().reduce(f32::max)) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return match first_error {
+                Some(why) => Err(why),
+                None => Ok(vec!["Выражение не дало чисел на этом отрезке".to_string()]),
+            },
+        };
+        // У постоянного выражения график - линия посередине
+        let (bottom, top) = if min == max { (min - 1.0, max + 1.0) } else { (min, max) };
+        let row = |y: f32| ((top - y) / (top - bottom) * (HEIGHT - 1) as f32).round() as usize;
+
+        let mut grid = vec![vec![' '; WIDTH]; HEIGHT];
+        if bottom < 0.0 && top > 0.0 {
+            grid[row(0.0)].iter_mut().for_each(|cell| *cell = '-');
+        }
+        for (column, y) in points.iter().enumerate() {
+            if let Some(y) = y {
+                grid[row(*y)][column] = '*';
+            }
+        }
+
+        let top_label = Value::Number(top).formatted(format).to_string();
+        let bottom_label = Value::Number(bottom).formatted(format).to_string();
+        let width = top_label.chars().count().max(bottom_label.chars().count());
+        let mut lines: Vec<String> = grid.into_iter().enumerate().map(|(index, cells)| {
+            let label = match index {
+                0 => &top_label,
+                index if index == HEIGHT - 1 => &bottom_label,
+                _ => "",
+            };
+            format!("{:>width$} |{}", label, cells.into_iter().collect::<String>().trim_end(), width = width)
+        }).collect();
+        lines.push(format!("{:>width$} +{}", "", "-".repeat(WIDTH), width = width));
+        let low_label = Value::Number(self.low).formatted(format).to_string();
+        let high_label = Value::Number(self.high).formatted(format).to_string();
+        let gap = WIDTH.saturating_sub(low_label.chars().count());
+        lines.push(format!("{:>width$}  {}{:>gap$}  ({})", "", low_label, high_label, self.variable, width = width, gap = gap));
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use less_2_task::calc::ErrorKind;
+    use crate::Session;
+
+    #[test]
+    fn parse_command() {
+        let plot = Plot::parse(":plot max(x, 1), x = -2..3.5").expect("команда записана правильно");
+        assert_eq!(plot.expression, "      max(x, 1)");
+        assert_eq!((plot.variable.as_str(), plot.low, plot.high), ("x", -2.0, 3.5));
+        assert!(Plot::parse(":plot sin(x)").is_none());
+        assert!(Plot::parse(":plot sin(x), x = 3..1").is_none());
+        assert!(Plot::parse(":plot sin(x), x1 = 0..1").is_none());
+        assert!(Plot::parse(":plot sin(x), x = 0..").is_none());
+    }
+
+    #[test]
+    fn render_samples_every_column() {
+        let limits = Limits::default();
+        let session = Session::new(&limits);
+        let lines = Plot::parse(":plot sin(x), x = 0..6.28").unwrap().render(&limits, &session, NumberFormat::default()).unwrap();
+        assert_eq!(lines.len(), HEIGHT + 2);
+        assert_eq!(lines.iter().map(|line| line.matches('*').count()).sum::<usize>(), WIDTH);
+        assert!(lines[0].starts_with(" 1.00 |"));
+        assert!(lines[HEIGHT - 1].starts_with("-1.00 |"));
+        assert!(lines[HEIGHT + 1].trim_start().starts_with("0.00"));
+        assert!(lines[HEIGHT + 1].ends_with("6.28  (x)"));
+    }
+
+    #[test]
+    fn render_resolves_session_variables() {
+        let limits = Limits::default();
+        let mut session = Session::new(&limits);
+        session.ans = Some(Value::Number(3.0));
+        let lines = Plot::parse(":plot x * ans, x = 0..2").unwrap().render(&limits, &session, NumberFormat::default()).unwrap();
+        assert!(lines[0].starts_with("6.00 |"));
+        assert!(lines[HEIGHT - 1].starts_with("0.00 |"));
+
+        let why = Plot::parse(":plot x * y, x = 0..2").unwrap().render(&limits, &session, NumberFormat::default()).expect_err("y не определена");
+        assert_eq!(why.kind, ErrorKind::UnknownVariable);
+    }
+}
@@ -0,0 +1,89 @@
+// Модуль less2task для Python (PyO3)
+use std::collections::HashMap;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use crate::calc::{self, describe, parse, process, process_with, Limits, NumberFormat, Resolver, Value};
+
+// Исключение с аргументами (сообщение, код ошибки, позиция начала или None)
+create_exception!(less2task, CalcError, PyValueError);
+
+fn error_value(error: calc::CalcError) -> PyErr {
+    CalcError::new_err((error.message, error.kind.code(), error.span.map(|span| span.0)))
+}
+
+// Вычисление выражения
+#[pyfunction]
+#[pyo3(name = "eval")]
+fn evaluate(expr: &str) -> PyResult<String> {
+    process(expr, &Limits::default()).map(|evaluation| evaluation.result).map_err(error_value)
+}
+
+// Запись выражения в ОПН без вычисления
+#[pyfunction]
+fn to_rpn(expr: &str) -> PyResult<String> {
+    let output = parse(expr, &Limits::default()).map_err(error_value)?;
+    Ok(output.iter().map(|tok| &*tok.1).collect::<Vec<&str>>().join(" "))
+}
+
+// Окружение с переменными: env = Env(); env.set("x", 2); env.eval("x * 3");
+// результат вычисления сохраняется в переменной ans
+#[pyclass]
+#[derive(Default)]
+struct Env {
+    variables: HashMap<String, Value>,
+}
+
+impl Resolver for Env {
+    fn variable(&self, name: &str) -> Option<Value> {
+        self.variables.get(name).cloned()
+    }
+
+    fn call(&self, _name: &str, _args: &[Value]) -> Option<Value> {
+        None
+    }
+}
+
+#[pymethods]
+impl Env {
+    #[new]
+    fn new() -> Self {
+        Env::default()
+    }
+
+    // Имя переменной - латинские буквы, не занятые операторами, функциями и единицами
+    fn set(&mut self, name: &str, value: f32) -> PyResult<()> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) || describe(name).is_some() {
+            return Err(PyValueError::new_err(format!("Недопустимое имя переменной '{}'", name)));
+        }
+        self.variables.insert(name.to_string(), Value::Number(value));
+        Ok(())
+    }
+
+    // Значение переменной в записи результата или None, если переменная не задана
+    fn get(&self, name: &str) -> Option<String> {
+        self.variables.get(name).map(|value| value.formatted(NumberFormat::default()).to_string())
+    }
+
+    fn remove(&mut self, name: &str) -> bool {
+        self.variables.remove(name).is_some()
+    }
+
+    #[pyo3(name = "eval")]
+    fn evaluate(&mut self, expr: &str) -> PyResult<String> {
+        let evaluation = process_with(expr, &Limits::default(), self).map_err(error_value)?;
+        self.variables.insert("ans".to_string(), evaluation.value);
+        Ok(evaluation.result)
+    }
+}
+
+#[pymodule]
+fn less2task(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(evaluate, m)?)?;
+    m.add_function(wrap_pyfunction!(to_rpn, m)?)?;
+    m.add_class::<Env>()?;
+    m.add("CalcError", m.py().get_type::<CalcError>())?;
+    Ok(())
+}
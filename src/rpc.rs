@@ -0,0 +1,241 @@
+// Режим JSON-RPC 2.0 через стандартные потоки (--stdio-rpc): по одному запросу в строке,
+// по одному ответу в строке. Сеанс хранит состояние между запросами, как REPL: ans и out(N),
+// псевдонимы (метод define) и настройки :set (метод set)
+use std::io::{self, BufRead, Write};
+use less_2_task::calc::{describe, json_string, parse, process_with, CalcError, Limits};
+
+use crate::json::{self, Json};
+use crate::{parse_setting, run_integer, Session, Settings};
+
+// Коды ошибок протокола JSON-RPC
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+// Ошибка разбора или вычисления выражения; подробности в поле data
+const CALC_ERROR: i32 = -32000;
+
+// Ошибка обработки запроса: код, сообщение и необязательные данные в формате JSON
+type RpcError = (i32, String, Option<String>);
+
+fn calc_error(why: CalcError) -> RpcError {
+    (CALC_ERROR, why.message.clone(), Some(why.to_json()))
+}
+
+pub fn run(limits: &Limits, settings: Settings) -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    serve(stdin.lock(), &mut stdout.lock(), limits, settings)
+}
+
+// Запросы из input, ответы в output; один сеанс на весь поток запросов
+fn serve(input: impl BufRead, output: &mut impl Write, limits: &Limits, mut settings: Settings) -> io::Result<()> {
+    let mut session = Session::new(limits);
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_line(&line, &mut session, &mut settings) {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+// Ответ на строку запроса; на уведомления (запросы без id) ответ не отправляется
+fn handle_line(line: &str, session: &mut Session, settings: &mut Settings) -> Option<String> {
+    let request = match json::parse(line) {
+        Ok(request) => request,
+        Err(why) => return Some(response(&Json::Null, Err((PARSE_ERROR, format!("некорректный JSON: {}", why), None)))),
+    };
+    let id = request.get("id").cloned();
+    let result = match (request.get("jsonrpc").and_then(Json::as_str), request.get("method").and_then(Json::as_str)) {
+        (Some("2.0"), Some(method)) => call(method, request.get("params"), session, settings),
+        _ => Err((INVALID_REQUEST, "ожидается запрос JSON-RPC 2.0 с полем 'method'".to_string(), None)),
+    };
+    id.map(|id| response(&id, result))
+}
+
+fn response(id: &Json, result: Result<String, RpcError>) -> String {
+    match result {
+        Ok(result) => format!("{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{}}}", id, result),
+        Err((code, message, data)) => {
+            let data = data.map_or(String::new(), |data| format!(",\"data\":{}", data));
+            format!("{{\"jsonrpc\":\"2.0\",\"id\":{},\"error\":{{\"code\":{},\"message\":{}{}}}}}",
+                id, code, json_string(&message), data)
+        },
+    }
+}
+
+// Строковый параметр запроса
+fn string_param<'a>(params: Option<&'a Json>, name: &str) -> Result<&'a str, RpcError> {
+    params.and_then(|params| params.get(name)).and_then(Json::as_str)
+        .ok_or_else(|| (INVALID_PARAMS, format!("ожидается строковый параметр '{}'", name), None))
+}
+
+// Выполнение метода; результат - значение в формате JSON
+fn call(method: &str, params: Option<&Json>, session: &mut Session, settings: &mut Settings) -> Result<String, RpcError> {
+    let limits: &Limits = session.limits;
+    match method {
+        // {"expression"} -> {"rpn", "result"}: результат записывается по настройкам сеанса
+        // и становится ans; целые результаты, как и в REPL, в ans не попадают
+        "evaluate" => {
+            let expression = string_param(params, "expression")?;
+            let (rpn, result) = match settings.integer {
+                Some(division) => run_integer(expression, limits, division).map(|(rpn, result)| (rpn, result.to_string())),
+                None => process_with(expression, limits, session).map(|evaluation| {
+                    let result = evaluation.value.formatted(settings.format).to_string();
+                    session.record(evaluation.value);
+                    (evaluation.rpn, result)
+                }),
+            }.map_err(calc_error)?;
+            Ok(format!("{{\"rpn\":{},\"result\":{}}}", json_string(&rpn), json_string(&result)))
+        },
+        // {"name", "expression"} -> true: псевдоним, как :alias ИМЯ = ВЫРАЖЕНИЕ
+        "define" => {
+            let name = string_param(params, "name")?;
+            let expression = string_param(params, "expression")?;
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err((INVALID_PARAMS, "имя псевдонима только из латинских букв".to_string(), None));
+            }
+            if describe(name).is_some() || ["ans", "out"].contains(&name) {
+                return Err((INVALID_PARAMS, format!("имя '{}' уже занято", name), None));
+            }
+            parse(expression, limits).map_err(calc_error)?;
+            session.aliases.retain(|alias| alias.0 != name);
+            session.aliases.push((name.to_string(), expression.to_string()));
+            Ok("true".to_string())
+        },
+        // {"name", "value"} -> true: настройка, как :set ИМЯ ЗНАЧЕНИЕ
+        "set" => {
+            let command = format!(":set {} {}", string_param(params, "name")?, string_param(params, "value")?);
+            *settings = parse_setting(&command, *settings)
+                .ok_or_else(|| (INVALID_PARAMS, format!("неизвестная настройка '{}'", &command[":set ".len()..]), None))?;
+            Ok("true".to_string())
+        },
+        // {"expression"} -> {"valid", "error"}: проверка разбора без вычисления
+        "check" => match parse(string_param(params, "expression")?, limits) {
+            Ok(_) => Ok("{\"valid\":true,\"error\":null}".to_string()),
+            Err(why) => Ok(format!("{{\"valid\":false,\"error\":{}}}", why.to_json())),
+        },
+        // {"expression"} -> строка в ОПН
+        "toRpn" => {
+            let output = parse(string_param(params, "expression")?, limits).map_err(calc_error)?;
+            Ok(json_string(&output.iter().map(|tok| &*tok.1).collect::<Vec<&str>>().join(" ")))
+        },
+        // {"prefix"} -> [{"label", "kind"}]: функции, единицы измерения и переменные сеанса,
+        // начинающиеся с prefix
+        "complete" => {
+            let prefix = string_param(params, "prefix")?;
+            let items = session.completions(prefix).into_iter()
+                .map(|item| format!("{{\"label\":{},\"kind\":\"{}\"}}", json_string(item.0), item.1))
+                .collect::<Vec<String>>();
+            Ok(format!("[{}]", items.join(",")))
+        },
+        _ => Err((METHOD_NOT_FOUND, format!("неизвестный метод '{}'", method), None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ответы на запросы requests, по одному в строке, в одном сеансе
+    fn exchange(requests: &[&str]) -> Vec<String> {
+        let limits = Limits::default();
+        let mut output = Vec::new();
+        serve(requests.join("\n").as_bytes(), &mut output, &limits, Settings::default()).unwrap();
+        String::from_utf8(output).unwrap().lines().map(str::to_string).collect()
+    }
+
+    fn request(id: &str, method: &str, params: &str) -> String {
+        format!("{{\"jsonrpc\":\"2.0\",\"id\":{},\"method\":\"{}\",\"params\":{}}}", id, method, params)
+    }
+
+    #[test]
+    fn one_response_line_per_request() {
+        let evaluate = request("1", "evaluate", "{\"expression\":\"2 + 3\"}");
+        let notification = "{\"jsonrpc\":\"2.0\",\"method\":\"evaluate\",\"params\":{\"expression\":\"100\"}}";
+        let by_name = request("\"next\"", "evaluate", "{\"expression\":\"ans\"}");
+        // Пустые строки пропускаются, на уведомление без id ответа нет, id возвращается как есть
+        assert_eq!(exchange(&[&evaluate, "", "   ", notification, &by_name]), [
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"rpn\":\"2 3 +\",\"result\":\"5.00\"}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":\"next\",\"result\":{\"rpn\":\"ans\",\"result\":\"100.00\"}}",
+        ]);
+    }
+
+    #[test]
+    fn error_codes() {
+        let responses = exchange(&[
+            "{\"jsonrpc\":\"2.0\",",
+            "{\"id\":2,\"method\":\"evaluate\"}",
+            &request("3", "nope", "{}"),
+            &request("4", "evaluate", "{}"),
+            &request("5", "define", "{\"name\":\"sin\",\"expression\":\"1\"}"),
+            &request("6", "set", "{\"name\":\"color\",\"value\":\"red\"}"),
+            &request("7", "evaluate", "{\"expression\":\"2 +\"}"),
+        ]);
+        assert_eq!(responses[..6], [
+            "{\"jsonrpc\":\"2.0\",\"id\":null,\"error\":{\"code\":-32700,\"message\":\"некорректный JSON: ожидалось имя поля\"}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":2,\"error\":{\"code\":-32600,\"message\":\"ожидается запрос JSON-RPC 2.0 с полем 'method'\"}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":3,\"error\":{\"code\":-32601,\"message\":\"неизвестный метод 'nope'\"}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":4,\"error\":{\"code\":-32602,\"message\":\"ожидается строковый параметр 'expression'\"}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":5,\"error\":{\"code\":-32602,\"message\":\"имя 'sin' уже занято\"}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":6,\"error\":{\"code\":-32602,\"message\":\"неизвестная настройка 'color red'\"}}",
+        ]);
+        // Ошибка вычисления: описание ошибки как в --error-format json - в поле data
+        assert_eq!(responses[6], "{\"jsonrpc\":\"2.0\",\"id\":7,\"error\":{\"code\":-32000,\"message\":\"выражение заканчивается оператором '+'\",\
+            \"data\":{\"code\":\"E0015\",\"kind\":\"dangling_operator\",\"message\":\"выражение заканчивается оператором '+'\",\
+            \"span\":{\"start\":2,\"end\":3},\"lexeme\":\"+\"}}}");
+    }
+
+    #[test]
+    fn session_state_between_requests() {
+        let responses = exchange(&[
+            &request("1", "evaluate", "{\"expression\":\"2 + 3\"}"),
+            &request("2", "evaluate", "{\"expression\":\"ans * 2\"}"),
+            &request("3", "evaluate", "{\"expression\":\"out(1) + out(2)\"}"),
+            &request("4", "define", "{\"name\":\"vat\",\"expression\":\"ans * 0.2\"}"),
+            &request("5", "evaluate", "{\"expression\":\"vat\"}"),
+            &request("6", "complete", "{\"prefix\":\"va\"}"),
+            &request("7", "set", "{\"name\":\"notation\",\"value\":\"sci\"}"),
+            &request("8", "evaluate", "{\"expression\":\"12345678\"}"),
+            &request("9", "set", "{\"name\":\"integer\",\"value\":\"exact\"}"),
+            &request("10", "evaluate", "{\"expression\":\"8 / 2\"}"),
+            // Целые результаты в ans не попадают, а сам ans в целочисленном режиме недоступен
+            &request("11", "evaluate", "{\"expression\":\"ans\"}"),
+        ]);
+        let results: Vec<&str> = responses.iter().map(|response| response.split_once("\"result\":").map_or("", |(_, result)| result)).collect();
+        assert_eq!(results[..10], [
+            "{\"rpn\":\"2 3 +\",\"result\":\"5.00\"}}",
+            "{\"rpn\":\"ans 2 *\",\"result\":\"10.00\"}}",
+            "{\"rpn\":\"1 [1] out 2 [1] out +\",\"result\":\"15.00\"}}",
+            "true}",
+            "{\"rpn\":\"vat\",\"result\":\"3.00\"}}",
+            "[{\"label\":\"variance\",\"kind\":\"function\"},{\"label\":\"vat\",\"kind\":\"variable\"}]}",
+            "true}",
+            "{\"rpn\":\"12345678\",\"result\":\"1.2345678e7\"}}",
+            "true}",
+            "{\"rpn\":\"8 2 /\",\"result\":\"4\"}}",
+        ]);
+        assert!(responses[10].contains("\"code\":-32000") && responses[10].contains("\"kind\":\"forbidden\""), "{}", responses[10]);
+
+        // У каждого потока запросов свой сеанс
+        assert!(exchange(&[&request("1", "evaluate", "{\"expression\":\"vat\"}")])[0].contains("\"kind\":\"unknown_variable\""));
+    }
+
+    #[test]
+    fn check_and_to_rpn() {
+        assert_eq!(exchange(&[
+            &request("1", "check", "{\"expression\":\"2 + 1\"}"),
+            &request("2", "toRpn", "{\"expression\":\"(1 + 2) * 3\"}"),
+        ]), [
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"valid\":true,\"error\":null}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":\"1 2 + 3 *\"}",
+        ]);
+        let invalid = &exchange(&[&request("1", "check", "{\"expression\":\"2 +\"}")])[0];
+        assert!(invalid.starts_with("{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"valid\":false,\"error\":{\"code\":\"E0015\""), "{}", invalid);
+    }
+}
@@ -0,0 +1,259 @@
+// Режим HTTP-сервера: `less_2_task serve --port 8080`
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use less_2_task::calc::{json_string, process, Limits};
+
+use crate::connections::ConnectionLimit;
+use crate::json::{self, Json};
+
+// Максимальный размер тела запроса
+const MAX_BODY: usize = 64 * 1024;
+// Максимальная длина строки запроса и строки заголовка
+const MAX_HEADER_LINE: usize = 8 * 1024;
+// Время ожидания данных от клиента
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Ответ сервера: статус и тело в формате JSON
+type Response = (&'static str, String);
+
+fn bad_request(message: &str) -> Response {
+    ("400 Bad Request", format!("{{\"error\":{{\"code\":\"bad_request\",\"message\":{}}}}}", json_string(message)))
+}
+
+// Приём соединений; каждое соединение обрабатывается в отдельном потоке, одновременно
+// не больше max_connections соединений
+pub fn serve(port: u16, limits: Limits, max_connections: usize) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Сервер запущен: http://127.0.0.1:{}/eval", port);
+    accept_loop(listener, limits, max_connections)
+}
+
+fn accept_loop(listener: TcpListener, limits: Limits, max_connections: usize) -> io::Result<()> {
+    let limits = Arc::new(limits);
+    let connections = ConnectionLimit::new(max_connections);
+    loop {
+        let permit = connections.acquire();
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(why) => {
+                eprintln!("Не удалось принять соединение: {}", why);
+                continue;
+            },
+        };
+        let limits = Arc::clone(&limits);
+        thread::spawn(move || {
+            if let Err(why) = handle_connection(stream, &limits) {
+                eprintln!("Ошибка обработки запроса: {}", why);
+            }
+            drop(permit);
+        });
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, limits: &Limits) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (status, body) = match read_request(&mut reader) {
+        Ok((method, path, body)) => route(&method, &path, &body, limits),
+        Err(message) => bad_request(&message),
+    };
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body)?;
+    stream.flush()
+}
+
+// Чтение строки не длиннее MAX_HEADER_LINE байт; 0 - конец потока
+fn read_header_line(reader: &mut impl BufRead, line: &mut String) -> Result<usize, String> {
+    let read = reader.by_ref().take(MAX_HEADER_LINE as u64).read_line(line).map_err(|why| why.to_string())?;
+    if read == MAX_HEADER_LINE && !line.ends_with('\n') {
+        return Err(format!("строка запроса или заголовка длиннее {} байт", MAX_HEADER_LINE));
+    }
+    Ok(read)
+}
+
+// Чтение строки запроса, заголовков и тела; из заголовков нужна только длина тела
+fn read_request(reader: &mut impl BufRead) -> Result<(String, String, String), String> {
+    let mut line = String::new();
+    read_header_line(reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err("некорректная строка запроса".to_string()),
+    };
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if read_header_line(reader, &mut header)? == 0 {
+            return Err("неожиданный конец запроса".to_string());
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().map_err(|_| "некорректный заголовок Content-Length".to_string())?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(format!("тело запроса больше {} байт", MAX_BODY));
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|why| why.to_string())?;
+    let body = String::from_utf8(body).map_err(|_| "тело запроса не в кодировке UTF-8".to_string())?;
+    Ok((method, path, body))
+}
+
+fn route(method: &str, path: &str, body: &str, limits: &Limits) -> Response {
+    match (method, path) {
+        ("POST", "/eval") => eval(body, limits),
+        (_, "/eval") => ("405 Method Not Allowed", "{\"error\":{\"code\":\"method_not_allowed\",\"message\":\"используйте POST\"}}".to_string()),
+        _ => ("404 Not Found", "{\"error\":{\"code\":\"not_found\",\"message\":\"используйте POST /eval\"}}".to_string()),
+    }
+}
+
+// POST /eval с телом {"expression": "..."}; ответ {"rpn": "...", "result": "..."} или
+// {"error": {...}} с описанием ошибки как в --error-format json
+fn eval(body: &str, limits: &Limits) -> Response {
+    let request = match json::parse(body) {
+        Ok(request) => request,
+        Err(why) => return bad_request(&format!("некорректный JSON: {}", why)),
+    };
+    let expression = match request.get("expression").and_then(Json::as_str) {
+        Some(expression) => expression,
+        None => return bad_request("ожидается строковое поле 'expression'"),
+    };
+    match request.get("variables") {
+        None | Some(Json::Null) => (),
+        Some(Json::Object(fields)) if fields.is_empty() => (),
+        Some(_) => return bad_request("переменные не поддерживаются"),
+    }
+
+    match process(expression, limits) {
+        Ok(evaluation) => ("200 OK", format!("{{\"rpn\":{},\"result\":{}}}",
+            json_string(&evaluation.rpn), json_string(&evaluation.result))),
+        Err(why) => ("422 Unprocessable Entity", format!("{{\"error\":{}}}", why.to_json())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+    use less_2_task::calc::ErrorKind;
+
+    // Ответ сервера на запрос request: строка статуса и тело. Соединение обрабатывает
+    // handle_connection в отдельном потоке, клиент - в потоке теста
+    fn exchange(request: &[u8], limits: Limits) -> (String, String) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &limits)
+        });
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(request).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let response = read_response(&mut client);
+        server.join().unwrap().unwrap();
+        response
+    }
+
+    fn read_response(client: &mut TcpStream) -> (String, String) {
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").expect("заголовки и тело");
+        let status = head.lines().next().unwrap().trim_start_matches("HTTP/1.1 ").to_string();
+        assert!(head.contains(&format!("Content-Length: {}", body.len())), "{}", head);
+        (status, body.to_string())
+    }
+
+    fn post(path: &str, body: &str) -> Vec<u8> {
+        format!("POST {} HTTP/1.1\r\nHost: localhost\r\ncontent-length: {}\r\n\r\n{}", path, body.len(), body).into_bytes()
+    }
+
+    #[test]
+    fn routes_requests() {
+        let (status, body) = exchange(&post("/eval", "{\"expression\": \"2 + 3 * 4\"}"), Limits::default());
+        assert_eq!((status.as_str(), body.as_str()), ("200 OK", "{\"rpn\":\"2 3 4 * +\",\"result\":\"14.00\"}"));
+        let (status, body) = exchange(b"GET /eval HTTP/1.1\r\n\r\n", Limits::default());
+        assert_eq!(status, "405 Method Not Allowed");
+        assert!(body.contains("\"code\":\"method_not_allowed\""), "{}", body);
+        let (status, body) = exchange(&post("/calc", "{}"), Limits::default());
+        assert_eq!(status, "404 Not Found");
+        assert!(body.contains("\"code\":\"not_found\""), "{}", body);
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let cases: [(&[u8], &str); 6] = [
+            (&post("/eval", "{\"expression\": "), "некорректный JSON"),
+            (&post("/eval", "{\"expr\": \"1\"}"), "ожидается строковое поле 'expression'"),
+            (&post("/eval", "{\"expression\": \"x\", \"variables\": {\"x\": 1}}"), "переменные не поддерживаются"),
+            (b"POST\r\n\r\n", "некорректная строка запроса"),
+            (b"POST /eval HTTP/1.1\r\nContent-Length: many\r\n\r\n", "некорректный заголовок Content-Length"),
+            (b"POST /eval HTTP/1.1\r\nContent-Length: 2\r\n", "неожиданный конец запроса"),
+        ];
+        for (request, message) in cases {
+            let (status, body) = exchange(request, Limits::default());
+            assert_eq!(status, "400 Bad Request", "{}", message);
+            assert!(body.starts_with("{\"error\":{\"code\":\"bad_request\",\"message\":") && body.contains(message), "{}", body);
+        }
+    }
+
+    #[test]
+    fn reports_calculation_errors() {
+        let (status, body) = exchange(&post("/eval", "{\"expression\": \"2 +\"}"), Limits::default());
+        assert_eq!(status, "422 Unprocessable Entity");
+        assert!(body.starts_with("{\"error\":{\"code\":\"E"), "{}", body);
+        // Вложенные функции с переменной не обходят общий бюджет шагов вычисления
+        let expression = "sum(table(sum(table(x * y, y, 0, 300, 1)), x, 0, 300, 1))";
+        let limits = Limits { max_steps: 10_000, ..Limits::default() };
+        let (status, body) = exchange(&post("/eval", &format!("{{\"expression\": \"{}\"}}", expression)), limits);
+        assert_eq!(status, "422 Unprocessable Entity");
+        assert!(body.contains(ErrorKind::BudgetExceeded.name()), "{}", body);
+    }
+
+    #[test]
+    fn limits_request_size() {
+        let request = format!("POST /eval HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY + 1);
+        let (status, body) = exchange(request.as_bytes(), Limits::default());
+        assert_eq!(status, "400 Bad Request");
+        assert!(body.contains(&format!("тело запроса больше {} байт", MAX_BODY)), "{}", body);
+
+        // Строка без перевода строки читается не дальше MAX_HEADER_LINE байт; запрос ровно
+        // такой длины, чтобы сервер прочитал его целиком и не сбросил соединение
+        let request = format!("GET /{}", "a".repeat(MAX_HEADER_LINE - 5));
+        let (status, body) = exchange(request.as_bytes(), Limits::default());
+        assert_eq!(status, "400 Bad Request");
+        assert!(body.contains(&format!("длиннее {} байт", MAX_HEADER_LINE)), "{}", body);
+    }
+
+    #[test]
+    fn caps_concurrent_connections() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || accept_loop(listener, Limits::default(), 1));
+
+        // Первое соединение занимает единственное место, пока не отправит запрос
+        let mut first = TcpStream::connect(address).unwrap();
+        let mut second = TcpStream::connect(address).unwrap();
+        second.write_all(&post("/eval", "{\"expression\": \"2\"}")).unwrap();
+        second.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+        let mut byte = [0; 1];
+        let waiting = second.read(&mut byte).expect_err("второе соединение ещё не обслуживается");
+        assert!(matches!(waiting.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut), "{}", waiting);
+
+        first.write_all(&post("/eval", "{\"expression\": \"1\"}")).unwrap();
+        first.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(read_response(&mut first).1, "{\"rpn\":\"1\",\"result\":\"1.00\"}");
+        second.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        assert_eq!(read_response(&mut second).1, "{\"rpn\":\"2\",\"result\":\"2.00\"}");
+    }
+}
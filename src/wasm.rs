@@ -0,0 +1,22 @@
+// Интерфейс калькулятора для JavaScript (wasm-bindgen)
+use wasm_bindgen::prelude::*;
+
+use crate::calc::{parse, process, CalcError, Limits};
+
+// Ошибка передаётся в JavaScript в виде JSON: код, сообщение и позиция в выражении
+fn error_value(error: CalcError) -> JsValue {
+    JsValue::from_str(&error.to_json())
+}
+
+// Вычисление выражения; при ошибке выбрасывается исключение с описанием ошибки в JSON
+#[wasm_bindgen]
+pub fn evaluate(expr: &str) -> Result<String, JsValue> {
+    process(expr, &Limits::default()).map(|evaluation| evaluation.result).map_err(error_value)
+}
+
+// Запись выражения в ОПН без вычисления
+#[wasm_bindgen(js_name = toRpn)]
+pub fn to_rpn(expr: &str) -> Result<String, JsValue> {
+    let output = parse(expr, &Limits::default()).map_err(error_value)?;
+    Ok(output.iter().map(|tok| &*tok.1).collect::<Vec<&str>>().join(" "))
+}
@@ -0,0 +1,51 @@
+// Подсчёт выделений памяти: после разбиения на токены преобразование в ОПН и вычисление
+// с Workspace не выделяют память. Глобальный распределитель действует на всю программу
+// тестов, поэтому проверка вынесена в отдельный файл с единственным тестом
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use less_2_task::calc::{tokerize, Limits, NoResolver, Value, Workspace};
+
+// Распределитель, считающий выделения и перевыделения памяти
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+fn no_allocations_after_lexing() {
+    let limits = Limits::default();
+    let mut workspace = Workspace::new();
+    let long = format!("{}1", "1 + 2 * 3 - 4 / 5 - ".repeat(20));
+    // Первое выражение длиннее и глубже остальных: после него памяти хватает на все
+    let warm_up = format!("{}1{} + {}", "abs(min(1, -(2 + ".repeat(20), ")))".repeat(20), long);
+    let tokens = tokerize(&warm_up, &limits).unwrap();
+    workspace.evaluate(tokens, &warm_up, &limits, &NoResolver).unwrap();
+
+    let inputs = ["1+2", "2 + 3 * (4 - 1) / sqrt(16)", "-(-2) * 3 % 4", "max(1, abs(-4)) << 2", "5 km + 300 m", &long];
+    for input in inputs.iter() {
+        let tokens = tokerize(input, &limits).unwrap();
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let value = workspace.evaluate(tokens, input, &limits, &NoResolver);
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        assert!(matches!(value, Ok(Value::Number(_)) | Ok(Value::Quantity(..))), "{}: {:?}", input, value);
+        assert_eq!(allocations, 0, "{}", input);
+    }
+}